@@ -59,12 +59,12 @@ impl ResponseError for CustomError {
 
         match status_code.as_u16() {
             406 => {
-                return HttpResponse::Found().append_header(("Location", "/not_authorized")).finish()
+                HttpResponse::Found().append_header(("Location", "/not_authorized")).finish()
             },
             i if i > 500 => {
-                return HttpResponse::Found().append_header(("Location","/internal_server_error")).finish()
+                HttpResponse::Found().append_header(("Location","/internal_server_error")).finish()
             },
-            _ => return HttpResponse::Found().append_header(("Location","/internal_server_error")).finish()
-        };
+            _ => HttpResponse::Found().append_header(("Location","/internal_server_error")).finish()
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,35 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use async_graphql::Result;
+use async_trait::async_trait;
+
+use crate::anchoring::AnchorSink;
+use crate::models::MerkleAnchor;
+
+/// Writes each anchor to a directory as `<anchor id>.json`
+pub struct FileAnchorSink {
+    dir: PathBuf,
+}
+
+impl FileAnchorSink {
+    /// Creates `dir` if it doesn't exist
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+
+        Ok(FileAnchorSink {
+            dir: dir.as_ref().to_owned(),
+        })
+    }
+}
+
+#[async_trait]
+impl AnchorSink for FileAnchorSink {
+    async fn publish(&self, anchor: &MerkleAnchor) -> Result<()> {
+        let path = self.dir.join(format!("{}.json", anchor.id));
+
+        fs::write(&path, serde_json::to_vec_pretty(anchor)?)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+use async_graphql::{Error, Result};
+use async_trait::async_trait;
+
+use crate::anchoring::AnchorSink;
+use crate::models::MerkleAnchor;
+
+/// POSTs each anchor as JSON to a URL. Any 2xx response counts as published.
+pub struct HttpAnchorSink {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpAnchorSink {
+    pub fn new(url: &str) -> Self {
+        HttpAnchorSink {
+            client: reqwest::Client::new(),
+            url: url.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl AnchorSink for HttpAnchorSink {
+    async fn publish(&self, anchor: &MerkleAnchor) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .json(anchor)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| Error::new(format!("Unable to post anchor to {}: {}", self.url, e)))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+//! Periodic Merkle anchoring and publication of the roots.
+//!
+//! Every ANCHOR_INTERVAL seconds the job anchors the audit entries and
+//! receipts added since the last run (see `models/merkle.rs`) and hands each
+//! new anchor to an `AnchorSink`. `ANCHOR_HTTP_URL` POSTs the anchor as JSON
+//! to a notary or transparency service, and `ANCHOR_DIR` writes it to that
+//! directory as `<anchor id>.json`, for a store replicated off this host.
+//! With neither set, anchors are kept but not published. Anchors whose
+//! publication failed are retried on the next run.
+
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_graphql::Result;
+use async_trait::async_trait;
+
+use crate::config_variables::{ANCHOR_INTERVAL, MERKLE_AUDIT_BATCH_LIMIT, MERKLE_RECEIPT_BATCH_LIMIT};
+use crate::models::MerkleAnchor;
+use crate::repositories::Repositories;
+
+mod file;
+mod http;
+
+pub use self::file::FileAnchorSink;
+pub use self::http::HttpAnchorSink;
+
+/// Publishes anchors outside this service
+#[async_trait]
+pub trait AnchorSink: Send + Sync {
+    async fn publish(&self, anchor: &MerkleAnchor) -> Result<()>;
+}
+
+/// Creates and publishes anchors
+#[derive(Clone)]
+pub struct Anchorer {
+    repos: Repositories,
+    sink: Option<Arc<dyn AnchorSink>>,
+}
+
+impl Anchorer {
+    pub fn new(repos: Repositories, sink: Option<Arc<dyn AnchorSink>>) -> Self {
+        Anchorer { repos, sink }
+    }
+
+    /// Configures the sink from ANCHOR_HTTP_URL or ANCHOR_DIR
+    pub fn from_env(repos: Repositories) -> Self {
+        let sink: Option<Arc<dyn AnchorSink>> = if let Ok(url) = env::var("ANCHOR_HTTP_URL") {
+            Some(Arc::new(HttpAnchorSink::new(&url)))
+        } else if let Ok(dir) = env::var("ANCHOR_DIR") {
+            Some(Arc::new(FileAnchorSink::new(&dir).expect("Unable to use ANCHOR_DIR")))
+        } else {
+            None
+        };
+
+        Anchorer::new(repos, sink)
+    }
+
+    /// Publishes anchors left over from failed runs, then anchors whatever
+    /// is new and publishes that. Returns the new anchors.
+    pub async fn run_once(&self) -> Result<Vec<MerkleAnchor>> {
+        self.publish_pending().await;
+
+        let mut created = Vec::new();
+
+        // A backlog bigger than one batch is anchored in several
+        while let Some(anchor) = self
+            .repos
+            .merkle_anchors
+            .create_batch(MERKLE_AUDIT_BATCH_LIMIT, MERKLE_RECEIPT_BATCH_LIMIT)
+            .await?
+        {
+            let audit_entries = anchor.audit_entry_count();
+            let receipts = i64::from(anchor.leaf_count) - audit_entries;
+            let full = audit_entries >= MERKLE_AUDIT_BATCH_LIMIT || receipts >= MERKLE_RECEIPT_BATCH_LIMIT;
+            created.push(anchor);
+
+            if !full {
+                break;
+            }
+        }
+
+        self.publish_pending().await;
+
+        Ok(created)
+    }
+
+    async fn publish_pending(&self) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+
+        let pending = match self.repos.merkle_anchors.get_unpublished().await {
+            Ok(pending) => pending,
+            Err(e) => {
+                println!("Unable to load unpublished Merkle anchors: {:?}", e);
+                return;
+            }
+        };
+
+        for anchor in pending {
+            let published = match sink.publish(&anchor).await {
+                Ok(()) => self.repos.merkle_anchors.mark_published(anchor.id).await.map(|_| ()),
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = published {
+                println!("Unable to publish Merkle anchor {}: {:?}", anchor.id, e);
+            }
+        }
+    }
+
+    /// Runs every ANCHOR_INTERVAL seconds (the ANCHOR_INTERVAL variable
+    /// overrides the default) for the life of the server. Failures are logged
+    /// and retried on the next run.
+    pub fn spawn(self) {
+        let seconds = env::var("ANCHOR_INTERVAL")
+            .ok()
+            .map(|s| s.parse().expect("Unable to parse ANCHOR_INTERVAL"))
+            .unwrap_or(ANCHOR_INTERVAL);
+
+        actix_rt::spawn(async move {
+            let mut interval = actix_rt::time::interval(Duration::from_secs(seconds));
+
+            loop {
+                interval.tick().await;
+
+                match self.run_once().await {
+                    Ok(anchors) => {
+                        for anchor in anchors {
+                            println!("Anchored {} leaves under Merkle root {}", anchor.leaf_count, anchor.root_hash);
+                        }
+                    }
+                    Err(e) => println!("Unable to anchor the audit trail: {:?}", e),
+                }
+            }
+        });
+    }
+}
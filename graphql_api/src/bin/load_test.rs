@@ -0,0 +1,140 @@
+//! Load test for the GraphQL endpoint.
+//!
+//! First sends a few requests one at a time to measure the unloaded latency,
+//! then fires `LOAD_TEST_REQUESTS` queries at `LOAD_TEST_URL` with up to
+//! `LOAD_TEST_CONCURRENCY` in flight and reports throughput, latency
+//! percentiles and the speedup over running the same requests sequentially.
+//!
+//! When resolvers block actix workers on Diesel calls, concurrent requests
+//! landing on the same worker serialize and the speedup is capped at the
+//! server's worker count. With database work offloaded to the blocking pool it
+//! should approach the smaller of LOAD_TEST_CONCURRENCY and the server's
+//! DATABASE_POOL_SIZE. Running the server with `HTTP_WORKERS=1` against a
+//! database with some network latency makes the difference obvious.
+//!
+//! ```bash
+//! HTTP_WORKERS=1 cargo run
+//! LOAD_TEST_CONCURRENCY=16 cargo run --bin load_test
+//! ```
+//!
+//! `tests/concurrent_resolvers.rs` checks the same behaviour against a
+//! stand-in Postgres server; this binary measures a real deployment.
+//!
+//! Set `LOAD_TEST_QUERY` to exercise a different query and
+//! `LOAD_TEST_BEARER` to send an Authorization header. The default query
+//! needs a token for an ANALYST or higher.
+
+use std::env;
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+const DEFAULT_URL: &str = "http://localhost:8080/graphql";
+const SEQUENTIAL_REQUESTS: usize = 8;
+// A single database round trip per request, so the speedup reflects how many
+// requests the server runs at once rather than fan-out within one request
+const DEFAULT_QUERY: &str = "{ searchDataObjects(query: \"intelligence OR operation\") \
+    { rank titleHighlight } }";
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let idx = ((sorted.len() as f64 - 1.0) * pct).round() as usize;
+    sorted[idx]
+}
+
+/// Sends one query and returns its latency and whether it succeeded without
+/// GraphQL errors
+async fn send(request: reqwest::RequestBuilder) -> (Duration, bool) {
+    let sent = Instant::now();
+
+    let ok = match request.send().await {
+        Ok(res) if res.status().is_success() => res
+            .json::<Value>()
+            .await
+            .map(|v| v.get("errors").is_none())
+            .unwrap_or(false),
+        _ => false,
+    };
+
+    (sent.elapsed(), ok)
+}
+
+#[actix_rt::main]
+async fn main() {
+    dotenv::dotenv().ok();
+
+    let url = env::var("LOAD_TEST_URL").unwrap_or_else(|_| DEFAULT_URL.to_owned());
+    let query = env::var("LOAD_TEST_QUERY").unwrap_or_else(|_| DEFAULT_QUERY.to_owned());
+    let bearer = env::var("LOAD_TEST_BEARER").ok();
+    let total: usize = env_or("LOAD_TEST_REQUESTS", 256);
+    let concurrency: usize = env_or("LOAD_TEST_CONCURRENCY", 16);
+
+    println!("Load testing {} ({} requests, {} concurrent)", &url, total, concurrency);
+
+    let client = reqwest::Client::new();
+    let body = json!({ "query": query });
+
+    let build = || {
+        let request = client.post(&url).json(&body);
+
+        match &bearer {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    };
+
+    // Unloaded latency, one request at a time
+    let mut sequential = Duration::ZERO;
+
+    for _ in 0..SEQUENTIAL_REQUESTS {
+        let (elapsed, ok) = send(build()).await;
+
+        if !ok {
+            println!("Request failed before load was applied; check the URL, query and token");
+            return;
+        }
+        sequential += elapsed;
+    }
+
+    let unloaded = sequential / SEQUENTIAL_REQUESTS as u32;
+
+    let started = Instant::now();
+
+    let results: Vec<(Duration, bool)> = futures::stream::iter(0..total)
+        .map(|_| send(build()))
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+
+    let wall = started.elapsed();
+
+    let failures = results.iter().filter(|(_, ok)| !ok).count();
+    let mut latencies: Vec<Duration> = results.iter().map(|(d, _)| *d).collect();
+    latencies.sort();
+
+    println!("Unloaded latency: {:?}", unloaded);
+    println!("Completed in {:.2}s ({} failed)", wall.as_secs_f64(), failures);
+    println!("Throughput: {:.1} req/s", total as f64 / wall.as_secs_f64());
+    println!(
+        "Latency p50: {:?}  p95: {:?}  p99: {:?}  max: {:?}",
+        percentile(&latencies, 0.50),
+        percentile(&latencies, 0.95),
+        percentile(&latencies, 0.99),
+        latencies.last().copied().unwrap_or_default(),
+    );
+    println!(
+        "Speedup over sequential: {:.1}x with {} in flight (~1x means requests are serializing)",
+        (unloaded * total as u32).as_secs_f64() / wall.as_secs_f64(),
+        concurrency
+    );
+}
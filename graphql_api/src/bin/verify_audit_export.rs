@@ -0,0 +1,51 @@
+//! Verifies a signed audit log export offline.
+//!
+//! Takes an export downloaded from `/audit/export`, NDJSON or CSV, and the
+//! receipt public keys, either as saved from `/.well-known/receipt-keys.json`
+//! or as an Ed25519 public key PEM. Prints what the export states and exits
+//! non-zero if the signature doesn't hold or the entries were changed,
+//! removed or cut short. Nothing is fetched from the service.
+//!
+//! ```bash
+//! cargo run --bin verify_audit_export -- audit-export.ndjson receipt-keys.json
+//! ```
+
+use std::fs;
+use std::process::ExitCode;
+
+use graphql_api::models::{find_receipt_key, split_audit_export, verify_audit_export};
+
+fn verify(export_path: &str, keys_path: &str) -> Result<String, String> {
+    let keys = fs::read_to_string(keys_path).map_err(|e| format!("Can't read {}: {}", keys_path, e))?;
+    let export = fs::read(export_path).map_err(|e| format!("Can't read {}: {}", export_path, e))?;
+
+    let (_, trailer) = split_audit_export(&export)?;
+    let kid = trailer
+        .signing_key_id
+        .ok_or_else(|| "the export is not signed".to_owned())?;
+
+    let key = find_receipt_key(&keys, &kid)?;
+    let statement = verify_audit_export(&export, &key)?;
+
+    serde_json::to_string_pretty(&statement).map_err(|e| e.to_string())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+
+    let [_, export_path, keys_path] = args.as_slice() else {
+        eprintln!("Usage: verify_audit_export <audit-export.ndjson | audit-export.csv> <receipt-keys.json | public-key.pem>");
+        return ExitCode::from(2);
+    };
+
+    match verify(export_path, keys_path) {
+        Ok(report) => {
+            println!("Export is valid:\n{}", report);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Export is NOT valid: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Verifies a conversion receipt offline.
+//!
+//! Takes a conversion result as returned by GraphQL (a JSON object with at
+//! least `receipt`, `signature` and `signingKeyId`) and the receipt public
+//! keys, either as saved from `/.well-known/receipt-keys.json` or as an
+//! Ed25519 public key PEM. Prints what the receipt states and exits non-zero
+//! if the signature doesn't hold. Nothing is fetched from the service.
+//!
+//! Given the receipt's `merkleProof` as well (with at least `steps` and the
+//! anchor's `statement`, `signature` and `signingKeyId`), it also checks that
+//! the receipt is included under the signed root of its anchor.
+//!
+//! ```bash
+//! cargo run --bin verify_receipt -- result.json receipt-keys.json [proof.json]
+//! ```
+
+use std::fs;
+use std::process::ExitCode;
+
+use serde::Deserialize;
+
+use graphql_api::models::{find_receipt_key, verify_receipt, verify_receipt_inclusion, MerkleProofStep};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignedResult {
+    receipt: Option<String>,
+    signature: Option<String>,
+    signing_key_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InclusionProof {
+    steps: Vec<MerkleProofStep>,
+    anchor: SignedAnchor,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignedAnchor {
+    statement: String,
+    signature: Option<String>,
+    signing_key_id: Option<String>,
+}
+
+fn read(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))
+}
+
+fn verify(result_path: &str, keys_path: &str, proof_path: Option<&String>) -> Result<String, String> {
+    let keys = read(keys_path)?;
+
+    let result: SignedResult = serde_json::from_str(&read(result_path)?)
+        .map_err(|e| format!("{} is not a conversion result: {}", result_path, e))?;
+
+    let (Some(receipt), Some(signature), Some(kid)) = (result.receipt, result.signature, result.signing_key_id)
+    else {
+        return Err("the result has no signed receipt".to_owned());
+    };
+
+    let key = find_receipt_key(&keys, &kid)?;
+    let statement = verify_receipt(&receipt, &signature, &key)?;
+    let mut report = serde_json::to_string_pretty(&statement).map_err(|e| e.to_string())?;
+
+    if let Some(proof_path) = proof_path {
+        let proof: InclusionProof = serde_json::from_str(&read(proof_path)?)
+            .map_err(|e| format!("{} is not a Merkle proof: {}", proof_path, e))?;
+
+        let (Some(signature), Some(kid)) = (proof.anchor.signature, proof.anchor.signing_key_id) else {
+            return Err("the anchor has no signed statement".to_owned());
+        };
+
+        let key = find_receipt_key(&keys, &kid)?;
+        let anchor = verify_receipt_inclusion(&receipt, &proof.steps, &proof.anchor.statement, &signature, &key)?;
+
+        report.push_str(&format!(
+            "\nIncluded in Merkle anchor {} with root {}, created {}",
+            anchor.anchor_id, anchor.root_hash, anchor.created_at
+        ));
+    }
+
+    Ok(report)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+
+    let (result_path, keys_path, proof_path) = match args.as_slice() {
+        [_, result, keys] => (result, keys, None),
+        [_, result, keys, proof] => (result, keys, Some(proof)),
+        _ => {
+            eprintln!("Usage: verify_receipt <result.json> <receipt-keys.json | public-key.pem> [proof.json]");
+            return ExitCode::from(2);
+        }
+    };
+
+    match verify(result_path, keys_path, proof_path) {
+        Ok(report) => {
+            println!("Receipt is valid:\n{}", report);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Receipt is NOT valid: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
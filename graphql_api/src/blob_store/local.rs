@@ -0,0 +1,142 @@
+use std::path::{Path, PathBuf};
+
+use async_graphql::{Error, Result};
+use async_trait::async_trait;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+
+use crate::blob_store::{BlobStore, BlobWriter};
+
+/// Keeps each blob as a file in a directory, named by its key. Blobs are
+/// written to `<key>.part` and renamed when committed.
+pub struct LocalBlobStore {
+    dir: PathBuf,
+}
+
+impl LocalBlobStore {
+    /// Creates `dir` if it doesn't exist
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(LocalBlobStore {
+            dir: dir.as_ref().to_owned(),
+        })
+    }
+
+    /// Keys are limited to letters, digits and dashes, so they can't leave
+    /// the directory
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(Error::new(format!("Invalid blob key {}", key)));
+        }
+
+        Ok(self.dir.join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn create(&self, key: &str) -> Result<Box<dyn BlobWriter>> {
+        let path = self.path(key)?;
+
+        if fs::try_exists(&path).await? {
+            return Err(Error::new(format!("Blob {} already exists", key)));
+        }
+
+        let part = path.with_extension("part");
+        let file = File::create(&part).await?;
+
+        Ok(Box::new(LocalBlobWriter { file, part, path }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        fs::remove_file(self.path(key)?).await?;
+
+        Ok(())
+    }
+}
+
+struct LocalBlobWriter {
+    file: File,
+    part: PathBuf,
+    path: PathBuf,
+}
+
+#[async_trait]
+impl BlobWriter for LocalBlobWriter {
+    async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.file.write_all(chunk).await?;
+
+        Ok(())
+    }
+
+    async fn commit(mut self: Box<Self>) -> Result<()> {
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+        fs::rename(&self.part, &self.path).await?;
+
+        Ok(())
+    }
+
+    async fn abort(self: Box<Self>) {
+        let LocalBlobWriter { file, part, .. } = *self;
+        drop(file);
+
+        if let Err(e) = fs::remove_file(&part).await {
+            println!("Unable to remove partial upload {}: {:?}", part.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LocalBlobStore;
+    use crate::blob_store::BlobStore;
+
+    fn store() -> LocalBlobStore {
+        LocalBlobStore::new(std::env::temp_dir().join(format!("scc-blobs-{}", uuid::Uuid::new_v4()))).unwrap()
+    }
+
+    #[test]
+    fn keys_that_could_leave_the_directory_are_refused() {
+        let store = store();
+
+        for key in ["../x", "..", "a/b", "/etc/passwd", "x.part", "a\\b", ""] {
+            assert!(store.path(key).is_err(), "{:?}", key);
+        }
+
+        let key = uuid::Uuid::new_v4().to_string();
+        assert_eq!(store.path(&key).unwrap(), store.dir.join(&key));
+    }
+
+    #[actix_rt::test]
+    async fn blobs_appear_only_once_committed() {
+        let store = store();
+
+        let mut writer = store.create("committed").await.unwrap();
+        writer.write(b"the ").await.unwrap();
+        writer.write(b"plan").await.unwrap();
+        assert!(!store.dir.join("committed").exists());
+
+        writer.commit().await.unwrap();
+        assert_eq!(std::fs::read(store.dir.join("committed")).unwrap(), b"the plan");
+        assert!(!store.dir.join("committed.part").exists());
+
+        // Keys aren't reused
+        assert!(store.create("committed").await.is_err());
+
+        store.delete("committed").await.unwrap();
+        assert!(!store.dir.join("committed").exists());
+    }
+
+    #[actix_rt::test]
+    async fn aborted_blobs_leave_nothing_behind() {
+        let store = store();
+
+        let mut writer = store.create("aborted").await.unwrap();
+        writer.write(b"the plan").await.unwrap();
+        writer.abort().await;
+
+        assert_eq!(std::fs::read_dir(&store.dir).unwrap().count(), 0);
+    }
+}
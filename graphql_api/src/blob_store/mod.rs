@@ -0,0 +1,54 @@
+//! Storage for uploaded documents.
+//!
+//! `/data-objects/upload` streams each file into a `BlobStore` under a fresh
+//! key, which is kept on the data object as `storage_key`. `BLOB_DIR` keeps
+//! blobs as files in that directory, and `S3_BUCKET` keeps them in an
+//! S3-compatible bucket (see `s3.rs` for its other variables). With neither
+//! set, uploads are refused.
+
+use std::env;
+use std::sync::Arc;
+
+use async_graphql::Result;
+use async_trait::async_trait;
+
+mod local;
+mod s3;
+mod sniff;
+
+pub use self::local::LocalBlobStore;
+pub use self::s3::S3BlobStore;
+pub use self::sniff::{is_executable, sniff_media_type, SNIFF_LEN};
+
+/// Keeps blobs by key
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Starts writing a blob. Nothing is visible under `key` until the
+    /// writer is committed.
+    async fn create(&self, key: &str) -> Result<Box<dyn BlobWriter>>;
+
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// A blob being written, a chunk at a time
+#[async_trait]
+pub trait BlobWriter: Send {
+    async fn write(&mut self, chunk: &[u8]) -> Result<()>;
+
+    /// Stores the blob under its key
+    async fn commit(self: Box<Self>) -> Result<()>;
+
+    /// Throws away what was written
+    async fn abort(self: Box<Self>);
+}
+
+/// The store configured by S3_BUCKET or BLOB_DIR, if any
+pub fn from_env() -> Option<Arc<dyn BlobStore>> {
+    if env::var("S3_BUCKET").is_ok() {
+        Some(Arc::new(S3BlobStore::from_env()))
+    } else if let Ok(dir) = env::var("BLOB_DIR") {
+        Some(Arc::new(LocalBlobStore::new(&dir).expect("Unable to use BLOB_DIR")))
+    } else {
+        None
+    }
+}
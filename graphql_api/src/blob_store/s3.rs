@@ -0,0 +1,320 @@
+//! S3-compatible object storage: AWS S3, or MinIO, Ceph and the like.
+//!
+//! Blobs are sent as multipart uploads of UPLOAD_PART_SIZE parts, so one
+//! part at most is held in memory, and the object only appears once the
+//! upload is completed. Blobs smaller than a part are sent with a single
+//! PUT. Requests are signed with AWS Signature Version 4 and use path-style
+//! URLs (`<endpoint>/<bucket>/<key>`), which S3-compatible stores accept.
+//!
+//! Configured by S3_BUCKET, S3_ACCESS_KEY_ID, S3_SECRET_ACCESS_KEY, S3_REGION
+//! (us-east-1 unless set) and S3_ENDPOINT (AWS for the region unless set).
+
+use std::env;
+
+use async_graphql::{Error, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, Url};
+use sha2::{Digest, Sha256};
+
+use crate::blob_store::{BlobStore, BlobWriter};
+use crate::config_variables::UPLOAD_PART_SIZE;
+
+/// Stores blobs in an S3 bucket, as objects named by their keys
+#[derive(Clone)]
+pub struct S3BlobStore {
+    client: reqwest::Client,
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+impl S3BlobStore {
+    pub fn new(endpoint: &str, bucket: &str, region: &str, access_key_id: &str, secret_access_key: &str) -> Self {
+        S3BlobStore {
+            client: reqwest::Client::new(),
+            endpoint: Url::parse(endpoint).expect("Unable to parse the S3 endpoint"),
+            bucket: bucket.to_owned(),
+            region: region.to_owned(),
+            access_key_id: access_key_id.to_owned(),
+            secret_access_key: secret_access_key.to_owned(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let bucket = env::var("S3_BUCKET").expect("Unable to load S3_BUCKET");
+        let region = env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_owned());
+        let endpoint = env::var("S3_ENDPOINT").unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", region));
+        let access_key_id = env::var("S3_ACCESS_KEY_ID").expect("Unable to load S3_ACCESS_KEY_ID");
+        let secret_access_key = env::var("S3_SECRET_ACCESS_KEY").expect("Unable to load S3_SECRET_ACCESS_KEY");
+
+        S3BlobStore::new(&endpoint, &bucket, &region, &access_key_id, &secret_access_key)
+    }
+
+    /// Sends a signed request about the object `key`, returning the response
+    /// body, or an error for anything but a 2xx
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> Result<(reqwest::header::HeaderMap, String)> {
+        let path = format!(
+            "{}/{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            uri_encode(&self.bucket),
+            key.split('/').map(uri_encode).collect::<Vec<_>>().join("/"),
+        );
+
+        let mut pairs: Vec<(String, String)> = query.iter().map(|(k, v)| (uri_encode(k), uri_encode(v))).collect();
+        pairs.sort();
+        let canonical_query = pairs
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let host = match self.endpoint.port() {
+            Some(port) => format!("{}:{}", self.endpoint.host_str().unwrap_or_default(), port),
+            None => self.endpoint.host_str().unwrap_or_default().to_owned(),
+        };
+
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = format!("{:x}", Sha256::digest(&body));
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, canonical_query, host, payload_hash, amz_date, signed_headers, payload_hash,
+        );
+
+        let credential_scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            amz_date,
+            credential_scope,
+            Sha256::digest(canonical_request.as_bytes()),
+        );
+
+        let signing_key = [self.region.as_str(), "s3", "aws4_request"].iter().fold(
+            hmac_sha256(format!("AWS4{}", self.secret_access_key).as_bytes(), date.as_bytes()),
+            |key, part| hmac_sha256(&key, part.as_bytes()),
+        );
+        let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id, credential_scope, signed_headers, signature,
+        );
+
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        url.set_query((!canonical_query.is_empty()).then_some(canonical_query.as_str()));
+
+        let response = self
+            .client
+            .request(method.clone(), url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| Error::new(format!("Unable to reach S3 for {} {}: {}", method, key, e)))?;
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let text = response.text().await.unwrap_or_default();
+
+        // CompleteMultipartUpload can fail after a 200
+        if !status.is_success() || text.contains("<Error>") {
+            return Err(Error::new(format!("S3 {} {} failed with {}: {}", method, key, status, text)));
+        }
+
+        Ok((headers, text))
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn create(&self, key: &str) -> Result<Box<dyn BlobWriter>> {
+        Ok(Box::new(S3BlobWriter {
+            store: self.clone(),
+            key: key.to_owned(),
+            buffer: Vec::new(),
+            upload_id: None,
+            etags: Vec::new(),
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.send(Method::DELETE, key, &[], Vec::new()).await?;
+
+        Ok(())
+    }
+}
+
+struct S3BlobWriter {
+    store: S3BlobStore,
+    key: String,
+    buffer: Vec<u8>,
+    /// Set once the first part is sent
+    upload_id: Option<String>,
+    /// ETags of the parts sent, in order
+    etags: Vec<String>,
+}
+
+impl S3BlobWriter {
+    async fn upload_part(&mut self, part: Vec<u8>) -> Result<()> {
+        let upload_id = match &self.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let (_, body) = self
+                    .store
+                    .send(Method::POST, &self.key, &[("uploads", "")], Vec::new())
+                    .await?;
+                let upload_id = xml_element(&body, "UploadId")
+                    .ok_or_else(|| Error::new("S3 returned no UploadId"))?;
+                self.upload_id = Some(upload_id.clone());
+                upload_id
+            }
+        };
+
+        let part_number = (self.etags.len() + 1).to_string();
+        let (headers, _) = self
+            .store
+            .send(
+                Method::PUT,
+                &self.key,
+                &[("partNumber", &part_number), ("uploadId", &upload_id)],
+                part,
+            )
+            .await?;
+
+        let etag = headers
+            .get("etag")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| Error::new("S3 returned no ETag for a part"))?;
+        self.etags.push(etag.to_owned());
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlobWriter for S3BlobWriter {
+    async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.buffer.extend_from_slice(chunk);
+
+        while self.buffer.len() >= UPLOAD_PART_SIZE {
+            let rest = self.buffer.split_off(UPLOAD_PART_SIZE);
+            let part = std::mem::replace(&mut self.buffer, rest);
+            self.upload_part(part).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn commit(mut self: Box<Self>) -> Result<()> {
+        let Some(upload_id) = self.upload_id.clone() else {
+            let body = std::mem::take(&mut self.buffer);
+            self.store.send(Method::PUT, &self.key, &[], body).await?;
+            return Ok(());
+        };
+
+        if !self.buffer.is_empty() {
+            let part = std::mem::take(&mut self.buffer);
+            self.upload_part(part).await?;
+        }
+
+        let parts: String = self
+            .etags
+            .iter()
+            .enumerate()
+            .map(|(i, etag)| format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", i + 1, etag))
+            .collect();
+        let body = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+
+        self.store
+            .send(Method::POST, &self.key, &[("uploadId", &upload_id)], body.into_bytes())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn abort(self: Box<Self>) {
+        let Some(upload_id) = &self.upload_id else {
+            return;
+        };
+
+        let aborted = self
+            .store
+            .send(Method::DELETE, &self.key, &[("uploadId", upload_id)], Vec::new())
+            .await;
+
+        if let Err(e) = aborted {
+            println!("Unable to abort S3 upload of {}: {:?}", self.key, e);
+        }
+    }
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent-encodes all but the unreserved characters, as SigV4 requires
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Text of the first `<name>` element of an S3 XML response
+fn xml_element(xml: &str, name: &str) -> Option<String> {
+    let open = format!("<{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&format!("</{}>", name))? + start;
+
+    Some(xml[start..end].to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{uri_encode, xml_element};
+
+    #[test]
+    fn only_unreserved_characters_are_left_unencoded() {
+        assert_eq!(uri_encode("AZaz09-_.~"), "AZaz09-_.~");
+        assert_eq!(uri_encode("a b/c+d=é"), "a%20b%2Fc%2Bd%3D%C3%A9");
+        assert_eq!(uri_encode(""), "");
+    }
+
+    #[test]
+    fn xml_element_reads_the_first_match() {
+        let body = "<InitiateMultipartUploadResult><Bucket>docs</Bucket>\
+            <UploadId>abc-123</UploadId><UploadId>other</UploadId></InitiateMultipartUploadResult>";
+
+        assert_eq!(xml_element(body, "UploadId").as_deref(), Some("abc-123"));
+        assert_eq!(xml_element(body, "Bucket").as_deref(), Some("docs"));
+        assert_eq!(xml_element(body, "Key"), None);
+        assert_eq!(xml_element("<UploadId>unterminated", "UploadId"), None);
+    }
+}
@@ -0,0 +1,124 @@
+/// Bytes of a file looked at to tell its type
+pub const SNIFF_LEN: usize = 512;
+
+/// Leading bytes of the formats recognised, most specific first
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"%PDF-", "application/pdf"),
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"II*\x00", "image/tiff"),
+    (b"MM\x00*", "image/tiff"),
+    (b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", "application/x-ole-storage"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"{\\rtf", "application/rtf"),
+    (b"<?xml", "application/xml"),
+    (b"\x7fELF", "application/x-executable"),
+];
+
+/// Media type of a file from its first SNIFF_LEN bytes (or all of them, if
+/// it is shorter), ignoring whatever the client claimed. Text is anything
+/// that is UTF-8 without control characters other than whitespace.
+pub fn sniff_media_type(head: &[u8]) -> &'static str {
+    if let Some((_, media_type)) = SIGNATURES.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return media_type;
+    }
+
+    let text = is_text(head);
+
+    // "MZ" alone also starts text, such as a CSV of names
+    if head.starts_with(b"MZ") && (has_pe_header(head) || !text) {
+        "application/x-msdownload"
+    } else if text {
+        "text/plain; charset=utf-8"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+fn is_text(head: &[u8]) -> bool {
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // A character cut in two at the end of the head
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or_default(),
+        Err(_) => return false,
+    };
+
+    !head.is_empty() && text.chars().all(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+}
+
+/// Whether the offset at 0x3C of an MZ header points to "PE\0\0" within
+/// `head`. A header further in is past what was read, and the stub is then
+/// only taken for an executable if it isn't text.
+fn has_pe_header(head: &[u8]) -> bool {
+    let Some(e_lfanew) = head.get(0x3c..0x40) else {
+        return false;
+    };
+    let offset = u32::from_le_bytes(e_lfanew.try_into().unwrap()) as usize;
+
+    head.get(offset..offset.saturating_add(4)) == Some(b"PE\0\0".as_slice())
+}
+
+/// Whether uploads of this media type are refused
+pub fn is_executable(media_type: &str) -> bool {
+    matches!(media_type, "application/x-msdownload" | "application/x-executable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_executable, sniff_media_type};
+
+    /// A DOS stub whose e_lfanew points to a PE signature at 0x80
+    fn portable_executable() -> Vec<u8> {
+        let mut head = vec![0u8; 0x88];
+        head[..2].copy_from_slice(b"MZ");
+        head[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        head[0x80..0x84].copy_from_slice(b"PE\0\0");
+        head
+    }
+
+    #[test]
+    fn known_signatures_win_over_the_text_check() {
+        assert_eq!(sniff_media_type(b"%PDF-1.7\n%\xe2\xe3"), "application/pdf");
+        assert_eq!(sniff_media_type(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff_media_type(b"PK\x03\x04\x14\0"), "application/zip");
+        assert_eq!(sniff_media_type(b"<?xml version=\"1.0\"?>"), "application/xml");
+    }
+
+    #[test]
+    fn windows_and_elf_binaries_are_executables() {
+        assert!(is_executable(sniff_media_type(&portable_executable())));
+        assert!(is_executable(sniff_media_type(b"MZ\x90\0\x03\0\0\0")));
+        assert!(is_executable(sniff_media_type(b"\x7fELF\x02\x01\x01")));
+        assert!(!is_executable(sniff_media_type(b"%PDF-1.7")));
+        assert!(!is_executable(sniff_media_type(b"MARKING: SECRET")));
+    }
+
+    #[test]
+    fn text_starting_with_mz_is_text() {
+        let csv = b"MZ,Mozambique,SECRET\nGB,United Kingdom,SECRET\n";
+        assert_eq!(sniff_media_type(csv), "text/plain; charset=utf-8");
+
+        // Long enough to reach e_lfanew, which as text points past the head
+        let long = "MZ notes ".repeat(20);
+        assert_eq!(sniff_media_type(long.as_bytes()), "text/plain; charset=utf-8");
+    }
+
+    #[test]
+    fn printable_utf8_is_text() {
+        assert_eq!(sniff_media_type("The plan\r\n\tfor Zürich\n".as_bytes()), "text/plain; charset=utf-8");
+
+        // A character cut in two by the end of the head
+        let cut = &"Zürich".as_bytes()[..2];
+        assert_eq!(sniff_media_type(cut), "text/plain; charset=utf-8");
+    }
+
+    #[test]
+    fn control_characters_and_invalid_utf8_are_binary() {
+        assert_eq!(sniff_media_type(b"text\0with a nul"), "application/octet-stream");
+        assert_eq!(sniff_media_type(b"\xff\xfe\xfd"), "application/octet-stream");
+        assert_eq!(sniff_media_type(b""), "application/octet-stream");
+    }
+}
@@ -1,73 +1,139 @@
-use std::cmp::Ordering;
-
 use async_graphql::Guard;
 use async_graphql::*;
 
-#[derive(Eq, PartialEq, Display, EnumString, Copy, Clone, PartialOrd, Ord)]
+#[derive(Debug, Eq, PartialEq, Display, EnumString, EnumIter, Copy, Clone, PartialOrd, Ord)]
 #[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
 pub enum UserRole {
     User,
+    /// Reads, searches and exports the audit log, and nothing else a USER
+    /// can't. Ranked below ANALYST so OIDC group mapping prefers the roles
+    /// that can see data.
+    Auditor,
     Analyst,
     Operator,
     Admin,
 }
 
-pub struct RoleGuard {
-    pub user_role: UserRole,
+/// What a role is allowed to do. Guards and visibility functions check
+/// permissions rather than roles, so every check follows the same grants.
+#[derive(Debug, Eq, PartialEq, Display, EnumIter, Copy, Clone)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum Permission {
+    /// Read nations, authorities and classification schemas
+    ReadReferenceData,
+    /// Read data objects and conversion requests, and follow conversions
+    ReadConversions,
+    /// Submit conversion requests
+    SubmitConversions,
+    /// Read user accounts, create and update them and sign them out
+    ManageUsers,
+    /// Issue, rotate and revoke API keys
+    ManageApiKeys,
+    /// See and submit conversion requests for every authority rather than
+    /// only the caller's own
+    AccessAllAuthorities,
+    /// Verify, search and export the audit log
+    ReadAuditLog,
+}
+
+impl UserRole {
+    /// The role whose permissions this role inherits
+    pub fn inherits(self) -> Option<UserRole> {
+        match self {
+            UserRole::User => None,
+            UserRole::Auditor => Some(UserRole::User),
+            UserRole::Analyst => Some(UserRole::User),
+            UserRole::Operator => Some(UserRole::Analyst),
+            UserRole::Admin => Some(UserRole::Operator),
+        }
+    }
+
+    /// Permissions granted to this role itself, on top of inherited ones
+    fn grants(self) -> &'static [Permission] {
+        match self {
+            UserRole::User => &[Permission::ReadReferenceData],
+            UserRole::Auditor => &[Permission::ReadAuditLog],
+            UserRole::Analyst => &[Permission::ReadConversions],
+            UserRole::Operator => &[Permission::SubmitConversions],
+            UserRole::Admin => &[
+                Permission::ManageUsers,
+                Permission::ManageApiKeys,
+                Permission::AccessAllAuthorities,
+                Permission::ReadAuditLog,
+            ],
+        }
+    }
+
+    pub fn has_permission(self, permission: Permission) -> bool {
+        self.grants().contains(&permission)
+            || self.inherits().is_some_and(|role| role.has_permission(permission))
+    }
+}
+
+/// Whether the caller's role grants `permission`. False for anonymous callers.
+pub fn has_permission(ctx: &Context<'_>, permission: Permission) -> bool {
+    ctx.data_opt::<UserRole>()
+        .is_some_and(|role| role.has_permission(permission))
+}
+
+pub struct PermissionGuard {
+    pub permission: Permission,
 }
 
-impl RoleGuard {
-    pub fn new(user_role: UserRole) -> Self {
-        Self { user_role }
+impl PermissionGuard {
+    pub fn new(permission: Permission) -> Self {
+        Self { permission }
     }
 }
 
-impl Guard for RoleGuard {
+impl Guard for PermissionGuard {
     async fn check(&self, context: &Context<'_>) -> Result<(), async_graphql::Error> {
-        
-        if context.data_opt::<UserRole>() == Some(&UserRole::Admin) || context.data_opt::<UserRole>() == Some(&self.user_role) {
-            Ok(())
-        } else {
-            let guard_error = context.data_opt::<jsonwebtoken::errors::Error>().clone();
-            match guard_error {
-                Some(e) => return Err(format!("{:?}", e.kind()).into()),
-                None => return Err(format!("Access denied: {} UserRole required", &self.user_role).into())
-            }
+        match context.data_opt::<UserRole>() {
+            None => Err(unauthenticated("Sign in required")),
+            Some(role) if role.has_permission(self.permission) => Ok(()),
+            Some(_) => Err(forbidden(format!("Access denied: {} permission required", &self.permission))),
         }
     }
 }
 
-/// Field will be visible to users with UserRole::Admin and
-/// UserRole::Analyst
-pub fn is_analyst(ctx: &Context<'_>) -> bool {
-    if let Some(role) = ctx.data_opt::<UserRole>() {
-        let result = match role.cmp(&UserRole::Analyst) {
-            Ordering::Less => false,
-            Ordering::Equal => true,
-            Ordering::Greater => true,
-        };
-        return result
-    } else {
-        return false
-    };
+/// Error for callers who are not signed in or whose credentials were refused,
+/// with `extensions.code` set to UNAUTHENTICATED
+pub fn unauthenticated(message: impl Into<String>) -> async_graphql::Error {
+    async_graphql::Error::new(message).extend_with(|_, e| e.set("code", "UNAUTHENTICATED"))
+}
+
+/// Error for signed-in callers who lack a permission, with `extensions.code`
+/// set to FORBIDDEN
+pub fn forbidden(message: impl Into<String>) -> async_graphql::Error {
+    async_graphql::Error::new(message).extend_with(|_, e| e.set("code", "FORBIDDEN"))
+}
+
+/// Error for callers who have to wait before trying again, with
+/// `extensions.code` set to TOO_MANY_REQUESTS and `extensions.retryAfter` to
+/// the wait in seconds
+pub fn too_many_requests(message: impl Into<String>, retry_after: i64) -> async_graphql::Error {
+    async_graphql::Error::new(message).extend_with(|_, e| {
+        e.set("code", "TOO_MANY_REQUESTS");
+        e.set("retryAfter", retry_after);
+    })
+}
+
+/// Field will be visible to roles with Permission::SubmitConversions
+pub fn can_submit_conversions(ctx: &Context<'_>) -> bool {
+    has_permission(ctx, Permission::SubmitConversions)
 }
 
-/// Field will be visible to users with UserRole::Admin and
-/// UserRole::Analyst
-pub fn is_operator(ctx: &Context<'_>) -> bool {
-    if let Some(role) = ctx.data_opt::<UserRole>() {
-        let result = match role.cmp(&UserRole::Operator) {
-            Ordering::Less => false,
-            Ordering::Equal => true,
-            Ordering::Greater => true,
-        };
-        return result
-    } else {
-        return false
-    };
+/// Field will be visible to roles with Permission::ManageUsers
+pub fn can_manage_users(ctx: &Context<'_>) -> bool {
+    has_permission(ctx, Permission::ManageUsers)
 }
 
-/// Field will only be visible to users with UserRole::Admin
-pub fn is_admin(ctx: &Context<'_>) -> bool {
-    ctx.data_opt::<UserRole>() == Some(&UserRole::Admin)
-}
\ No newline at end of file
+/// Field will be visible to roles with Permission::ManageApiKeys
+pub fn can_manage_api_keys(ctx: &Context<'_>) -> bool {
+    has_permission(ctx, Permission::ManageApiKeys)
+}
+
+/// Field will be visible to roles with Permission::ReadAuditLog
+pub fn can_read_audit_log(ctx: &Context<'_>) -> bool {
+    has_permission(ctx, Permission::ReadAuditLog)
+}
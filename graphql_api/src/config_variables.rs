@@ -1,4 +1,31 @@
 // Constants
 pub const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
-pub const TOKEN_DURATION: i64 = 7200; // Duration for JWT sign-in in seconds
-pub const MANDATORY_TESTING_RATE: f64 = 0.01; // fraction of referrals to mandatory testing
\ No newline at end of file
+pub const TOKEN_DURATION: i64 = 900; // Duration of JWT access tokens in seconds
+pub const REFRESH_TOKEN_DURATION: i64 = 60 * 60 * 24 * 30; // Idle lifetime of a sign-in session in seconds
+pub const PASSWORD_RESET_DURATION: i64 = 60 * 30; // Lifetime of a password reset token in seconds
+pub const EMAIL_VERIFICATION_DURATION: i64 = 60 * 60 * 24; // Lifetime of an email verification token in seconds
+pub const MFA_CHALLENGE_DURATION: i64 = 60 * 5; // Time to enter a second factor after the password in seconds
+pub const MFA_MAX_ATTEMPTS: i32 = 5; // Wrong codes before a sign-in challenge is discarded
+pub const RECOVERY_CODE_COUNT: usize = 10; // Recovery codes issued with a TOTP enrolment
+pub const API_KEY_DEFAULT_DAYS: i64 = 90; // Lifetime of API keys issued without an explicit expiry
+pub const API_KEY_MAX_DAYS: i64 = 3650; // Longest lifetime an API key can be issued with
+pub const MANDATORY_TESTING_RATE: f64 = 0.01; // fraction of referrals to mandatory testing
+pub const SEARCH_RESULT_LIMIT: i64 = 50; // Maximum results returned by full-text search
+pub const SIGN_IN_FREE_ATTEMPTS: i32 = 3; // Failed sign-ins to an account before backoff starts
+pub const SIGN_IN_LOCKOUT_ATTEMPTS: i32 = 10; // Failed sign-ins to an account before it is locked
+pub const IP_FREE_ATTEMPTS: i32 = 10; // Failed sign-ins from one IP before backoff starts
+pub const IP_LOCKOUT_ATTEMPTS: i32 = 50; // Failed sign-ins from one IP before it is locked out
+pub const SIGN_IN_MAX_BACKOFF: i64 = 60 * 5; // Longest backoff between failed sign-ins in seconds
+pub const SIGN_IN_LOCKOUT_DURATION: i64 = 60 * 30; // Lockout after too many failed sign-ins in seconds
+pub const SIGN_IN_FAILURE_WINDOW: i64 = 60 * 60 * 24; // Quiet time after which failed sign-ins are forgotten in seconds
+pub const SECURITY_EVENT_LIMIT: i64 = 500; // Maximum security events returned at once
+pub const AUDIT_VERIFY_BATCH: i64 = 1000; // Audit entries loaded at a time while verifying the chain
+pub const AUDIT_EVENTS_LIMIT: usize = 500; // Maximum audit events in one page of auditEvents
+pub const AUDIT_EXPORT_BATCH: i64 = 1000; // Audit entries loaded at a time while streaming an export
+pub const ANCHOR_INTERVAL: u64 = 60 * 60; // Seconds between Merkle anchoring runs unless ANCHOR_INTERVAL is set
+pub const MERKLE_AUDIT_BATCH_LIMIT: i64 = 5_000; // Audit entries anchored under one root
+pub const MERKLE_RECEIPT_BATCH_LIMIT: i64 = 5_000; // Receipts anchored under one root, next to its audit entries
+pub const UPLOAD_MAX_BYTES: u64 = 100 * 1024 * 1024; // Largest document accepted by an upload unless UPLOAD_MAX_BYTES is set
+pub const UPLOAD_FIELD_LIMIT: usize = 64 * 1024; // Largest text field of an upload form
+pub const UPLOAD_MAX_FIELDS: usize = 64; // Most text fields, tags included, in one upload form
+pub const UPLOAD_PART_SIZE: usize = 8 * 1024 * 1024; // Size of the parts of S3 multipart uploads, at least 5 MiB
@@ -1,67 +1,110 @@
+use actix_web::web;
 use diesel::pg::PgConnection;
 use diesel::r2d2::ConnectionManager;
-use lazy_static::lazy_static;
 use r2d2::{self};
 use std::env;
+use std::str::FromStr;
 
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 
 use crate::database_utils::pre_populate_db_schema;
-use errors::CustomError;
+use crate::repositories::Repositories;
 
 pub type PostgresPool = r2d2::Pool<ConnectionManager<PgConnection>>;
 pub type DbConnection = r2d2::PooledConnection<ConnectionManager<PgConnection>>;
 
-use crate::models::{User, UserData, InsertableUser};
+use crate::models::{AccountStatus, UserData, InsertableUser, NatoClassification};
 
 
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
-lazy_static! {
-    pub static ref POOL: PostgresPool = {
-        let db_url = env::var("DATABASE_URL").expect("Database url not set");
-        let manager = ConnectionManager::<PgConnection>::new(db_url);
-        PostgresPool::new(manager).expect("Failed to create DB Pool")
-    };
+/// Default number of pooled connections. This also bounds how many resolvers
+/// can be talking to the database at once.
+const DEFAULT_POOL_SIZE: u32 = 16;
+
+/// Builds a connection pool for `db_url`, sized by DATABASE_POOL_SIZE
+pub fn create_pool(db_url: &str) -> PostgresPool {
+    let pool_size: u32 = env::var("DATABASE_POOL_SIZE")
+        .map(|s| s.parse().expect("Unable to parse DATABASE_POOL_SIZE"))
+        .unwrap_or(DEFAULT_POOL_SIZE);
+    let manager = ConnectionManager::<PgConnection>::new(db_url);
+    PostgresPool::builder()
+        .max_size(pool_size)
+        .build(manager)
+        .expect("Failed to create DB Pool")
 }
 
-fn run_migration(conn: &mut PgConnection) {
+pub fn run_migrations(pool: &PostgresPool) {
+    let mut conn = pool.get().expect("Failed to get DB connection");
     conn.run_pending_migrations(MIGRATIONS).unwrap();
 }
 
-pub fn init() {
-
-    lazy_static::initialize(&POOL);
-    let mut conn = connection().expect("Failed to get DB connection");
-    run_migration(&mut conn);
+/// Seeds the admin user from ADMIN_* and, on first run, the demo data set.
+/// ADMIN_CLEARANCE and ADMIN_NATIONALITY are optional; without them the admin
+/// is UNCLASSIFIED with no nationality and sees data objects redacted.
+pub async fn init(repos: &Repositories) {
 
     // Auto-add admin if does not exist
     let admin_name = env::var("ADMIN_NAME").expect("Unable to load admin name");
     let admin_email = env::var("ADMIN_EMAIL").expect("Unable to load admin email");
     let admin_pwd = env::var("ADMIN_PASSWORD").expect("Unable to load admin password");
+
+    seed(repos, admin_name, admin_email, admin_pwd).await;
+}
+
+/// Admin used by the in-memory backend when ADMIN_* are not set
+#[cfg(feature = "in-memory")]
+const DEMO_ADMIN: (&str, &str, &str) = ("Demo Admin", "admin@demo.local", "demo-admin");
+
+/// Seeds an in-memory store. ADMIN_* are optional here and fall back to
+/// DEMO_ADMIN so offline demos and CI need no configuration.
+#[cfg(feature = "in-memory")]
+pub async fn init_in_memory(repos: &Repositories) {
+    let (name, email, pwd) = DEMO_ADMIN;
+
+    let admin_name = env::var("ADMIN_NAME").unwrap_or_else(|_| name.to_owned());
+    let admin_email = env::var("ADMIN_EMAIL").unwrap_or_else(|_| email.to_owned());
+    let admin_pwd = env::var("ADMIN_PASSWORD").unwrap_or_else(|_| pwd.to_owned());
+
+    seed(repos, admin_name, admin_email, admin_pwd).await;
+}
+
+async fn seed(repos: &Repositories, admin_name: String, admin_email: String, admin_pwd: String) {
     
-    let admin = User::get_by_email(&admin_email);
+    let admin = repos.users.get_by_email(admin_email.trim().to_owned()).await;
 
     match admin {
         // Checking admin and if not, add default data structures
         Ok(u) => println!("Admin exists {:?} - bypass setup", &u),
         Err(_e) => {
 
+            let clearance = env::var("ADMIN_CLEARANCE").ok().map(|c| {
+                NatoClassification::from_str(c.trim()).expect("Unable to parse ADMIN_CLEARANCE")
+            });
+
             let admin_data = UserData {
                 name: admin_name.trim().to_owned(),
                 email: admin_email.trim().to_owned(),
                 password: admin_pwd.trim().to_owned(),
                 role: "ADMIN".to_owned(),
+                clearance,
+                nationality: env::var("ADMIN_NATIONALITY").ok().map(|n| n.trim().to_uppercase()),
             };
         
-            let test_admin = InsertableUser::from(admin_data);
+            let mut test_admin = InsertableUser::from(admin_data);
+
+            // The first admin has no one to approve them
+            test_admin.account_status = AccountStatus::Approved.to_string();
         
-            let admin = User::create(test_admin)
+            let admin = repos.users.create(test_admin)
+                .await
                 .expect("Unable to create admin");
         
             println!("Admin created: {:?}", &admin);
 
-            let _res = pre_populate_db_schema()
+            // Seeded reference data is on the record as the system's
+            pre_populate_db_schema(&repos.audited(None))
+                .await
                 .expect("Unable to pre-populate database");
             
             /*
@@ -76,7 +119,13 @@ pub fn init() {
     }
 }
 
-pub fn connection() -> Result<DbConnection, CustomError> {
-    POOL.get()
-        .map_err(|e| CustomError::new(500, format!("Failed getting db connection: {}", e)))
-}
\ No newline at end of file
+/// Runs synchronous Diesel (or otherwise blocking) work on the blocking thread
+/// pool so async resolvers don't stall the actix worker threads while they wait
+/// on Postgres. Connections should be checked out inside `f`.
+pub async fn run_blocking<F, T>(f: F) -> async_graphql::Result<T>
+where
+    F: FnOnce() -> async_graphql::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    web::block(f).await?
+}
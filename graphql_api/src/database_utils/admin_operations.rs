@@ -1,10 +1,11 @@
 use std::{io::stdin};
 
-use crate::models::{User, UserData, InsertableUser};
+use crate::models::{AccountStatus, UserData, InsertableUser};
+use crate::repositories::Repositories;
 
 /// Create an administrative user. An admin account is needed to create additional users and access
 /// some guarded mutations.
-pub fn create_admin_user() {
+pub async fn create_admin_user(repos: &Repositories) {
 
         println!("What is the administrator's name?");
 
@@ -26,13 +27,19 @@ pub fn create_admin_user() {
             email: email_input.trim().to_owned(),
             password: password_input.trim().to_owned(),
             role: "ADMIN".to_owned(),
+            clearance: None,
+            nationality: None,
         };
     
         let mut test_admin = InsertableUser::from(admin_data);
     
         test_admin.role = "ADMIN".to_owned();
+
+        // Created at the console by someone with access to the server
+        test_admin.account_status = AccountStatus::Approved.to_string();
     
-        let admin = User::create(test_admin)
+        let admin = repos.users.create(test_admin)
+            .await
             .expect("Unable to create admin");
     
         println!("Admin created: {:?}", &admin);
@@ -1,28 +1,27 @@
-use async_graphql::{Data, Error};
-use diesel::{self, RunQueryDsl};
-use rand::Rng;
+use async_graphql::Error;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use uuid::Uuid;
+use rand::{Rng, SeedableRng};
+use sha2::{Digest, Sha256};
 
 use crate::models::{
-    Authority, ConversionRequest, DataObject, InsertableConversionRequest, InsertableDataObject,
-    InsertableMetadata, Nation, NewAuthority, NewClassificationSchema, NewDataObject, NewMetadata,
-    NewNation, User,
+    Authority, InsertableConversionRequest, InsertableDataObject, InsertableMetadata,
+    NewAuthority, NewClassificationSchema, NewDataObject, NewMetadata, NewNation,
 };
 use crate::progress::progress::ProgressLogger;
-use crate::{database, schema::*};
+use crate::repositories::Repositories;
 
-/// Creates basic test objects in the database for testing
-pub fn pre_populate_db_schema() -> Result<(), Error> {
+/// Creates basic test objects through the given repositories for testing
+pub async fn pre_populate_db_schema(repos: &Repositories) -> Result<(), Error> {
     // Get all users to assign as creators
-    let users = User::get_all()?;
+    let users = repos.users.get_all().await?;
     if users.is_empty() {
         return Err(Error::new(
             "No users found in database. Please create users first.",
         ));
     }
 
-    let mut rng = rand::thread_rng();
+    let mut rng = StdRng::from_entropy();
 
     // ========== Create NATO Nations ==========
     println!("\n========== Creating NATO Nations ==========");
@@ -62,21 +61,18 @@ pub fn pre_populate_db_schema() -> Result<(), Error> {
     let mut progress_nations =
         ProgressLogger::new("Inserting Nations".to_owned(), new_nations.len());
 
-    // Batch insert nations
-    let mut conn = database::connection()?;
-    let inserted_count = diesel::insert_into(nations::table)
-        .values(&new_nations)
-        .execute(&mut conn)?;
+    let mut created_nations = Vec::new();
+    for nation in new_nations {
+        created_nations.push(repos.nations.create(nation).await?);
+    }
+    let inserted_count = created_nations.len();
 
     progress_nations.done();
     println!("✓ Inserted {} nations", inserted_count);
 
-    // Retrieve created nations for foreign key references
-    let created_nations = Nation::get_all()?;
-
     // ========== Create Authorities ==========
     println!("\n========== Creating Authorities ==========");
-    let authority_titles = vec![
+    let authority_titles = [
         "National Security Authority",
         "Defense Intelligence Agency",
         "Ministry of Defence Security",
@@ -132,17 +128,15 @@ pub fn pre_populate_db_schema() -> Result<(), Error> {
     let mut progress_authorities =
         ProgressLogger::new("Inserting Authorities".to_owned(), new_authorities.len());
 
-    // Batch insert authorities
-    let inserted_authorities = diesel::insert_into(authorities::table)
-        .values(&new_authorities)
-        .execute(&mut conn)?;
+    let mut created_authorities = Vec::new();
+    for authority in new_authorities {
+        created_authorities.push(repos.authorities.create(authority).await?);
+    }
+    let inserted_authorities = created_authorities.len();
 
     progress_authorities.done();
     println!("✓ Inserted {} authorities", inserted_authorities);
 
-    // Retrieve created authorities for foreign key references
-    let created_authorities = Authority::get_all()?;
-
     // ========== Create Classification Schemas ==========
     println!("\n========== Creating Classification Schemas ==========");
 
@@ -316,7 +310,7 @@ pub fn pre_populate_db_schema() -> Result<(), Error> {
         ),
     ];
 
-    let caveats_options = vec![
+    let caveats_options = [
         "NOFORN",
         "REL TO NATO",
         "EYES ONLY",
@@ -433,10 +427,10 @@ pub fn pre_populate_db_schema() -> Result<(), Error> {
         new_classification_schemas.len(),
     );
 
-    // Batch insert classification schemas
-    let inserted_schemas = diesel::insert_into(classification_schemas::table)
-        .values(&new_classification_schemas)
-        .execute(&mut conn)?;
+    let inserted_schemas = new_classification_schemas.len();
+    for schema in new_classification_schemas {
+        repos.schemas.create(schema).await?;
+    }
 
     progress_schemas.done();
     println!("✓ Inserted {} classification schemas", inserted_schemas);
@@ -628,14 +622,16 @@ pub fn pre_populate_db_schema() -> Result<(), Error> {
         new_data_objects.push(NewDataObject::new(creator.id, title, description));
     }
 
-    // Batch insert data objects
-    let inserted_data_objects = diesel::insert_into(data_objects::table)
-        .values(&new_data_objects)
-        .execute(&mut conn)?;
+    let mut progress_data_objects =
+        ProgressLogger::new("Inserting DataObjects".to_owned(), new_data_objects.len());
 
-    let data_object_ids = DataObject::get_all()?;
+    let mut created_data_objects = Vec::new();
+    for data_object in new_data_objects {
+        created_data_objects.push(repos.data_objects.create(data_object).await?);
+    }
+    let inserted_data_objects = created_data_objects.len();
 
-    for elem in data_object_ids.iter() {
+    for elem in created_data_objects.iter() {
         let domain = metadata_domains.choose(&mut rng).unwrap();
         let tags = &domain.1;
         new_metadata.push(NewMetadata::new(
@@ -647,13 +643,10 @@ pub fn pre_populate_db_schema() -> Result<(), Error> {
         ));
     }
 
-    // Batch insert metadata
-    let inserted_metadata = diesel::insert_into(metadata::table)
-        .values(&new_metadata)
-        .execute(&mut conn)?;
-
-    let mut progress_data_objects =
-        ProgressLogger::new("Inserting DataObjects".to_owned(), new_data_objects.len());
+    let inserted_metadata = new_metadata.len();
+    for metadata in new_metadata {
+        repos.metadata.create(metadata).await?;
+    }
 
     progress_data_objects.done();
     println!("✓ Inserted {} data objects", inserted_data_objects);
@@ -661,7 +654,6 @@ pub fn pre_populate_db_schema() -> Result<(), Error> {
     // ========== Create Conversion Requests ==========
     println!("\n========== Creating Conversion Requests ==========");
 
-    let classification_levels = vec!["UNCLASSIFIED", "RESTRICTED", "CONFIDENTIAL", "SECRET", "TOP SECRET"];
     let num_conversion_requests = 30;
     let mut created_conversion_requests = 0;
 
@@ -704,7 +696,7 @@ pub fn pre_populate_db_schema() -> Result<(), Error> {
                 created_nations
                     .iter()
                     .find(|n| n.nation_code != source_nation.nation_code)
-                    .unwrap_or(&source_nation)
+                    .unwrap_or(source_nation)
                     .nation_code
                     .clone()
             );
@@ -720,9 +712,13 @@ pub fn pre_populate_db_schema() -> Result<(), Error> {
             target_nations
         );
 
+        // The description stands in for the document's bytes
         let insertable_data_object = InsertableDataObject {
             title: title.clone(),
             description: description.clone(),
+            content_digest: Some(format!("{:x}", Sha256::digest(description.as_bytes()))),
+            digest_algorithm: None,
+            content_size: Some(description.len() as i64),
         };
 
         // Create insertable metadata
@@ -752,14 +748,15 @@ pub fn pre_populate_db_schema() -> Result<(), Error> {
         let conversion_payload = InsertableConversionRequest {
             user_id: creator.id,
             authority_id: authority.id,
-            data_object: insertable_data_object,
-            metadata: insertable_metadata,
+            data_object_id: None,
+            data_object: Some(insertable_data_object),
+            metadata: Some(insertable_metadata),
             source_nation_code: source_nation.nation_code.clone(),
             target_nation_codes: target_nations,
         };
 
         // Process the payload to create the conversion request
-        match ConversionRequest::process_payload(&conversion_payload) {
+        match repos.conversion_requests.process_payload(conversion_payload).await {
             Ok(_) => {
                 created_conversion_requests += 1;
             }
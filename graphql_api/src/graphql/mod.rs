@@ -1,9 +1,9 @@
 mod query;
 mod mutation;
 mod utilities;
-// mod subscription;
+mod subscription;
 
 pub use self::query::*;
 pub use self::mutation::*;
 pub use self::utilities::*;
-// pub use self::subscription::*;
+pub use self::subscription::*;
@@ -0,0 +1,98 @@
+use async_graphql::*;
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::common_utils::{can_manage_api_keys, unauthenticated, Permission, PermissionGuard};
+use crate::graphql::get_repositories_from_context;
+use crate::models::{ApiKey, ApiKeyCreated, ApiKeyInput, NewApiKey};
+
+#[derive(Default)]
+pub struct ApiKeyMutation;
+
+#[Object]
+impl ApiKeyMutation {
+    #[graphql(
+        name = "createApiKey",
+        guard = "PermissionGuard::new(Permission::ManageApiKeys)",
+        visible = "can_manage_api_keys",
+    )]
+    /// Issues an API key acting as a service user. The key is only returned
+    /// here; send it in the X-Api-Key header.
+    pub async fn create_api_key(
+        &self,
+        context: &Context<'_>,
+        input: ApiKeyInput,
+    ) -> Result<ApiKeyCreated> {
+        let admin_id = *context
+            .data_opt::<Uuid>()
+            .ok_or_else(|| unauthenticated("Sign in required"))?;
+
+        let (new_key, key) = NewApiKey::generate(input, admin_id)?;
+
+        let api_key = get_repositories_from_context(context).api_keys.create(new_key).await?;
+
+        Ok(ApiKeyCreated { api_key, key })
+    }
+
+    #[graphql(
+        name = "rotateApiKey",
+        guard = "PermissionGuard::new(Permission::ManageApiKeys)",
+        visible = "can_manage_api_keys",
+    )]
+    /// Issues a replacement with the same user, authority, role and scopes.
+    /// The old key keeps working for `graceHours` (default 0) so clients can
+    /// switch over.
+    pub async fn rotate_api_key(
+        &self,
+        context: &Context<'_>,
+        id: Uuid,
+        expires_in_days: Option<i64>,
+        grace_hours: Option<i64>,
+    ) -> Result<ApiKeyCreated> {
+        let admin_id = *context
+            .data_opt::<Uuid>()
+            .ok_or_else(|| unauthenticated("Sign in required"))?;
+
+        let repos = get_repositories_from_context(context);
+
+        let old = repos.api_keys.get_by_id(id).await?;
+
+        if old.revoked_at.is_some() {
+            return Err(Error::new("Can't rotate a revoked API key"));
+        }
+
+        let input = ApiKeyInput {
+            user_id: old.user_id,
+            authority_id: old.authority_id,
+            name: old.name.clone(),
+            role: old.role.clone(),
+            scopes: old.api_scopes(),
+            expires_in_days,
+        };
+
+        let (new_key, key) = NewApiKey::generate(input, admin_id)?;
+        let api_key = repos.api_keys.create(new_key).await?;
+
+        match grace_hours.unwrap_or(0) {
+            hours if hours > 0 => {
+                let cutoff = Utc::now().naive_utc() + Duration::hours(hours);
+                repos.api_keys.expire_by(old.id, cutoff).await?;
+            }
+            _ => {
+                repos.api_keys.revoke(old.id).await?;
+            }
+        };
+
+        Ok(ApiKeyCreated { api_key, key })
+    }
+
+    #[graphql(
+        name = "revokeApiKey",
+        guard = "PermissionGuard::new(Permission::ManageApiKeys)",
+        visible = "can_manage_api_keys",
+    )]
+    /// Revokes an API key immediately
+    pub async fn revoke_api_key(&self, context: &Context<'_>, id: Uuid) -> Result<ApiKey> {
+        get_repositories_from_context(context).api_keys.revoke(id).await
+    }
+}
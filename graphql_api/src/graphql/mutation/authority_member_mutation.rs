@@ -0,0 +1,48 @@
+use async_graphql::*;
+use uuid::Uuid;
+
+use crate::common_utils::{can_manage_users, Permission, PermissionGuard};
+use crate::graphql::get_repositories_from_context;
+use crate::models::{AuthorityMember, NewAuthorityMember};
+
+#[derive(Default)]
+pub struct AuthorityMemberMutation;
+
+#[Object]
+impl AuthorityMemberMutation {
+    #[graphql(
+        name = "addAuthorityMember",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
+    )]
+    /// Lets a user act for an authority. Takes effect on the user's next request.
+    pub async fn add_authority_member(
+        &self,
+        context: &Context<'_>,
+        user_id: Uuid,
+        authority_id: Uuid,
+    ) -> Result<AuthorityMember> {
+        get_repositories_from_context(context)
+            .authority_members
+            .add(NewAuthorityMember { user_id, authority_id })
+            .await
+    }
+
+    #[graphql(
+        name = "removeAuthorityMember",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
+    )]
+    /// Removes a user from an authority, returning whether they were a member
+    pub async fn remove_authority_member(
+        &self,
+        context: &Context<'_>,
+        user_id: Uuid,
+        authority_id: Uuid,
+    ) -> Result<bool> {
+        get_repositories_from_context(context)
+            .authority_members
+            .remove(user_id, authority_id)
+            .await
+    }
+}
@@ -0,0 +1,109 @@
+use async_graphql::*;
+use uuid::Uuid;
+
+use crate::common_utils::{can_submit_conversions, unauthenticated, Permission, PermissionGuard};
+use crate::graphql::{get_repositories_from_context, ConversionBroker};
+use crate::mail::Mailer;
+use crate::models::{
+    ConversionCompleted, ConversionRequestInput, InsertableConversionRequest, NewConversionResult,
+};
+use crate::repositories::ConversionResultsFor;
+
+#[derive(Default)]
+pub struct ConversionRequestMutation;
+
+#[Object]
+impl ConversionRequestMutation {
+    #[graphql(
+        name = "submitConversionRequest",
+        guard = "PermissionGuard::new(Permission::SubmitConversions)",
+        visible = "can_submit_conversions",
+    )]
+    /// Converts the source marking to each target nation's equivalent using the
+    /// latest classification schemas, via the NATO scale. Stores the request and
+    /// its results, then notifies `conversionCompleted` subscribers and emails
+    /// the authority's contact address.
+    pub async fn submit_conversion_request(
+        &self,
+        context: &Context<'_>,
+        input: ConversionRequestInput,
+    ) -> Result<ConversionCompleted> {
+        let user_id = *context
+            .data_opt::<Uuid>()
+            .ok_or_else(|| unauthenticated("Sign in required"))?;
+
+        if input.target_nation_codes.is_empty() {
+            return Err(Error::new("At least one target nation code is required"));
+        }
+
+        let repos = get_repositories_from_context(context);
+
+        let latest_schema = |nation_code: String| async move {
+            repos
+                .schemas
+                .get_latest_by_nation_code(nation_code.clone())
+                .await
+                .map_err(|_| Error::new(format!("No classification schema for {}", nation_code)))
+        };
+
+        // Validate everything before anything is written
+        let source_schema = latest_schema(input.source_nation_code.clone()).await?;
+        let level = source_schema.to_nato(&input.source_marking)?;
+
+        let mut target_schemas = Vec::new();
+        for nation_code in input.target_nation_codes.iter() {
+            target_schemas.push(latest_schema(nation_code.clone()).await?);
+        }
+
+        let source_marking = input.source_marking.clone();
+        let convert: ConversionResultsFor = Box::new(move |request| {
+            NewConversionResult::convert(request.id, &source_marking, level, &source_schema, &target_schemas)
+        });
+
+        // The request is stored with its results or not at all
+        let (conversion_request, results) = repos
+            .conversion_requests
+            .process_and_complete(
+                InsertableConversionRequest {
+                    user_id,
+                    authority_id: input.authority_id,
+                    data_object_id: input.data_object_id,
+                    data_object: input.data_object,
+                    metadata: input.metadata,
+                    source_nation_code: input.source_nation_code,
+                    target_nation_codes: input.target_nation_codes,
+                },
+                convert,
+                Box::new(|_| Vec::new()),
+            )
+            .await?;
+
+        let completed = ConversionCompleted {
+            conversion_request,
+            results,
+        };
+
+        context
+            .data::<ConversionBroker>()?
+            .publish(completed.clone());
+
+        // The conversion is already stored, so a missing contact only skips the notice
+        if let Ok(authority) = repos.authorities.get_by_id(completed.conversion_request.authority_id).await {
+            context.data::<Mailer>()?.dispatch(
+                authority.email,
+                format!("Conversion request {} completed", completed.conversion_request.id),
+                "conversion_completed",
+                serde_json::json!({
+                    "request_id": completed.conversion_request.id,
+                    "authority": authority.name,
+                    "source_nation_code": completed.conversion_request.source_nation_code,
+                    "source_marking": input.source_marking,
+                    "nato_classification": level.to_string(),
+                    "results": completed.results,
+                }),
+            );
+        }
+
+        Ok(completed)
+    }
+}
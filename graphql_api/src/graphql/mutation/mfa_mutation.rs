@@ -0,0 +1,289 @@
+use async_graphql::*;
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::common_utils::{can_manage_users, forbidden, unauthenticated, Permission, PermissionGuard};
+use crate::config_variables::MFA_MAX_ATTEMPTS;
+use crate::graphql::{get_repositories_from_context, start_session, UserResponse};
+use crate::models::{
+    generate_mfa_challenge, generate_recovery_codes, generate_totp_secret, hash_mfa_challenge,
+    hash_recovery_code, mfa_required, qr_code_svg, totp_provisioning_uri, verify_totp,
+    ClientIp, NewSignInChallenge, SessionId, SignInAttempt, TotpEnrolment, User,
+};
+use crate::repositories::Repositories;
+
+#[derive(Default)]
+pub struct MfaMutation;
+
+#[derive(Debug, Serialize, SimpleObject)]
+/// A password or single sign-on waiting for its second factor
+pub struct MfaChallenge {
+    /// Pass to `verifyMfa` with a code from the authenticator app
+    challenge: String,
+    expires_at: NaiveDateTime,
+    /// Set when the user's role requires TOTP and they haven't enrolled. The
+    /// first code from the newly enrolled app completes both.
+    enrolment: Option<TotpEnrolment>,
+}
+
+#[derive(Debug, Union)]
+/// Tokens, or a challenge when the user has to pass a second factor first
+pub enum SignInResult {
+    Authenticated(UserResponse),
+    MfaChallenge(MfaChallenge),
+}
+
+/// Issues tokens for a user whose password or IdP sign-in checked out, or a
+/// challenge if they have TOTP enabled or their role requires it
+pub async fn begin_sign_in(repos: &Repositories, user: User) -> Result<SignInResult> {
+    user.ensure_approved()?;
+
+    if !user.totp_enabled() && !mfa_required(&user.role) {
+        return Ok(SignInResult::Authenticated(start_session(repos, user).await?));
+    }
+
+    let user_id = user.id;
+
+    let enrolment = match user.totp_enabled() {
+        true => None,
+        false => Some(begin_enrolment(repos, user).await?),
+    };
+
+    let challenge = generate_mfa_challenge();
+    let stored = repos
+        .mfa_challenges
+        .create(NewSignInChallenge::new(user_id, &challenge))
+        .await?;
+
+    Ok(SignInResult::MfaChallenge(MfaChallenge {
+        challenge,
+        expires_at: stored.expires_at,
+        enrolment,
+    }))
+}
+
+/// Gives the user a pending TOTP secret and recovery codes. A pending secret
+/// is kept until the first code confirms it, so signing in again doesn't
+/// replace what the user already scanned; only `resetTotp` discards it.
+async fn begin_enrolment(repos: &Repositories, mut user: User) -> Result<TotpEnrolment> {
+    if let Some(secret) = user.totp_secret.clone() {
+        let provisioning_uri = totp_provisioning_uri(&secret, &user.email)?;
+
+        return Ok(TotpEnrolment {
+            qr_code_svg: qr_code_svg(&provisioning_uri)?,
+            secret,
+            provisioning_uri,
+            recovery_codes: Vec::new(),
+        });
+    }
+
+    let secret = generate_totp_secret();
+    let provisioning_uri = totp_provisioning_uri(&secret, &user.email)?;
+
+    user.totp_secret = Some(secret.clone());
+    user.totp_enabled_at = None;
+    user.totp_last_step = None;
+    let user = repos.users.update(user).await?;
+
+    let recovery_codes = generate_recovery_codes();
+    repos
+        .recovery_codes
+        .replace_for_user(user.id, recovery_codes.iter().map(|c| hash_recovery_code(c)).collect())
+        .await?;
+
+    Ok(TotpEnrolment {
+        qr_code_svg: qr_code_svg(&provisioning_uri)?,
+        secret,
+        provisioning_uri,
+        recovery_codes,
+    })
+}
+
+/// Checks a TOTP code, or a recovery code once TOTP is enabled. A TOTP code
+/// for a pending secret confirms the enrolment. Returns the updated user if
+/// the code was accepted.
+async fn check_second_factor(repos: &Repositories, mut user: User, code: &str) -> Result<Option<User>> {
+    let Some(secret) = user.totp_secret.clone() else {
+        return Ok(None);
+    };
+
+    if let Some(step) = verify_totp(&secret, code, user.totp_last_step)? {
+        user.totp_last_step = Some(step);
+        user.totp_enabled_at.get_or_insert_with(|| Utc::now().naive_utc());
+
+        return Ok(Some(repos.users.update(user).await?));
+    }
+
+    if user.totp_enabled() && repos.recovery_codes.redeem(user.id, hash_recovery_code(code)).await? {
+        return Ok(Some(user));
+    }
+
+    Ok(None)
+}
+
+/// The signed-in user. API keys can't manage a second factor.
+async fn session_user(context: &Context<'_>) -> Result<User> {
+    let user_id = match (context.data_opt::<SessionId>(), context.data_opt::<Uuid>()) {
+        (Some(_), Some(user_id)) => *user_id,
+        _ => return Err(unauthenticated("Sign in required")),
+    };
+
+    get_repositories_from_context(context).users.get_by_id(user_id).await
+}
+
+fn clear_totp(user: &mut User) {
+    user.totp_secret = None;
+    user.totp_enabled_at = None;
+    user.totp_last_step = None;
+}
+
+#[Object]
+impl MfaMutation {
+    #[graphql(name = "verifyMfa")]
+    /// Completes a sign-in with the challenge from `signIn` and a TOTP or
+    /// recovery code. The challenge is discarded after too many wrong codes,
+    /// and wrong codes count towards the account's sign-in lockout.
+    pub async fn verify_mfa(&self, context: &Context<'_>, challenge: String, code: String) -> Result<UserResponse> {
+        let repos = get_repositories_from_context(context);
+
+        let challenge = match repos.mfa_challenges.get_by_challenge_hash(hash_mfa_challenge(&challenge)).await {
+            Ok(challenge) if !challenge.is_expired() => challenge,
+            _ => return Err(Error::new("Invalid or expired sign-in challenge")),
+        };
+
+        let user = repos.users.get_by_id(challenge.user_id).await?;
+        user.ensure_approved()?;
+
+        let attempt = SignInAttempt::new(&user.email, context.data_opt::<ClientIp>());
+        attempt.ensure_allowed(repos).await?;
+
+        let user_id = user.id;
+
+        let Some(user) = check_second_factor(repos, user, &code).await? else {
+            attempt.failed(repos, Some(user_id), "Wrong second-factor code").await?;
+
+            let challenge = repos.mfa_challenges.record_failure(challenge.id).await?;
+
+            if challenge.failed_attempts >= MFA_MAX_ATTEMPTS {
+                repos.mfa_challenges.delete(challenge.id).await?;
+                return Err(Error::new("Too many invalid codes, sign in again"));
+            }
+
+            return Err(Error::new("Invalid code"));
+        };
+
+        // Only one use of the challenge gets a session
+        if !repos.mfa_challenges.delete(challenge.id).await? {
+            return Err(Error::new("Invalid or expired sign-in challenge"));
+        }
+
+        let tokens = start_session(repos, user).await?;
+        attempt.succeeded(repos, user_id, "Password and second factor").await?;
+
+        Ok(tokens)
+    }
+
+    #[graphql(name = "enrolTotp")]
+    /// Starts TOTP enrolment for the signed-in user, or shows the pending one
+    /// again. It takes effect once `confirmTotp` is called with the first code.
+    pub async fn enrol_totp(&self, context: &Context<'_>) -> Result<TotpEnrolment> {
+        let user = session_user(context).await?;
+
+        if user.totp_enabled() {
+            return Err(Error::new("Two-factor authentication is already enabled"));
+        }
+
+        begin_enrolment(get_repositories_from_context(context), user).await
+    }
+
+    #[graphql(name = "confirmTotp")]
+    /// Enables TOTP with the first code from the enrolled app
+    pub async fn confirm_totp(&self, context: &Context<'_>, code: String) -> Result<bool> {
+        let user = session_user(context).await?;
+
+        if user.totp_enabled() {
+            return Err(Error::new("Two-factor authentication is already enabled"));
+        }
+
+        if user.totp_secret.is_none() {
+            return Err(Error::new("Start with enrolTotp"));
+        }
+
+        match check_second_factor(get_repositories_from_context(context), user, &code).await? {
+            Some(_) => Ok(true),
+            None => Err(Error::new("Invalid code")),
+        }
+    }
+
+    #[graphql(name = "regenerateRecoveryCodes")]
+    /// Replaces the signed-in user's recovery codes, given a current TOTP or
+    /// recovery code
+    pub async fn regenerate_recovery_codes(&self, context: &Context<'_>, code: String) -> Result<Vec<String>> {
+        let repos = get_repositories_from_context(context);
+        let user = session_user(context).await?;
+
+        if !user.totp_enabled() {
+            return Err(Error::new("Two-factor authentication is not enabled"));
+        }
+
+        let Some(user) = check_second_factor(repos, user, &code).await? else {
+            return Err(Error::new("Invalid code"));
+        };
+
+        let recovery_codes = generate_recovery_codes();
+        repos
+            .recovery_codes
+            .replace_for_user(user.id, recovery_codes.iter().map(|c| hash_recovery_code(c)).collect())
+            .await?;
+
+        Ok(recovery_codes)
+    }
+
+    #[graphql(name = "disableTotp")]
+    /// Turns TOTP off for the signed-in user, given a current TOTP or recovery
+    /// code. Not allowed for roles that require it.
+    pub async fn disable_totp(&self, context: &Context<'_>, code: String) -> Result<bool> {
+        let repos = get_repositories_from_context(context);
+        let user = session_user(context).await?;
+
+        if mfa_required(&user.role) {
+            return Err(forbidden("Your role requires two-factor authentication"));
+        }
+
+        if !user.totp_enabled() {
+            return Err(Error::new("Two-factor authentication is not enabled"));
+        }
+
+        let Some(mut user) = check_second_factor(repos, user, &code).await? else {
+            return Err(Error::new("Invalid code"));
+        };
+
+        clear_totp(&mut user);
+        let user = repos.users.update(user).await?;
+        repos.recovery_codes.replace_for_user(user.id, Vec::new()).await?;
+
+        Ok(true)
+    }
+
+    #[graphql(
+        name = "resetTotp",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
+    )]
+    /// Removes a user's TOTP and recovery codes, e.g. after a lost device,
+    /// and signs them out everywhere. Roles that require TOTP enrol again on
+    /// their next sign-in.
+    pub async fn reset_totp(&self, context: &Context<'_>, id: Uuid) -> Result<User> {
+        let repos = get_repositories_from_context(context);
+
+        let mut user = repos.users.get_by_id(id).await?;
+        clear_totp(&mut user);
+
+        let user = repos.users.update(user).await?;
+        repos.recovery_codes.replace_for_user(user.id, Vec::new()).await?;
+        repos.sessions.revoke_all_by_user_id(user.id).await?;
+
+        Ok(user)
+    }
+}
@@ -1,6 +1,15 @@
+#[allow(clippy::module_inception)]
 mod mutation;
 mod user_mutation;
+mod conversion_request_mutation;
+mod api_key_mutation;
+mod authority_member_mutation;
+mod mfa_mutation;
 
 
 pub use self::mutation::*;
 pub use self::user_mutation::*;
+pub use self::conversion_request_mutation::*;
+pub use self::api_key_mutation::*;
+pub use self::authority_member_mutation::*;
+pub use self::mfa_mutation::*;
@@ -3,11 +3,17 @@ use async_graphql::*;
 // use rdkafka::producer::FutureProducer;
 // use crate::kafka::send_message;
 
-use crate::graphql::{mutation::{UserMutation}};
+use crate::graphql::{mutation::{
+    ApiKeyMutation, AuthorityMemberMutation, ConversionRequestMutation, MfaMutation, UserMutation,
+}};
 
 #[derive(MergedObject, Default)]
 pub struct Mutation(
     UserMutation, 
+    ConversionRequestMutation,
+    ApiKeyMutation,
+    AuthorityMemberMutation,
+    MfaMutation,
 /*
 PersonMutation,
 RoleMutation,
@@ -2,11 +2,20 @@ use std::str::FromStr;
 
 use async_graphql::*;
 use serde::{Deserialize, Serialize};
-use crate::models::{InsertableUser, LoginQuery,
-    User, UserData, create_token, decode_token,
-    verify_password, UserUpdate, hash_password};
-use crate::common_utils::{UserRole,
-    is_admin, RoleGuard};
+use uuid::Uuid;
+use crate::models::{AccountStatus, InsertableUser, LoginQuery,
+    User, UserData, create_token,
+    verify_password, verify_dummy_password, UserUpdate, hash_password, PasswordCheck,
+    NewSession, SessionId, generate_refresh_token, hash_refresh_token,
+    InsertablePasswordResetToken, InsertableVerification, hash_email_token, SSO_ONLY_HASH,
+    account_subject, record_security_event, AuditAction, AuditEntity, NewAuditEntry, ClientIp, NewSecurityEvent, SecurityEventType, SignInAttempt, ThrottleKind};
+use crate::common_utils::{UserRole, Permission,
+    can_manage_users, forbidden, has_permission, unauthenticated, PermissionGuard};
+use crate::config_variables::{EMAIL_VERIFICATION_DURATION, PASSWORD_RESET_DURATION};
+use crate::database::run_blocking;
+use crate::mail::Mailer;
+use crate::graphql::{begin_sign_in, get_repositories_from_context, SignInResult};
+use crate::repositories::Repositories;
 // use rdkafka::producer::FutureProducer;
 // use crate::kafka::send_message;
 
@@ -16,10 +25,94 @@ pub struct UserMutation;
 #[derive(Debug, Serialize, Deserialize, SimpleObject)]
 pub struct UserResponse {
     bearer: String,
+    /// Exchange with `refreshToken` for a new token pair before `bearer`
+    /// expires. Can be used once.
+    refresh_token: String,
     role: String,
     email: String,
 }
 
+/// Issues an access token for `session_id` with the user's current role
+fn token_response(user: User, session_id: SessionId, refresh_token: String) -> Result<UserResponse> {
+    let role = UserRole::from_str(user.role.as_str())
+        .expect("Cannot convert &str to UserRole");
+
+    // Return the token which would be accepted by the Epicenter 
+    // app and used to authenticate actions. Tokens are credentials and are
+    // never logged.
+    let token = create_token(user.id.to_string(), role, session_id)?;
+
+    Ok(UserResponse {
+        email: user.email,
+        bearer: token,
+        refresh_token,
+        role: user.role,
+    })
+}
+
+/// Starts a new session for a user who just authenticated. Accounts that are
+/// not approved are refused.
+pub async fn start_session(repos: &Repositories, user: User) -> Result<UserResponse> {
+    user.ensure_approved()?;
+
+    let refresh_token = generate_refresh_token();
+
+    let session = repos
+        .sessions
+        .create(NewSession::new(user.id, &refresh_token))
+        .await?;
+
+    token_response(user, SessionId(session.id), refresh_token)
+}
+
+/// Normalises a nationality and checks it names a known nation
+async fn known_nationality(repos: &Repositories, nationality: Option<String>) -> Result<Option<String>> {
+    let Some(code) = nationality.map(|n| n.trim().to_uppercase()) else {
+        return Ok(None);
+    };
+
+    repos
+        .nations
+        .get_by_code(code.clone())
+        .await
+        .map_err(|_| Error::new(format!("Unknown nation code {}", code)))?;
+
+    Ok(Some(code))
+}
+
+/// Issues a new email verification token for `user`, replacing any pending
+/// one, and emails it to them
+async fn issue_verification(context: &Context<'_>, user: &User) -> Result<()> {
+    let (verification, token) = InsertableVerification::new(&user.email);
+    get_repositories_from_context(context)
+        .email_verifications
+        .create(verification)
+        .await?;
+
+    let mailer = context.data::<Mailer>()?;
+    mailer.dispatch(
+        user.email.clone(),
+        "Verify your email address".to_owned(),
+        "email_verification",
+        serde_json::json!({
+            "name": user.name,
+            "token": token,
+            "hours": EMAIL_VERIFICATION_DURATION / 3600,
+            "link": mailer.link(&format!("/verify-email?token={}", token)),
+        }),
+    );
+
+    Ok(())
+}
+
+/// Whether `approver` may approve `user`: an administrator other than the user
+/// and, once there is another approved administrator to ask, other than the
+/// one who created the account. Until then the seeded administrator approves
+/// the accounts they create, which is how a second one is brought in.
+fn can_approve(approver: Uuid, user: &User, approved_admins: usize) -> bool {
+    user.id != approver && (user.created_by_user_uid != Some(approver) || approved_admins <= 1)
+}
+
 // Mutation Example
 
 #[Object]
@@ -77,86 +170,488 @@ impl UserMutation {
 */
     #[graphql(
         name = "createUser",
-        guard = "RoleGuard::new(UserRole::Admin)",
-        visible = "is_admin",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
     )]
     pub async fn create_user(
         &self,
-        _context: &Context<'_>,
-        user_data: UserData,
+        context: &Context<'_>,
+        mut user_data: UserData,
     ) -> FieldResult<User> {
-        let new_user = InsertableUser::from(user_data);
+        let admin_id = *context
+            .data_opt::<Uuid>()
+            .ok_or_else(|| unauthenticated("Sign in required"))?;
 
-        let created_user = User::create(new_user);
+        let repos = get_repositories_from_context(context);
 
-        created_user
+        user_data.nationality = known_nationality(repos, user_data.nationality).await?;
+
+        // Password hashing is blocking
+        let mut new_user = run_blocking(move || Ok(InsertableUser::from(user_data))).await?;
+
+        // Pending until another administrator approves it
+        new_user.created_by_user_uid = Some(admin_id);
+
+        let user = repos.users.create(new_user).await?;
+        issue_verification(context, &user).await?;
+
+        Ok(user)
+    }
+
+    #[graphql(
+        name = "approveUser",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
+    )]
+    /// Approves a pending account or reinstates a suspended one. The approver
+    /// must be an administrator other than the one who created the account,
+    /// unless the creator is the only approved administrator.
+    pub async fn approve_user(&self, context: &Context<'_>, id: Uuid) -> Result<User> {
+        let admin_id = *context
+            .data_opt::<Uuid>()
+            .ok_or_else(|| unauthenticated("Sign in required"))?;
+
+        let repos = get_repositories_from_context(context);
+
+        let mut user = repos.users.get_by_id(id).await?;
+
+        if user.status() == AccountStatus::Approved {
+            return Err(Error::new("Account is already approved"));
+        }
+
+        let approved_admins = repos
+            .users
+            .get_all()
+            .await?
+            .iter()
+            .filter(|u| u.role == UserRole::Admin.to_string() && u.status() == AccountStatus::Approved)
+            .count();
+
+        if !can_approve(admin_id, &user, approved_admins) {
+            return Err(forbidden(
+                "Accounts must be approved by an administrator other than the one who created them",
+            ));
+        }
+
+        let bootstrap = user.created_by_user_uid == Some(admin_id);
+
+        user.account_status = AccountStatus::Approved.to_string();
+        user.approved_by_user_uid = Some(admin_id);
+
+        let user = repos.users.update(user).await?;
+
+        // The exception to the two-administrator rule goes on the record
+        if bootstrap {
+            let entry = NewAuditEntry {
+                detail: serde_json::json!({ "email": user.email, "role": user.role }),
+                ..NewAuditEntry::new(Some(admin_id), AuditAction::BootstrapApproval, AuditEntity::User, Some(user.id))
+            };
+            repos.audit_log.append(vec![entry]).await?;
+        }
+
+        let mailer = context.data::<Mailer>()?;
+        mailer.dispatch(
+            user.email.clone(),
+            "Your account has been approved".to_owned(),
+            "account_approved",
+            serde_json::json!({ "name": user.name, "link": mailer.link("/") }),
+        );
+
+        Ok(user)
+    }
+
+    #[graphql(
+        name = "rejectUser",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
+    )]
+    /// Deletes a pending account, returning whether there was one
+    pub async fn reject_user(&self, context: &Context<'_>, id: Uuid) -> Result<bool> {
+        let repos = get_repositories_from_context(context);
+
+        let user = match repos.users.get_by_id(id).await {
+            Ok(user) => user,
+            Err(_) => return Ok(false),
+        };
+
+        if user.status() != AccountStatus::Pending {
+            return Err(Error::new("Only pending accounts can be rejected, suspend it instead"));
+        }
+
+        repos.users.delete(id).await
+    }
+
+    #[graphql(
+        name = "suspendUser",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
+    )]
+    /// Blocks an account and signs it out everywhere. API keys acting as the
+    /// user stop working until it is approved again.
+    pub async fn suspend_user(&self, context: &Context<'_>, id: Uuid) -> Result<User> {
+        let admin_id = *context
+            .data_opt::<Uuid>()
+            .ok_or_else(|| unauthenticated("Sign in required"))?;
+
+        if id == admin_id {
+            return Err(Error::new("Administrators can't suspend themselves"));
+        }
+
+        let repos = get_repositories_from_context(context);
+
+        let mut user = repos.users.get_by_id(id).await?;
+        user.account_status = AccountStatus::Suspended.to_string();
+
+        let user = repos.users.update(user).await?;
+        repos.sessions.revoke_all_by_user_id(user.id).await?;
+
+        Ok(user)
     }
 
     #[graphql(
         name = "updateUser",
-        guard = "RoleGuard::new(UserRole::Admin)",
-        visible = "is_admin",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
     )]
     pub async fn update_user(
         &self,
-        _context: &Context<'_>,
+        context: &Context<'_>,
         user_data: UserUpdate,
     ) -> FieldResult<User> {
-        let mut target_user = User::get_by_id(&user_data.id)?;
+        let repos = get_repositories_from_context(context);
+
+        let mut target_user = repos.users.get_by_id(user_data.id).await?;
+
+        let password_changed = user_data.password.is_some();
 
         if let Some(s) = user_data.name {
             target_user.name = s;
         };
 
+        let email_changed = user_data.email.as_ref().is_some_and(|e| *e != target_user.email);
+
         if let Some(s) = user_data.email {
             target_user.email = s;
         };
 
+        // A new address has to be verified again
+        if email_changed {
+            target_user.email_verified_at = None;
+        }
+
         if let Some(s) = user_data.password {
-            target_user.hash = hash_password(&s)?.to_string();
+            target_user.hash = run_blocking(move || Ok(hash_password(&s)?)).await?;
         };
 
+        let privileges = (target_user.role.clone(), target_user.access_level.clone(), target_user.nationality.clone());
+
         if let Some(s) = user_data.role {
             target_user.role = s;
         };
 
-        let updated_user = target_user.update();
+        if let Some(c) = user_data.clearance {
+            target_user.access_level = c.to_string();
+        };
+
+        if let Some(n) = known_nationality(repos, user_data.nationality).await? {
+            target_user.nationality = Some(n);
+        };
+
+        let privileges_changed =
+            privileges != (target_user.role.clone(), target_user.access_level.clone(), target_user.nationality.clone());
+
+        let user = repos.users.update(target_user).await?;
+
+        // A new password, role, clearance or nationality signs the user out
+        // everywhere, so no open session or socket keeps the old ones
+        if password_changed || privileges_changed {
+            repos.sessions.revoke_all_by_user_id(user.id).await?;
+        }
+
+        // A reset sent to the old address must not take over the account
+        if email_changed {
+            repos.password_resets.delete_by_email(user.email.clone()).await?;
+            issue_verification(context, &user).await?;
+        }
 
-        updated_user
+        Ok(user)
     }
 
+    #[graphql(
+        name = "unlockSignIn",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
+    )]
+    /// Clears the failed sign-ins of an email, a client address or both,
+    /// lifting any backoff or lockout. Returns whether there were any.
+    pub async fn unlock_sign_in(
+        &self,
+        context: &Context<'_>,
+        email: Option<String>,
+        ip_address: Option<String>,
+    ) -> Result<bool> {
+        let admin_id = *context
+            .data_opt::<Uuid>()
+            .ok_or_else(|| unauthenticated("Sign in required"))?;
+
+        if email.is_none() && ip_address.is_none() {
+            return Err(Error::new("Give an email, an IP address or both"));
+        }
+
+        let repos = get_repositories_from_context(context);
+        let mut cleared = false;
+
+        if let Some(email) = &email {
+            cleared |= repos
+                .sign_in_throttles
+                .clear(ThrottleKind::Account, account_subject(email))
+                .await?;
+        }
+
+        if let Some(ip) = &ip_address {
+            cleared |= repos.sign_in_throttles.clear(ThrottleKind::Ip, ip.trim().to_owned()).await?;
+        }
+
+        let user_id = match &email {
+            Some(email) => repos.users.get_by_email(email.trim().to_owned()).await.ok().map(|u| u.id),
+            None => None,
+        };
+
+        let event = NewSecurityEvent::new(
+            SecurityEventType::SignInUnlocked,
+            user_id,
+            email,
+            ip_address,
+            Some(format!("Unlocked by {}", admin_id)),
+        );
+        record_security_event(repos, Some(admin_id), event).await;
+
+        Ok(cleared)
+    }
+
+    #[graphql(name = "requestPasswordReset")]
+    /// Sends a single-use password reset token to the address if it belongs
+    /// to an approved account with a password. Always returns true, so the
+    /// result doesn't reveal which addresses have accounts.
+    pub async fn request_password_reset(&self, context: &Context<'_>, email: String) -> Result<bool> {
+        let repos = get_repositories_from_context(context);
+
+        let user = match repos.users.get_by_email(email).await {
+            Ok(user) if user.ensure_approved().is_ok() && user.hash != SSO_ONLY_HASH => user,
+            _ => return Ok(true),
+        };
+
+        let (reset, token) = InsertablePasswordResetToken::new(&user.email);
+        repos.password_resets.create(reset).await?;
+
+        let mailer = context.data::<Mailer>()?;
+        mailer.dispatch(
+            user.email,
+            "Reset your password".to_owned(),
+            "password_reset",
+            serde_json::json!({
+                "name": user.name,
+                "token": token,
+                "minutes": PASSWORD_RESET_DURATION / 60,
+                "link": mailer.link(&format!("/reset-password?token={}", token)),
+            }),
+        );
+
+        Ok(true)
+    }
+
+    #[graphql(name = "resetPassword")]
+    /// Sets a new password with a token from `requestPasswordReset`. The
+    /// token works once, and the user is signed out everywhere.
+    pub async fn reset_password(
+        &self,
+        context: &Context<'_>,
+        token: String,
+        new_password: String,
+    ) -> Result<bool> {
+        let repos = get_repositories_from_context(context);
+
+        let reset = match repos.password_resets.consume(hash_email_token(&token)).await {
+            Ok(reset) if !reset.is_expired() => reset,
+            _ => return Err(Error::new("Invalid or expired reset token")),
+        };
+
+        let mut user = repos.users.get_by_email(reset.email_address).await?;
+
+        user.hash = run_blocking(move || Ok(hash_password(&new_password)?)).await?;
+
+        // The token arrived by email, which proves the address
+        if user.email_verified_at.is_none() {
+            user.email_verified_at = Some(chrono::Utc::now().naive_utc());
+        }
+
+        let user = repos.users.update(user).await?;
+        repos.sessions.revoke_all_by_user_id(user.id).await?;
+
+        Ok(true)
+    }
+
+    #[graphql(name = "verifyEmail")]
+    /// Confirms an email address with the token sent to it. The token works
+    /// once.
+    pub async fn verify_email(&self, context: &Context<'_>, token: String) -> Result<bool> {
+        let repos = get_repositories_from_context(context);
+
+        let verification = match repos.email_verifications.consume(hash_email_token(&token)).await {
+            Ok(verification) if !verification.is_expired() => verification,
+            _ => return Err(Error::new("Invalid or expired verification token")),
+        };
+
+        let mut user = repos.users.get_by_email(verification.email_address).await?;
+        user.email_verified_at = Some(chrono::Utc::now().naive_utc());
+        repos.users.update(user).await?;
+
+        Ok(true)
+    }
+
+    /// Checks the password. Users with TOTP enabled, or whose role requires
+    /// it, get an `MfaChallenge` for `verifyMfa` instead of tokens. Repeated
+    /// failures for an email or from an address back off and then lock out,
+    /// see `models/sign_in_throttle.rs`.
     pub async fn sign_in(
         &self,
-        _context: &Context<'_>,
+        context: &Context<'_>,
         input: LoginQuery,
-    ) -> Result<UserResponse, Error> {
-        let maybe_user = User::get_by_email(&input.email).ok();
+    ) -> Result<SignInResult, Error> {
+        let repos = get_repositories_from_context(context);
+        let users = &repos.users;
 
-        if let Some(user) = maybe_user {
+        let attempt = SignInAttempt::new(&input.email, context.data_opt::<ClientIp>());
+        attempt.ensure_allowed(repos).await?;
 
-            if let Ok(matching) = verify_password(user.hash.to_string(), &input.password) {
-                if matching {
-                    let role = UserRole::from_str(user.role.as_str())
-                        .expect("Cannot convert &str to UserRole");
+        let known_user = users.get_by_email(input.email).await.ok();
+        let known_user_id = known_user.as_ref().map(|u| u.id);
 
-                    // Return the token which would be accepted by the Epicenter 
-                    // app and used to authenticate actions
-                    let token = create_token(user.id.to_string(), role);
-
-                    let res = UserResponse {
-                        email: user.email.to_owned(),
-                        bearer: token.to_owned(),
-                        role: user.role,
-                    };
+        let maybe_user = match known_user {
+            // argon2 verification is blocking
+            Some(mut user) => run_blocking(move || {
+                let check = verify_password(&user.hash, &input.password)?;
 
+                // Migrate hashes with a shared salt or outdated parameters
+                // while the plain password is at hand
+                if check == PasswordCheck::MatchNeedsRehash {
+                    user.hash = hash_password(&input.password)?;
+                }
 
-                    println!("JWT: {}\nData{:?}", &token, decode_token(&token));
+                Ok(check.is_match().then_some((user, check)))
+            })
+            .await
+            .ok()
+            .flatten(),
+            // Verify anyway, so the response time doesn't tell which
+            // emails have an account
+            None => {
+                let _ = run_blocking(move || {
+                    verify_dummy_password(&input.password);
+                    Ok(())
+                })
+                .await;
+                None
+            }
+        };
 
-                    return Ok(res);
+        let maybe_user = match maybe_user {
+            Some((user, PasswordCheck::MatchNeedsRehash)) => {
+                // A failed rehash shouldn't block sign-in, the old hash
+                // stays valid and is retried next time
+                if let Err(e) = users.update(user.clone()).await {
+                    println!("Unable to rehash password for user {}: {:?}", user.id, e);
                 }
+                Some(user)
             }
+            other => other.map(|(user, _)| user),
+        };
+
+        let Some(user) = maybe_user else {
+            attempt.failed(repos, known_user_id, "Wrong password or unknown email").await?;
+            return Err(Error::new("Can't authenticate a user"));
+        };
+
+        let user_id = user.id;
+        let result = begin_sign_in(repos, user).await?;
+
+        if let SignInResult::Authenticated(_) = result {
+            attempt.succeeded(repos, user_id, "Password").await?;
+        }
+
+        Ok(result)
+    }
+
+    #[graphql(name = "refreshToken")]
+    /// Exchanges a refresh token for a new access and refresh token pair.
+    /// The old refresh token stops working.
+    pub async fn refresh_token(
+        &self,
+        context: &Context<'_>,
+        refresh_token: String,
+    ) -> Result<UserResponse> {
+        let repos = get_repositories_from_context(context);
+
+        let current_hash = hash_refresh_token(&refresh_token);
+
+        let session = match repos.sessions.get_by_refresh_token_hash(current_hash.clone()).await {
+            Ok(session) if session.is_active() => session,
+            _ => return Err(Error::new("Invalid or expired refresh token")),
+        };
+
+        // Re-read the user so role and status changes apply from the next token
+        let user = repos.users.get_by_id(session.user_id).await?;
+        user.ensure_approved()?;
+
+        let new_token = generate_refresh_token();
+
+        let session = repos
+            .sessions
+            .rotate(current_hash, hash_refresh_token(&new_token))
+            .await
+            .map_err(|_| Error::new("Invalid or expired refresh token"))?;
+
+        token_response(user, SessionId(session.id), new_token)
+    }
+
+    #[graphql(name = "signOut")]
+    /// Ends the session of the calling access token. Its refresh token is
+    /// revoked along with it.
+    pub async fn sign_out(&self, context: &Context<'_>) -> Result<bool> {
+        let session_id = context
+            .data_opt::<SessionId>()
+            .ok_or_else(|| unauthenticated("Sign in required"))?;
+
+        get_repositories_from_context(context)
+            .sessions
+            .revoke(session_id.0)
+            .await?;
+
+        Ok(true)
+    }
+
+    #[graphql(name = "revokeAllSessions")]
+    /// Signs a user out everywhere, returning the number of sessions revoked.
+    /// Defaults to the caller; revoking another user's sessions requires
+    /// Permission::ManageUsers.
+    pub async fn revoke_all_sessions(
+        &self,
+        context: &Context<'_>,
+        user_id: Option<Uuid>,
+    ) -> Result<usize> {
+        let caller_id = *context
+            .data_opt::<Uuid>()
+            .ok_or_else(|| unauthenticated("Sign in required"))?;
+
+        let user_id = user_id.unwrap_or(caller_id);
+
+        if user_id != caller_id && !has_permission(context, Permission::ManageUsers) {
+            return Err(forbidden(format!("Access denied: {} permission required", Permission::ManageUsers)));
         }
 
-        Err(Error::new("Can't authenticate a user"))
+        get_repositories_from_context(context)
+            .sessions
+            .revoke_all_by_user_id(user_id)
+            .await
     }
-}
\ No newline at end of file
+}
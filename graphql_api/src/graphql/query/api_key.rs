@@ -0,0 +1,21 @@
+use async_graphql::*;
+
+use crate::common_utils::{can_manage_api_keys, Permission, PermissionGuard};
+use crate::graphql::get_repositories_from_context;
+use crate::models::ApiKey;
+
+#[derive(Default)]
+pub struct ApiKeyQuery;
+
+#[Object]
+impl ApiKeyQuery {
+    #[graphql(
+        name = "apiKeys",
+        guard = "PermissionGuard::new(Permission::ManageApiKeys)",
+        visible = "can_manage_api_keys",
+    )]
+    /// Returns all API keys, newest first, including revoked and expired ones
+    pub async fn api_keys(&self, context: &Context<'_>) -> Result<Vec<ApiKey>> {
+        get_repositories_from_context(context).api_keys.get_all().await
+    }
+}
@@ -0,0 +1,99 @@
+use async_graphql::connection::{query, Connection, Edge};
+use async_graphql::*;
+
+use crate::common_utils::{can_read_audit_log, Permission, PermissionGuard};
+use crate::config_variables::{AUDIT_EVENTS_LIMIT, AUDIT_VERIFY_BATCH};
+use crate::graphql::get_repositories_from_context;
+use crate::models::{AuditChainReport, AuditChainVerifier, AuditEntry, AuditFilter, AuditPage};
+
+#[derive(Default)]
+pub struct AuditQuery;
+
+#[Object]
+impl AuditQuery {
+    #[graphql(
+        name = "verifyAuditChain",
+        guard = "PermissionGuard::new(Permission::ReadAuditLog)",
+        visible = "can_read_audit_log",
+    )]
+    /// Recomputes every audit entry hash from the first entry on and reports
+    /// the first entry that doesn't fit the chain, if any
+    pub async fn verify_audit_chain(&self, context: &Context<'_>) -> Result<AuditChainReport> {
+        let repos = get_repositories_from_context(context);
+        let mut verifier = AuditChainVerifier::default();
+        let mut after_sequence = 0;
+
+        loop {
+            let page = repos.audit_log.get_page(after_sequence, AUDIT_VERIFY_BATCH).await?;
+
+            let Some(last) = page.last() else {
+                return Ok(verifier.report(None));
+            };
+            after_sequence = last.sequence;
+
+            for entry in &page {
+                if let Err(reason) = verifier.check(entry) {
+                    return Ok(verifier.report(Some((entry.sequence, reason))));
+                }
+            }
+        }
+    }
+
+    #[graphql(
+        name = "auditEvents",
+        guard = "PermissionGuard::new(Permission::ReadAuditLog)",
+        visible = "can_read_audit_log",
+    )]
+    /// Audit entries matching the filter, in chain order. Cursors are entry
+    /// sequences. Pages hold at most 500 entries; `last` without `before`
+    /// gives the newest.
+    pub async fn audit_events(
+        &self,
+        context: &Context<'_>,
+        filter: Option<AuditFilter>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<i64, AuditEntry>> {
+        let repos = get_repositories_from_context(context);
+        let filter = filter.unwrap_or_default();
+
+        query(after, before, first, last, |after: Option<i64>, before: Option<i64>, first, last| async move {
+            if first.is_some() && last.is_some() {
+                return Err(Error::new("Pass either first or last, not both"));
+            }
+
+            let limit = first.or(last).unwrap_or(AUDIT_EVENTS_LIMIT).min(AUDIT_EVENTS_LIMIT);
+            let newest_first = last.is_some();
+
+            // One extra entry tells whether there is another page
+            let page = AuditPage {
+                after_sequence: after,
+                before_sequence: before,
+                limit: limit as i64 + 1,
+                newest_first,
+            };
+            let mut entries = repos.audit_log.search(filter, page).await?;
+
+            let more = entries.len() > limit;
+            entries.truncate(limit);
+
+            if newest_first {
+                entries.reverse();
+            }
+
+            let mut connection = if newest_first {
+                Connection::new(more, before.is_some())
+            } else {
+                Connection::new(after.is_some(), more)
+            };
+            connection
+                .edges
+                .extend(entries.into_iter().map(|entry| Edge::new(entry.sequence, entry)));
+
+            Ok::<_, Error>(connection)
+        })
+        .await
+    }
+}
@@ -1,46 +1,71 @@
 use async_graphql::*;
 
+use crate::graphql::get_repositories_from_context;
 use crate::models::Authority;
 use uuid::Uuid;
 
-//use crate::common_utils::{RoleGuard, is_admin, UserRole};
+use crate::common_utils::{Permission, PermissionGuard};
+use crate::repositories::TenantScope;
 
 #[derive(Default)]
 pub struct AuthorityQuery;
 
 #[Object]
 impl AuthorityQuery {
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns count of Authorities in the system
-    pub async fn authority_count(&self, _context: &Context<'_>) -> Result<i64> {
-        let authorities = Authority::get_all()?;
+    pub async fn authority_count(&self, context: &Context<'_>) -> Result<i64> {
+        let authorities = get_repositories_from_context(context).authorities.get_all().await?;
         Ok(authorities.len() as i64)
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns an authority by its Uuid
-    pub async fn authority_by_id(&self, _context: &Context<'_>, id: Uuid) -> Result<Authority> {
-        Authority::get_by_id(&id)
+    pub async fn authority_by_id(&self, context: &Context<'_>, id: Uuid) -> Result<Authority> {
+        get_repositories_from_context(context).authorities.get_by_id(id).await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns authorities by creator ID
     pub async fn authorities_by_creator_id(
         &self,
-        _context: &Context<'_>,
+        context: &Context<'_>,
         creator_id: Uuid,
     ) -> Result<Vec<Authority>> {
-        Authority::get_by_creator_id(creator_id)
+        get_repositories_from_context(context).authorities.get_by_creator_id(creator_id).await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns authorities by nation ID
     pub async fn authorities_by_nation_code(
         &self,
-        _context: &Context<'_>,
+        context: &Context<'_>,
         nation_code: String,
     ) -> Result<Vec<Authority>> {
-        Authority::get_by_nation_code(&nation_code)
+        get_repositories_from_context(context).authorities.get_by_nation_code(nation_code).await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns vector of all authorities
-    pub async fn authorities(&self, _context: &Context<'_>) -> Result<Vec<Authority>> {
-        Authority::get_all()
+    pub async fn authorities(&self, context: &Context<'_>) -> Result<Vec<Authority>> {
+        get_repositories_from_context(context).authorities.get_all().await
+    }
+
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
+    /// Returns the authorities the caller acts for: their memberships, the
+    /// authority of their API key, or every authority for admins
+    pub async fn my_authorities(&self, context: &Context<'_>) -> Result<Vec<Authority>> {
+        let repos = get_repositories_from_context(context);
+
+        match context.data_opt::<TenantScope>().cloned().unwrap_or_default() {
+            TenantScope::All => repos.authorities.get_all().await,
+            TenantScope::Members { authority_ids, .. } => Ok(repos
+                .authorities
+                .get_all()
+                .await?
+                .into_iter()
+                .filter(|a| authority_ids.contains(&a.id))
+                .collect()),
+        }
     }
 }
@@ -1,73 +1,85 @@
 use async_graphql::*;
 
+use crate::graphql::get_repositories_from_context;
 use crate::models::ClassificationSchema;
 use uuid::Uuid;
 
-//use crate::common_utils::{RoleGuard, is_admin, UserRole};
+use crate::common_utils::{Permission, PermissionGuard};
 
 #[derive(Default)]
 pub struct ClassificationSchemaQuery;
 
 #[Object]
 impl ClassificationSchemaQuery {
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns count of ClassificationSchemas in the system
-    pub async fn classification_schema_count(&self, _context: &Context<'_>) -> Result<i64> {
-        ClassificationSchema::get_count()
+    pub async fn classification_schema_count(&self, context: &Context<'_>) -> Result<i64> {
+        get_repositories_from_context(context).schemas.count().await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns a classification schema by its Uuid
-    pub async fn classification_schema_by_id(&self, _context: &Context<'_>, id: Uuid) -> Result<ClassificationSchema> {
-        ClassificationSchema::get_by_id(&id)
+    pub async fn classification_schema_by_id(&self, context: &Context<'_>, id: Uuid) -> Result<ClassificationSchema> {
+        get_repositories_from_context(context).schemas.get_by_id(id).await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns classification schemas by creator ID
     pub async fn classification_schemas_by_creator_id(
         &self,
-        _context: &Context<'_>,
+        context: &Context<'_>,
         creator_id: Uuid,
     ) -> Result<Vec<ClassificationSchema>> {
-        ClassificationSchema::get_by_creator_id(creator_id)
+        get_repositories_from_context(context).schemas.get_by_creator_id(creator_id).await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns classification schemas by nation code
     pub async fn classification_schemas_by_nation_code(
         &self,
-        _context: &Context<'_>,
+        context: &Context<'_>,
         nation_code: String,
     ) -> Result<Vec<ClassificationSchema>> {
-        ClassificationSchema::get_by_nation_code(&nation_code)
+        get_repositories_from_context(context).schemas.get_by_nation_code(nation_code).await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns a specific classification schema by nation code and version
     pub async fn classification_schema_by_nation_code_and_version(
         &self,
-        _context: &Context<'_>,
+        context: &Context<'_>,
         nation_code: String,
         version: String,
     ) -> Result<ClassificationSchema> {
-        ClassificationSchema::get_by_nation_code_and_version(&nation_code, &version)
+        get_repositories_from_context(context)
+            .schemas
+            .get_by_nation_code_and_version(nation_code, version)
+            .await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns classification schemas by authority ID
     pub async fn classification_schemas_by_authority_id(
         &self,
-        _context: &Context<'_>,
+        context: &Context<'_>,
         authority_id: Uuid,
     ) -> Result<Vec<ClassificationSchema>> {
-        ClassificationSchema::get_by_authority_id(&authority_id)
+        get_repositories_from_context(context).schemas.get_by_authority_id(authority_id).await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns the latest classification schema for a given nation code
     pub async fn classification_schema_latest_by_nation_code(
         &self,
-        _context: &Context<'_>,
+        context: &Context<'_>,
         nation_code: String,
     ) -> Result<ClassificationSchema> {
-        ClassificationSchema::get_latest_by_nation_code(&nation_code)
+        get_repositories_from_context(context).schemas.get_latest_by_nation_code(nation_code).await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns vector of all classification schemas
-    pub async fn classification_schemas(&self, _context: &Context<'_>) -> Result<Vec<ClassificationSchema>> {
-        ClassificationSchema::get_all()
+    pub async fn classification_schemas(&self, context: &Context<'_>) -> Result<Vec<ClassificationSchema>> {
+        get_repositories_from_context(context).schemas.get_all().await
     }
 }
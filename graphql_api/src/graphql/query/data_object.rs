@@ -1,9 +1,11 @@
 use async_graphql::*;
 
-use crate::models::{DataObject, Metadata};
+use crate::graphql::get_repositories_from_context;
+use crate::repositories::SearchVisibility;
+use crate::models::{DataObject, DataObjectSearchResult};
 use uuid::Uuid;
 
-//use crate::common_utils::{RoleGuard, is_admin, UserRole};
+use crate::common_utils::{Permission, PermissionGuard};
 
 #[derive(Default)]
 pub struct DataObjectQuery;
@@ -11,41 +13,81 @@ pub struct DataObjectQuery;
 #[Object]
 impl DataObjectQuery {
     // DataObjects
-    /// Returns count of DataObjects in the system
-    pub async fn data_object_count(&self, _context: &Context<'_>) -> Result<i64> {
-        DataObject::get_count()
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadConversions)")]
+    /// Returns count of DataObjects the caller can list, redacted ones included
+    pub async fn data_object_count(&self, context: &Context<'_>) -> Result<i64> {
+        get_repositories_from_context(context).data_objects.count().await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadConversions)")]
     /// Returns a data_object by its Uuid
-    pub async fn data_object_by_id(&self, _context: &Context<'_>, id: Uuid) -> Result<DataObject> {
-        DataObject::get_by_id(&id)
+    pub async fn data_object_by_id(&self, context: &Context<'_>, id: Uuid) -> Result<DataObject> {
+        get_repositories_from_context(context).data_objects.get_by_id(id).await
     }
 
-    /// Accepts a String "name" and returns a vector of data_objects that
-    /// match in EN or FR against it
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadConversions)")]
+    /// Accepts a String "title" and returns a vector of data_objects whose
+    /// title contains it (case-insensitive)
     pub async fn data_objects_by_title(
         &self,
-        _context: &Context<'_>,
+        context: &Context<'_>,
         title: String,
     ) -> Result<Vec<DataObject>> {
-        DataObject::get_by_title(&title)
+        get_repositories_from_context(context).data_objects.get_by_title(title).await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadConversions)")]
+    /// Returns the data_objects submitted with this content digest, oldest
+    /// first. A document resubmitted unchanged matches its earlier objects;
+    /// a changed one doesn't.
+    pub async fn data_objects_by_content_digest(
+        &self,
+        context: &Context<'_>,
+        content_digest: String,
+    ) -> Result<Vec<DataObject>> {
+        get_repositories_from_context(context)
+            .data_objects
+            .get_by_content_digest(content_digest)
+            .await
+    }
+
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadConversions)")]
+    /// Full-text search over data object titles, descriptions and metadata
+    /// domains and tags. Accepts web-search syntax ("quoted phrases", OR, -exclude)
+    /// and returns results ranked by relevance with highlighted fragments.
+    /// Optional domains and tags narrow the results to matching metadata.
+    pub async fn search_data_objects(
+        &self,
+        context: &Context<'_>,
+        query: String,
+        domains: Option<Vec<String>>,
+        tags: Option<Vec<String>>,
+    ) -> Result<Vec<DataObjectSearchResult>> {
+        get_repositories_from_context(context)
+            .data_objects
+            .search(query, domains, tags, SearchVisibility::default())
+            .await
+    }
+
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadConversions)")]
     /// Return a DataObjectCount by a specific DataObjectDomain (SCIENTIFIC, etc.)
     pub async fn data_object_counts_by_metadata_domain(
         &self,
-        _context: &Context<'_>,
+        context: &Context<'_>,
         domain: String,
     ) -> Result<Vec<DataObject>> {
-        let data_object_ids = Metadata::get_data_object_ids_by_domain(domain)?;
+        let repos = get_repositories_from_context(context);
+
+        let data_object_ids = repos.metadata.get_data_object_ids_by_domain(domain).await?;
 
-        DataObject::get_by_ids(data_object_ids)
+        repos.data_objects.get_by_ids(data_object_ids).await
     }
 
     // DataObjects
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadConversions)")]
     /// Returns vector of all data_objects
-    pub async fn data_objects(&self, _context: &Context<'_>) -> Result<Vec<DataObject>> {
-        DataObject::get_all()
+    pub async fn data_objects(&self, context: &Context<'_>) -> Result<Vec<DataObject>> {
+        get_repositories_from_context(context).data_objects.get_all().await
     }
 }
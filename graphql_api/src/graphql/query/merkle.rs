@@ -0,0 +1,35 @@
+use async_graphql::*;
+use uuid::Uuid;
+
+use crate::common_utils::{Permission, PermissionGuard};
+use crate::graphql::get_repositories_from_context;
+use crate::models::MerkleProof;
+
+#[derive(Default)]
+pub struct MerkleQuery;
+
+#[Object]
+impl MerkleQuery {
+    #[graphql(
+        name = "merkleProof",
+        guard = "PermissionGuard::new(Permission::ReadConversions)",
+    )]
+    /// Returns the inclusion proof of a conversion result's receipt in its
+    /// Merkle anchor, or null until the receipt has been anchored
+    pub async fn merkle_proof(&self, context: &Context<'_>, receipt_id: Uuid) -> Result<Option<MerkleProof>> {
+        let repos = get_repositories_from_context(context);
+
+        // Fails for results of requests outside the caller's scope
+        let result = repos.conversion_results.get_by_id(receipt_id).await?;
+        repos.conversion_requests.get_by_id(result.conversion_request_id).await?;
+
+        let Some(leaf) = repos.merkle_anchors.get_leaf_by_receipt_id(receipt_id).await? else {
+            return Ok(None);
+        };
+
+        let anchor = repos.merkle_anchors.get_by_id(leaf.anchor_id).await?;
+        let leaves = repos.merkle_anchors.get_leaves(leaf.anchor_id).await?;
+
+        MerkleProof::build(receipt_id, anchor, &leaves).map(Some)
+    }
+}
@@ -1,15 +1,24 @@
+mod api_key;
+mod audit;
 mod authority;
 mod classification_schema;
 mod conversion_request;
 mod data_object;
+mod merkle;
 mod nation;
+#[allow(clippy::module_inception)]
 mod query;
+mod security_event;
 mod user_query;
 
+pub use self::api_key::*;
+pub use self::audit::*;
 pub use self::authority::*;
 pub use self::classification_schema::*;
 pub use self::conversion_request::*;
 pub use self::data_object::*;
+pub use self::merkle::*;
 pub use self::nation::*;
 pub use self::query::*;
+pub use self::security_event::*;
 pub use self::user_query::*;
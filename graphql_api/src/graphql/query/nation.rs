@@ -1,42 +1,48 @@
 use async_graphql::*;
 
+use crate::graphql::get_repositories_from_context;
 use crate::models::Nation;
 use uuid::Uuid;
 
-//use crate::common_utils::{RoleGuard, is_admin, UserRole};
+use crate::common_utils::{Permission, PermissionGuard};
 
 #[derive(Default)]
 pub struct NationQuery;
 
 #[Object]
 impl NationQuery {
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns count of Nations in the system
-    pub async fn nation_count(&self, _context: &Context<'_>) -> Result<i64> {
-        let nations = Nation::get_all()?;
+    pub async fn nation_count(&self, context: &Context<'_>) -> Result<i64> {
+        let nations = get_repositories_from_context(context).nations.get_all().await?;
         Ok(nations.len() as i64)
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns a nation by its Uuid
-    pub async fn nation_by_id(&self, _context: &Context<'_>, id: Uuid) -> Result<Nation> {
-        Nation::get_by_id(&id)
+    pub async fn nation_by_id(&self, context: &Context<'_>, id: Uuid) -> Result<Nation> {
+        get_repositories_from_context(context).nations.get_by_id(id).await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns a nation by its nation code
-    pub async fn nation_by_code(&self, _context: &Context<'_>, nation_code: String) -> Result<Nation> {
-        Nation::get_by_code(&nation_code)
+    pub async fn nation_by_code(&self, context: &Context<'_>, nation_code: String) -> Result<Nation> {
+        get_repositories_from_context(context).nations.get_by_code(nation_code).await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns nations by creator ID
     pub async fn nations_by_creator_id(
         &self,
-        _context: &Context<'_>,
+        context: &Context<'_>,
         creator_id: Uuid,
     ) -> Result<Vec<Nation>> {
-        Nation::get_by_creator_id(creator_id)
+        get_repositories_from_context(context).nations.get_by_creator_id(creator_id).await
     }
 
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadReferenceData)")]
     /// Returns vector of all nations
-    pub async fn nations(&self, _context: &Context<'_>) -> Result<Vec<Nation>> {
-        Nation::get_all()
+    pub async fn nations(&self, context: &Context<'_>) -> Result<Vec<Nation>> {
+        get_repositories_from_context(context).nations.get_all().await
     }
 }
@@ -1,12 +1,17 @@
 use async_graphql::*;
 
-use crate::graphql::{ClassificationSchemaQuery, ConversionRequestQuery, DataObjectQuery, NationQuery, query::UserQuery};
+use crate::graphql::{ApiKeyQuery, AuditQuery, AuthorityQuery, ClassificationSchemaQuery, ConversionRequestQuery, DataObjectQuery, MerkleQuery, NationQuery, SecurityEventQuery, query::UserQuery};
 
 #[derive(Default, MergedObject)]
 pub struct Query(
     UserQuery,
     DataObjectQuery,
     NationQuery,
+    AuthorityQuery,
     ClassificationSchemaQuery,
     ConversionRequestQuery,
+    MerkleQuery,
+    ApiKeyQuery,
+    SecurityEventQuery,
+    AuditQuery,
 );
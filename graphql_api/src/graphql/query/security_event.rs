@@ -0,0 +1,50 @@
+use async_graphql::*;
+use chrono::Utc;
+
+use crate::common_utils::{can_manage_users, Permission, PermissionGuard};
+use crate::config_variables::SECURITY_EVENT_LIMIT;
+use crate::graphql::get_repositories_from_context;
+use crate::models::{SecurityEvent, SignInThrottle};
+
+#[derive(Default)]
+pub struct SecurityEventQuery;
+
+#[Object]
+impl SecurityEventQuery {
+    #[graphql(
+        name = "securityEvents",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
+    )]
+    /// Returns the latest sign-in successes, failures, lockouts and unlocks,
+    /// newest first. At most SECURITY_EVENT_LIMIT are returned.
+    pub async fn security_events(&self, context: &Context<'_>, limit: Option<i64>) -> Result<Vec<SecurityEvent>> {
+        let limit = limit.unwrap_or(SECURITY_EVENT_LIMIT).clamp(0, SECURITY_EVENT_LIMIT);
+
+        get_repositories_from_context(context)
+            .security_events
+            .get_recent(limit)
+            .await
+    }
+
+    #[graphql(
+        name = "signInLockouts",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
+    )]
+    /// Returns the emails and client addresses whose sign-ins are currently
+    /// refused, for `unlockSignIn`
+    pub async fn sign_in_lockouts(&self, context: &Context<'_>) -> Result<Vec<SignInThrottle>> {
+        let now = Utc::now().naive_utc();
+
+        let throttles = get_repositories_from_context(context)
+            .sign_in_throttles
+            .get_all()
+            .await?;
+
+        Ok(throttles
+            .into_iter()
+            .filter(|t| t.blocked_until().is_some_and(|until| until > now))
+            .collect())
+    }
+}
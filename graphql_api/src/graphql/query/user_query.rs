@@ -1,13 +1,10 @@
-use diesel::{RunQueryDsl};
-use crate::schema::*;
-
 use async_graphql::*;
 
 use crate::models::{User};
 use uuid::Uuid;
 
-use crate::graphql::{get_connection_from_context};
-use crate::common_utils::{RoleGuard, is_admin, UserRole};
+use crate::graphql::get_repositories_from_context;
+use crate::common_utils::{can_manage_users, Permission, PermissionGuard};
 
 #[derive(Default)]
 pub struct UserQuery;
@@ -19,40 +16,36 @@ impl UserQuery {
 
     #[graphql(
         name = "allUsers",
-        guard = "RoleGuard::new(UserRole::Admin)",
-        visible = "is_admin",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
     )]
     /// Returns a vector of all users
     pub async fn all_users(&self, context: &Context<'_>) -> Result<Vec<User>> {
-        let mut conn = get_connection_from_context(context);
-
-        let res = users::table.load::<User>(&mut conn)?;
-
-        Ok(res)
+        get_repositories_from_context(context).users.get_all().await
     }
 
     #[graphql(
         name = "userByEmail",
-        guard = "RoleGuard::new(UserRole::Admin)",
-        visible = "is_admin",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
     )]
     /// Returns a vector of all users
-    pub async fn user_by_email(&self, _context: &Context<'_>, email: String) -> Result<User> {
+    pub async fn user_by_email(&self, context: &Context<'_>, email: String) -> Result<User> {
 
-        let res = User::get_by_email(&email)?;
+        let res = get_repositories_from_context(context).users.get_by_email(email).await?;
 
         Ok(res)
     }
 
     #[graphql(
         name = "userById",
-        guard = "RoleGuard::new(UserRole::Admin)",
-        visible = "is_admin",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
     )]
     /// Returns a vector of all users
-    pub async fn user_by_id(&self, _context: &Context<'_>, id: Uuid) -> Result<User> {
+    pub async fn user_by_id(&self, context: &Context<'_>, id: Uuid) -> Result<User> {
 
-        let res = User::get_by_id(&id)?;
+        let res = get_repositories_from_context(context).users.get_by_id(id).await?;
 
         Ok(res)
     }
@@ -1,113 +1,118 @@
-use std::sync::Mutex;
-
 use async_graphql::*;
-use futures::{Stream, StreamExt};
-use rdkafka::{Message};
+use futures::Stream;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use crate::common_utils::{Permission, PermissionGuard, UserRole};
+use crate::models::{reauthorize, ApiKeyAuth, ConversionCompleted, SessionId};
+use crate::graphql::get_repositories_from_context;
+use crate::repositories::TenantScope;
+
+/// Number of events a slow subscriber can fall behind before it starts
+/// missing them
+const BROKER_CAPACITY: usize = 256;
 
-// use crate::kafka::create_consumer;
-// use crate::kafka::get_kafka_consumer_group;
-use crate::models::TravelData;
-use crate::models::{Trip, Person};
-use crate::common_utils::{is_analyst};
-use crate::common_utils::{RoleGuard, Role};
+/// In-process fan-out of conversion events to every open subscription.
+/// Cloning shares the same channel.
+#[derive(Clone)]
+pub struct ConversionBroker {
+    sender: broadcast::Sender<ConversionCompleted>,
+}
 
+impl Default for ConversionBroker {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(BROKER_CAPACITY);
+        ConversionBroker { sender }
+    }
+}
+
+impl ConversionBroker {
+    /// Publish an event to current subscribers. Events with no subscribers
+    /// are dropped.
+    pub fn publish(&self, event: ConversionCompleted) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ConversionCompleted> {
+        self.sender.subscribe()
+    }
+}
+
+#[derive(Default)]
 pub struct Subscription;
 
 #[Subscription]
 impl Subscription {
-    /// Subscription service that returns a stream of the latest trips created
-    #[graphql(
-        guard = "RoleGuard::new(Role::Analyst)",
-        visible = "is_analyst",
-    )]
-    async fn latest_trip<'ctx>(
-        &self, 
-        ctx: &'ctx Context<'_>,
-    ) -> impl Stream<Item = Trip> + 'ctx {
-        let kafka_consumer_counter = ctx 
-            .data::<Mutex<i32>>()
-            .expect("Can't get Kafka consumer counter");
-
-        let consumer_group_id = get_kafka_consumer_group(kafka_consumer_counter);
-        let consumer = create_consumer(consumer_group_id, "trips");
-
-        async_stream::stream! {
-            let mut stream = consumer.stream();
-
-            while let Some(value) = stream.next().await {
-                yield match value {
-                    Ok(message) => {
-                        let payload = message.payload().expect("Kafka message shoudl contain payload");
-                        let message = String::from_utf8_lossy(payload).to_string();
-                        serde_json::from_str(&message).expect("Can't deserialize Trip")
-                    }
-                    Err(e) => panic!("Error while Kafka message processing: {}", e)
-                };
+    /// Streams conversion requests as their results are produced. Pass
+    /// `authorityId` to follow the requests an authority submitted and/or
+    /// `nationCode` to follow conversions targeting that nation; an event
+    /// matching either is delivered. With neither, every event is delivered.
+    /// Only requests within the caller's `TenantScope` are ever delivered,
+    /// and only for data objects their clearance lets them read. The stream
+    /// ends with an error once the caller's session ends, their API key is
+    /// revoked or their role changes.
+    #[graphql(guard = "PermissionGuard::new(Permission::ReadConversions)")]
+    async fn conversion_completed(
+        &self,
+        ctx: &Context<'_>,
+        authority_id: Option<Uuid>,
+        nation_code: Option<String>,
+    ) -> Result<impl Stream<Item = Result<ConversionCompleted>> + use<>> {
+        let mut receiver = ctx
+            .data::<ConversionBroker>()
+            .expect("Can't get conversion broker")
+            .subscribe();
+
+        // Events go through the broker rather than the scoped repositories
+        let scope = ctx.data_opt::<TenantScope>().cloned().unwrap_or_default();
+        let repos = get_repositories_from_context(ctx).clone();
+
+        // Websockets authenticate once, so every event re-checks the caller
+        let role = *ctx.data::<UserRole>()?;
+        let user_id = *ctx.data::<Uuid>()?;
+        let session_id = ctx.data_opt::<SessionId>().copied();
+        let api_key = ctx.data_opt::<ApiKeyAuth>().cloned();
+
+        let matches = move |event: &ConversionCompleted| {
+            let request = &event.conversion_request;
+
+            if !scope.allows(request) {
+                return false;
             }
-        }
-    }
 
-    /// Subscription to live person feed
-    #[graphql(
-        //guard = "RoleGuard::new(Role::Analyst)",
-        //visible = "is_analyst",
-    )]
-    async fn latest_person<'ctx>(
-        &self, 
-        ctx: &'ctx Context<'_>,
-    ) -> impl Stream<Item = Person> + 'ctx {
-        let kafka_consumer_counter = ctx 
-            .data::<Mutex<i32>>()
-            .expect("Can't get Kafka consumer counter");
-
-        let consumer_group_id = get_kafka_consumer_group(kafka_consumer_counter);
-        let consumer = create_consumer(consumer_group_id, "people");
-
-        async_stream::stream! {
-            let mut stream = consumer.stream();
-
-            while let Some(value) = stream.next().await {
-                yield match value {
-                    Ok(message) => {
-                        let payload = message.payload().expect("Kafka message shoudl contain payload");
-                        let message = String::from_utf8_lossy(payload).to_string();
-                        serde_json::from_str(&message).expect("Can't deserialize Trip")
-                    }
-                    Err(e) => panic!("Error while Kafka message processing: {}", e)
-                };
+            if authority_id.is_none() && nation_code.is_none() {
+                return true;
             }
-        }
-    }
 
-    /// Subscription to raw ArriveCan feed from PIL
-    #[graphql(
-        //guard = "RoleGuard::new(Role::Analyst)",
-        //visible = "is_analyst",
-    )]
-    async fn latest_traveller<'ctx>(
-        &self, 
-        ctx: &'ctx Context<'_>,
-    ) -> impl Stream<Item = TravelData> + 'ctx {
-        let kafka_consumer_counter = ctx 
-            .data::<Mutex<i32>>()
-            .expect("Can't get Kafka consumer counter");
-
-        let consumer_group_id = get_kafka_consumer_group(kafka_consumer_counter);
-        let consumer = create_consumer(consumer_group_id, "arrivecan_pil");
-
-        async_stream::stream! {
-            let mut stream = consumer.stream();
-
-            while let Some(value) = stream.next().await {
-                yield match value {
-                    Ok(message) => {
-                        let payload = message.payload().expect("Kafka message shoudl contain payload");
-                        let message = String::from_utf8_lossy(payload).to_string();
-                        serde_json::from_str(&message).expect("Can't deserialize ArriveCan PIL message")
+            authority_id == Some(request.authority_id)
+                || nation_code.as_ref().is_some_and(|code| {
+                    request.target_nation_codes.iter().flatten().any(|t| t == code)
+                })
+        };
+
+        Ok(async_stream::stream! {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) if matches(&event) => {
+                        if let Err(e) = reauthorize(&repos, role, user_id, session_id.as_ref(), api_key.as_ref()).await {
+                            yield Err(e);
+                            break;
+                        }
+
+                        let data_object_id = event.conversion_request.data_object_id;
+
+                        // Same clearance as reading the object; errors count as a denial
+                        if let Ok(false) = repos.data_objects.is_redacted(data_object_id).await {
+                            yield Ok(event);
+                        }
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(missed)) => {
+                        println!("conversionCompleted subscriber lagged, {} events skipped", missed);
                     }
-                    Err(e) => panic!("Error while Kafka message processing: {}", e)
-                };
+                    Err(RecvError::Closed) => break,
+                }
             }
-        }
+        })
     }
-}
\ No newline at end of file
+}
@@ -1,12 +1,10 @@
-use std::sync::{Arc, Mutex};
-use crate::database::PostgresPool;
+use std::sync::Mutex;
+use crate::mail::Mailer;
+use crate::repositories::Repositories;
 
 use async_graphql::*;
-use diesel::{PgConnection};
-use diesel::r2d2::ConnectionManager;
-use r2d2::PooledConnection;
 
-use crate::graphql::{Mutation, query::Query}; // Removed Subscription
+use crate::graphql::{ConversionBroker, Mutation, Subscription, query::Query};
 
 // use crate::kafka::{create_producer};
 
@@ -17,13 +15,9 @@ pub fn graphql_translate<T>(res: Result<T, diesel::result::Error>) -> FieldResul
     }
 }
 
-pub type AppSchema = Schema<Query, Mutation, EmptySubscription>;
+pub type AppSchema = Schema<Query, Mutation, Subscription>;
 
-pub fn create_schema_with_context(pg_pool: PostgresPool) -> async_graphql::Schema<Query, Mutation, EmptySubscription> {
-    
-    //let cloned_conn = pg_pool.clone().get().expect("Unable to connect to db");
-    
-    let arc_pool = Arc::new(pg_pool);
+pub fn create_schema_with_context(repos: Repositories) -> AppSchema {
 
     /*
     let countries = Arc::new(Mutex::new(Country::load_into_hash(&cloned_conn)));
@@ -34,9 +28,13 @@ pub fn create_schema_with_context(pg_pool: PostgresPool) -> async_graphql::Schem
 
     let kafka_consumer_counter = Mutex::new(0);
     
-    Schema::build(Query::default(), Mutation::default(), EmptySubscription)
-        // Database connection
-        .data(arc_pool)
+    Schema::build(Query::default(), Mutation::default(), Subscription)
+        // Storage
+        .data(repos)
+        // Conversion events for subscriptions
+        .data(ConversionBroker::default())
+        // Outbound email
+        .data(Mailer::from_env())
         // Live cached data -> may want to remove once dataloaders in place
         /*
         .data(countries)
@@ -50,11 +48,7 @@ pub fn create_schema_with_context(pg_pool: PostgresPool) -> async_graphql::Schem
         .finish()
 }
 
-type Conn = PooledConnection<ConnectionManager<PgConnection>>;
-
-pub fn get_connection_from_context(ctx: &Context<'_>) -> Conn {
-    ctx.data::<Arc<PostgresPool>>()
-        .expect("Can't get pool")
-        .get()
-        .expect("Can't get DB connection")
-}
\ No newline at end of file
+pub fn get_repositories_from_context<'a>(ctx: &Context<'a>) -> &'a Repositories {
+    ctx.data::<Repositories>()
+        .expect("Can't get repositories")
+}
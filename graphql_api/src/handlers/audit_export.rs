@@ -0,0 +1,138 @@
+use std::str::FromStr;
+
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use bytes::Bytes;
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::common_utils::Permission;
+use crate::config_variables::AUDIT_EXPORT_BATCH;
+use crate::handlers::endpoints::{authorize_http, json_error};
+use crate::models::{
+    ApiScope, AuditAction, AuditEntity, AuditExportFormat, AuditExportWriter, AuditFilter, AuditPage,
+    NewAuditEntry,
+};
+use crate::repositories::Repositories;
+
+fn bad_request(message: String) -> HttpResponse {
+    json_error(StatusCode::BAD_REQUEST, message)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// Query string of `/audit/export`: the `AuditFilter` fields by their GraphQL
+/// names, and the format, NDJSON unless `format=csv`
+pub struct AuditExportQuery {
+    format: Option<String>,
+    actor_id: Option<Uuid>,
+    action: Option<String>,
+    entity_type: Option<String>,
+    entity_id: Option<Uuid>,
+    nation_code: Option<String>,
+    occurred_from: Option<NaiveDateTime>,
+    occurred_until: Option<NaiveDateTime>,
+}
+
+impl AuditExportQuery {
+    fn parse(self) -> Result<(AuditExportFormat, AuditFilter), String> {
+        fn parse_enum<T: FromStr>(name: &str, value: Option<String>) -> Result<Option<T>, String> {
+            value
+                .map(|v| T::from_str(&v).map_err(|_| format!("Unknown {} {}", name, v)))
+                .transpose()
+        }
+
+        let format = parse_enum("format", self.format)?.unwrap_or(AuditExportFormat::Ndjson);
+
+        let filter = AuditFilter {
+            actor_id: self.actor_id,
+            action: parse_enum::<AuditAction>("action", self.action)?,
+            entity_type: parse_enum::<AuditEntity>("entity type", self.entity_type)?,
+            entity_id: self.entity_id,
+            nation_code: self.nation_code,
+            occurred_from: self.occurred_from,
+            occurred_until: self.occurred_until,
+        };
+
+        Ok((format, filter))
+    }
+}
+
+/// Streams the audit entries matching the query string as a signed NDJSON or
+/// CSV bundle (see `models/audit_export.rs`). Entries are read a page at a
+/// time, so the log is never held in memory. The export is itself audited,
+/// and covers the entries before its own audit entry.
+pub async fn audit_export(
+    repos: web::Data<Repositories>,
+    http_request: HttpRequest,
+    params: web::Query<AuditExportQuery>,
+) -> HttpResponse {
+    let signed_out = "Sign in to export the audit log";
+    let user_id = match authorize_http(&http_request, &repos, Permission::ReadAuditLog, ApiScope::Read, signed_out).await {
+        Ok((_, user_id, _)) => user_id,
+        Err(response) => return response,
+    };
+
+    let (format, filter) = match params.into_inner().parse() {
+        Ok(parsed) => parsed,
+        Err(message) => return bad_request(message),
+    };
+
+    let mut entry = NewAuditEntry::new(Some(user_id), AuditAction::AuditLogExported, AuditEntity::AuditLog, None);
+    entry.detail = serde_json::json!({ "format": format.to_string(), "filter": filter.describe() });
+
+    let export_sequence = match repos.audit_log.append(vec![entry]).await {
+        Ok(appended) => appended.first().map(|e| e.sequence),
+        Err(e) => {
+            println!("Unable to audit an audit log export: {:?}", e);
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Unable to record the export in the audit log");
+        }
+    };
+
+    let repos = Repositories::clone(&repos);
+
+    let body = async_stream::stream! {
+        let mut writer = AuditExportWriter::new(format);
+        yield Ok::<_, actix_web::Error>(Bytes::from(writer.header()));
+
+        let mut after_sequence = None;
+
+        loop {
+            let page = AuditPage {
+                after_sequence,
+                before_sequence: export_sequence,
+                limit: AUDIT_EXPORT_BATCH,
+                newest_first: false,
+            };
+
+            let entries = match repos.audit_log.search(filter.clone(), page).await {
+                Ok(entries) => entries,
+                Err(e) => {
+                    // Ends the response without a trailer, so the export won't verify
+                    println!("Unable to read the audit log for an export: {:?}", e);
+                    yield Err(actix_web::error::ErrorInternalServerError(e.message));
+                    return;
+                }
+            };
+
+            let Some(last) = entries.last() else {
+                break;
+            };
+            after_sequence = Some(last.sequence);
+
+            let chunk: Vec<u8> = entries.iter().flat_map(|entry| writer.entry(entry)).collect();
+            yield Ok(Bytes::from(chunk));
+        }
+
+        yield Ok(Bytes::from(writer.finish(&filter)));
+    };
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"audit-export.{}\"", format),
+        ))
+        .streaming(body)
+}
@@ -4,7 +4,7 @@ use actix_web::{web, get, HttpResponse, HttpRequest, Responder};
 use tera::Context;
 
 use crate::AppData;
-use crate::database::PostgresPool;
+use crate::repositories::Repositories;
 
 #[get("/")]
 pub async fn index(data: web::Data<AppData>, _req:HttpRequest) -> impl Responder {
@@ -25,7 +25,7 @@ pub async fn org_chart(data: web::Data<AppData>, _req:HttpRequest) -> impl Respo
 #[get("/{lang}/api")]
 pub async fn api_base(
     data: web::Data<AppData>,
-    _pool: web::Data<PostgresPool>,
+    _repos: web::Data<Repositories>,
     _lang: web::Path<String>,
     _req: HttpRequest,
     // id: Identity,
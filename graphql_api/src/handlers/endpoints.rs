@@ -1,13 +1,37 @@
+use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse, HttpRequest, Result};
 use async_graphql::http::{GraphiQLSource};
-use async_graphql::Schema;
+use async_graphql::{Data, Pos, Response, Schema};
+use jsonwebtoken::errors::ErrorKind;
+use lazy_static::lazy_static;
 
 use async_graphql_actix_web::{GraphQLSubscription,
     GraphQLRequest, GraphQLResponse};
 
-use crate::models;
+use crate::common_utils::{forbidden, unauthenticated};
+use crate::models::{self, ApiKeyAuth, ApiScope, ClientIp};
 use crate::graphql::{AppSchema};
+use crate::common_utils::{Permission, UserRole};
+use crate::repositories::{Clearance, Repositories, TenantScope};
+use uuid::Uuid;
 
+lazy_static! {
+    /// Whether to take client addresses from Forwarded / X-Forwarded-For,
+    /// from TRUST_PROXY_HEADERS. Only set it behind a proxy that overwrites
+    /// them, or clients can pick their own address.
+    static ref TRUST_PROXY_HEADERS: bool = std::env::var("TRUST_PROXY_HEADERS")
+        .is_ok_and(|v| v == "true" || v == "1");
+}
+
+/// Address of the client, for sign-in throttling and security events
+pub fn client_ip(http_request: &HttpRequest) -> Option<ClientIp> {
+    let ip = match *TRUST_PROXY_HEADERS {
+        true => http_request.connection_info().realip_remote_addr().map(str::to_owned),
+        false => http_request.peer_addr().map(|addr| addr.ip().to_string()),
+    };
+
+    ip.map(ClientIp)
+}
 
 pub async fn playground_handler() -> HttpResponse {
     HttpResponse::Ok()
@@ -15,35 +39,240 @@ pub async fn playground_handler() -> HttpResponse {
         .body(GraphiQLSource::build().endpoint("/graphql").finish())
 }
 
+/// Refuses a whole request whose credentials were presented but not accepted
+fn reject(error: async_graphql::Error) -> GraphQLResponse {
+    let mut error = error.into_server_error(Pos::default());
+
+    // Not caused by any one place in the query
+    error.locations.clear();
+
+    Response::from_errors(vec![error]).into()
+}
+
+fn token_error_message(e: &jsonwebtoken::errors::Error) -> &'static str {
+    match e.kind() {
+        ErrorKind::ExpiredSignature => "Access token has expired",
+        _ => "Invalid access token",
+    }
+}
+
+/// Repositories limited to what an authenticated caller may see and auditing
+/// what they do, with the tenant scope and clearance they were limited to.
+/// Callers whose account is not approved, including the service users of API
+/// keys, are refused.
+pub(super) async fn caller_data(
+    repos: &Repositories,
+    role: UserRole,
+    user_id: Uuid,
+    api_key: Option<&ApiKeyAuth>,
+) -> async_graphql::Result<(Repositories, TenantScope, Clearance)> {
+    let user = repos.users.get_by_id(user_id).await?;
+    user.ensure_approved()?;
+
+    let scope = TenantScope::for_caller(repos, role, user_id, api_key).await?;
+    let clearance = Clearance::for_user(&user);
+
+    Ok((repos.scoped(&scope, &clearance).audited(Some(user_id)), scope, clearance))
+}
+
+/// A JSON `{"error": ...}` response, for endpoints outside GraphQL
+pub(super) fn json_error(status: StatusCode, message: impl Into<String>) -> HttpResponse {
+    HttpResponse::build(status).json(serde_json::json!({ "error": message.into() }))
+}
+
+/// The caller behind a bearer token, or an API key with `scope`, for
+/// endpoints outside GraphQL, if they are approved and hold `permission`.
+/// `signed_out` is the error for requests without credentials.
+pub(super) async fn authorize_http(
+    http_request: &HttpRequest,
+    repos: &Repositories,
+    permission: Permission,
+    scope: ApiScope,
+    signed_out: &str,
+) -> std::result::Result<(UserRole, Uuid, Option<ApiKeyAuth>), HttpResponse> {
+    let (role, user_id, api_key) = match models::get_api_key_claim(http_request, repos).await {
+        Some(Ok((role, user_id, _, api_key))) => {
+            if !api_key.scopes.contains(&scope) {
+                return Err(json_error(StatusCode::FORBIDDEN, format!("API key lacks the {} scope", scope)));
+            }
+            (role, user_id, Some(api_key))
+        }
+        Some(Err(_)) => return Err(json_error(StatusCode::UNAUTHORIZED, "Invalid, expired or revoked API key")),
+        None => match models::get_claim(http_request.clone(), repos).await {
+            Ok(Some((role, user_id, _, _))) => (role, user_id, None),
+            Ok(None) => return Err(json_error(StatusCode::UNAUTHORIZED, signed_out)),
+            Err(_) => return Err(json_error(StatusCode::UNAUTHORIZED, "Invalid or expired token")),
+        },
+    };
+
+    let approved = repos
+        .users
+        .get_by_id(user_id)
+        .await
+        .and_then(|user| user.ensure_approved());
+
+    if let Err(e) = approved {
+        return Err(json_error(StatusCode::FORBIDDEN, e.message));
+    }
+
+    if !role.has_permission(permission) {
+        return Err(json_error(StatusCode::FORBIDDEN, format!("Missing the {} permission", permission)));
+    }
+
+    Ok((role, user_id, api_key))
+}
+
 pub async fn graphql(
     schema: web::Data<AppSchema>,
+    repos: web::Data<Repositories>,
     http_request: HttpRequest,
     req: GraphQLRequest,
 ) -> GraphQLResponse {
     
     let mut query = req.into_inner();
 
-    let maybe_role_id = models::get_claim(http_request);
+    if let Some(ip) = client_ip(&http_request) {
+        query = query.data(ip);
+    }
+
+    // Machine clients authenticate with an API key instead of a bearer token
+    if let Some(maybe_key) = models::get_api_key_claim(&http_request, &repos).await {
+        let Ok((role, uuid, exp_time, api_key)) = maybe_key else {
+            return reject(unauthenticated("Invalid, expired or revoked API key"));
+        };
+
+        let required = ApiScope::required_for(&query.query, query.operation_name.as_deref());
 
-    // insert claim data into query or error for response
+        let denied = match required {
+            Ok(scopes) => scopes
+                .into_iter()
+                .find(|s| !api_key.scopes.contains(s))
+                .map(|s| format!("API key lacks the {} scope", s)),
+            Err(e) => Some(e),
+        };
+
+        if let Some(message) = denied {
+            return reject(forbidden(message));
+        }
+
+        let (caller_repos, scope, clearance) = match caller_data(&repos, role, uuid, Some(&api_key)).await {
+            Ok(data) => data,
+            Err(e) => return reject(e),
+        };
+
+        query = query.data(caller_repos);
+        query = query.data(scope);
+        query = query.data(clearance);
+        query = query.data(role);
+        query = query.data(uuid);
+        query = query.data(exp_time);
+        query = query.data(api_key);
+
+        return schema.execute(query).await.into();
+    }
+
+    let maybe_role_id = models::get_claim(http_request, &repos).await;
+
+    // insert claim data into query, run anonymously or refuse a bad token
     match maybe_role_id {
-        Ok((role, uuid, exp_time)) => {
+        Ok(Some((role, uuid, exp_time, session_id))) => {
+            let (caller_repos, scope, clearance) = match caller_data(&repos, role, uuid, None).await {
+                Ok(data) => data,
+                Err(e) => return reject(e),
+            };
+
+            query = query.data(caller_repos);
+            query = query.data(scope);
+            query = query.data(clearance);
             query = query.data(role);
             query = query.data(uuid);
-            query = query.data(exp_time)
+            query = query.data(exp_time);
+            query = query.data(session_id)
         },
+        Ok(None) => {},
         Err(e) => {
-            query = query.data(e);
+            return reject(unauthenticated(token_error_message(&e)));
         }
     };
 
     schema.execute(query).await.into()
 }
 
+/// Public keys for verifying our access tokens, for partner nodes
+pub async fn jwks() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(models::public_jwks())
+}
+
+/// Public keys for verifying conversion receipts, for partner nations
+pub async fn receipt_keys() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(models::receipt_public_keys())
+}
+
 pub async fn graphql_ws(
     schema: web::Data<AppSchema>,
+    repos: web::Data<Repositories>,
     req: HttpRequest,
     payload: web::Payload,
 ) -> Result<HttpResponse> {
-    GraphQLSubscription::new(Schema::clone(&*schema)).start(&req, payload)
+    let repos = Repositories::clone(&repos);
+
+    GraphQLSubscription::new(Schema::clone(&*schema))
+        .on_connection_init(move |init| connection_data(init, repos))
+        .start(&req, payload)
+}
+
+/// Authenticates a websocket from its connection_init payload, which carries
+/// `Authorization` or `X-Api-Key` like an HTTP request would. Sockets
+/// without either stay anonymous; bad credentials close the socket.
+async fn connection_data(init: serde_json::Value, repos: Repositories) -> async_graphql::Result<Data> {
+    let mut data = Data::default();
+
+    let entry = |name: &str| {
+        init.as_object()?
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .and_then(|(_, v)| v.as_str())
+    };
+
+    if let Some(key) = entry("X-Api-Key") {
+        let (role, uuid, exp_time, api_key) = models::verify_api_key(key, &repos)
+            .await
+            .map_err(|_| unauthenticated("Invalid, expired or revoked API key"))?;
+
+        if !api_key.scopes.contains(&ApiScope::Read) {
+            return Err(forbidden(format!("API key lacks the {} scope", ApiScope::Read)));
+        }
+
+        let (caller_repos, scope, clearance) = caller_data(&repos, role, uuid, Some(&api_key)).await?;
+
+        data.insert(caller_repos);
+        data.insert(scope);
+        data.insert(clearance);
+        data.insert(role);
+        data.insert(uuid);
+        data.insert(exp_time);
+        data.insert(api_key);
+    } else if let Some(header) = entry("Authorization") {
+        let jwt = header.strip_prefix("Bearer ").unwrap_or(header);
+
+        let (role, uuid, exp_time, session_id) = models::verify_token(jwt, &repos)
+            .await
+            .map_err(|e| unauthenticated(token_error_message(&e)))?;
+
+        let (caller_repos, scope, clearance) = caller_data(&repos, role, uuid, None).await?;
+
+        data.insert(caller_repos);
+        data.insert(scope);
+        data.insert(clearance);
+        data.insert(role);
+        data.insert(uuid);
+        data.insert(exp_time);
+        data.insert(session_id);
+    }
+
+    Ok(data)
 }
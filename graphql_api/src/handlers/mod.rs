@@ -1,8 +1,14 @@
 mod base;
 mod routes;
 mod endpoints;
+mod oidc;
+mod audit_export;
+mod upload;
 
 pub use self::routes::configure_services;
 
 pub use self::base::{index, api_base, org_chart};
-pub use self::endpoints::*;
\ No newline at end of file
+pub use self::endpoints::*;
+pub use self::oidc::{oidc_login, oidc_callback};
+pub use self::audit_export::audit_export;
+pub use self::upload::upload_data_object;
\ No newline at end of file
@@ -0,0 +1,142 @@
+use std::str::FromStr;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use async_graphql::{Error, Result};
+use serde::Deserialize;
+
+use crate::common_utils::{forbidden, UserRole};
+
+use crate::graphql::{begin_sign_in, SignInResult};
+use crate::handlers::client_ip;
+use crate::models::{
+    record_security_event, InsertableUser, NewSecurityEvent, OidcIdentity, OidcProvider, SecurityEventType, User,
+    SSO_ONLY_HASH,
+};
+use crate::repositories::Repositories;
+
+#[derive(Deserialize)]
+pub struct OidcCallback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+fn not_configured() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({ "error": "OIDC sign-in is not configured" }))
+}
+
+fn unauthorized(message: String) -> HttpResponse {
+    HttpResponse::Unauthorized().json(serde_json::json!({ "error": message }))
+}
+
+/// 403 for FORBIDDEN errors, 500 for anything else
+fn error_response(e: Error) -> HttpResponse {
+    let forbidden = e
+        .extensions
+        .as_ref()
+        .and_then(|x| x.get("code"))
+        .is_some_and(|code| *code == async_graphql::Value::from("FORBIDDEN"));
+
+    let body = serde_json::json!({ "error": e.message });
+
+    match forbidden {
+        true => HttpResponse::Forbidden().json(body),
+        false => HttpResponse::InternalServerError().json(body),
+    }
+}
+
+/// Redirects the browser to the IdP to start a single sign-on
+pub async fn oidc_login(oidc: Option<web::Data<OidcProvider>>) -> HttpResponse {
+    match oidc {
+        Some(oidc) => HttpResponse::Found()
+            .insert_header(("Location", oidc.authorization_url()))
+            .finish(),
+        None => not_configured(),
+    }
+}
+
+/// IdP redirect target. Signs the user in, provisioning them on first use, and
+/// returns what the `signIn` mutation would: the token pair, or a challenge
+/// to complete with `verifyMfa` when the user has or needs TOTP.
+pub async fn oidc_callback(
+    oidc: Option<web::Data<OidcProvider>>,
+    repos: web::Data<Repositories>,
+    query: web::Query<OidcCallback>,
+    http_request: HttpRequest,
+) -> HttpResponse {
+    let Some(oidc) = oidc else {
+        return not_configured();
+    };
+
+    let (code, state) = match (&query.code, &query.state, &query.error) {
+        (Some(code), Some(state), None) => (code, state),
+        (_, _, Some(error)) => return unauthorized(format!("IdP refused sign-in: {}", error)),
+        _ => return unauthorized("Missing code or state".to_owned()),
+    };
+
+    let identity = match oidc.complete_login(code, state).await {
+        Ok(identity) => identity,
+        Err(e) => return unauthorized(e.message),
+    };
+
+    let signed_in = match provision_user(&repos, identity).await {
+        Ok(user) => {
+            // First sign-ins are provisioned as PENDING until an admin approves them
+            if let Err(e) = user.ensure_approved() {
+                return error_response(e);
+            }
+
+            let event = NewSecurityEvent::new(
+                SecurityEventType::SignInSucceeded,
+                Some(user.id),
+                Some(user.email.clone()),
+                client_ip(&http_request).map(|ip| ip.0),
+                Some("OIDC".to_owned()),
+            );
+
+            let user_id = user.id;
+            let result = begin_sign_in(&repos, user).await;
+
+            // With a second factor the sign-in succeeds in `verifyMfa`
+            if let Ok(SignInResult::Authenticated(_)) = result {
+                record_security_event(&repos, Some(user_id), event).await;
+            }
+
+            result
+        }
+        Err(e) => Err(e),
+    };
+
+    match signed_in {
+        Ok(SignInResult::Authenticated(tokens)) => HttpResponse::Ok().json(tokens),
+        Ok(SignInResult::MfaChallenge(challenge)) => HttpResponse::Ok().json(challenge),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Finds the user for an IdP identity, creating them on first sign-in. Only
+/// accounts that were provisioned by single sign-on are linked; a password
+/// account with the same email is refused. Role and clearance follow the IdP
+/// down but never up, as raising them on an existing account is left to an
+/// administrator. The nationality is only taken on provisioning.
+async fn provision_user(repos: &Repositories, identity: OidcIdentity) -> Result<User> {
+    let mut user = match repos.users.get_by_email(identity.email.clone()).await {
+        Ok(user) => user,
+        Err(_) => return repos.users.create(InsertableUser::from(identity)).await,
+    };
+
+    if user.hash != SSO_ONLY_HASH {
+        return Err(forbidden("This account signs in with a password"));
+    }
+
+    let role = UserRole::from_str(&user.role).map_or(identity.role, |role| role.min(identity.role));
+    let access_level = user.clearance().min(identity.access_level);
+
+    if user.role == role.to_string() && user.access_level == access_level.to_string() {
+        return Ok(user);
+    }
+
+    user.role = role.to_string();
+    user.access_level = access_level.to_string();
+    repos.users.update(user).await
+}
@@ -8,6 +8,12 @@ use crate::handlers::{
     playground_handler,
     graphql,
     graphql_ws,
+    jwks,
+    receipt_keys,
+    oidc_login,
+    oidc_callback,
+    audit_export,
+    upload_data_object,
 };
 
 pub fn configure_services(config: &mut web::ServiceConfig) {
@@ -19,6 +25,17 @@ pub fn configure_services(config: &mut web::ServiceConfig) {
     // Playground
     config.route("/playground", web::post().to(graphql));
     config.route("/playground", web::get().to(playground_handler));
+    // Token verification keys
+    config.route("/.well-known/jwks.json", web::get().to(jwks));
+    // Conversion receipt verification keys
+    config.route("/.well-known/receipt-keys.json", web::get().to(receipt_keys));
+    // Single sign-on
+    config.route("/auth/oidc/login", web::get().to(oidc_login));
+    config.route("/auth/oidc/callback", web::get().to(oidc_callback));
+    // Signed audit log exports
+    config.route("/audit/export", web::get().to(audit_export));
+    // Document uploads
+    config.route("/data-objects/upload", web::post().to(upload_data_object));
     // Websocket
     config.service(
         web::resource("/graphql")
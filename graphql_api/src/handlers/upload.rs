@@ -0,0 +1,290 @@
+use std::env;
+
+use actix_multipart::{Field, Multipart};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::blob_store::{is_executable, sniff_media_type, BlobStore, BlobWriter, SNIFF_LEN};
+use crate::common_utils::Permission;
+use crate::config_variables::{UPLOAD_FIELD_LIMIT, UPLOAD_MAX_BYTES, UPLOAD_MAX_FIELDS};
+use crate::handlers::endpoints::{authorize_http, caller_data, json_error};
+use crate::models::{ApiScope, DigestAlgorithm, InsertableMetadata, NewDataObject};
+use crate::repositories::Repositories;
+
+fn bad_request(message: impl Into<String>) -> HttpResponse {
+    json_error(StatusCode::BAD_REQUEST, message)
+}
+
+/// A document written to the blob store, not yet recorded
+struct StoredFile {
+    size: u64,
+    sha256: String,
+    media_type: &'static str,
+}
+
+/// The fields of an upload form
+#[derive(Default)]
+struct UploadForm {
+    title: Option<String>,
+    description: Option<String>,
+    domain: Option<String>,
+    tags: Vec<String>,
+    file: Option<StoredFile>,
+}
+
+/// Largest document accepted, UPLOAD_MAX_BYTES unless the variable is set
+fn max_upload_bytes() -> u64 {
+    env::var("UPLOAD_MAX_BYTES")
+        .ok()
+        .map(|s| s.parse().expect("Unable to parse UPLOAD_MAX_BYTES"))
+        .unwrap_or(UPLOAD_MAX_BYTES)
+}
+
+/// Reads a text field, up to UPLOAD_FIELD_LIMIT bytes
+async fn read_text(field: &mut Field, name: &str) -> Result<String, HttpResponse> {
+    let mut bytes = Vec::new();
+
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(|e| bad_request(format!("Unable to read the {} field: {}", name, e)))?;
+
+        if bytes.len() + chunk.len() > UPLOAD_FIELD_LIMIT {
+            return Err(json_error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("The {} field is over {} bytes", name, UPLOAD_FIELD_LIMIT),
+            ));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    let text = String::from_utf8(bytes).map_err(|_| bad_request(format!("The {} field is not UTF-8", name)))?;
+
+    Ok(text.trim().to_owned())
+}
+
+/// Streams the file field into `writer`, hashing it and telling its type
+/// from its first bytes on the way
+async fn write_file(field: &mut Field, writer: &mut dyn BlobWriter, max_bytes: u64) -> Result<StoredFile, HttpResponse> {
+    let mut hasher = Sha256::new();
+    let mut size: u64 = 0;
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    let mut media_type = None;
+
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(|e| bad_request(format!("Unable to read the file: {}", e)))?;
+
+        size += chunk.len() as u64;
+        if size > max_bytes {
+            return Err(json_error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Documents may be at most {} bytes", max_bytes),
+            ));
+        }
+
+        if media_type.is_none() {
+            let wanted = (SNIFF_LEN - head.len()).min(chunk.len());
+            head.extend_from_slice(&chunk[..wanted]);
+
+            if head.len() == SNIFF_LEN {
+                media_type = Some(sniff_media_type(&head));
+            }
+        }
+
+        if media_type.is_some_and(is_executable) {
+            return Err(json_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Executables can't be uploaded"));
+        }
+
+        hasher.update(&chunk);
+        writer.write(&chunk).await.map_err(|e| {
+            println!("Unable to write an upload to the blob store: {:?}", e);
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "Unable to store the document")
+        })?;
+    }
+
+    if size == 0 {
+        return Err(bad_request("The file is empty"));
+    }
+
+    let media_type = media_type.unwrap_or_else(|| sniff_media_type(&head));
+    if is_executable(media_type) {
+        return Err(json_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Executables can't be uploaded"));
+    }
+
+    Ok(StoredFile {
+        size,
+        sha256: format!("{:x}", hasher.finalize()),
+        media_type,
+    })
+}
+
+/// Reads the form into `form`, storing the file under `key`. On an error
+/// after the file was stored, `form.file` is set and the blob is left for
+/// the caller to delete.
+async fn read_form(
+    payload: &mut Multipart,
+    store: &dyn BlobStore,
+    key: &str,
+    form: &mut UploadForm,
+) -> Result<(), HttpResponse> {
+    let max_bytes = max_upload_bytes();
+    // Each text field is capped, so capping their number bounds the form
+    let mut text_fields = 0;
+
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|e| bad_request(format!("Malformed multipart body: {}", e)))?;
+        let name = field.name().unwrap_or_default().to_owned();
+
+        if name != "file" {
+            text_fields += 1;
+
+            if text_fields > UPLOAD_MAX_FIELDS {
+                return Err(json_error(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("Forms may have at most {} text fields", UPLOAD_MAX_FIELDS),
+                ));
+            }
+        }
+
+        match name.as_str() {
+            "title" => form.title = Some(read_text(&mut field, &name).await?),
+            "description" => form.description = Some(read_text(&mut field, &name).await?),
+            "domain" => form.domain = Some(read_text(&mut field, &name).await?),
+            // Repeated, or comma separated
+            "tags" => form.tags.extend(
+                read_text(&mut field, &name)
+                    .await?
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::to_owned),
+            ),
+            "file" => {
+                if form.file.is_some() {
+                    return Err(bad_request("Upload one file at a time"));
+                }
+
+                let mut writer = store.create(key).await.map_err(|e| {
+                    println!("Unable to start an upload to the blob store: {:?}", e);
+                    json_error(StatusCode::INTERNAL_SERVER_ERROR, "Unable to store the document")
+                })?;
+
+                let stored = match write_file(&mut field, writer.as_mut(), max_bytes).await {
+                    Ok(stored) => stored,
+                    Err(response) => {
+                        writer.abort().await;
+                        return Err(response);
+                    }
+                };
+
+                writer.commit().await.map_err(|e| {
+                    println!("Unable to commit an upload to the blob store: {:?}", e);
+                    json_error(StatusCode::INTERNAL_SERVER_ERROR, "Unable to store the document")
+                })?;
+
+                form.file = Some(stored);
+            }
+            other => return Err(bad_request(format!("Unknown field {}", other))),
+        }
+    }
+
+    Ok(())
+}
+
+async fn discard(store: &dyn BlobStore, key: &str) {
+    if let Err(e) = store.delete(key).await {
+        println!("Unable to delete unrecorded upload {}: {:?}", key, e);
+    }
+}
+
+/// Uploads a document as `multipart/form-data` and records it as a data
+/// object with its metadata, for a later `submitConversionRequest` to refer
+/// to by `dataObjectId`. Fields are `title` and `domain` (required),
+/// `description`, `tags` (repeated or comma separated) and a single `file`,
+/// with at most UPLOAD_MAX_FIELDS text fields in all.
+///
+/// The file is streamed into the blob store, hashed with SHA-256 as it
+/// arrives, and refused once it grows past UPLOAD_MAX_BYTES. Its media type
+/// is sniffed from its first bytes; what the client declares is ignored.
+/// Callers need the SubmitConversions permission, and API keys the
+/// SubmitConversions scope.
+pub async fn upload_data_object(
+    repos: web::Data<Repositories>,
+    blob_store: Option<web::Data<dyn BlobStore>>,
+    http_request: HttpRequest,
+    mut payload: Multipart,
+) -> HttpResponse {
+    let Some(store) = blob_store else {
+        return json_error(StatusCode::NOT_FOUND, "Uploads are not configured");
+    };
+    let store = store.get_ref();
+
+    let signed_out = "Sign in to upload documents";
+    let (role, user_id, api_key) = match authorize_http(
+        &http_request,
+        &repos,
+        Permission::SubmitConversions,
+        ApiScope::SubmitConversions,
+        signed_out,
+    )
+    .await
+    {
+        Ok(caller) => caller,
+        Err(response) => return response,
+    };
+
+    let repos = match caller_data(&repos, role, user_id, api_key.as_ref()).await {
+        Ok((repos, _, _)) => repos,
+        Err(e) => return json_error(StatusCode::FORBIDDEN, e.message),
+    };
+
+    let key = Uuid::new_v4().to_string();
+    let mut form = UploadForm::default();
+
+    if let Err(response) = read_form(&mut payload, store, &key, &mut form).await {
+        if form.file.is_some() {
+            discard(store, &key).await;
+        }
+        return response;
+    }
+
+    let Some(file) = form.file else {
+        return bad_request("Missing the file field");
+    };
+
+    let (Some(title), Some(domain)) = (form.title.filter(|t| !t.is_empty()), form.domain.filter(|d| !d.is_empty()))
+    else {
+        discard(store, &key).await;
+        return bad_request("title and domain are required");
+    };
+
+    let data_object = NewDataObject {
+        content_digest: Some(file.sha256.clone()),
+        digest_algorithm: Some(DigestAlgorithm::Sha256.to_string()),
+        content_size: Some(file.size as i64),
+        storage_key: Some(key.clone()),
+        media_type: Some(file.media_type.to_owned()),
+        ..NewDataObject::new(user_id, title, form.description.unwrap_or_default())
+    };
+
+    let metadata = InsertableMetadata {
+        domain,
+        tags: form.tags.into_iter().map(Some).collect(),
+    };
+
+    match repos.data_objects.create_with_metadata(data_object, metadata).await {
+        Ok((data_object, _)) => HttpResponse::Created().json(serde_json::json!({
+            "dataObjectId": data_object.id,
+            "contentDigest": file.sha256,
+            "digestAlgorithm": DigestAlgorithm::Sha256,
+            "contentSize": file.size,
+            "mediaType": file.media_type,
+        })),
+        Err(e) => {
+            println!("Unable to record an upload: {:?}", e);
+            discard(store, &key).await;
+            bad_request(e.message)
+        }
+    }
+}
@@ -13,6 +13,7 @@ extern crate shrinkwraprs;
 extern crate strum_macros;
 
 use tera::{Tera};
+use tera_text_filters::snake_case;
 
 use diesel::prelude::*;
 use diesel::r2d2::{self, ConnectionManager};
@@ -21,17 +22,32 @@ pub mod models;
 pub mod handlers;
 pub mod progress;
 pub mod database;
+pub mod repositories;
 pub mod database_utils;
 pub mod schema;
 pub mod graphql;
 pub mod common_utils;
 pub mod config_variables;
+pub mod mail;
+pub mod anchoring;
+pub mod blob_store;
 //pub mod kafka;
 
 pub struct AppData {
     pub tmpl: Tera
 }
 
+/// Loads the page and email templates under `templates/`
+pub fn load_templates() -> Tera {
+    let mut tera = Tera::new("templates/**/*").unwrap();
+
+    tera.register_filter("snake_case", snake_case);
+    tera.full_reload()
+        .expect("Error running auto reload with Tera");
+
+    tera
+}
+
 pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
 
 // Old code for caching common data. Might need this for skills
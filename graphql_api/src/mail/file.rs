@@ -0,0 +1,36 @@
+use std::fs;
+use std::path::Path;
+
+use async_graphql::{Error, Result};
+use async_trait::async_trait;
+use lettre::{AsyncFileTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::mail::MailTransport;
+
+/// Writes each message to a directory as `<uuid>.eml` instead of sending it
+pub struct FileDropTransport {
+    inner: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl FileDropTransport {
+    /// Creates `dir` if it doesn't exist
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+
+        Ok(FileDropTransport {
+            inner: AsyncFileTransport::new(dir),
+        })
+    }
+}
+
+#[async_trait]
+impl MailTransport for FileDropTransport {
+    async fn send(&self, message: Message) -> Result<()> {
+        self.inner
+            .send(message)
+            .await
+            .map_err(|e| Error::new(format!("Unable to write email: {}", e)))?;
+
+        Ok(())
+    }
+}
@@ -2,13 +2,15 @@ use actix_cors::Cors;
 use actix_web::{middleware, web, App, HttpServer};
 use std::env;
 use std::time::Instant;
-use tera::Tera;
-use tera_text_filters::snake_case;
 
-use graphql_api::database::{self, POOL};
+use graphql_api::database;
 use graphql_api::graphql::create_schema_with_context;
 use graphql_api::handlers;
-use graphql_api::AppData;
+use graphql_api::models;
+use graphql_api::anchoring::Anchorer;
+use graphql_api::blob_store;
+use graphql_api::repositories::Repositories;
+use graphql_api::{load_templates, AppData};
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
@@ -17,7 +19,24 @@ async fn main() -> std::io::Result<()> {
 
     println!("Starting DB initialization");
     let now = Instant::now();
-    database::init();
+    let repos = match env::var("DATABASE_URL") {
+        Ok(db_url) => {
+            let pool = database::create_pool(&db_url);
+            database::run_migrations(&pool);
+            let repos = Repositories::postgres(pool);
+            database::init(&repos).await;
+            repos
+        }
+        #[cfg(feature = "in-memory")]
+        Err(_) => {
+            println!("DATABASE_URL not set - using in-memory storage");
+            let repos = Repositories::in_memory();
+            database::init_in_memory(&repos).await;
+            repos
+        }
+        #[cfg(not(feature = "in-memory"))]
+        Err(_) => panic!("Database url not set"),
+    };
     println!("DB initialization done in {}s.", now.elapsed().as_secs());
 
     let environment = env::var("ENVIRONMENT");
@@ -29,6 +48,9 @@ async fn main() -> std::io::Result<()> {
 
     let _secret_key = env::var("SECRET_KEY").expect("Unable to find secret key");
 
+    models::load_token_keys();
+    models::load_receipt_key(environment == "production");
+
     let (host, port) = if environment == "production" {
         let p: u16 = env::var("PORT")
             .unwrap()
@@ -45,30 +67,59 @@ async fn main() -> std::io::Result<()> {
 
     println!("Serving on: {}:{}", &host, &port);
 
+    // Number of actix worker threads; defaults to one per physical core
+    let workers: Option<usize> = env::var("HTTP_WORKERS")
+        .ok()
+        .map(|w| w.parse().expect("Unable to parse HTTP_WORKERS"));
+
     // Create Schema
-    let schema = web::Data::new(create_schema_with_context(POOL.clone()));
+    let schema = web::Data::new(create_schema_with_context(repos.clone()));
     println!("Got schema");
 
-    HttpServer::new(move || {
-        let cors = Cors::permissive();
+    // Merkle roots over the audit trail and receipts, published periodically
+    Anchorer::from_env(repos.clone()).spawn();
+
+    let repos = web::Data::new(repos);
+
+    let oidc = models::OidcProvider::from_env().await.map(web::Data::new);
+    if oidc.is_some() {
+        println!("OIDC sign-in enabled");
+    }
 
-        let mut tera = Tera::new("templates/**/*").unwrap();
+    let blob_store = blob_store::from_env().map(web::Data::from);
+    if blob_store.is_some() {
+        println!("Document uploads enabled");
+    }
 
-        tera.register_filter("snake_case", snake_case);
-        tera.full_reload()
-            .expect("Error running auto reload with Tera");
+    let mut server = HttpServer::new(move || {
+        let cors = Cors::permissive();
 
-        let app_data = web::Data::new(AppData { tmpl: tera });
+        let app_data = web::Data::new(AppData { tmpl: load_templates() });
 
-        App::new()
+        let mut app = App::new()
             .wrap(cors)
-            //.data(POOL.clone())
+            .app_data(repos.clone())
             .configure(handlers::configure_services)
             .app_data(schema.clone())
-            .app_data(app_data)
-            .wrap(middleware::Logger::default())
-    })
-    .bind((host, port))?
-    .run()
-    .await
+            .app_data(app_data);
+
+        if let Some(oidc) = &oidc {
+            app = app.app_data(oidc.clone());
+        }
+
+        if let Some(blob_store) = &blob_store {
+            app = app.app_data(blob_store.clone());
+        }
+
+        app.wrap(middleware::Logger::default())
+    });
+
+    if let Some(w) = workers {
+        server = server.workers(w);
+    }
+
+    server
+        .bind((host, port))?
+        .run()
+        .await
 }
@@ -0,0 +1,378 @@
+use std::str::FromStr;
+
+use async_graphql::parser::types::{DocumentOperations, OperationType, Selection};
+use async_graphql::*;
+use chrono::prelude::*;
+use chrono::Duration;
+use diesel::prelude::*;
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, QueryDsl, Queryable, RunQueryDsl};
+use rand::distributions::{Alphanumeric, DistString};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::common_utils::UserRole;
+use crate::config_variables::{API_KEY_DEFAULT_DAYS, API_KEY_MAX_DAYS};
+use crate::graphql::get_repositories_from_context;
+use crate::models::Authority;
+use crate::schema::*;
+
+/// What an API key may be used for
+#[derive(Debug, Display, EnumString, Enum, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum ApiScope {
+    /// Run queries and subscriptions
+    Read,
+    /// Call `submitConversionRequest` and upload documents
+    SubmitConversions,
+}
+
+/// Mutations open to API keys and the scope each needs. Any other mutation
+/// is refused for API key requests.
+const MUTATION_SCOPES: &[(&str, ApiScope)] = &[("submitConversionRequest", ApiScope::SubmitConversions)];
+
+impl ApiScope {
+    /// Scopes a GraphQL request needs, or an error if it calls something no
+    /// API key may call. Unparseable requests need nothing here and are left
+    /// for the executor to reject.
+    pub fn required_for(
+        query: &str,
+        operation_name: Option<&str>,
+    ) -> std::result::Result<Vec<ApiScope>, String> {
+        let Ok(document) = async_graphql::parser::parse_query(query) else {
+            return Ok(Vec::new());
+        };
+
+        let operation = match (&document.operations, operation_name) {
+            (DocumentOperations::Single(op), _) => &op.node,
+            (DocumentOperations::Multiple(ops), Some(name)) => match ops.get(name) {
+                Some(op) => &op.node,
+                None => return Ok(Vec::new()),
+            },
+            (DocumentOperations::Multiple(_), None) => return Ok(Vec::new()),
+        };
+
+        if operation.ty != OperationType::Mutation {
+            return Ok(vec![ApiScope::Read]);
+        }
+
+        operation
+            .selection_set
+            .node
+            .items
+            .iter()
+            .filter_map(|selection| match &selection.node {
+                Selection::Field(field) if field.node.name.node.starts_with("__") => None,
+                Selection::Field(field) => Some(field.node.name.node.as_str()),
+                // Fragments at the mutation root would hide what is called
+                _ => Some("fragment"),
+            })
+            .map(|name| {
+                MUTATION_SCOPES
+                    .iter()
+                    .find(|(mutation, _)| *mutation == name)
+                    .map(|(_, scope)| *scope)
+                    .ok_or_else(|| format!("{} can't be called with an API key", name))
+            })
+            .collect()
+    }
+}
+
+/// Credentials of a request authenticated with an API key, inserted into the
+/// GraphQL context alongside the key's role and service user id
+#[derive(Debug, Clone)]
+pub struct ApiKeyAuth {
+    pub key_id: Uuid,
+    pub authority_id: Uuid,
+    pub scopes: Vec<ApiScope>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable, SimpleObject)]
+#[diesel(table_name = api_keys)]
+#[graphql(complex)]
+/// A key for a machine-to-machine client. It acts as `user_id` with `role`,
+/// for `authority_id` only. The key itself is only shown when created.
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid, // User the key acts as
+    pub authority_id: Uuid,
+    pub name: String,
+    /// Start of the key, to tell keys apart
+    pub key_prefix: String,
+    #[graphql(skip)]
+    pub key_hash: String,
+    pub role: String,
+    #[graphql(skip)]
+    pub scopes: Vec<Option<String>>,
+    pub created_by: Uuid, // User
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+// GraphQL Complex Object implementation
+#[ComplexObject]
+impl ApiKey {
+    #[graphql(name = "scopes")]
+    pub async fn granted_scopes(&self) -> Vec<ApiScope> {
+        self.api_scopes()
+    }
+
+    pub async fn authority(&self, ctx: &Context<'_>) -> Result<Authority> {
+        get_repositories_from_context(ctx).authorities.get_by_id(self.authority_id).await
+    }
+}
+
+// Non GraphQL implementation
+impl ApiKey {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at > Utc::now().naive_utc()
+    }
+
+    pub fn api_scopes(&self) -> Vec<ApiScope> {
+        self.scopes
+            .iter()
+            .flatten()
+            .filter_map(|s| ApiScope::from_str(s).ok())
+            .collect()
+    }
+
+    pub fn create(conn: &mut PgConnection, api_key: &NewApiKey) -> Result<Self> {
+        let res = diesel::insert_into(api_keys::table)
+            .values(api_key)
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    pub fn get_by_id(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
+        let res = api_keys::table
+            .filter(api_keys::id.eq(id))
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    pub fn get_by_key_hash(conn: &mut PgConnection, key_hash: &str) -> Result<Self> {
+        let res = api_keys::table
+            .filter(api_keys::key_hash.eq(key_hash))
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    pub fn get_all(conn: &mut PgConnection) -> Result<Vec<Self>> {
+        let res = api_keys::table
+            .order(api_keys::created_at.desc())
+            .load::<ApiKey>(conn)?;
+
+        Ok(res)
+    }
+
+    /// Bring the expiry forward to `expires_at`, never extending it
+    pub fn expire_by(conn: &mut PgConnection, id: &Uuid, expires_at: NaiveDateTime) -> Result<Self> {
+        let key = Self::get_by_id(conn, id)?;
+
+        let res = diesel::update(api_keys::table)
+            .filter(api_keys::id.eq(id))
+            .set(api_keys::expires_at.eq(key.expires_at.min(expires_at)))
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    pub fn revoke(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
+        let res = diesel::update(api_keys::table)
+            .filter(api_keys::id.eq(id))
+            .set(api_keys::revoked_at.eq(Utc::now().naive_utc()))
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    pub fn touch(conn: &mut PgConnection, id: &Uuid) -> Result<()> {
+        diesel::update(api_keys::table)
+            .filter(api_keys::id.eq(id))
+            .set(api_keys::last_used_at.eq(Utc::now().naive_utc()))
+            .execute(conn)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct NewApiKey {
+    pub user_id: Uuid,
+    pub authority_id: Uuid,
+    pub name: String,
+    pub key_prefix: String,
+    pub key_hash: String,
+    pub role: String,
+    pub scopes: Vec<Option<String>>,
+    pub created_by: Uuid,
+    pub expires_at: NaiveDateTime,
+}
+
+impl NewApiKey {
+    /// Generates a key for `input`, returning the row to store and the key to
+    /// hand to the client once
+    pub fn generate(input: ApiKeyInput, created_by: Uuid) -> Result<(Self, String)> {
+        let role = UserRole::from_str(&input.role)
+            .map_err(|_| Error::new(format!("Unknown role {}", input.role)))?;
+
+        if role == UserRole::Admin {
+            return Err(Error::new("API keys can't be granted the ADMIN role"));
+        }
+
+        let days = input.expires_in_days.unwrap_or(API_KEY_DEFAULT_DAYS);
+        if days <= 0 {
+            return Err(Error::new("expiresInDays must be positive"));
+        }
+        if days > API_KEY_MAX_DAYS {
+            return Err(Error::new(format!("expiresInDays can't be more than {}", API_KEY_MAX_DAYS)));
+        }
+
+        let key_prefix = Alphanumeric.sample_string(&mut OsRng, 8);
+        let key = format!("scc_{}_{}", key_prefix, Alphanumeric.sample_string(&mut OsRng, 40));
+
+        let api_key = NewApiKey {
+            user_id: input.user_id,
+            authority_id: input.authority_id,
+            name: input.name,
+            key_prefix,
+            key_hash: hash_api_key(&key),
+            role: role.to_string(),
+            scopes: input.scopes.iter().map(|s| Some(s.to_string())).collect(),
+            created_by,
+            expires_at: Utc::now().naive_utc() + Duration::days(days),
+        };
+
+        Ok((api_key, key))
+    }
+}
+
+pub fn hash_api_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, InputObject)]
+pub struct ApiKeyInput {
+    /// Service user the key acts as
+    pub user_id: Uuid,
+    /// The only authority the key may submit conversions for
+    pub authority_id: Uuid,
+    pub name: String,
+    /// UserRole granted to the key: USER, AUDITOR, ANALYST or OPERATOR
+    pub role: String,
+    pub scopes: Vec<ApiScope>,
+    /// Defaults to 90 days, and can't be more than 3650
+    pub expires_in_days: Option<i64>,
+}
+
+/// A newly issued key. `key` is not stored and can't be shown again.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ApiKeyCreated {
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{ApiKeyInput, ApiScope, NewApiKey};
+    use crate::config_variables::API_KEY_MAX_DAYS;
+
+    fn input(expires_in_days: i64) -> ApiKeyInput {
+        ApiKeyInput {
+            user_id: Uuid::new_v4(),
+            authority_id: Uuid::new_v4(),
+            name: "partner feed".to_owned(),
+            role: "OPERATOR".to_owned(),
+            scopes: vec![ApiScope::Read],
+            expires_in_days: Some(expires_in_days),
+        }
+    }
+
+    #[test]
+    fn expiry_is_bounded() {
+        assert!(NewApiKey::generate(input(API_KEY_MAX_DAYS), Uuid::new_v4()).is_ok());
+
+        for days in [0, API_KEY_MAX_DAYS + 1, i64::MAX] {
+            assert!(NewApiKey::generate(input(days), Uuid::new_v4()).is_err(), "{} days", days);
+        }
+    }
+
+    #[test]
+    fn queries_need_read() {
+        assert_eq!(ApiScope::required_for("{ nations { nationCode } }", None), Ok(vec![ApiScope::Read]));
+    }
+
+    #[test]
+    fn listed_mutation_needs_its_scope() {
+        let query = r#"mutation { submitConversionRequest(conversionRequestData: {}) { id } }"#;
+
+        assert_eq!(ApiScope::required_for(query, None), Ok(vec![ApiScope::SubmitConversions]));
+    }
+
+    #[test]
+    fn unlisted_mutation_is_refused() {
+        let query = r#"mutation {
+            submitConversionRequest(conversionRequestData: {}) { id }
+            createUser(userData: {}) { id }
+        }"#;
+
+        assert_eq!(
+            ApiScope::required_for(query, None),
+            Err("createUser can't be called with an API key".to_owned()),
+        );
+    }
+
+    #[test]
+    fn fragment_at_mutation_root_is_refused() {
+        let spread = r#"
+            mutation { ...Calls }
+            fragment Calls on Mutation { createUser(userData: {}) { id } }
+        "#;
+        let inline = r#"mutation { ... on Mutation { createUser(userData: {}) { id } } }"#;
+
+        for query in [spread, inline] {
+            assert_eq!(
+                ApiScope::required_for(query, None),
+                Err("fragment can't be called with an API key".to_owned()),
+            );
+        }
+    }
+
+    #[test]
+    fn operation_name_selects_the_operation() {
+        let query = r#"
+            query Read { nations { nationCode } }
+            mutation Write { createUser(userData: {}) { id } }
+        "#;
+
+        assert_eq!(ApiScope::required_for(query, Some("Read")), Ok(vec![ApiScope::Read]));
+        assert!(ApiScope::required_for(query, Some("Write")).is_err());
+    }
+
+    #[test]
+    fn ambiguous_documents_are_left_to_the_executor() {
+        // The executor refuses to run several operations without operationName
+        let query = r#"
+            query Read { nations { nationCode } }
+            mutation Write { createUser(userData: {}) { id } }
+        "#;
+
+        assert_eq!(ApiScope::required_for(query, None), Ok(Vec::new()));
+        assert_eq!(ApiScope::required_for(query, Some("Missing")), Ok(Vec::new()));
+        assert_eq!(ApiScope::required_for("mutation {", None), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn typename_only_mutation_needs_nothing() {
+        assert_eq!(ApiScope::required_for("mutation { __typename }", None), Ok(Vec::new()));
+    }
+}
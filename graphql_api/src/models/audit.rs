@@ -0,0 +1,385 @@
+//! Tamper-evident audit trail.
+//!
+//! Sign-ins, data object reads, conversion submissions and results, and
+//! reference data changes are appended to `audit_log`. Entries are numbered
+//! without gaps, and each one's hash covers its fields and the hash of the
+//! entry before it, so changing, removing or reordering any entry breaks
+//! every hash after it. `verifyAuditChain` recomputes the chain. Dropping
+//! entries from the end leaves a valid but shorter chain, so keep the head
+//! hash it reports somewhere the database can't reach.
+
+use async_graphql::*;
+use chrono::prelude::*;
+use diesel::prelude::*;
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, QueryDsl, Queryable, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::models::SecurityEventType;
+use crate::schema::audit_log;
+
+/// Advisory lock key serialising appends to the chain
+const CHAIN_HEAD_LOCK: i64 = 0x6175_6469_745f_6c6f;
+
+/// `prev_hash` of the first entry
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// What an audit entry records
+#[derive(Debug, Display, EnumString, Enum, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum AuditAction {
+    SignInSucceeded,
+    SignInFailed,
+    SignInThrottled,
+    SignInLocked,
+    SignInUnlocked,
+    /// A data object was returned to the actor, redacted or not
+    DataObjectRead,
+    /// A data object was created with its metadata, as by an upload
+    DataObjectCreated,
+    ConversionSubmitted,
+    /// One target nation's result of a conversion
+    ConversionCompleted,
+    NationCreated,
+    AuthorityCreated,
+    ClassificationSchemaCreated,
+    AuthorityMemberAdded,
+    AuthorityMemberRemoved,
+    /// An account was approved by the administrator who created it, allowed
+    /// while they are the only approved administrator
+    BootstrapApproval,
+    /// Audit entries were exported from `/audit/export`
+    AuditLogExported,
+}
+
+impl From<SecurityEventType> for AuditAction {
+    fn from(event_type: SecurityEventType) -> Self {
+        match event_type {
+            SecurityEventType::SignInSucceeded => AuditAction::SignInSucceeded,
+            SecurityEventType::SignInFailed => AuditAction::SignInFailed,
+            SecurityEventType::SignInThrottled => AuditAction::SignInThrottled,
+            SecurityEventType::SignInLocked => AuditAction::SignInLocked,
+            SecurityEventType::SignInUnlocked => AuditAction::SignInUnlocked,
+        }
+    }
+}
+
+/// The kind of record an audit entry is about
+#[derive(Debug, Display, EnumString, Enum, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum AuditEntity {
+    User,
+    DataObject,
+    ConversionRequest,
+    ConversionResult,
+    Nation,
+    Authority,
+    ClassificationSchema,
+    AuditLog,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable, SimpleObject)]
+#[diesel(table_name = audit_log)]
+/// One entry of the audit trail
+pub struct AuditEntry {
+    /// Position in the chain, from 1 without gaps
+    pub sequence: i64,
+    pub occurred_at: NaiveDateTime,
+    /// User who acted, or null for the system and unknown callers
+    pub actor_id: Option<Uuid>,
+    /// AuditAction
+    pub action: String,
+    /// AuditEntity
+    pub entity_type: String,
+    pub entity_id: Option<Uuid>,
+    pub nation_code: Option<String>,
+    /// JSON object with details of the action
+    pub detail: String,
+    /// `entryHash` of the previous entry
+    pub prev_hash: String,
+    /// SHA-256 of the canonical form of this entry
+    pub entry_hash: String,
+}
+
+impl AuditEntry {
+    /// The JSON array the entry hash is taken over: every field but the hash
+    /// itself, in column order, with the timestamp to the microsecond
+    pub fn canonical_form(&self) -> String {
+        serde_json::json!([
+            self.sequence,
+            self.occurred_at.format("%Y-%m-%dT%H:%M:%S%.6f").to_string(),
+            self.actor_id,
+            self.action,
+            self.entity_type,
+            self.entity_id,
+            self.nation_code,
+            self.detail,
+            self.prev_hash,
+        ])
+        .to_string()
+    }
+
+    pub fn compute_hash(&self) -> String {
+        format!("{:x}", Sha256::digest(self.canonical_form().as_bytes()))
+    }
+
+    /// Appends entries after the current last one. Appenders take an
+    /// advisory lock on the chain head until their transaction ends, so
+    /// concurrent appends queue up rather than fork the chain, while reads
+    /// and verification of the log carry on.
+    pub fn append(conn: &mut PgConnection, entries: Vec<NewAuditEntry>) -> Result<Vec<Self>> {
+        let res = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            diesel::sql_query("SELECT pg_advisory_xact_lock($1)")
+                .bind::<diesel::sql_types::BigInt, _>(CHAIN_HEAD_LOCK)
+                .execute(conn)?;
+
+            let mut last: Option<AuditEntry> = audit_log::table
+                .order(audit_log::sequence.desc())
+                .first(conn)
+                .optional()?;
+
+            let mut appended = Vec::with_capacity(entries.len());
+
+            for entry in entries {
+                let entry = entry.chain(last.as_ref());
+
+                let entry: AuditEntry = diesel::insert_into(audit_log::table)
+                    .values(&entry)
+                    .get_result(conn)?;
+
+                last = Some(entry.clone());
+                appended.push(entry);
+            }
+
+            Ok(appended)
+        })?;
+
+        Ok(res)
+    }
+
+    /// Up to `page.limit` entries matching `filter` between the page's
+    /// sequences, in chain order or newest first
+    pub fn search(conn: &mut PgConnection, filter: &AuditFilter, page: AuditPage) -> Result<Vec<Self>> {
+        let mut query = audit_log::table.into_boxed();
+
+        if let Some(actor_id) = filter.actor_id {
+            query = query.filter(audit_log::actor_id.eq(actor_id));
+        }
+        if let Some(action) = filter.action {
+            query = query.filter(audit_log::action.eq(action.to_string()));
+        }
+        if let Some(entity_type) = filter.entity_type {
+            query = query.filter(audit_log::entity_type.eq(entity_type.to_string()));
+        }
+        if let Some(entity_id) = filter.entity_id {
+            query = query.filter(audit_log::entity_id.eq(entity_id));
+        }
+        if let Some(nation_code) = &filter.nation_code {
+            query = query.filter(audit_log::nation_code.eq(nation_code.clone()));
+        }
+        if let Some(from) = filter.occurred_from {
+            query = query.filter(audit_log::occurred_at.ge(from));
+        }
+        if let Some(until) = filter.occurred_until {
+            query = query.filter(audit_log::occurred_at.lt(until));
+        }
+        if let Some(after) = page.after_sequence {
+            query = query.filter(audit_log::sequence.gt(after));
+        }
+        if let Some(before) = page.before_sequence {
+            query = query.filter(audit_log::sequence.lt(before));
+        }
+
+        query = if page.newest_first {
+            query.order(audit_log::sequence.desc())
+        } else {
+            query.order(audit_log::sequence.asc())
+        };
+
+        let res = query.limit(page.limit).load::<AuditEntry>(conn)?;
+
+        Ok(res)
+    }
+
+    /// Up to `limit` entries after `after_sequence`, in chain order
+    pub fn get_page(conn: &mut PgConnection, after_sequence: i64, limit: i64) -> Result<Vec<Self>> {
+        let res = audit_log::table
+            .filter(audit_log::sequence.gt(after_sequence))
+            .order(audit_log::sequence.asc())
+            .limit(limit)
+            .load::<AuditEntry>(conn)?;
+
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone, Default, InputObject)]
+/// Which audit entries to search or export. Unset fields match everything.
+pub struct AuditFilter {
+    pub actor_id: Option<Uuid>,
+    pub action: Option<AuditAction>,
+    pub entity_type: Option<AuditEntity>,
+    pub entity_id: Option<Uuid>,
+    pub nation_code: Option<String>,
+    /// Entries at or after this time (UTC)
+    pub occurred_from: Option<NaiveDateTime>,
+    /// Entries before this time (UTC)
+    pub occurred_until: Option<NaiveDateTime>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.actor_id.is_none_or(|id| entry.actor_id == Some(id))
+            && self.action.is_none_or(|action| entry.action == action.to_string())
+            && self.entity_type.is_none_or(|entity| entry.entity_type == entity.to_string())
+            && self.entity_id.is_none_or(|id| entry.entity_id == Some(id))
+            && self.nation_code.as_ref().is_none_or(|code| entry.nation_code.as_ref() == Some(code))
+            && self.occurred_from.is_none_or(|from| entry.occurred_at >= from)
+            && self.occurred_until.is_none_or(|until| entry.occurred_at < until)
+    }
+
+    /// The fields that are set, by their GraphQL names, as strings. Used to
+    /// state what an export covers.
+    pub fn describe(&self) -> std::collections::BTreeMap<&'static str, String> {
+        let fields = [
+            ("actorId", self.actor_id.map(|id| id.to_string())),
+            ("action", self.action.map(|action| action.to_string())),
+            ("entityType", self.entity_type.map(|entity| entity.to_string())),
+            ("entityId", self.entity_id.map(|id| id.to_string())),
+            ("nationCode", self.nation_code.clone()),
+            ("occurredFrom", self.occurred_from.map(|t| t.format("%Y-%m-%dT%H:%M:%S%.6f").to_string())),
+            ("occurredUntil", self.occurred_until.map(|t| t.format("%Y-%m-%dT%H:%M:%S%.6f").to_string())),
+        ];
+
+        fields
+            .into_iter()
+            .filter_map(|(name, value)| value.map(|value| (name, value)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A window of audit entries by sequence, exclusive at both ends
+pub struct AuditPage {
+    pub after_sequence: Option<i64>,
+    pub before_sequence: Option<i64>,
+    pub limit: i64,
+    /// Take the entries nearest `before_sequence` rather than
+    /// `after_sequence`, returned newest first
+    pub newest_first: bool,
+}
+
+#[derive(Debug, Clone)]
+/// An action to audit, before it is placed in the chain
+pub struct NewAuditEntry {
+    pub actor_id: Option<Uuid>,
+    pub action: AuditAction,
+    pub entity_type: AuditEntity,
+    pub entity_id: Option<Uuid>,
+    pub nation_code: Option<String>,
+    pub detail: serde_json::Value,
+}
+
+impl NewAuditEntry {
+    /// An entry with no nation and empty detail
+    pub fn new(actor_id: Option<Uuid>, action: AuditAction, entity_type: AuditEntity, entity_id: Option<Uuid>) -> Self {
+        NewAuditEntry {
+            actor_id,
+            action,
+            entity_type,
+            entity_id,
+            nation_code: None,
+            detail: serde_json::json!({}),
+        }
+    }
+
+    /// The entry placed after `prev`, or first if there is none
+    pub fn chain(self, prev: Option<&AuditEntry>) -> AuditEntry {
+        let now = Utc::now().naive_utc();
+
+        let mut entry = AuditEntry {
+            sequence: prev.map_or(1, |p| p.sequence + 1),
+            // Postgres keeps microseconds, and the hash has to survive a round trip
+            occurred_at: now.with_nanosecond(now.nanosecond() / 1_000 * 1_000).unwrap_or(now),
+            actor_id: self.actor_id,
+            action: self.action.to_string(),
+            entity_type: self.entity_type.to_string(),
+            entity_id: self.entity_id,
+            nation_code: self.nation_code,
+            detail: self.detail.to_string(),
+            prev_hash: prev.map_or(GENESIS_HASH.to_owned(), |p| p.entry_hash.clone()),
+            entry_hash: String::new(),
+        };
+
+        entry.entry_hash = entry.compute_hash();
+        entry
+    }
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+/// Outcome of recomputing the audit chain
+pub struct AuditChainReport {
+    /// Whether every entry checked out
+    pub valid: bool,
+    /// Entries checked, up to and including the first bad one
+    pub entries_checked: i64,
+    /// Sequence of the first entry that doesn't fit the chain
+    pub first_invalid_sequence: Option<i64>,
+    pub reason: Option<String>,
+    /// Hash of the last valid entry. Compare it with a copy kept elsewhere
+    /// to detect entries dropped from the end.
+    pub head_hash: String,
+    pub head_sequence: i64,
+}
+
+/// Checks audit entries one at a time in chain order
+pub struct AuditChainVerifier {
+    head_hash: String,
+    head_sequence: i64,
+    checked: i64,
+}
+
+impl Default for AuditChainVerifier {
+    fn default() -> Self {
+        AuditChainVerifier {
+            head_hash: GENESIS_HASH.to_owned(),
+            head_sequence: 0,
+            checked: 0,
+        }
+    }
+}
+
+impl AuditChainVerifier {
+    /// Checks the next entry, returning why it doesn't fit if it doesn't
+    pub fn check(&mut self, entry: &AuditEntry) -> std::result::Result<(), String> {
+        self.checked += 1;
+
+        if entry.sequence != self.head_sequence + 1 {
+            return Err(format!("expected entry {} but found {}", self.head_sequence + 1, entry.sequence));
+        }
+
+        if entry.prev_hash != self.head_hash {
+            return Err("previous hash doesn't match the entry before it".to_owned());
+        }
+
+        if entry.compute_hash() != entry.entry_hash {
+            return Err("entry hash doesn't match its contents".to_owned());
+        }
+
+        self.head_hash = entry.entry_hash.clone();
+        self.head_sequence = entry.sequence;
+        Ok(())
+    }
+
+    pub fn report(&self, failure: Option<(i64, String)>) -> AuditChainReport {
+        AuditChainReport {
+            valid: failure.is_none(),
+            entries_checked: self.checked,
+            first_invalid_sequence: failure.as_ref().map(|(sequence, _)| *sequence),
+            reason: failure.map(|(_, reason)| reason),
+            head_hash: self.head_hash.clone(),
+            head_sequence: self.head_sequence,
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! Signed exports of the audit log.
+//!
+//! `/audit/export` streams the entries matching a filter as NDJSON (one
+//! `AuditEntry` JSON object per line) or CSV (a header row, then one row per
+//! entry), a page at a time. The last line is a trailer holding a statement
+//! of what was exported: the filter, the number of entries, the last
+//! sequence and the SHA-256 of every byte before the trailer. With
+//! `RECEIPT_SIGNING_KEY` set the statement is signed by the receipt key, so
+//! an inspector can check offline that the bundle is complete and unaltered
+//! with `verify_audit_export` or the `verify_audit_export` binary. In CSV the
+//! trailer line starts with `# `.
+//!
+//! An export cut short by an error has no trailer, and doesn't verify.
+
+use std::collections::BTreeMap;
+
+use chrono::{SecondsFormat, Utc};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::{sign_with_receipt_key, verify_receipt_signature, AuditEntry, AuditFilter};
+
+/// Version of the export statement, signed with it
+pub const AUDIT_EXPORT_VERSION: u32 = 1;
+
+/// Column order of CSV exports, the `AuditEntry` field order
+const CSV_HEADER: [&str; 10] = [
+    "sequence",
+    "occurred_at",
+    "actor_id",
+    "action",
+    "entity_type",
+    "entity_id",
+    "nation_code",
+    "detail",
+    "prev_hash",
+    "entry_hash",
+];
+
+#[derive(Debug, Display, EnumString, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum AuditExportFormat {
+    Ndjson,
+    Csv,
+}
+
+impl AuditExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            AuditExportFormat::Ndjson => "application/x-ndjson",
+            AuditExportFormat::Csv => "text/csv",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+/// What an export contains. Fields are declared in alphabetical order, which
+/// is the order they are serialized and signed in.
+pub struct AuditExportStatement {
+    pub entries: i64,
+    pub exported_at: String,
+    /// `AuditFilter::describe` of the filter the export was made with
+    pub filter: BTreeMap<String, String>,
+    pub format: AuditExportFormat,
+    pub last_sequence: Option<i64>,
+    /// SHA-256 of the export up to the trailer line
+    pub sha256: String,
+    pub version: u32,
+}
+
+impl AuditExportStatement {
+    pub fn canonical_form(&self) -> String {
+        serde_json::to_string(self).expect("Export statements always serialize")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+/// The last line of an export
+pub struct AuditExportTrailer {
+    /// Canonical `AuditExportStatement`
+    pub statement: String,
+    /// Base64 Ed25519 signature of the statement, or null if exports aren't
+    /// signed
+    pub signature: Option<String>,
+    pub signing_key_id: Option<String>,
+}
+
+/// Encodes entries for an export while hashing them, then the trailer
+pub struct AuditExportWriter {
+    format: AuditExportFormat,
+    hasher: Sha256,
+    entries: i64,
+    last_sequence: Option<i64>,
+}
+
+impl AuditExportWriter {
+    pub fn new(format: AuditExportFormat) -> Self {
+        AuditExportWriter {
+            format,
+            hasher: Sha256::new(),
+            entries: 0,
+            last_sequence: None,
+        }
+    }
+
+    fn hashed(&mut self, bytes: Vec<u8>) -> Vec<u8> {
+        self.hasher.update(&bytes);
+        bytes
+    }
+
+    /// What comes before the first entry: the CSV header row, or nothing
+    pub fn header(&mut self) -> Vec<u8> {
+        match self.format {
+            AuditExportFormat::Ndjson => Vec::new(),
+            AuditExportFormat::Csv => {
+                let bytes = csv_row(|writer| writer.write_record(CSV_HEADER));
+                self.hashed(bytes)
+            }
+        }
+    }
+
+    /// One entry, as a line of the export
+    pub fn entry(&mut self, entry: &AuditEntry) -> Vec<u8> {
+        self.entries += 1;
+        self.last_sequence = Some(entry.sequence);
+
+        let bytes = match self.format {
+            AuditExportFormat::Ndjson => {
+                let mut line = serde_json::to_vec(entry).expect("Audit entries always serialize");
+                line.push(b'\n');
+                line
+            }
+            AuditExportFormat::Csv => csv_row(|writer| writer.serialize(entry)),
+        };
+
+        self.hashed(bytes)
+    }
+
+    /// The trailer line, signed when RECEIPT_SIGNING_KEY is set
+    pub fn finish(self, filter: &AuditFilter) -> Vec<u8> {
+        let statement = AuditExportStatement {
+            entries: self.entries,
+            exported_at: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            filter: filter
+                .describe()
+                .into_iter()
+                .map(|(name, value)| (name.to_owned(), value))
+                .collect(),
+            format: self.format,
+            last_sequence: self.last_sequence,
+            sha256: format!("{:x}", self.hasher.finalize()),
+            version: AUDIT_EXPORT_VERSION,
+        }
+        .canonical_form();
+
+        let signed = sign_with_receipt_key(statement.as_bytes());
+
+        let trailer = AuditExportTrailer {
+            statement,
+            signature: signed.as_ref().map(|(signature, _)| signature.clone()),
+            signing_key_id: signed.map(|(_, kid)| kid),
+        };
+
+        let prefix = match self.format {
+            AuditExportFormat::Ndjson => "",
+            AuditExportFormat::Csv => "# ",
+        };
+
+        let trailer = serde_json::to_string(&trailer).expect("Trailers always serialize");
+        format!("{}{}\n", prefix, trailer).into_bytes()
+    }
+}
+
+/// One CSV record, written to memory
+fn csv_row(write: impl FnOnce(&mut csv::Writer<Vec<u8>>) -> csv::Result<()>) -> Vec<u8> {
+    let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(Vec::new());
+    write(&mut writer).expect("Audit entries always serialize");
+    writer.into_inner().expect("Writing to memory doesn't fail")
+}
+
+/// Splits an export into the entries and the trailer
+pub fn split_audit_export(export: &[u8]) -> std::result::Result<(&[u8], AuditExportTrailer), String> {
+    let without_newline = export
+        .strip_suffix(b"\n")
+        .ok_or_else(|| "the export doesn't end with a trailer line".to_owned())?;
+
+    let start = without_newline.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+    let (body, line) = export.split_at(start);
+
+    let line = std::str::from_utf8(line).map_err(|_| "the trailer is not UTF-8".to_owned())?;
+    let line = line.trim_end_matches('\n');
+    let line = line.strip_prefix("# ").unwrap_or(line);
+
+    let trailer = serde_json::from_str(line).map_err(|e| format!("malformed trailer: {}", e))?;
+
+    Ok((body, trailer))
+}
+
+/// Checks an export against a receipt public key, without the database or
+/// the service. Returns what the trailer states if its signature holds, the
+/// statement is in canonical form and the entries hash to what it states.
+pub fn verify_audit_export(
+    export: &[u8],
+    key: &VerifyingKey,
+) -> std::result::Result<AuditExportStatement, String> {
+    let (body, trailer) = split_audit_export(export)?;
+
+    let signature = trailer
+        .signature
+        .as_deref()
+        .ok_or_else(|| "the export is not signed".to_owned())?;
+    verify_receipt_signature(trailer.statement.as_bytes(), signature, key)?;
+
+    let statement: AuditExportStatement =
+        serde_json::from_str(&trailer.statement).map_err(|e| format!("malformed statement: {}", e))?;
+
+    if statement.canonical_form() != trailer.statement {
+        return Err("statement is not in canonical form".to_owned());
+    }
+
+    if format!("{:x}", Sha256::digest(body)) != statement.sha256 {
+        return Err("the entries don't match the signed hash".to_owned());
+    }
+
+    Ok(statement)
+}
@@ -1,29 +1,56 @@
 use std::str::FromStr;
 
 use actix_web::{HttpRequest, Result};
-use argon2::password_hash::{PasswordHashString, SaltString};
+use argon2::password_hash::{PasswordHash, SaltString};
 use chrono::{Duration, Local};
-use jsonwebtoken::{decode, DecodingKey, TokenData, Validation};
-use jsonwebtoken::{encode, EncodingKey, Header};
+use jsonwebtoken::{decode, decode_header, encode, TokenData};
 use serde::{Deserialize, Serialize};
 use lazy_static::lazy_static;
 use argon2::{
-    Argon2,
+    Algorithm, Argon2, Params, Version,
     password_hash::{PasswordHasher, PasswordVerifier},
 };
+use rand::rngs::OsRng;
 use jsonwebtoken::errors::*;
 
-use crate::common_utils::UserRole;
+use crate::common_utils::{unauthenticated, UserRole};
 use crate::config_variables::TOKEN_DURATION;
+use crate::models::{current_signing_key, hash_api_key, verifying_key, ApiKeyAuth, SessionId};
+use crate::repositories::Repositories;
 
 lazy_static! {
-    static ref JWT_SECRET_KEY: String = 
-        std::env::var("JWT_SECRET_KEY").expect("Can't read JWT_SECRET_KEY");
+    /// Optional Argon2 secret (pepper) mixed into every new hash. Before
+    /// per-user salts this value was the salt shared by every user, so it
+    /// also identifies hashes that still need migrating.
+    static ref PASSWORD_PEPPER: Option<String> = std::env::var("PASSWORD_SECRET_KEY")
+        .ok()
+        .filter(|s| !s.is_empty());
 }
 
 lazy_static! {
-    static ref PASSWORD_SECRET_KEY: String = 
-        std::env::var("PASSWORD_SECRET_KEY").expect("Can't read PASSWORD_SECRET_KEY");
+    /// Cost parameters for new hashes, read from ARGON2_MEMORY_KIB,
+    /// ARGON2_ITERATIONS and ARGON2_PARALLELISM with the argon2 crate
+    /// defaults as fallback
+    static ref ARGON2_PARAMS: Params = Params::new(
+        env_or("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST),
+        env_or("ARGON2_ITERATIONS", Params::DEFAULT_T_COST),
+        env_or("ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+        None,
+    )
+    .expect("Invalid Argon2 parameters");
+}
+
+lazy_static! {
+    /// Hash with the current parameters that no sign-in can match, so
+    /// unknown emails cost as much to check as known ones
+    static ref DUMMY_HASH: String = hash_password(&uuid::Uuid::new_v4().to_string())
+        .expect("Unable to hash the dummy password");
+}
+
+fn env_or(name: &str, default: u32) -> u32 {
+    std::env::var(name)
+        .map(|s| s.parse().unwrap_or_else(|_| panic!("Unable to parse {}", name)))
+        .unwrap_or(default)
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -31,95 +58,244 @@ pub struct Claims {
     pub sub: String,
     pub exp: i64,
     pub role: String,
+    /// Session the token was issued for, see `Session`
+    pub sid: String,
 }
 
-pub fn create_token(user_id: String, role: UserRole) -> String {
+/// Signs an access token. Fails when no signing key is active.
+pub fn create_token(user_id: String, role: UserRole, session_id: SessionId) -> async_graphql::Result<String> {
     let exp_time = Local::now() + Duration::seconds(TOKEN_DURATION);
 
     let claims = Claims {
         sub: user_id,
         exp: exp_time.timestamp(),
         role: role.to_string(),
+        sid: session_id.0.to_string(),
     };
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(JWT_SECRET_KEY.as_ref()),
-    )
-    .expect("Can't create token")
+    let (header, key) = current_signing_key()
+        .ok_or_else(|| async_graphql::Error::new("No token signing key is active"))?;
+
+    Ok(encode(&header, &claims, key)?)
 }
 
-pub fn get_claim(http_request: HttpRequest) -> Result<(UserRole, uuid::Uuid, i64), jsonwebtoken::errors::Error> {
+/// Reads the bearer token of a request. Ok(None) when there is no
+/// Authorization header, so the request runs anonymously.
+pub async fn get_claim(
+    http_request: HttpRequest,
+    repos: &Repositories,
+) -> Result<Option<(UserRole, uuid::Uuid, i64, SessionId)>, jsonwebtoken::errors::Error> {
+    let Some(header_value) = http_request.headers().get("Authorization") else {
+        return Ok(None);
+    };
 
-    println!("{:?}", &http_request.headers().get("Authorization"));
+    let jwt = header_value
+        .to_str()
+        .ok()
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .ok_or_else(|| jsonwebtoken::errors::Error::from(ErrorKind::InvalidToken))?;
 
-    let token_data = http_request
-        .headers()
-        .get("Authorization")
-        .and_then(|header_value| {
-            header_value.to_str().ok().map(|s| {
-                let jwt_start_index = "Bearer ".len();
-                let jwt = s[jwt_start_index..s.len()].to_string();
-                let token_data = decode_token(&jwt);
-                println!("TOKEN: {:?}", &token_data);
-                token_data
-            })
-        });
+    verify_token(jwt, repos).await.map(Some)
+}
+
+/// Decodes an access token and checks that its session is still active, so
+/// revoked sessions are refused before expiry. Tokens whose role claim no
+/// longer matches the user's role are refused too.
+pub async fn verify_token(
+    jwt: &str,
+    repos: &Repositories,
+) -> Result<(UserRole, uuid::Uuid, i64, SessionId), jsonwebtoken::errors::Error> {
+    let invalid = || jsonwebtoken::errors::Error::from(ErrorKind::InvalidToken);
+
+    let token = decode_token(jwt)?;
+
+    let role = UserRole::from_str(&token.claims.role).map_err(|_| invalid())?;
+    let uuid = uuid::Uuid::from_str(&token.claims.sub).map_err(|_| invalid())?;
+    let exp_time = &token.claims.exp;
+
+    let session_id = uuid::Uuid::from_str(&token.claims.sid).map_err(|_| invalid())?;
+
+    match repos.sessions.get_by_id(session_id).await {
+        Ok(session) if session.is_active() && session.user_id == uuid => {},
+        _ => return Err(invalid()),
+    };
+
+    match repos.users.get_by_id(uuid).await {
+        Ok(user) if user.role == role.to_string() => {},
+        _ => return Err(invalid()),
+    };
+
+    Ok((role, uuid.to_owned(), *exp_time, SessionId(session_id)))
+}
+
+/// Checks that a caller authenticated earlier still may act as `role`: their
+/// session or API key is active, their account approved and, for sessions,
+/// their role unchanged. Websockets authenticate once, so subscriptions check
+/// this before each event. Callers with neither a session nor a key were
+/// authenticated in-process and pass.
+pub async fn reauthorize(
+    repos: &Repositories,
+    role: UserRole,
+    user_id: uuid::Uuid,
+    session_id: Option<&SessionId>,
+    api_key: Option<&ApiKeyAuth>,
+) -> async_graphql::Result<()> {
+    if let Some(SessionId(id)) = session_id {
+        match repos.sessions.get_by_id(*id).await {
+            Ok(session) if session.is_active() && session.user_id == user_id => {},
+            _ => return Err(unauthenticated("Session has ended")),
+        }
+    }
+
+    if let Some(api_key) = api_key {
+        match repos.api_keys.get_by_id(api_key.key_id).await {
+            Ok(key) if key.is_active() => {},
+            _ => return Err(unauthenticated("Invalid, expired or revoked API key")),
+        }
+    }
+
+    if session_id.is_some() || api_key.is_some() {
+        let user = repos.users.get_by_id(user_id).await?;
+        user.ensure_approved()?;
+
+        if session_id.is_some() && user.role != role.to_string() {
+            return Err(unauthenticated("Role has changed, sign in again"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Authenticates a request carrying an X-Api-Key header. Returns None when the
+/// header is absent, so bearer tokens are checked instead.
+pub async fn get_api_key_claim(
+    http_request: &HttpRequest,
+    repos: &Repositories,
+) -> Option<Result<(UserRole, uuid::Uuid, i64, ApiKeyAuth), jsonwebtoken::errors::Error>> {
+    let key = http_request.headers().get("X-Api-Key")?;
+
+    match key.to_str() {
+        Ok(key) => Some(verify_api_key(key, repos).await),
+        Err(_) => Some(Err(jsonwebtoken::errors::Error::from(ErrorKind::InvalidToken))),
+    }
+}
+
+/// Looks up an API key and records its use. Unknown, expired and revoked
+/// keys are all refused the same way.
+pub async fn verify_api_key(
+    key: &str,
+    repos: &Repositories,
+) -> Result<(UserRole, uuid::Uuid, i64, ApiKeyAuth), jsonwebtoken::errors::Error> {
+    let invalid = || jsonwebtoken::errors::Error::from(ErrorKind::InvalidToken);
 
-        let token = match token_data {
-            Some(td) => {
-                let token = match td {
-                    Ok(t) => t,
-                    Err(e) => return Err(e),
-                };
-                token
-            },
-            None => return Err(jsonwebtoken::errors::Error::from(ErrorKind::InvalidToken)),
-        };
+    let api_key = match repos.api_keys.get_by_key_hash(hash_api_key(key.trim())).await {
+        Ok(api_key) if api_key.is_active() => api_key,
+        _ => return Err(invalid()),
+    };
 
-        let role = UserRole::from_str(&token.claims.role).expect("Can't parse role");
-        let uuid = uuid::Uuid::from_str(&token.claims.sub).expect("Can't parse CBSA_ID");
-        let exp_time = &token.claims.exp;
+    let role = UserRole::from_str(&api_key.role).map_err(|_| invalid())?;
 
-        Ok((role, uuid.to_owned(), *exp_time))
+    if let Err(e) = repos.api_keys.touch(api_key.id).await {
+        println!("Unable to record API key use: {:?}", e);
+    }
+
+    let auth = ApiKeyAuth {
+        key_id: api_key.id,
+        authority_id: api_key.authority_id,
+        scopes: api_key.api_scopes(),
+    };
+
+    Ok((role, api_key.user_id, api_key.expires_at.and_utc().timestamp(), auth))
 }
 
 pub fn decode_token(token: &str) -> Result<TokenData<Claims>, jsonwebtoken::errors::Error> {
-    Ok(decode::<Claims>(
-        &token,
-        &DecodingKey::from_secret(JWT_SECRET_KEY.as_ref()),
-        &Validation::default(),
-    )?)
+    let header = decode_header(token)?;
+
+    let (key, validation) = verifying_key(&header)
+        .ok_or_else(|| jsonwebtoken::errors::Error::from(ErrorKind::InvalidAlgorithm))?;
+
+    decode::<Claims>(token, &key, &validation)
 }
 
-pub fn hash_password(password: &str) -> Result<PasswordHashString, argon2::password_hash::Error> {
-    let argon2 = Argon2::default();
+/// Outcome of checking a password against a stored hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordCheck {
+    Mismatch,
+    Match,
+    /// The password is correct but the hash uses a shared salt, no pepper or
+    /// outdated Argon2 parameters, and should be replaced with a fresh
+    /// `hash_password`
+    MatchNeedsRehash,
+}
 
-    let pwd = password.as_bytes();
+impl PasswordCheck {
+    pub fn is_match(self) -> bool {
+        self != PasswordCheck::Mismatch
+    }
+}
 
-    let salt = SaltString::from_b64(&PASSWORD_SECRET_KEY).expect("Unable to generate salt").to_owned();
+/// Argon2id with the configured parameters, keyed with the pepper when
+/// `peppered` is set and a pepper is configured
+fn hasher(peppered: bool) -> Argon2<'static> {
+    match PASSWORD_PEPPER.as_deref().filter(|_| peppered) {
+        Some(pepper) => Argon2::new_with_secret(
+            pepper.as_bytes(),
+            Algorithm::Argon2id,
+            Version::V0x13,
+            ARGON2_PARAMS.clone(),
+        )
+        .expect("Unable to use PASSWORD_SECRET_KEY as pepper"),
+        None => Argon2::new(Algorithm::Argon2id, Version::V0x13, ARGON2_PARAMS.clone()),
+    }
+}
 
-    let result = argon2.hash_password(
-        pwd,
-        &salt,
-    )?
-    .serialize();
+/// Hashes a password with a fresh random salt and the optional pepper
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    let result = hasher(true)
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string();
 
     Ok(result)
 }
 
-pub fn verify_password(hash_string: String, password: &str) -> Result<bool, argon2::password_hash::Error> {
-    
+/// Checks a password against a hash no account has, taking as long as
+/// `verify_password` does for a known account. Always a mismatch.
+pub fn verify_dummy_password(password: &str) {
+    let _ = verify_password(&DUMMY_HASH, password);
+}
+
+/// Checks a password against a stored hash. Hashes created before per-user
+/// salts (shared salt, no pepper) still verify but are flagged for rehashing.
+pub fn verify_password(hash_string: &str, password: &str) -> Result<PasswordCheck, argon2::password_hash::Error> {
     let pwd = password.as_bytes();
 
-    let hash = PasswordHashString::from_str(&hash_string)?;
-    
-    let result = Argon2::default().verify_password(pwd, &hash.password_hash());
+    let hash = PasswordHash::new(hash_string)?;
 
-    if result == Ok(()) {
-        Ok(true)
+    // Argon2 takes its cost parameters from the stored hash, only the
+    // pepper has to be supplied
+    let peppered = if hasher(true).verify_password(pwd, &hash).is_ok() {
+        PASSWORD_PEPPER.is_some()
+    } else if PASSWORD_PEPPER.is_some() && hasher(false).verify_password(pwd, &hash).is_ok() {
+        false
     } else {
-        Ok(false)
+        return Ok(PasswordCheck::Mismatch);
+    };
+
+    let shared_salt = PASSWORD_PEPPER.is_some()
+        && hash.salt.map(|s| s.as_str()) == PASSWORD_PEPPER.as_deref();
+
+    let outdated = Algorithm::try_from(hash.algorithm) != Ok(Algorithm::Argon2id)
+        || Params::try_from(&hash).map_or(true, |p| {
+            p.m_cost() != ARGON2_PARAMS.m_cost()
+                || p.t_cost() != ARGON2_PARAMS.t_cost()
+                || p.p_cost() != ARGON2_PARAMS.p_cost()
+        });
+
+    if shared_salt || outdated || (PASSWORD_PEPPER.is_some() && !peppered) {
+        Ok(PasswordCheck::MatchNeedsRehash)
+    } else {
+        Ok(PasswordCheck::Match)
     }
-}
\ No newline at end of file
+}
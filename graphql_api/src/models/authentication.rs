@@ -1,137 +1,155 @@
-use crate::errors::CustomError;
+use async_graphql::*;
 use chrono::{Duration, prelude::*};
+use diesel::prelude::*;
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, Queryable, RunQueryDsl};
+use rand::distributions::{Alphanumeric, DistString};
+use rand::rngs::OsRng;
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
-use crate::{generate_unique_code, schema::{
-    email_verification_code, password_reset_token}
-};
-use crate::database;
+use crate::config_variables::{EMAIL_VERIFICATION_DURATION, PASSWORD_RESET_DURATION};
+use crate::schema::{email_verification_code, password_reset_token};
 
-use diesel::prelude::*;
-use diesel::RunQueryDsl;
-use diesel::{QueryDsl};
+/// Length of the tokens sent for email verification and password resets
+const EMAIL_TOKEN_LENGTH: usize = 40;
+
+/// A new random token for an email link. Only its hash is stored.
+pub fn generate_email_token() -> String {
+    Alphanumeric.sample_string(&mut OsRng, EMAIL_TOKEN_LENGTH)
+}
+
+pub fn hash_email_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
 
-#[derive(Serialize, Deserialize, Queryable, Insertable, Debug, Identifiable, AsChangeset, Clone)]
-#[table_name = "email_verification_code"]
+#[derive(Serialize, Deserialize, Queryable, Debug, Identifiable, Clone)]
+#[diesel(table_name = email_verification_code)]
+/// A pending email verification. Issuing a new one for the same address
+/// replaces it.
 pub struct EmailVerification {
     pub id: Uuid,
     pub email_address: String,
-    pub activation_code: String,
+    pub activation_code_hash: String,
     pub expires_on: NaiveDateTime,
 }
 
 #[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable)]
-#[table_name = "email_verification_code"]
+#[diesel(table_name = email_verification_code)]
 pub struct InsertableVerification {
     pub email_address: String,
-    pub activation_code: String,
+    pub activation_code_hash: String,
     pub expires_on: NaiveDateTime,
 }
 
 impl InsertableVerification {
-    pub fn new(email_address: &String) -> Self {
-        let expires_on = Utc::now().naive_utc() + Duration::minutes(30);
-        let activation_code = generate_unique_code(5, false);
+    /// A verification for `email_address`, returned with the token to send
+    pub fn new(email_address: &str) -> (Self, String) {
+        let token = generate_email_token();
 
-        InsertableVerification {
+        let verification = InsertableVerification {
             email_address: email_address.to_owned(),
-            activation_code,
-            expires_on,
-        }
+            activation_code_hash: hash_email_token(&token),
+            expires_on: Utc::now().naive_utc() + Duration::seconds(EMAIL_VERIFICATION_DURATION),
+        };
+
+        (verification, token)
     }
 }
 
 impl EmailVerification {
-    pub fn create(e: &InsertableVerification) -> Result<Self, CustomError> {
-        let conn = database::connection()?;
-        let ev = diesel::insert_into(email_verification_code::table)
+    pub fn is_expired(&self) -> bool {
+        self.expires_on <= Utc::now().naive_utc()
+    }
+
+    pub fn create(conn: &mut PgConnection, e: &InsertableVerification) -> Result<Self> {
+        let res = diesel::insert_into(email_verification_code::table)
             .values(e)
             .on_conflict(email_verification_code::email_address)
             .do_update()
-            .set((
-                email_verification_code::activation_code.eq(&e.activation_code),
-                email_verification_code::expires_on.eq(&e.expires_on),
-            ))
-            .get_result(&conn)?;
-        Ok(ev)
-    }
+            .set(e)
+            .get_result(conn)?;
 
-    pub fn find_by_email(email: &String) -> Result<Self, CustomError> {
-        let conn = database::connection()?;
-        let v = email_verification_code::table
-            .filter(email_verification_code::email_address.eq(&email))
-            .first(&conn)?;
-        Ok(v)
+        Ok(res)
     }
 
-    pub fn delete(id: Uuid) -> Result<usize, CustomError> {
-        let conn = database::connection()?;
-        let res = diesel::delete(email_verification_code::table.filter(
-            email_verification_code::id.eq(id)
-        )).execute(&conn)?;
+    /// Deletes and returns the verification with this code hash, so each code
+    /// is only ever accepted once
+    pub fn consume(conn: &mut PgConnection, activation_code_hash: &str) -> Result<Self> {
+        let res = diesel::delete(email_verification_code::table)
+            .filter(email_verification_code::activation_code_hash.eq(activation_code_hash))
+            .get_result(conn)?;
+
         Ok(res)
     }
 }
 
-#[derive(Serialize, Deserialize, Queryable, Insertable, Debug, Identifiable, AsChangeset, Clone)]
-#[table_name = "password_reset_token"]
+#[derive(Serialize, Deserialize, Queryable, Debug, Identifiable, Clone)]
+#[diesel(table_name = password_reset_token)]
+/// A pending password reset. Issuing a new one for the same address
+/// replaces it.
 pub struct PasswordResetToken {
     pub id: Uuid,
     pub email_address: String,
-    pub reset_token: String,
+    pub reset_token_hash: String,
     pub expires_on: NaiveDateTime,
 }
 
 #[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable)]
-#[table_name = "password_reset_token"]
+#[diesel(table_name = password_reset_token)]
 pub struct InsertablePasswordResetToken {
     pub email_address: String,
-    pub reset_token: String,
+    pub reset_token_hash: String,
     pub expires_on: NaiveDateTime,
 }
 
 impl InsertablePasswordResetToken {
-    pub fn new(email_address: &String) -> Self {
-        let expires_on = Utc::now().naive_utc() + Duration::minutes(30);
-        let reset_token = generate_unique_code(36, false);
+    /// A reset for `email_address`, returned with the token to send
+    pub fn new(email_address: &str) -> (Self, String) {
+        let token = generate_email_token();
 
-        InsertablePasswordResetToken {
+        let reset = InsertablePasswordResetToken {
             email_address: email_address.to_owned(),
-            reset_token,
-            expires_on,
-        }
+            reset_token_hash: hash_email_token(&token),
+            expires_on: Utc::now().naive_utc() + Duration::seconds(PASSWORD_RESET_DURATION),
+        };
+
+        (reset, token)
     }
 }
 
 impl PasswordResetToken {
-    pub fn create(e: &InsertablePasswordResetToken) -> Result<Self, CustomError> {
-        let conn = database::connection()?;
-        let ev = diesel::insert_into(password_reset_token::table)
+    pub fn is_expired(&self) -> bool {
+        self.expires_on <= Utc::now().naive_utc()
+    }
+
+    pub fn create(conn: &mut PgConnection, e: &InsertablePasswordResetToken) -> Result<Self> {
+        let res = diesel::insert_into(password_reset_token::table)
             .values(e)
             .on_conflict(password_reset_token::email_address)
             .do_update()
-            .set((
-                password_reset_token::reset_token.eq(&e.reset_token),
-                password_reset_token::expires_on.eq(&e.expires_on),
-            ))
-            .get_result(&conn)?;
-        Ok(ev)
+            .set(e)
+            .get_result(conn)?;
+
+        Ok(res)
     }
 
-    pub fn find_by_token(token: &String) -> Result<Self, CustomError> {
-        let conn = database::connection()?;
-        let v = password_reset_token::table
-            .filter(password_reset_token::reset_token.eq(&token))
-            .first(&conn)?;
-        Ok(v)
+    /// Deletes and returns the reset with this token hash, so each token is
+    /// only ever accepted once
+    pub fn consume(conn: &mut PgConnection, reset_token_hash: &str) -> Result<Self> {
+        let res = diesel::delete(password_reset_token::table)
+            .filter(password_reset_token::reset_token_hash.eq(reset_token_hash))
+            .get_result(conn)?;
+
+        Ok(res)
     }
 
-    pub fn delete(id: Uuid) -> Result<usize, CustomError> {
-        let conn = database::connection()?;
-        let res = diesel::delete(password_reset_token::table.filter(
-            password_reset_token::id.eq(id)
-        )).execute(&conn)?;
+    /// Deletes any pending reset for this address, returning how many
+    pub fn delete_by_email(conn: &mut PgConnection, email_address: &str) -> Result<usize> {
+        let res = diesel::delete(password_reset_token::table)
+            .filter(password_reset_token::email_address.eq(email_address))
+            .execute(conn)?;
+
         Ok(res)
     }
-}
\ No newline at end of file
+}
@@ -2,13 +2,14 @@ use std::fmt::Debug;
 
 use async_graphql::*;
 use chrono::prelude::*;
-use diesel::{self, ExpressionMethods, Insertable, PgTextExpressionMethods, Queryable};
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, Queryable};
 use diesel::{QueryDsl, RunQueryDsl};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::models::{Nation, User};
-use crate::{database, schema::*};
+use crate::graphql::get_repositories_from_context;
+use crate::schema::*;
 
 #[derive(
     Debug, Clone, Deserialize, Serialize, Queryable, Insertable, AsChangeset, SimpleObject,
@@ -31,95 +32,86 @@ pub struct Authority {
 
 #[ComplexObject]
 impl Authority {
-    pub async fn creator(&self) -> Result<User> {
-        User::get_by_id(&self.creator_id)
+    pub async fn creator(&self, ctx: &Context<'_>) -> Result<User> {
+        get_repositories_from_context(ctx).users.get_by_id(self.creator_id).await
     }
 
-    pub async fn nation(&self) -> Result<Nation> {
-        Nation::get_by_id(&self.nation_id)
+    pub async fn nation(&self, ctx: &Context<'_>) -> Result<Nation> {
+        get_repositories_from_context(ctx).nations.get_by_id(self.nation_id).await
     }
 }
 
 // Non Graphql
 impl Authority {
-    pub fn create(authority: &NewAuthority) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn create(conn: &mut PgConnection, authority: &NewAuthority) -> Result<Self> {
 
         let res = diesel::insert_into(authorities::table)
             .values(authority)
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(res)
     }
 
-    pub fn get_or_create(authority: &NewAuthority) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_or_create(conn: &mut PgConnection, authority: &NewAuthority) -> Result<Self> {
 
         let res = authorities::table
             .filter(authorities::creator_id.eq(&authority.creator_id))
             .distinct()
-            .first(&mut conn);
+            .first(conn);
 
         let authority = match res {
             Ok(p) => p,
             Err(e) => {
                 // Authority not found
                 println!("{:?}", e);
-                let p = Authority::create(authority).expect("Unable to create authority");
-                p
+                Authority::create(conn, authority).expect("Unable to create authority")
             }
         };
         Ok(authority)
     }
 
-    pub fn get_all() -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
-        let res = authorities::table.load::<Authority>(&mut conn)?;
+    pub fn get_all(conn: &mut PgConnection) -> Result<Vec<Self>> {
+        let res = authorities::table.load::<Authority>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_id(id: &Uuid) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_by_id(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
         let res = authorities::table
             .filter(authorities::id.eq(id))
-            .first(&mut conn)?;
+            .first(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_creator_id(creator_id: Uuid) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    pub fn get_by_creator_id(conn: &mut PgConnection, creator_id: Uuid) -> Result<Vec<Self>> {
         let res = authorities::table
             .filter(authorities::creator_id.eq(creator_id))
-            .load::<Authority>(&mut conn)?;
+            .load::<Authority>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_nation_id(nation_id: &Uuid) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    pub fn get_by_nation_id(conn: &mut PgConnection, nation_id: &Uuid) -> Result<Vec<Self>> {
         let res = authorities::table
             .filter(authorities::nation_id.eq(nation_id))
-            .load::<Authority>(&mut conn)?;
+            .load::<Authority>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_nation_code(nation_code: &String) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    pub fn get_by_nation_code(conn: &mut PgConnection, nation_code: &String) -> Result<Vec<Self>> {
 
-        let nation_id = Nation::get_by_code(nation_code)?.id;
+        let nation_id = Nation::get_by_code(conn, nation_code)?.id;
 
         let res = authorities::table
             .filter(authorities::nation_id.eq(nation_id))
-            .load::<Authority>(&mut conn)?;
+            .load::<Authority>(conn)?;
         Ok(res)
     }
 
-    pub fn update(&self) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn update(&self, conn: &mut PgConnection) -> Result<Self> {
 
         let res = diesel::update(authorities::table)
             .filter(authorities::id.eq(&self.id))
             .set(self)
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(res)
     }
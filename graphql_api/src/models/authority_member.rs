@@ -0,0 +1,80 @@
+use async_graphql::*;
+use chrono::prelude::*;
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, QueryDsl, Queryable, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::graphql::get_repositories_from_context;
+use crate::models::Authority;
+use crate::schema::*;
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, SimpleObject)]
+#[diesel(table_name = authority_members)]
+#[graphql(complex)]
+/// A user acting for an authority. Members see the conversion requests
+/// created by, or targeted at, the authority and its nation.
+pub struct AuthorityMember {
+    pub user_id: Uuid,
+    pub authority_id: Uuid,
+    pub created_at: NaiveDateTime,
+}
+
+// GraphQL Complex Object implementation
+#[ComplexObject]
+impl AuthorityMember {
+    pub async fn authority(&self, ctx: &Context<'_>) -> Result<Authority> {
+        get_repositories_from_context(ctx).authorities.get_by_id(self.authority_id).await
+    }
+}
+
+// Non GraphQL implementation
+impl AuthorityMember {
+    /// Adds a membership, returning the existing one if the user is already
+    /// a member
+    pub fn add(conn: &mut PgConnection, member: &NewAuthorityMember) -> Result<Self> {
+        diesel::insert_into(authority_members::table)
+            .values(member)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        let res = authority_members::table
+            .filter(authority_members::user_id.eq(member.user_id))
+            .filter(authority_members::authority_id.eq(member.authority_id))
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    /// Removes a membership, returning whether there was one
+    pub fn remove(conn: &mut PgConnection, user_id: &Uuid, authority_id: &Uuid) -> Result<bool> {
+        let res = diesel::delete(authority_members::table)
+            .filter(authority_members::user_id.eq(user_id))
+            .filter(authority_members::authority_id.eq(authority_id))
+            .execute(conn)?;
+
+        Ok(res > 0)
+    }
+
+    pub fn get_by_user_id(conn: &mut PgConnection, user_id: &Uuid) -> Result<Vec<Self>> {
+        let res = authority_members::table
+            .filter(authority_members::user_id.eq(user_id))
+            .load::<AuthorityMember>(conn)?;
+
+        Ok(res)
+    }
+
+    pub fn get_by_authority_id(conn: &mut PgConnection, authority_id: &Uuid) -> Result<Vec<Self>> {
+        let res = authority_members::table
+            .filter(authority_members::authority_id.eq(authority_id))
+            .load::<AuthorityMember>(conn)?;
+
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Insertable)]
+#[diesel(table_name = authority_members)]
+pub struct NewAuthorityMember {
+    pub user_id: Uuid,
+    pub authority_id: Uuid,
+}
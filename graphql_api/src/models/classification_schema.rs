@@ -2,13 +2,27 @@ use std::fmt::Debug;
 
 use async_graphql::*;
 use chrono::prelude::*;
-use diesel::{self, ExpressionMethods, Insertable, PgTextExpressionMethods, Queryable};
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, Queryable};
 use diesel::{QueryDsl, RunQueryDsl};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::models::{Authority, User};
-use crate::{database, schema::*};
+use crate::graphql::get_repositories_from_context;
+use crate::schema::*;
+
+/// The NATO classification scale every national schema maps onto
+#[derive(
+    Debug, Display, EnumString, EnumIter, Enum, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum NatoClassification {
+    Unclassified,
+    Restricted,
+    Confidential,
+    Secret,
+    TopSecret,
+}
 
 #[derive(
     Debug, Clone, Deserialize, Serialize, Queryable, Insertable, AsChangeset, SimpleObject,
@@ -45,130 +59,152 @@ pub struct ClassificationSchema {
 // GraphQL implementation
 #[ComplexObject]
 impl ClassificationSchema {
-    pub async fn get_creator(&self) -> Result<User> {
-        User::get_by_id(&self.creator_id)
+    pub async fn get_creator(&self, ctx: &Context<'_>) -> Result<User> {
+        get_repositories_from_context(ctx).users.get_by_id(self.creator_id).await
     }
 
-    pub async fn get_authority(&self) -> Result<Authority> {
-        Authority::get_by_id(&self.authority_id)
+    pub async fn get_authority(&self, ctx: &Context<'_>) -> Result<Authority> {
+        get_repositories_from_context(ctx).authorities.get_by_id(self.authority_id).await
     }
 }
 
 // Non GraphQL
 impl ClassificationSchema {
-    pub fn create(schema: &NewClassificationSchema) -> Result<Self> {
-        let mut conn = database::connection()?;
+    /// Maps a national marking onto the NATO scale (case-insensitive).
+    /// Where one marking covers several NATO levels the highest is returned,
+    /// so a conversion never downgrades.
+    pub fn to_nato(&self, marking: &str) -> Result<NatoClassification> {
+        let lowercase = marking.trim().to_lowercase();
+
+        [
+            (NatoClassification::TopSecret, &self.to_nato_top_secret),
+            (NatoClassification::Secret, &self.to_nato_secret),
+            (NatoClassification::Confidential, &self.to_nato_confidential),
+            (NatoClassification::Restricted, &self.to_nato_restricted),
+            (NatoClassification::Unclassified, &self.to_nato_unclassified),
+        ]
+        .into_iter()
+        .find(|(_, m)| m.to_lowercase() == lowercase)
+        .map(|(level, _)| level)
+        .ok_or_else(|| {
+            Error::new(format!(
+                "Marking \"{}\" is not defined in the {} {} schema",
+                marking.trim(), self.nation_code, self.version
+            ))
+        })
+    }
+
+    /// The national marking for a NATO level
+    pub fn from_nato(&self, level: NatoClassification) -> &str {
+        match level {
+            NatoClassification::Unclassified => &self.from_nato_unclassified,
+            NatoClassification::Restricted => &self.from_nato_restricted,
+            NatoClassification::Confidential => &self.from_nato_confidential,
+            NatoClassification::Secret => &self.from_nato_secret,
+            NatoClassification::TopSecret => &self.from_nato_top_secret,
+        }
+    }
+
+    pub fn create(conn: &mut PgConnection, schema: &NewClassificationSchema) -> Result<Self> {
 
         let res = diesel::insert_into(classification_schemas::table)
             .values(schema)
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(res)
     }
 
-    pub fn get_or_create(schema: &NewClassificationSchema) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_or_create(conn: &mut PgConnection, schema: &NewClassificationSchema) -> Result<Self> {
 
         let res = classification_schemas::table
             .filter(classification_schemas::nation_code.eq(&schema.nation_code))
             .filter(classification_schemas::version.eq(&schema.version))
             .distinct()
-            .first(&mut conn);
+            .first(conn);
 
         let schema = match res {
             Ok(s) => s,
             Err(e) => {
                 // ClassificationSchema not found
                 println!("{:?}", e);
-                let s = ClassificationSchema::create(schema)
-                    .expect("Unable to create classification_schema");
-                s
+                ClassificationSchema::create(conn, schema)
+                    .expect("Unable to create classification_schema")
             }
         };
         Ok(schema)
     }
 
-    pub fn get_all() -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
-        let res = classification_schemas::table.load::<ClassificationSchema>(&mut conn)?;
+    pub fn get_all(conn: &mut PgConnection) -> Result<Vec<Self>> {
+        let res = classification_schemas::table.load::<ClassificationSchema>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_id(id: &Uuid) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_by_id(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
         let res = classification_schemas::table
             .filter(classification_schemas::id.eq(id))
-            .first(&mut conn)?;
+            .first(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_ids(ids: Vec<Uuid>) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    pub fn get_by_ids(conn: &mut PgConnection, ids: Vec<Uuid>) -> Result<Vec<Self>> {
         let res = classification_schemas::table
             .filter(classification_schemas::id.eq_any(ids))
-            .load::<ClassificationSchema>(&mut conn)?;
+            .load::<ClassificationSchema>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_creator_id(creator_id: Uuid) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    pub fn get_by_creator_id(conn: &mut PgConnection, creator_id: Uuid) -> Result<Vec<Self>> {
         let res = classification_schemas::table
             .filter(classification_schemas::creator_id.eq(creator_id))
-            .load::<ClassificationSchema>(&mut conn)?;
+            .load::<ClassificationSchema>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_nation_code(nation_code: &String) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    pub fn get_by_nation_code(conn: &mut PgConnection, nation_code: &String) -> Result<Vec<Self>> {
         let res = classification_schemas::table
             .filter(classification_schemas::nation_code.eq(nation_code))
-            .load::<ClassificationSchema>(&mut conn)?;
+            .load::<ClassificationSchema>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_nation_code_and_version(nation_code: &String, version: &String) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_by_nation_code_and_version(conn: &mut PgConnection, nation_code: &String, version: &String) -> Result<Self> {
         let res = classification_schemas::table
             .filter(classification_schemas::nation_code.eq(nation_code))
             .filter(classification_schemas::version.eq(version))
-            .first(&mut conn)?;
+            .first(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_authority_id(authority_id: &Uuid) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    pub fn get_by_authority_id(conn: &mut PgConnection, authority_id: &Uuid) -> Result<Vec<Self>> {
         let res = classification_schemas::table
             .filter(classification_schemas::authority_id.eq(authority_id))
-            .load::<ClassificationSchema>(&mut conn)?;
+            .load::<ClassificationSchema>(conn)?;
         Ok(res)
     }
 
-    pub fn get_latest_by_nation_code(nation_code: &String) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_latest_by_nation_code(conn: &mut PgConnection, nation_code: &String) -> Result<Self> {
         let res = classification_schemas::table
             .filter(classification_schemas::nation_code.eq(nation_code))
             .order(classification_schemas::created_at.desc())
-            .first(&mut conn)?;
+            .first(conn)?;
         Ok(res)
     }
 
-    pub fn get_count() -> Result<i64> {
-        let mut conn = database::connection()?;
+    pub fn get_count(conn: &mut PgConnection) -> Result<i64> {
 
         let res = classification_schemas::table
             .count()
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(res)
     }
 
-    pub fn update(&self) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn update(&self, conn: &mut PgConnection) -> Result<Self> {
 
         let res = diesel::update(classification_schemas::table)
             .filter(classification_schemas::id.eq(&self.id))
             .set(self)
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(res)
     }
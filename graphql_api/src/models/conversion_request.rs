@@ -1,8 +1,12 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::str::FromStr;
 
 use async_graphql::*;
 use chrono::prelude::*;
+use diesel::pg::Pg;
 use diesel::prelude::*;
+use diesel::sql_types::Bool;
 use diesel::{
     self, ExpressionMethods, Insertable, Queryable,
 };
@@ -10,11 +14,14 @@ use diesel::{QueryDsl, RunQueryDsl};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::database::connection;
+use crate::graphql::get_repositories_from_context;
+use crate::repositories::{AuditEntriesFor, CompletedConversion, ConversionResultsFor, TenantScope};
+use crate::schema::*;
 
-use crate::{database, schema::*};
-
-use crate::models::{Authority, DataObject, InsertableDataObject, InsertableMetadata, Metadata, NewDataObject, NewMetadata, User};
+use crate::models::{
+    AuditEntry, Authority, ConversionResult, DataObject, InsertableDataObject, InsertableMetadata, Metadata, NatoClassification,
+    NewDataObject, User,
+};
 
 #[derive(
     Debug,
@@ -56,33 +63,88 @@ pub struct ConversionRequest {
 pub struct InsertableConversionRequest {
     pub user_id: Uuid,
     pub authority_id: Uuid,
-    pub data_object: InsertableDataObject,
-    pub metadata: InsertableMetadata,
+    /// A data object the user uploaded, converted instead of creating one
+    /// from data_object and metadata
+    pub data_object_id: Option<Uuid>,
+    pub data_object: Option<InsertableDataObject>,
+    pub metadata: Option<InsertableMetadata>,
     pub source_nation_code: String,
     pub target_nation_codes: Vec<String>,
 }
 
+/// The data object a conversion request converts
+pub enum ConversionSubject {
+    /// A data object the user uploaded
+    Uploaded(Uuid),
+    /// A data object to create, with its metadata
+    New(Box<NewDataObject>, InsertableMetadata),
+}
+
+impl InsertableConversionRequest {
+    pub fn subject(&self) -> Result<ConversionSubject> {
+        match (self.data_object_id, &self.data_object, &self.metadata) {
+            (Some(id), None, None) => Ok(ConversionSubject::Uploaded(id)),
+            (None, Some(data_object), Some(metadata)) => Ok(ConversionSubject::New(
+                Box::new(data_object.to_new_data_object(self.user_id)?),
+                metadata.clone(),
+            )),
+            _ => Err(Error::new("Pass either dataObjectId, or dataObject and metadata")),
+        }
+    }
+}
+
+/// A conversion request submitted through GraphQL. The signed-in user is
+/// recorded as the creator.
+#[derive(Debug, Clone, Deserialize, Serialize, InputObject)]
+pub struct ConversionRequestInput {
+    pub authority_id: Uuid,
+    /// Id returned by `/data-objects/upload` for a document uploaded by the
+    /// signed-in user. Leave out dataObject and metadata when it is set.
+    pub data_object_id: Option<Uuid>,
+    pub data_object: Option<InsertableDataObject>,
+    pub metadata: Option<InsertableMetadata>,
+    pub source_nation_code: String,
+    /// The data object's marking in the source nation's schema
+    pub source_marking: String,
+    pub target_nation_codes: Vec<String>,
+}
+
 // GraphQL Complex Object implementation
 #[ComplexObject]
 impl ConversionRequest {
     /// Get the user who created this conversion request
-    pub async fn creator(&self) -> Result<User> {
-        User::get_by_id(&self.creator_id)
+    pub async fn creator(&self, ctx: &Context<'_>) -> Result<User> {
+        get_repositories_from_context(ctx).users.get_by_id(self.creator_id).await
     }
 
     /// Get the authority that is requesting this conversion
-    pub async fn authority(&self) -> Result<Authority> {
-        Authority::get_by_id(&self.authority_id)
+    pub async fn authority(&self, ctx: &Context<'_>) -> Result<Authority> {
+        get_repositories_from_context(ctx).authorities.get_by_id(self.authority_id).await
     }
 
     /// Get the data object that is being converted
-    pub async fn data_object(&self) -> Result<DataObject> {
-        DataObject::get_by_id(&self.data_object_id)
+    pub async fn data_object(&self, ctx: &Context<'_>) -> Result<DataObject> {
+        get_repositories_from_context(ctx).data_objects.get_by_id(self.data_object_id).await
+    }
+
+    /// Get the metadata for the data object, or null when the object is
+    /// redacted for the caller
+    pub async fn metadata(&self, ctx: &Context<'_>) -> Result<Option<Metadata>> {
+        let repos = get_repositories_from_context(ctx);
+
+        if repos.data_objects.is_redacted(self.data_object_id).await? {
+            return Ok(None);
+        }
+
+        repos.metadata.get_by_data_object_id(self.data_object_id).await.map(Some)
     }
 
-    /// Get the metadata for the data object
-    pub async fn metadata(&self) -> Result<Metadata> {
-        Metadata::get_by_data_object_id(&self.data_object_id)
+    /// Get the converted markings for each target nation
+    pub async fn results(&self, ctx: &Context<'_>) -> Result<Vec<ConversionResult>> {
+        get_repositories_from_context(ctx)
+            .conversion_results
+            .get_by_conversion_request_id(self.id)
+            .await
     }
 
     /// Check if this conversion request has been completed
@@ -96,154 +158,236 @@ impl ConversionRequest {
     /// Process a conversion request payload by creating data objects, metadata, and the request itself
     /// This is the main entry point for handling incoming conversion requests
     ///
-    /// Workflow:
-    /// 1. Create DataObject from payload
+    /// Workflow (in a single transaction):
+    /// 1. Create DataObject from payload, or load the uploaded one
     /// 2. Create Metadata with the new DataObject ID
     /// 3. Create ConversionRequest with all IDs
-    /// 4. TODO: Trigger security classification conversion process
-    pub fn process_payload(payload: &InsertableConversionRequest) -> Result<ConversionRequest> {
-        let mut conn = connection()?;
-
-        // Step 1: Create the DataObject
-        let new_data_object = NewDataObject {
-            creator_id: payload.user_id,
-            title: payload.data_object.title.clone(),
-            description: payload.data_object.description.clone(),
-        };
-        let data_object = DataObject::create(&new_data_object)?;
-
-        // Step 2: Create the Metadata with the generated DataObject ID
-        let new_metadata = NewMetadata {
-            data_object_id: data_object.id,
-            domain: payload.metadata.domain.clone(),
-            tags: payload.metadata.tags.clone(),
-        };
-        let _metadata = Metadata::create(&new_metadata)?;
-
-        // Step 3: Create the ConversionRequest
-        let new_request = NewConversionRequest {
-            creator_id: payload.user_id,
-            authority_id: payload.authority_id,
-            data_object_id: data_object.id,
-            source_nation_code: payload.source_nation_code.clone(),
-            target_nation_codes: payload.target_nation_codes.clone(),
-        };
-
-        let conversion_request = diesel::insert_into(conversion_requests::table)
-            .values(&new_request)
-            .get_result(&mut conn)?;
-
-        // TODO: Trigger security classification conversion process here
-        // This would involve:
-        // - Looking up source nation classification schema
-        // - Converting to NATO standard
-        // - Converting from NATO to each target nation
-        // - Generating response
+    /// 4. Append the audit entries `audit` makes of the request
+    ///
+    /// `submitConversionRequest` goes through `process_and_complete` instead,
+    /// which stores the results in the same transaction.
+    pub fn process_payload(
+        conn: &mut PgConnection,
+        payload: &InsertableConversionRequest,
+        audit: AuditEntriesFor<ConversionRequest>,
+    ) -> Result<ConversionRequest> {
+
+        let conversion_request = conn.transaction::<_, Error, _>(|conn| {
+            // Steps 1 and 2: Create the DataObject and its Metadata, unless
+            // the document was uploaded with them
+            let data_object = match payload.subject()? {
+                ConversionSubject::New(new_data_object, metadata) => {
+                    DataObject::create_with_metadata(conn, &new_data_object, &metadata)?.0
+                }
+                ConversionSubject::Uploaded(id) => {
+                    let data_object = DataObject::get_by_id(conn, &id)?;
+                    data_object.ensure_uploaded_by(payload.user_id)?;
+                    data_object
+                }
+            };
+
+            // Step 3: Create the ConversionRequest
+            let new_request = NewConversionRequest {
+                creator_id: payload.user_id,
+                authority_id: payload.authority_id,
+                data_object_id: data_object.id,
+                source_nation_code: payload.source_nation_code.clone(),
+                target_nation_codes: payload.target_nation_codes.clone(),
+            };
+
+            let conversion_request = diesel::insert_into(conversion_requests::table)
+                .values(&new_request)
+                .get_result(conn)?;
+
+            // Step 4: Record it in the audit log
+            let entries = audit(&conversion_request);
+            if !entries.is_empty() {
+                AuditEntry::append(conn, entries)?;
+            }
+
+            Ok(conversion_request)
+        })?;
 
         Ok(conversion_request)
     }
 
+    /// `process_payload`, then `ConversionResult::complete_request` with the
+    /// results `convert` makes of the new request, and the audit entries
+    /// `audit` makes of the outcome, in a single transaction
+    pub fn process_and_complete(
+        conn: &mut PgConnection,
+        payload: &InsertableConversionRequest,
+        convert: ConversionResultsFor,
+        audit: AuditEntriesFor<CompletedConversion>,
+    ) -> Result<CompletedConversion> {
+        let completed = conn.transaction::<_, Error, _>(|conn| {
+            let request = Self::process_payload(conn, payload, Box::new(|_| Vec::new()))?;
+            let completed = ConversionResult::complete_request(conn, &request.id, convert(&request))?;
+
+            let entries = audit(&completed);
+            if !entries.is_empty() {
+                AuditEntry::append(conn, entries)?;
+            }
+
+            Ok(completed)
+        })?;
+
+        Ok(completed)
+    }
+
     /// Get all conversion requests
-    pub fn get_all() -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
-        let res = conversion_requests::table.load::<ConversionRequest>(&mut conn)?;
+    pub fn get_all(conn: &mut PgConnection) -> Result<Vec<Self>> {
+        let res = conversion_requests::table.load::<ConversionRequest>(conn)?;
         Ok(res)
     }
 
     /// Get a limited number of conversion requests
-    pub fn get_count(count: i64) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    pub fn get_count(conn: &mut PgConnection, count: i64) -> Result<Vec<Self>> {
         let res = conversion_requests::table
             .limit(count)
-            .load::<ConversionRequest>(&mut conn)?;
+            .load::<ConversionRequest>(conn)?;
+        Ok(res)
+    }
+
+    /// Conversion requests visible in each of `scopes`, at most `limit` of them
+    pub fn get_in_scope(conn: &mut PgConnection, scopes: &[TenantScope], limit: Option<i64>) -> Result<Vec<Self>> {
+        let mut query = conversion_requests::table.into_boxed();
+
+        for scope in scopes {
+            query = query.filter(in_scope(scope));
+        }
+
+        if let Some(limit) = limit {
+            query = query.limit(limit);
+        }
+
+        let res = query.load::<ConversionRequest>(conn)?;
         Ok(res)
     }
 
+    /// Ids of the data objects carried by conversion requests visible in
+    /// `scope`, only out of `data_object_ids` when given
+    pub fn get_data_object_ids_in_scope(
+        conn: &mut PgConnection,
+        scope: &TenantScope,
+        data_object_ids: Option<Vec<Uuid>>,
+    ) -> Result<Vec<Uuid>> {
+        let mut query = conversion_requests::table
+            .filter(in_scope(scope))
+            .select(conversion_requests::data_object_id)
+            .distinct()
+            .into_boxed();
+
+        if let Some(ids) = data_object_ids {
+            query = query.filter(conversion_requests::data_object_id.eq_any(ids));
+        }
+
+        let res = query.load::<Uuid>(conn)?;
+        Ok(res)
+    }
+
+    /// Markings of the data objects in `data_object_ids`, from every request
+    /// carrying them and those requests' results, in one query
+    pub fn get_markings(conn: &mut PgConnection, data_object_ids: Vec<Uuid>) -> Result<HashMap<Uuid, DataObjectMarking>> {
+        let rows = conversion_requests::table
+            .left_join(conversion_results::table)
+            .filter(conversion_requests::data_object_id.eq_any(data_object_ids))
+            .select((
+                conversion_requests::data_object_id,
+                conversion_requests::source_nation_code,
+                conversion_requests::target_nation_codes,
+                conversion_results::nato_classification.nullable(),
+            ))
+            .load::<(Uuid, String, Vec<Option<String>>, Option<String>)>(conn)?;
+
+        let mut markings: HashMap<Uuid, DataObjectMarking> = HashMap::new();
+        for (data_object_id, source_nation_code, target_nation_codes, classification) in rows {
+            markings.entry(data_object_id).or_default().add(
+                source_nation_code,
+                target_nation_codes,
+                classification.as_deref(),
+            );
+        }
+
+        Ok(markings)
+    }
+
     /// Get a conversion request by ID
-    pub fn get_by_id(id: &Uuid) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_by_id(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
         let res = conversion_requests::table
             .filter(conversion_requests::id.eq(id))
-            .first(&mut conn)?;
+            .first(conn)?;
         Ok(res)
     }
 
     /// Get all conversion requests by creator ID
-    pub fn get_by_creator_id(creator_id: &Uuid) -> Result<Vec<Self>> {
-        let mut conn = connection()?;
+    pub fn get_by_creator_id(conn: &mut PgConnection, creator_id: &Uuid) -> Result<Vec<Self>> {
         let res = conversion_requests::table
             .filter(conversion_requests::creator_id.eq(creator_id))
-            .load::<ConversionRequest>(&mut conn)?;
+            .load::<ConversionRequest>(conn)?;
         Ok(res)
     }
 
     /// Get all conversion requests by authority ID
-    pub fn get_by_authority_id(authority_id: &Uuid) -> Result<Vec<Self>> {
-        let mut conn = connection()?;
+    pub fn get_by_authority_id(conn: &mut PgConnection, authority_id: &Uuid) -> Result<Vec<Self>> {
         let res = conversion_requests::table
             .filter(conversion_requests::authority_id.eq(authority_id))
-            .load::<ConversionRequest>(&mut conn)?;
+            .load::<ConversionRequest>(conn)?;
         Ok(res)
     }
 
     /// Get all conversion requests by data object ID
-    pub fn get_by_data_object_id(data_object_id: &Uuid) -> Result<Vec<Self>> {
-        let mut conn = connection()?;
+    pub fn get_by_data_object_id(conn: &mut PgConnection, data_object_id: &Uuid) -> Result<Vec<Self>> {
         let res = conversion_requests::table
             .filter(conversion_requests::data_object_id.eq(data_object_id))
-            .load::<ConversionRequest>(&mut conn)?;
+            .load::<ConversionRequest>(conn)?;
         Ok(res)
     }
 
     /// Get all conversion requests by source nation code
-    pub fn get_by_source_nation_code(nation_code: &str) -> Result<Vec<Self>> {
-        let mut conn = connection()?;
+    pub fn get_by_source_nation_code(conn: &mut PgConnection, nation_code: &str) -> Result<Vec<Self>> {
         let res = conversion_requests::table
             .filter(conversion_requests::source_nation_code.eq(nation_code))
-            .load::<ConversionRequest>(&mut conn)?;
+            .load::<ConversionRequest>(conn)?;
         Ok(res)
     }
 
     /// Get all pending (not completed) conversion requests
-    pub fn get_pending() -> Result<Vec<Self>> {
-        let mut conn = connection()?;
+    pub fn get_pending(conn: &mut PgConnection) -> Result<Vec<Self>> {
         let res = conversion_requests::table
             .filter(conversion_requests::completed_at.is_null())
-            .load::<ConversionRequest>(&mut conn)?;
+            .load::<ConversionRequest>(conn)?;
         Ok(res)
     }
 
     /// Get all completed conversion requests
-    pub fn get_completed() -> Result<Vec<Self>> {
-        let mut conn = connection()?;
+    pub fn get_completed(conn: &mut PgConnection) -> Result<Vec<Self>> {
         let res = conversion_requests::table
             .filter(conversion_requests::completed_at.is_not_null())
-            .load::<ConversionRequest>(&mut conn)?;
+            .load::<ConversionRequest>(conn)?;
         Ok(res)
     }
 
     /// Mark a conversion request as completed
-    pub fn mark_completed(&mut self) -> Result<Self> {
+    pub fn mark_completed(&mut self, conn: &mut PgConnection) -> Result<Self> {
         self.completed_at = Some(Utc::now().naive_utc());
-        self.update()
+        self.update(conn)
     }
 
     /// Update a conversion request with changed data
-    pub fn update(&self) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn update(&self, conn: &mut PgConnection) -> Result<Self> {
         let res = diesel::update(conversion_requests::table)
             .filter(conversion_requests::id.eq(&self.id))
             .set(self)
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
         Ok(res)
     }
 
     /// Delete a conversion request
-    pub fn delete(&self) -> Result<usize> {
-        let mut conn = database::connection()?;
+    pub fn delete(&self, conn: &mut PgConnection) -> Result<usize> {
         let res = diesel::delete(conversion_requests::table)
             .filter(conversion_requests::id.eq(&self.id))
-            .execute(&mut conn)?;
+            .execute(conn)?;
         Ok(res)
     }
 }
@@ -259,3 +403,43 @@ struct NewConversionRequest {
     pub source_nation_code: String,
     pub target_nation_codes: Vec<String>,
 }
+
+/// The classification of a data object and the nations it is released to,
+/// from the conversion requests carrying it
+#[derive(Debug, Clone, Default)]
+pub struct DataObjectMarking {
+    /// Highest level its conversions produced, None before any completes
+    pub classification: Option<NatoClassification>,
+    pub nations: HashSet<String>,
+}
+
+impl DataObjectMarking {
+    /// Adds one request carrying the data object, with one of its results'
+    /// classification if it has any. Unrecognised levels count as TOP_SECRET.
+    pub fn add(&mut self, source_nation_code: String, target_nation_codes: Vec<Option<String>>, classification: Option<&str>) {
+        if let Some(classification) = classification {
+            let level = NatoClassification::from_str(classification).unwrap_or(NatoClassification::TopSecret);
+            self.classification = self.classification.max(Some(level));
+        }
+
+        self.nations.insert(source_nation_code);
+        self.nations.extend(target_nation_codes.into_iter().flatten());
+    }
+}
+
+/// SQL form of `TenantScope::allows`
+fn in_scope(scope: &TenantScope) -> Box<dyn BoxableExpression<conversion_requests::table, Pg, SqlType = Bool>> {
+    match scope {
+        TenantScope::All => Box::new(diesel::dsl::sql::<Bool>("TRUE")),
+        TenantScope::Members { authority_ids, nation_codes } => {
+            let targets: Vec<Option<String>> = nation_codes.iter().cloned().map(Some).collect();
+
+            Box::new(
+                conversion_requests::authority_id
+                    .eq_any(authority_ids.iter().copied().collect::<Vec<_>>())
+                    .or(conversion_requests::source_nation_code.eq_any(nation_codes.iter().cloned().collect::<Vec<_>>()))
+                    .or(conversion_requests::target_nation_codes.overlaps_with(targets)),
+            )
+        }
+    }
+}
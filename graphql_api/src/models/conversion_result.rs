@@ -0,0 +1,303 @@
+use std::fmt::Debug;
+
+use async_graphql::*;
+use chrono::prelude::*;
+use diesel::prelude::*;
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, Queryable};
+use diesel::{QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::graphql::get_repositories_from_context;
+use crate::schema::*;
+
+use crate::models::{issue_receipts, ClassificationSchema, ConversionRequest, DataObject, NatoClassification};
+
+#[derive(
+    Debug,
+    Clone,
+    Deserialize,
+    Serialize,
+    Queryable,
+    Identifiable,
+    Insertable,
+    SimpleObject,
+    Associations,
+)]
+#[diesel(belongs_to(ConversionRequest, foreign_key = conversion_request_id))]
+#[diesel(table_name = conversion_results)]
+#[graphql(complex)]
+/// The equivalent marking for one target nation, produced by converting the
+/// source nation's marking to NATO and from NATO to the target's schema.
+pub struct ConversionResult {
+    pub id: Uuid,
+    pub conversion_request_id: Uuid,
+    pub source_nation_code: String,
+    pub source_marking: String,
+    pub source_schema_id: Uuid, // ClassificationSchema
+    /// NATO level the source marking maps to
+    pub nato_classification: String,
+    pub target_nation_code: String,
+    pub target_marking: String,
+    pub target_schema_id: Uuid, // ClassificationSchema
+    pub created_at: NaiveDateTime,
+    /// Canonical JSON statement of this conversion; see `verify_receipt`
+    #[graphql(skip)]
+    pub receipt: Option<String>,
+    /// Base64 Ed25519 signature of `receipt`, or null if it wasn't signed
+    pub signature: Option<String>,
+    /// Key in `/.well-known/receipt-keys.json` that signed `receipt`
+    pub signing_key_id: Option<String>,
+}
+
+/// Published when a conversion request has produced its results
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ConversionCompleted {
+    pub conversion_request: ConversionRequest,
+    pub results: Vec<ConversionResult>,
+}
+
+// GraphQL Complex Object implementation
+#[ComplexObject]
+impl ConversionResult {
+    /// Get the source nation's schema used for this conversion
+    pub async fn source_schema(&self, ctx: &Context<'_>) -> Result<ClassificationSchema> {
+        get_repositories_from_context(ctx).schemas.get_by_id(self.source_schema_id).await
+    }
+
+    /// Get the target nation's schema used for this conversion
+    pub async fn target_schema(&self, ctx: &Context<'_>) -> Result<ClassificationSchema> {
+        get_repositories_from_context(ctx).schemas.get_by_id(self.target_schema_id).await
+    }
+
+    /// Canonical JSON statement of this conversion; see `verify_receipt`.
+    /// Null when the data object is redacted for the caller, as the receipt
+    /// states its digest and size.
+    pub async fn receipt(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        let repos = get_repositories_from_context(ctx);
+        let request = repos.conversion_requests.get_by_id(self.conversion_request_id).await?;
+
+        if repos.data_objects.is_redacted(request.data_object_id).await? {
+            return Ok(None);
+        }
+
+        Ok(self.receipt.clone())
+    }
+}
+
+// Non GraphQL implementation
+impl ConversionResult {
+    /// Store the results for a conversion request with their receipts and
+    /// mark it completed, in a single transaction
+    pub fn complete_request(
+        conn: &mut PgConnection,
+        conversion_request_id: &Uuid,
+        results: Vec<NewConversionResult>,
+    ) -> Result<(ConversionRequest, Vec<Self>)> {
+        let completed = conn.transaction::<_, Error, _>(|conn| {
+            let mut request = ConversionRequest::get_by_id(conn, conversion_request_id)?;
+            let data_object = DataObject::get_by_id(conn, &request.data_object_id)?;
+
+            let schema_ids = results
+                .iter()
+                .flat_map(|r| [r.source_schema_id, r.target_schema_id])
+                .collect();
+            let schemas = ClassificationSchema::get_by_ids(conn, schema_ids)?;
+
+            let results = issue_receipts(results, &data_object, &schemas)?;
+
+            let results = diesel::insert_into(conversion_results::table)
+                .values(&results)
+                .get_results(conn)?;
+
+            let request = request.mark_completed(conn)?;
+
+            Ok((request, results))
+        })?;
+
+        Ok(completed)
+    }
+
+    pub fn get_by_id(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
+        let res = conversion_results::table
+            .filter(conversion_results::id.eq(id))
+            .first(conn)?;
+        Ok(res)
+    }
+
+    /// Get all results for a conversion request
+    pub fn get_by_conversion_request_id(
+        conn: &mut PgConnection,
+        conversion_request_id: &Uuid,
+    ) -> Result<Vec<Self>> {
+        let res = conversion_results::table
+            .filter(conversion_results::conversion_request_id.eq(conversion_request_id))
+            .load::<ConversionResult>(conn)?;
+        Ok(res)
+    }
+}
+
+/// A converted marking ready to be stored against its ConversionRequest
+#[derive(Debug, Clone, Deserialize, Serialize, Insertable)]
+#[diesel(table_name = conversion_results)]
+pub struct NewConversionResult {
+    pub conversion_request_id: Uuid,
+    pub source_nation_code: String,
+    pub source_marking: String,
+    pub source_schema_id: Uuid,
+    pub nato_classification: String,
+    pub target_nation_code: String,
+    pub target_marking: String,
+    pub target_schema_id: Uuid,
+    /// Set by `issue_receipts` when the result is stored
+    pub receipt: Option<String>,
+    pub signature: Option<String>,
+    pub signing_key_id: Option<String>,
+}
+
+impl NewConversionResult {
+    /// One result per target schema for a source marking already mapped to
+    /// `level` by `ClassificationSchema::to_nato`
+    pub fn convert(
+        conversion_request_id: Uuid,
+        source_marking: &str,
+        level: NatoClassification,
+        source_schema: &ClassificationSchema,
+        target_schemas: &[ClassificationSchema],
+    ) -> Vec<Self> {
+        target_schemas
+            .iter()
+            .map(|target| NewConversionResult {
+                conversion_request_id,
+                source_nation_code: source_schema.nation_code.clone(),
+                source_marking: source_marking.trim().to_owned(),
+                source_schema_id: source_schema.id,
+                nato_classification: level.to_string(),
+                target_nation_code: target.nation_code.clone(),
+                target_marking: target.from_nato(level).to_owned(),
+                target_schema_id: target.id,
+                receipt: None,
+                signature: None,
+                signing_key_id: None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A schema whose markings are `to` on the way in and `from` on the
+    /// way out, in NATO order
+    fn schema(nation_code: &str, to: [&str; 5], from: [&str; 5]) -> ClassificationSchema {
+        let now = Utc::now().naive_utc();
+
+        ClassificationSchema {
+            id: Uuid::new_v4(),
+            creator_id: Uuid::new_v4(),
+            nation_code: nation_code.to_owned(),
+            to_nato_unclassified: to[0].to_owned(),
+            to_nato_restricted: to[1].to_owned(),
+            to_nato_confidential: to[2].to_owned(),
+            to_nato_secret: to[3].to_owned(),
+            to_nato_top_secret: to[4].to_owned(),
+            from_nato_unclassified: from[0].to_owned(),
+            from_nato_restricted: from[1].to_owned(),
+            from_nato_confidential: from[2].to_owned(),
+            from_nato_secret: from[3].to_owned(),
+            from_nato_top_secret: from[4].to_owned(),
+            caveats: String::new(),
+            version: "1".to_owned(),
+            authority_id: Uuid::new_v4(),
+            created_at: now,
+            updated_at: now,
+            expires_at: None,
+        }
+    }
+
+    fn gbr() -> ClassificationSchema {
+        let markings = ["OFFICIAL", "OFFICIAL-SENSITIVE", "CONFIDENTIAL", "SECRET", "TOP SECRET"];
+        schema("GBR", markings, markings)
+    }
+
+    fn usa() -> ClassificationSchema {
+        let markings = ["UNCLASSIFIED", "CUI", "CONFIDENTIAL", "SECRET", "TOP SECRET"];
+        schema("USA", markings, markings)
+    }
+
+    #[test]
+    fn markings_convert_through_nato_to_each_target() {
+        let source = gbr();
+        let targets = [usa(), gbr()];
+        let request_id = Uuid::new_v4();
+
+        let level = source.to_nato("OFFICIAL-SENSITIVE").unwrap();
+        assert_eq!(level, NatoClassification::Restricted);
+
+        let results = NewConversionResult::convert(request_id, " OFFICIAL-SENSITIVE ", level, &source, &targets);
+
+        let converted: Vec<(&str, &str, &str)> = results
+            .iter()
+            .map(|r| (r.target_nation_code.as_str(), r.nato_classification.as_str(), r.target_marking.as_str()))
+            .collect();
+        assert_eq!(
+            converted,
+            vec![("USA", "RESTRICTED", "CUI"), ("GBR", "RESTRICTED", "OFFICIAL-SENSITIVE")],
+        );
+
+        for result in &results {
+            assert_eq!(result.conversion_request_id, request_id);
+            assert_eq!(result.source_nation_code, "GBR");
+            assert_eq!(result.source_marking, "OFFICIAL-SENSITIVE");
+            assert_eq!(result.source_schema_id, source.id);
+            assert_eq!(result.receipt, None);
+        }
+        assert_eq!(results[0].target_schema_id, targets[0].id);
+    }
+
+    #[test]
+    fn every_level_round_trips_through_its_own_schema() {
+        let schema = usa();
+
+        for level in [
+            NatoClassification::Unclassified,
+            NatoClassification::Restricted,
+            NatoClassification::Confidential,
+            NatoClassification::Secret,
+            NatoClassification::TopSecret,
+        ] {
+            assert_eq!(schema.to_nato(schema.from_nato(level)).unwrap(), level);
+        }
+    }
+
+    #[test]
+    fn markings_match_case_insensitively() {
+        let schema = gbr();
+
+        assert_eq!(schema.to_nato("top secret").unwrap(), NatoClassification::TopSecret);
+        assert_eq!(schema.to_nato("Official-Sensitive").unwrap(), NatoClassification::Restricted);
+        assert_eq!(schema.to_nato("  secret\n").unwrap(), NatoClassification::Secret);
+    }
+
+    #[test]
+    fn unknown_markings_are_refused() {
+        let error = gbr().to_nato("COSMIC TOP SECRET").unwrap_err();
+        assert_eq!(error.message, "Marking \"COSMIC TOP SECRET\" is not defined in the GBR 1 schema");
+
+        assert!(gbr().to_nato("").is_err());
+    }
+
+    #[test]
+    fn a_marking_covering_several_levels_maps_to_the_highest() {
+        // One national marking for both of NATO's lowest levels
+        let schema = schema(
+            "FRA",
+            ["DIFFUSION RESTREINTE", "DIFFUSION RESTREINTE", "SECRET", "SECRET", "TRES SECRET"],
+            ["NON PROTEGE", "DIFFUSION RESTREINTE", "SECRET", "SECRET", "TRES SECRET"],
+        );
+
+        assert_eq!(schema.to_nato("diffusion restreinte").unwrap(), NatoClassification::Restricted);
+        assert_eq!(schema.to_nato("SECRET").unwrap(), NatoClassification::Secret);
+    }
+}
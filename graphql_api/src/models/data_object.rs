@@ -2,16 +2,40 @@ use std::fmt::Debug;
 
 use async_graphql::*;
 use chrono::prelude::*;
-use diesel::{self, ExpressionMethods, Insertable, PgTextExpressionMethods, Queryable};
-use diesel::{QueryDsl, RunQueryDsl};
+use diesel::pg::Pg;
+use diesel::sql_types::{Array, BigInt, Float4, Nullable, Text, Uuid as SqlUuid};
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, PgTextExpressionMethods, Queryable, QueryableByName};
+use diesel::{Connection, QueryDsl, RunQueryDsl};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use strum::IntoEnumIterator;
 use uuid::Uuid;
 
-use crate::models::{Metadata, User};
-use crate::{database, schema::*};
+use crate::config_variables::SEARCH_RESULT_LIMIT;
+use crate::models::{InsertableMetadata, Metadata, NatoClassification, NewMetadata, User};
+use crate::graphql::get_repositories_from_context;
+use crate::repositories::{SearchVisibility, TenantScope};
+use crate::schema::*;
+
+/// Hash function of a data object's content digest
+#[derive(Debug, Display, EnumString, Enum, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum DigestAlgorithm {
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    /// Hex digits in a digest
+    pub fn hex_len(self) -> usize {
+        match self {
+            DigestAlgorithm::Sha256 => 64,
+        }
+    }
+}
 
 #[derive(
-    Debug, Clone, Deserialize, Serialize, Queryable, Insertable, AsChangeset, SimpleObject,
+    Debug, Clone, Deserialize, Serialize, Queryable, QueryableByName, Insertable, AsChangeset, SimpleObject,
 )]
 #[graphql(complex)]
 #[diesel(table_name = data_objects)]
@@ -22,136 +46,576 @@ pub struct DataObject {
     pub description: String,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    /// Lowercase hex digest of the document's bytes, or null if the object
+    /// wasn't submitted with one
+    pub content_digest: Option<String>,
+    /// DigestAlgorithm of content_digest
+    pub digest_algorithm: Option<String>,
+    /// Size of the document in bytes
+    pub content_size: Option<i64>,
+    /// Where an uploaded document is kept in the blob store
+    #[graphql(skip)]
+    pub storage_key: Option<String>,
+    /// MIME type sniffed from an uploaded document
+    pub media_type: Option<String>,
 }
 
 // GraphQL implementation
 #[ComplexObject]
 impl DataObject {
-    pub async fn creator(&self) -> Result<User> {
-        User::get_by_id(&self.creator_id)
+    pub async fn creator(&self, ctx: &Context<'_>) -> Result<User> {
+        get_repositories_from_context(ctx).users.get_by_id(self.creator_id).await
     }
 
-    pub async fn metadata(&self) -> Result<Metadata> {
-        Metadata::get_by_data_object_id(&self.id)
+    /// Domain and tags, or null when the object is redacted for the caller
+    pub async fn metadata(&self, ctx: &Context<'_>) -> Result<Option<Metadata>> {
+        let repos = get_repositories_from_context(ctx);
+
+        if repos.data_objects.is_redacted(self.id).await? {
+            return Ok(None);
+        }
+
+        repos.metadata.get_by_data_object_id(self.id).await.map(Some)
     }
 }
 
 // Non Graphql
 impl DataObject {
-    pub fn create(data_object: &NewDataObject) -> Result<Self> {
-        let mut conn = database::connection()?;
+    /// SHA-256 hex of the record as a JSON array of its identifying fields,
+    /// so a receipt pins the object it was issued for
+    pub fn record_hash(&self) -> String {
+        let record = serde_json::json!([
+            self.id,
+            self.creator_id,
+            self.title,
+            self.description,
+            self.created_at.format("%Y-%m-%dT%H:%M:%S%.6f").to_string(),
+        ]);
+
+        format!("{:x}", Sha256::digest(record.to_string().as_bytes()))
+    }
+
+    /// Only the user who uploaded a document may submit it for conversion
+    /// by its id
+    pub fn ensure_uploaded_by(&self, user_id: Uuid) -> Result<()> {
+        if self.storage_key.is_none() || self.creator_id != user_id {
+            return Err(Error::new(format!("No document {} uploaded by you", self.id)));
+        }
+        Ok(())
+    }
+
+    pub fn create(conn: &mut PgConnection, data_object: &NewDataObject) -> Result<Self> {
 
         let res = diesel::insert_into(data_objects::table)
             .values(data_object)
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    /// Creates a data object and its metadata as a single unit
+    pub fn create_with_metadata(
+        conn: &mut PgConnection,
+        data_object: &NewDataObject,
+        metadata: &InsertableMetadata,
+    ) -> Result<(Self, Metadata)> {
+        let res = conn.transaction::<_, Error, _>(|conn| {
+            let data_object = DataObject::create(conn, data_object)?;
+
+            let metadata = Metadata::create(conn, &NewMetadata {
+                data_object_id: data_object.id,
+                domain: metadata.domain.clone(),
+                tags: metadata.tags.clone(),
+            })?;
+
+            Ok((data_object, metadata))
+        })?;
 
         Ok(res)
     }
 
-    pub fn get_or_create(data_object: &NewDataObject) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_or_create(conn: &mut PgConnection, data_object: &NewDataObject) -> Result<Self> {
 
         let res = data_objects::table
             .filter(data_objects::title.eq(&data_object.title))
             .filter(data_objects::creator_id.eq(&data_object.creator_id))
             .distinct()
-            .first(&mut conn);
+            .first(conn);
 
         let data_object = match res {
             Ok(d) => d,
             Err(e) => {
                 // DataObject not found
                 println!("{:?}", e);
-                let d = DataObject::create(data_object).expect("Unable to create data_object");
-                d
+                DataObject::create(conn, data_object).expect("Unable to create data_object")
             }
         };
         Ok(data_object)
     }
 
-    pub fn get_all() -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
-        let res = data_objects::table.load::<DataObject>(&mut conn)?;
+    pub fn get_all(conn: &mut PgConnection) -> Result<Vec<Self>> {
+        let res = data_objects::table.load::<DataObject>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_id(id: &Uuid) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_by_id(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
         let res = data_objects::table
             .filter(data_objects::id.eq(id))
-            .first(&mut conn)?;
+            .first(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_ids(ids: Vec<Uuid>) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    pub fn get_by_ids(conn: &mut PgConnection, ids: Vec<Uuid>) -> Result<Vec<Self>> {
         let res = data_objects::table
             .filter(data_objects::id.eq_any(ids))
-            .load::<DataObject>(&mut conn)?;
+            .load::<DataObject>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_creator_id(creator_id: Uuid) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    /// Data objects whose document has this digest, so a resubmitted
+    /// document can be matched to earlier conversions
+    pub fn get_by_content_digest(conn: &mut PgConnection, content_digest: &str) -> Result<Vec<Self>> {
+        let res = data_objects::table
+            .filter(data_objects::content_digest.eq(content_digest.to_lowercase()))
+            .order(data_objects::created_at.asc())
+            .load::<DataObject>(conn)?;
+        Ok(res)
+    }
+
+    pub fn get_by_creator_id(conn: &mut PgConnection, creator_id: Uuid) -> Result<Vec<Self>> {
         let res = data_objects::table
             .filter(data_objects::creator_id.eq(creator_id))
-            .load::<DataObject>(&mut conn)?;
+            .load::<DataObject>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_title(title: &String) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    /// Case-insensitive substring match against the data object's title.
+    /// Use `search` for ranked full-text search across descriptions and metadata.
+    pub fn get_by_title(conn: &mut PgConnection, title: &String) -> Result<Vec<Self>> {
         let search_pattern = format!("%{}%", title);
         let res = data_objects::table
             .filter(data_objects::title.ilike(search_pattern))
-            .load::<DataObject>(&mut conn)?;
+            .load::<DataObject>(conn)?;
         Ok(res)
     }
 
-    pub fn get_count() -> Result<i64> {
-        let mut conn = database::connection()?;
+    pub fn get_count(conn: &mut PgConnection) -> Result<i64> {
 
-        let res = data_objects::table.count().get_result(&mut conn)?;
+        let res = data_objects::table.count().get_result(conn)?;
 
         Ok(res)
     }
 
-    // TODO: get_by_metadata_tags
+    /// Full-text search across title, description, metadata domain and tags.
+    /// Results are ranked by relevance and carry highlighted title and description
+    /// fragments. Optional domain and tag filters are matched case-insensitively.
+    /// Objects `visibility` hides are filtered out before the result limit.
+    pub fn search(
+        conn: &mut PgConnection,
+        query: &str,
+        domains: Option<Vec<String>>,
+        tags: Option<Vec<String>>,
+        visibility: &SearchVisibility,
+    ) -> Result<Vec<DataObjectSearchResult>> {
+        let query = query.trim();
+
+        if query.is_empty() {
+            return Err(Error::new("Search query cannot be empty"));
+        }
+
+        let lowercase = |values: Vec<String>| -> Vec<String> {
+            values.iter().map(|v| v.to_lowercase()).collect()
+        };
+
+        let (visible, binds) = visibility_filter(visibility, 5);
+
+        let mut search = diesel::sql_query(format!(
+            "SELECT d.id, d.creator_id, d.title, d.description, d.created_at, d.updated_at,
+                d.content_digest, d.digest_algorithm, d.content_size, d.storage_key, d.media_type,
+                ts_rank_cd(s.document, q.query) AS rank,
+                ts_headline('english', {}, q.query,
+                    'StartSel=<mark>, StopSel=</mark>, HighlightAll=true') AS title_highlight,
+                ts_headline('english', {}, q.query,
+                    'StartSel=<mark>, StopSel=</mark>, MaxFragments=2, MaxWords=30, MinWords=10') AS description_highlight
+            FROM data_object_search s
+            JOIN data_objects d ON d.id = s.data_object_id
+            CROSS JOIN websearch_to_tsquery('english', $1) AS q(query)
+            WHERE s.document @@ q.query
+                AND ($2 IS NULL OR EXISTS (
+                    SELECT 1 FROM metadata m
+                    WHERE m.data_object_id = d.id AND lower(m.domain) = ANY($2)))
+                AND ($3 IS NULL OR EXISTS (
+                    SELECT 1 FROM metadata m, unnest(m.tags) AS t(tag)
+                    WHERE m.data_object_id = d.id AND lower(t.tag) = ANY($3))){}
+            ORDER BY rank DESC, d.updated_at DESC
+            LIMIT $4",
+            html_escaped("d.title"),
+            html_escaped("d.description"),
+            visible,
+        ))
+        .into_boxed::<Pg>()
+        .bind::<Text, _>(query.to_owned())
+        .bind::<Nullable<Array<Text>>, _>(domains.map(lowercase))
+        .bind::<Nullable<Array<Text>>, _>(tags.map(lowercase))
+        .bind::<BigInt, _>(SEARCH_RESULT_LIMIT);
+
+        for bind in binds {
+            search = match bind {
+                VisibilityBind::Uuids(ids) => search.bind::<Array<SqlUuid>, _>(ids),
+                VisibilityBind::Texts(values) => search.bind::<Array<Text>, _>(values),
+                VisibilityBind::Text(value) => search.bind::<Text, _>(value),
+            };
+        }
+
+        let res = search.load::<DataObjectSearchResult>(conn)?;
 
-    pub fn update(&self) -> Result<Self> {
-        let mut conn = database::connection()?;
+        Ok(res)
+    }
+
+    pub fn update(&self, conn: &mut PgConnection) -> Result<Self> {
 
         let res = diesel::update(data_objects::table)
             .filter(data_objects::id.eq(&self.id))
             .set(self)
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(res)
     }
 }
 
+/// A parameter of the search visibility filter, bound in order
+#[derive(Debug, PartialEq)]
+enum VisibilityBind {
+    Uuids(Vec<Uuid>),
+    Texts(Vec<String>),
+    Text(String),
+}
+
+/// SQL conditions on `d`, the searched data object, for what `visibility`
+/// allows, mirroring `TenantScope::allows` and `Clearance::may_read`. Their
+/// parameters are numbered from `$first` and bound in the order returned.
+fn visibility_filter(visibility: &SearchVisibility, first: usize) -> (String, Vec<VisibilityBind>) {
+    let mut sql = String::new();
+    let mut binds = Vec::new();
+    let mut param = |bind: VisibilityBind| {
+        binds.push(bind);
+        format!("${}", first + binds.len() - 1)
+    };
+
+    for scope in &visibility.scopes {
+        if let TenantScope::Members { authority_ids, nation_codes } = scope {
+            let authority_ids = param(VisibilityBind::Uuids(authority_ids.iter().copied().collect()));
+            let nation_codes = param(VisibilityBind::Texts(nation_codes.iter().cloned().collect()));
+
+            sql += &format!(
+                "
+                AND EXISTS (
+                    SELECT 1 FROM conversion_requests r
+                    WHERE r.data_object_id = d.id AND (r.authority_id = ANY({0})
+                        OR r.source_nation_code = ANY({1}) OR r.target_nation_codes::text[] && {1}))",
+                authority_ids, nation_codes,
+            );
+        }
+    }
+
+    for clearance in &visibility.clearances {
+        let Some(nationality) = &clearance.nationality else {
+            sql += "
+                AND FALSE";
+            continue;
+        };
+
+        let nationality = param(VisibilityBind::Text(nationality.clone()));
+        sql += &format!(
+            "
+                AND EXISTS (
+                    SELECT 1 FROM conversion_requests r
+                    WHERE r.data_object_id = d.id
+                        AND (r.source_nation_code = {0} OR {0} = ANY(r.target_nation_codes)))",
+            nationality,
+        );
+
+        // Unconverted objects and unrecognised levels count as TOP_SECRET
+        if clearance.level < NatoClassification::TopSecret {
+            let levels = NatoClassification::iter()
+                .filter(|level| *level <= clearance.level)
+                .map(|level| level.to_string())
+                .collect();
+            let levels = param(VisibilityBind::Texts(levels));
+
+            sql += &format!(
+                "
+                AND EXISTS (
+                    SELECT 1 FROM conversion_requests r
+                    JOIN conversion_results c ON c.conversion_request_id = r.id
+                    WHERE r.data_object_id = d.id)
+                AND NOT EXISTS (
+                    SELECT 1 FROM conversion_requests r
+                    JOIN conversion_results c ON c.conversion_request_id = r.id
+                    WHERE r.data_object_id = d.id AND NOT c.nato_classification = ANY({}))",
+                levels,
+            );
+        }
+    }
+
+    (sql, binds)
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Insertable, InputObject)]
 #[diesel(table_name = data_objects)]
 pub struct NewDataObject {
     pub creator_id: Uuid, // User
     pub title: String,
     pub description: String,
+    pub content_digest: Option<String>,
+    pub digest_algorithm: Option<String>,
+    pub content_size: Option<i64>,
+    pub storage_key: Option<String>,
+    pub media_type: Option<String>,
 }
 
 impl NewDataObject {
+    /// A data object without a document
     pub fn new(creator_id: Uuid, title: String, description: String) -> Self {
         NewDataObject {
             creator_id,
             title,
             description,
+            content_digest: None,
+            digest_algorithm: None,
+            content_size: None,
+            storage_key: None,
+            media_type: None,
         }
     }
 }
 
+/// SQL expression for a text column with HTML special characters escaped,
+/// so highlights can be rendered as HTML
+fn html_escaped(column: &str) -> String {
+    [("&", "&amp;"), ("<", "&lt;"), (">", "&gt;"), ("\"", "&quot;"), ("''", "&#39;")]
+        .iter()
+        .fold(column.to_owned(), |expr, (from, to)| format!("replace({}, '{}', '{}')", expr, from, to))
+}
+
+/// A ranked full-text search hit. Highlighted fragments are HTML: the
+/// object's text is escaped and matching terms are wrapped in `<mark>` tags.
+#[derive(Debug, Clone, Deserialize, Serialize, QueryableByName, SimpleObject)]
+pub struct DataObjectSearchResult {
+    #[diesel(embed)]
+    pub data_object: DataObject,
+    #[diesel(sql_type = Float4)]
+    pub rank: f32,
+    #[diesel(sql_type = Text)]
+    pub title_highlight: String,
+    #[diesel(sql_type = Text)]
+    pub description_highlight: String,
+}
+
 /// A lightweight struct to accept JSON formatted data from a ConversionRequest
 /// needed to create a NewDataObject
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, InputObject)]
 pub struct InsertableDataObject {
     pub title: String,
     pub description: String,
+    /// Hex digest of the document's bytes, binding the conversion receipt
+    /// to this exact version of the document
+    pub content_digest: Option<String>,
+    /// Algorithm of content_digest, SHA256 if not given
+    pub digest_algorithm: Option<DigestAlgorithm>,
+    /// Size of the document in bytes
+    pub content_size: Option<i64>,
+}
+
+impl InsertableDataObject {
+    /// The data object to create for `creator_id`, with the digest checked
+    /// against its algorithm and lowercased
+    pub fn to_new_data_object(&self, creator_id: Uuid) -> Result<NewDataObject> {
+        if self.content_size.is_some_and(|size| size < 0) {
+            return Err(Error::new("contentSize can't be negative"));
+        }
+
+        let (content_digest, digest_algorithm) = match (&self.content_digest, self.digest_algorithm) {
+            (None, None) => (None, None),
+            (None, Some(_)) => return Err(Error::new("digestAlgorithm needs a contentDigest")),
+            (Some(digest), algorithm) => {
+                let algorithm = algorithm.unwrap_or(DigestAlgorithm::Sha256);
+                let digest = digest.trim().to_lowercase();
+
+                if digest.len() != algorithm.hex_len() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(Error::new(format!(
+                        "contentDigest must be {} hex digits for {}",
+                        algorithm.hex_len(),
+                        algorithm
+                    )));
+                }
+
+                (Some(digest), Some(algorithm.to_string()))
+            }
+        };
+
+        Ok(NewDataObject {
+            creator_id,
+            title: self.title.clone(),
+            description: self.description.clone(),
+            content_digest,
+            digest_algorithm,
+            content_size: self.content_size,
+            storage_key: None,
+            media_type: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::models::DataObjectMarking;
+    use crate::repositories::Clearance;
+
+    fn members(authority_id: Uuid, nation_code: &str) -> TenantScope {
+        TenantScope::Members {
+            authority_ids: HashSet::from([authority_id]),
+            nation_codes: HashSet::from([nation_code.to_owned()]),
+        }
+    }
+
+    fn clearance(level: NatoClassification, nationality: Option<&str>) -> Clearance {
+        Clearance {
+            level,
+            nationality: nationality.map(str::to_owned),
+        }
+    }
+
+    fn levels(levels: &[NatoClassification]) -> VisibilityBind {
+        VisibilityBind::Texts(levels.iter().map(|l| l.to_string()).collect())
+    }
+
+    /// The SQL with whitespace collapsed
+    fn flat(sql: &str) -> String {
+        sql.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn unrestricted_visibility_adds_no_conditions() {
+        let visibility = SearchVisibility {
+            scopes: vec![TenantScope::All],
+            clearances: Vec::new(),
+        };
+
+        let (sql, binds) = visibility_filter(&visibility, 5);
+        assert_eq!(sql, "");
+        assert!(binds.is_empty());
+    }
+
+    #[test]
+    fn member_scopes_match_authority_source_or_target_nation() {
+        let authority_id = Uuid::new_v4();
+        let visibility = SearchVisibility {
+            scopes: vec![members(authority_id, "GBR")],
+            clearances: Vec::new(),
+        };
+
+        let (sql, binds) = visibility_filter(&visibility, 5);
+        assert_eq!(
+            flat(&sql),
+            "AND EXISTS ( SELECT 1 FROM conversion_requests r WHERE r.data_object_id = d.id \
+             AND (r.authority_id = ANY($5) OR r.source_nation_code = ANY($6) OR r.target_nation_codes::text[] && $6))",
+        );
+        assert_eq!(
+            binds,
+            vec![VisibilityBind::Uuids(vec![authority_id]), VisibilityBind::Texts(vec!["GBR".to_owned()])],
+        );
+    }
+
+    #[test]
+    fn clearances_bind_nationality_then_readable_levels() {
+        let authority_id = Uuid::new_v4();
+        let visibility = SearchVisibility {
+            scopes: vec![members(authority_id, "GBR")],
+            clearances: vec![clearance(NatoClassification::Confidential, Some("USA"))],
+        };
+
+        let (sql, binds) = visibility_filter(&visibility, 5);
+        let sql = flat(&sql);
+
+        assert!(
+            sql.ends_with(
+                "AND EXISTS ( SELECT 1 FROM conversion_requests r WHERE r.data_object_id = d.id \
+                 AND (r.source_nation_code = $7 OR $7 = ANY(r.target_nation_codes))) \
+                 AND EXISTS ( SELECT 1 FROM conversion_requests r JOIN conversion_results c ON c.conversion_request_id = r.id \
+                 WHERE r.data_object_id = d.id) \
+                 AND NOT EXISTS ( SELECT 1 FROM conversion_requests r JOIN conversion_results c ON c.conversion_request_id = r.id \
+                 WHERE r.data_object_id = d.id AND NOT c.nato_classification = ANY($8))",
+            ),
+            "{}",
+            sql,
+        );
+        assert_eq!(
+            binds,
+            vec![
+                VisibilityBind::Uuids(vec![authority_id]),
+                VisibilityBind::Texts(vec!["GBR".to_owned()]),
+                VisibilityBind::Text("USA".to_owned()),
+                levels(&[NatoClassification::Unclassified, NatoClassification::Restricted, NatoClassification::Confidential]),
+            ],
+        );
+    }
+
+    #[test]
+    fn top_secret_clearances_only_check_nationality() {
+        let visibility = SearchVisibility {
+            scopes: Vec::new(),
+            clearances: vec![clearance(NatoClassification::TopSecret, Some("GBR"))],
+        };
+
+        let (sql, binds) = visibility_filter(&visibility, 5);
+        assert!(!sql.contains("nato_classification"));
+        assert_eq!(binds, vec![VisibilityBind::Text("GBR".to_owned())]);
+    }
+
+    #[test]
+    fn clearances_without_nationality_match_nothing() {
+        let visibility = SearchVisibility {
+            scopes: Vec::new(),
+            clearances: vec![clearance(NatoClassification::TopSecret, None)],
+        };
+
+        let (sql, binds) = visibility_filter(&visibility, 5);
+        assert_eq!(flat(&sql), "AND FALSE");
+        assert!(binds.is_empty());
+    }
+
+    #[test]
+    fn bound_levels_are_those_clearance_may_read() {
+        for level in NatoClassification::iter() {
+            let clearance = clearance(level, Some("GBR"));
+            let visibility = SearchVisibility {
+                scopes: Vec::new(),
+                clearances: vec![clearance.clone()],
+            };
+
+            let readable: Vec<NatoClassification> = NatoClassification::iter()
+                .filter(|classification| {
+                    clearance.may_read(&DataObjectMarking {
+                        classification: Some(*classification),
+                        nations: HashSet::from(["GBR".to_owned()]),
+                    })
+                })
+                .collect();
+
+            let (_, binds) = visibility_filter(&visibility, 1);
+            let bound = match binds.get(1) {
+                Some(bind) => bind,
+                // Every level is readable, so none are bound
+                None => {
+                    assert_eq!(readable.len(), NatoClassification::iter().count());
+                    continue;
+                }
+            };
+            assert_eq!(*bound, levels(&readable), "{}", level);
+        }
+    }
 }
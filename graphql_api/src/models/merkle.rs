@@ -0,0 +1,482 @@
+//! Merkle anchoring of the audit trail and conversion receipts.
+//!
+//! The anchoring job (see `anchoring`) periodically gathers the audit entries
+//! and receipts not yet anchored into a batch, builds a Merkle tree over
+//! their hashes and stores the root with a statement signed by the receipt
+//! key. Publishing the statement somewhere outside this service fixes the
+//! batch: a receipt's inclusion proof leads from its hash to a published
+//! root, so it can be checked without trusting the database.
+//!
+//! Hashes are SHA-256 in lowercase hex. A leaf is `H(0x00 || content)`, where
+//! the content is an audit entry's `entry_hash` or a receipt's canonical
+//! JSON, and a node is `H(0x01 || left || right)` over the hex strings of its
+//! children. Leaves are paired left to right at each level, and an odd last
+//! node is carried up unchanged.
+
+use async_graphql::*;
+use chrono::{prelude::*, SecondsFormat};
+use diesel::dsl::{exists, not};
+use diesel::prelude::*;
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, QueryDsl, Queryable, RunQueryDsl};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::models::{sign_with_receipt_key, verify_receipt_signature, AuditEntry};
+use crate::schema::{audit_log, conversion_results, merkle_anchors, merkle_leaves};
+
+/// Version of the anchor statement format, signed with the statement
+pub const ANCHOR_STATEMENT_VERSION: u32 = 1;
+
+pub fn merkle_leaf_hash(content: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update([0x00]);
+    hasher.update(content);
+    format!("{:x}", hasher.finalize())
+}
+
+fn merkle_node_hash(left: &str, right: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// The level above `nodes`
+fn merkle_parents(nodes: &[String]) -> Vec<String> {
+    nodes
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => merkle_node_hash(left, right),
+            [single] => single.clone(),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Root of the tree over `leaves`, or None if there are none
+pub fn merkle_root(leaves: &[String]) -> Option<String> {
+    let mut level = leaves.to_vec();
+
+    while level.len() > 1 {
+        level = merkle_parents(&level);
+    }
+
+    level.pop()
+}
+
+/// Which side of the path a proof step's hash is on
+#[derive(Debug, Display, EnumString, Enum, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MerkleSide {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+/// A sibling hash on the way from a leaf to the root
+pub struct MerkleProofStep {
+    pub hash: String,
+    pub side: MerkleSide,
+}
+
+/// Sibling hashes from the leaf at `index` up to the root
+pub fn merkle_proof(leaves: &[String], index: usize) -> Vec<MerkleProofStep> {
+    let mut steps = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut index = index;
+
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+
+        // The last node of an odd level is carried up without a sibling
+        if let Some(hash) = level.get(sibling) {
+            steps.push(MerkleProofStep {
+                hash: hash.clone(),
+                side: if sibling < index { MerkleSide::Left } else { MerkleSide::Right },
+            });
+        }
+
+        level = merkle_parents(&level);
+        index /= 2;
+    }
+
+    steps
+}
+
+/// Whether `steps` lead from `leaf_hash` to `root`
+pub fn verify_merkle_proof(leaf_hash: &str, steps: &[MerkleProofStep], root: &str) -> bool {
+    let computed = steps.iter().fold(leaf_hash.to_owned(), |acc, step| match step.side {
+        MerkleSide::Left => merkle_node_hash(&step.hash, &acc),
+        MerkleSide::Right => merkle_node_hash(&acc, &step.hash),
+    });
+
+    computed == root
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+/// What an anchor statement states. Fields are declared in alphabetical
+/// order, which is the order they are serialized and signed in.
+pub struct AnchorStatement {
+    pub anchor_id: Uuid,
+    pub created_at: String,
+    pub first_audit_sequence: Option<i64>,
+    pub last_audit_sequence: Option<i64>,
+    pub leaf_count: i32,
+    pub root_hash: String,
+    pub version: u32,
+}
+
+impl AnchorStatement {
+    pub fn canonical_form(&self) -> String {
+        serde_json::to_string(self).expect("Anchor statements always serialize")
+    }
+}
+
+/// Checks offline that `receipt` is a leaf of the tree whose root
+/// `statement` states, and that `signature` by the receipt key signs the
+/// statement. Returns what the statement states.
+pub fn verify_receipt_inclusion(
+    receipt: &str,
+    steps: &[MerkleProofStep],
+    statement: &str,
+    signature: &str,
+    key: &VerifyingKey,
+) -> std::result::Result<AnchorStatement, String> {
+    verify_receipt_signature(statement.as_bytes(), signature, key)
+        .map_err(|e| format!("anchor statement {}", e))?;
+
+    let anchor: AnchorStatement =
+        serde_json::from_str(statement).map_err(|e| format!("malformed anchor statement: {}", e))?;
+
+    if !verify_merkle_proof(&merkle_leaf_hash(receipt.as_bytes()), steps, &anchor.root_hash) {
+        return Err("the proof does not lead from the receipt to the anchored root".to_owned());
+    }
+
+    Ok(anchor)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable, Insertable, SimpleObject)]
+#[diesel(table_name = merkle_anchors)]
+/// A batch of audit entries and receipts fixed by the root of their Merkle
+/// tree
+pub struct MerkleAnchor {
+    pub id: Uuid,
+    pub root_hash: String,
+    pub leaf_count: i32,
+    /// Audit entries anchored in this batch, if any
+    pub first_audit_sequence: Option<i64>,
+    pub last_audit_sequence: Option<i64>,
+    /// Canonical JSON of the root, as signed and published
+    pub statement: String,
+    /// Base64 Ed25519 signature of `statement` by the receipt key, or null
+    /// if it wasn't signed
+    pub signature: Option<String>,
+    /// Key in `/.well-known/receipt-keys.json` that signed `statement`
+    pub signing_key_id: Option<String>,
+    pub created_at: NaiveDateTime,
+    /// When the statement was handed to the publication sink
+    pub published_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Insertable)]
+#[diesel(table_name = merkle_leaves)]
+/// One leaf of an anchor's tree
+pub struct MerkleLeaf {
+    pub anchor_id: Uuid,
+    pub position: i32,
+    pub leaf_hash: String,
+    pub audit_sequence: Option<i64>,
+    pub receipt_id: Option<Uuid>,
+}
+
+impl MerkleAnchor {
+    /// The anchor over `audit_entries`, then `receipts` as (result id,
+    /// receipt) pairs, with its leaves. None if there is nothing to anchor.
+    pub fn new_batch(audit_entries: &[AuditEntry], receipts: &[(Uuid, String)]) -> Option<(Self, Vec<MerkleLeaf>)> {
+        let id = Uuid::new_v4();
+
+        let leaves: Vec<MerkleLeaf> = audit_entries
+            .iter()
+            .map(|e| (merkle_leaf_hash(e.entry_hash.as_bytes()), Some(e.sequence), None))
+            .chain(
+                receipts
+                    .iter()
+                    .map(|(receipt_id, receipt)| (merkle_leaf_hash(receipt.as_bytes()), None, Some(*receipt_id))),
+            )
+            .enumerate()
+            .map(|(position, (leaf_hash, audit_sequence, receipt_id))| MerkleLeaf {
+                anchor_id: id,
+                position: position as i32,
+                leaf_hash,
+                audit_sequence,
+                receipt_id,
+            })
+            .collect();
+
+        let hashes: Vec<String> = leaves.iter().map(|l| l.leaf_hash.clone()).collect();
+        let root_hash = merkle_root(&hashes)?;
+
+        let now = Utc::now();
+        // Postgres keeps microseconds, and the statement has to match the row
+        let now = now.with_nanosecond(now.nanosecond() / 1_000 * 1_000).unwrap_or(now);
+
+        let statement = AnchorStatement {
+            anchor_id: id,
+            created_at: now.to_rfc3339_opts(SecondsFormat::Micros, true),
+            first_audit_sequence: audit_entries.first().map(|e| e.sequence),
+            last_audit_sequence: audit_entries.last().map(|e| e.sequence),
+            leaf_count: leaves.len() as i32,
+            root_hash: root_hash.clone(),
+            version: ANCHOR_STATEMENT_VERSION,
+        };
+
+        let statement_json = statement.canonical_form();
+        let signed = sign_with_receipt_key(statement_json.as_bytes());
+
+        let anchor = MerkleAnchor {
+            id,
+            root_hash,
+            leaf_count: statement.leaf_count,
+            first_audit_sequence: statement.first_audit_sequence,
+            last_audit_sequence: statement.last_audit_sequence,
+            statement: statement_json,
+            signature: signed.as_ref().map(|(signature, _)| signature.clone()),
+            signing_key_id: signed.map(|(_, kid)| kid),
+            created_at: now.naive_utc(),
+            published_at: None,
+        };
+
+        Some((anchor, leaves))
+    }
+
+    /// Anchors the oldest `audit_limit` audit entries and `receipt_limit`
+    /// receipts that no anchor covers yet. Each has its own limit so a busy
+    /// audit trail can't hold receipts back. The anchors table is locked until
+    /// the transaction ends, so concurrent batches can't cover the same
+    /// entries.
+    pub fn create_batch(conn: &mut PgConnection, audit_limit: i64, receipt_limit: i64) -> Result<Option<Self>> {
+        let res = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            diesel::sql_query("LOCK TABLE merkle_anchors IN EXCLUSIVE MODE").execute(conn)?;
+
+            let last_sequence: Option<i64> = merkle_anchors::table
+                .select(diesel::dsl::max(merkle_anchors::last_audit_sequence))
+                .first(conn)?;
+
+            let audit_entries: Vec<AuditEntry> = audit_log::table
+                .filter(audit_log::sequence.gt(last_sequence.unwrap_or(0)))
+                .order(audit_log::sequence.asc())
+                .limit(audit_limit)
+                .load(conn)?;
+
+            let receipts: Vec<(Uuid, Option<String>)> = conversion_results::table
+                .filter(conversion_results::receipt.is_not_null())
+                .filter(not(exists(
+                    merkle_leaves::table.filter(merkle_leaves::receipt_id.eq(conversion_results::id.nullable())),
+                )))
+                .order(conversion_results::created_at.asc())
+                .limit(receipt_limit)
+                .select((conversion_results::id, conversion_results::receipt))
+                .load(conn)?;
+
+            let receipts: Vec<(Uuid, String)> = receipts
+                .into_iter()
+                .filter_map(|(id, receipt)| receipt.map(|r| (id, r)))
+                .collect();
+
+            let Some((anchor, leaves)) = MerkleAnchor::new_batch(&audit_entries, &receipts) else {
+                return Ok(None);
+            };
+
+            let anchor: MerkleAnchor = diesel::insert_into(merkle_anchors::table)
+                .values(&anchor)
+                .get_result(conn)?;
+
+            // Keep each insert under the Postgres bind parameter limit
+            for chunk in leaves.chunks(10_000) {
+                diesel::insert_into(merkle_leaves::table)
+                    .values(chunk)
+                    .execute(conn)?;
+            }
+
+            Ok(Some(anchor))
+        })?;
+
+        Ok(res)
+    }
+
+    /// Number of audit entries in the batch; the other leaves are receipts
+    pub fn audit_entry_count(&self) -> i64 {
+        match (self.first_audit_sequence, self.last_audit_sequence) {
+            (Some(first), Some(last)) => last - first + 1,
+            _ => 0,
+        }
+    }
+
+    pub fn get_by_id(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
+        let res = merkle_anchors::table
+            .filter(merkle_anchors::id.eq(id))
+            .first(conn)?;
+
+        Ok(res)
+    }
+
+    /// Anchors not yet handed to the publication sink, oldest first
+    pub fn get_unpublished(conn: &mut PgConnection) -> Result<Vec<Self>> {
+        let res = merkle_anchors::table
+            .filter(merkle_anchors::published_at.is_null())
+            .order(merkle_anchors::created_at.asc())
+            .load::<MerkleAnchor>(conn)?;
+
+        Ok(res)
+    }
+
+    pub fn mark_published(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
+        let res = diesel::update(merkle_anchors::table)
+            .filter(merkle_anchors::id.eq(id))
+            .set(merkle_anchors::published_at.eq(Utc::now().naive_utc()))
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+}
+
+impl MerkleLeaf {
+    /// The leaf of a receipt, if it has been anchored
+    pub fn get_by_receipt_id(conn: &mut PgConnection, receipt_id: &Uuid) -> Result<Option<Self>> {
+        let res = merkle_leaves::table
+            .filter(merkle_leaves::receipt_id.eq(receipt_id))
+            .first(conn)
+            .optional()?;
+
+        Ok(res)
+    }
+
+    /// Every leaf of an anchor in tree order
+    pub fn get_by_anchor_id(conn: &mut PgConnection, anchor_id: &Uuid) -> Result<Vec<Self>> {
+        let res = merkle_leaves::table
+            .filter(merkle_leaves::anchor_id.eq(anchor_id))
+            .order(merkle_leaves::position.asc())
+            .load::<MerkleLeaf>(conn)?;
+
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+/// Evidence that a receipt is part of an anchored batch. Hash the receipt as
+/// a leaf, combine it with each step in turn, and compare the result with
+/// the root in the anchor's signed statement.
+pub struct MerkleProof {
+    pub receipt_id: Uuid,
+    pub leaf_hash: String,
+    /// Position of the leaf in the anchor's tree
+    pub leaf_index: i32,
+    pub steps: Vec<MerkleProofStep>,
+    pub anchor: MerkleAnchor,
+}
+
+impl MerkleProof {
+    /// The proof for the leaf of `receipt_id` among the anchor's `leaves`
+    pub fn build(receipt_id: Uuid, anchor: MerkleAnchor, leaves: &[MerkleLeaf]) -> Result<Self> {
+        let leaf = leaves
+            .iter()
+            .find(|l| l.receipt_id == Some(receipt_id))
+            .ok_or_else(|| Error::new("Receipt is not a leaf of its anchor"))?;
+
+        let hashes: Vec<String> = leaves.iter().map(|l| l.leaf_hash.clone()).collect();
+
+        Ok(MerkleProof {
+            receipt_id,
+            leaf_hash: leaf.leaf_hash.clone(),
+            leaf_index: leaf.position,
+            steps: merkle_proof(&hashes, leaf.position as usize),
+            anchor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: usize) -> Vec<String> {
+        (0..count).map(|i| merkle_leaf_hash(format!("leaf {}", i).as_bytes())).collect()
+    }
+
+    #[test]
+    fn every_leaf_proves_into_the_root() {
+        for count in 1..=9 {
+            let leaves = leaves(count);
+            let root = merkle_root(&leaves).unwrap();
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                let steps = merkle_proof(&leaves, index);
+                assert!(verify_merkle_proof(leaf, &steps, &root), "leaf {} of {}", index, count);
+            }
+        }
+    }
+
+    #[test]
+    fn single_leaf_is_its_own_root() {
+        let leaves = leaves(1);
+
+        assert_eq!(merkle_root(&leaves), Some(leaves[0].clone()));
+        assert!(merkle_proof(&leaves, 0).is_empty());
+        assert!(verify_merkle_proof(&leaves[0], &[], &leaves[0]));
+    }
+
+    #[test]
+    fn no_leaves_have_no_root() {
+        assert_eq!(merkle_root(&[]), None);
+    }
+
+    #[test]
+    fn odd_last_leaf_is_carried_up() {
+        let leaves = leaves(3);
+        let root = merkle_root(&leaves).unwrap();
+
+        assert_eq!(root, merkle_node_hash(&merkle_node_hash(&leaves[0], &leaves[1]), &leaves[2]));
+
+        // No sibling on the first level, then the left pair
+        let steps = merkle_proof(&leaves, 2);
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].side, MerkleSide::Left);
+        assert_eq!(steps[0].hash, merkle_node_hash(&leaves[0], &leaves[1]));
+    }
+
+    #[test]
+    fn tampered_sibling_is_refused() {
+        let leaves = leaves(5);
+        let root = merkle_root(&leaves).unwrap();
+
+        let mut steps = merkle_proof(&leaves, 1);
+        steps[0].hash = merkle_leaf_hash(b"forged");
+
+        assert!(!verify_merkle_proof(&leaves[1], &steps, &root));
+    }
+
+    #[test]
+    fn swapped_side_is_refused() {
+        let leaves = leaves(4);
+        let root = merkle_root(&leaves).unwrap();
+
+        let mut steps = merkle_proof(&leaves, 0);
+        steps[0].side = MerkleSide::Left;
+
+        assert!(!verify_merkle_proof(&leaves[0], &steps, &root));
+    }
+
+    #[test]
+    fn other_leaf_does_not_prove_with_this_path() {
+        let leaves = leaves(6);
+        let root = merkle_root(&leaves).unwrap();
+
+        assert!(!verify_merkle_proof(&leaves[3], &merkle_proof(&leaves, 2), &root));
+        assert!(!verify_merkle_proof(&merkle_leaf_hash(b"leaf 2 "), &merkle_proof(&leaves, 2), &root));
+    }
+}
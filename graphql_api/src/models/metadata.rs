@@ -1,15 +1,15 @@
 use std::fmt::Debug;
-use std::fs::metadata;
 
 use async_graphql::*;
 use chrono::prelude::*;
-use diesel::{self, ExpressionMethods, Insertable, PgTextExpressionMethods, Queryable};
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, Queryable};
 use diesel::{QueryDsl, RunQueryDsl};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::models::DataObject;
-use crate::{database, schema::*};
+use crate::graphql::get_repositories_from_context;
+use crate::schema::*;
 
 #[derive(
     Debug, Clone, Deserialize, Serialize, Queryable, Insertable, AsChangeset, SimpleObject,
@@ -28,89 +28,80 @@ pub struct Metadata {
 // GraphQL implementation
 #[ComplexObject]
 impl Metadata {
-    pub async fn get_data_object(&self) -> Result<DataObject> {
-        DataObject::get_by_id(&self.data_object_id)
+    pub async fn get_data_object(&self, ctx: &Context<'_>) -> Result<DataObject> {
+        get_repositories_from_context(ctx).data_objects.get_by_id(self.data_object_id).await
     }
 }
 
 // Non Graphql
 impl Metadata {
-    pub fn create(metadata: &NewMetadata) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn create(conn: &mut PgConnection, metadata: &NewMetadata) -> Result<Self> {
 
         let res = diesel::insert_into(metadata::table)
             .values(metadata)
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(res)
     }
 
-    pub fn get_or_create(metadata: &NewMetadata) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_or_create(conn: &mut PgConnection, metadata: &NewMetadata) -> Result<Self> {
 
         let res = metadata::table
             .filter(metadata::domain.eq(&metadata.domain))
             .distinct()
-            .first(&mut conn);
+            .first(conn);
 
         let metadata = match res {
             Ok(m) => m,
             Err(e) => {
                 // Metadata not found
                 println!("{:?}", e);
-                let m = Metadata::create(metadata).expect("Unable to create metadata");
-                m
+                Metadata::create(conn, metadata).expect("Unable to create metadata")
             }
         };
         Ok(metadata)
     }
 
-    pub fn get_all() -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
-        let res = metadata::table.load::<Metadata>(&mut conn)?;
+    pub fn get_all(conn: &mut PgConnection) -> Result<Vec<Self>> {
+        let res = metadata::table.load::<Metadata>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_id(id: &Uuid) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_by_id(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
         let res = metadata::table
             .filter(metadata::id.eq(id))
-            .first(&mut conn)?;
+            .first(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_domain(domain: String) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    pub fn get_by_domain(conn: &mut PgConnection, domain: String) -> Result<Vec<Self>> {
         let res = metadata::table
             .filter(metadata::domain.eq(domain))
-            .load::<Metadata>(&mut conn)?;
+            .load::<Metadata>(conn)?;
         Ok(res)
     }
 
-    pub fn get_data_object_ids_by_domain(domain: String) -> Result<Vec<Uuid>> {
-        let mut conn = database::connection()?;
+    pub fn get_data_object_ids_by_domain(conn: &mut PgConnection, domain: String) -> Result<Vec<Uuid>> {
         let res = metadata::table
             .filter(metadata::domain.eq(domain))
-            .select((metadata::data_object_id))
-            .load::<Uuid>(&mut conn)?;
+            .select(metadata::data_object_id)
+            .load::<Uuid>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_data_object_id(data_object_id: &Uuid) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_by_data_object_id(conn: &mut PgConnection, data_object_id: &Uuid) -> Result<Self> {
         let res = metadata::table
             .filter(metadata::data_object_id.eq(data_object_id))
-            .first(&mut conn)?;
+            .first(conn)?;
         Ok(res)
     }
 
-    pub fn update(&self) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn update(&self, conn: &mut PgConnection) -> Result<Self> {
 
         let res = diesel::update(metadata::table)
             .filter(metadata::id.eq(&self.id))
             .set(self)
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(res)
     }
@@ -136,7 +127,7 @@ impl NewMetadata {
 
 /// A light struct to accept the JSON formatted Metadata included with
 /// a ConversionRequest
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, InputObject)]
 pub struct InsertableMetadata {
     pub domain: String,
     pub tags: Vec<Option<String>>,
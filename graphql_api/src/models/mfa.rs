@@ -0,0 +1,302 @@
+//! TOTP second factor for password sign-in.
+//!
+//! A user enrols by scanning the provisioning URI (or its QR code) into an
+//! authenticator app and confirming the first code, which enables the
+//! factor. Enrolment also hands out single-use recovery codes for a lost
+//! device. Once the password checks out, `signIn` returns a challenge instead
+//! of tokens when the user has TOTP enabled or their role requires it (see
+//! MFA_REQUIRED_ROLES), and `verifyMfa` exchanges the challenge and a code
+//! for a session.
+
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_graphql::*;
+use chrono::{Duration, prelude::*};
+use diesel::prelude::*;
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, QueryDsl, Queryable, RunQueryDsl};
+use lazy_static::lazy_static;
+use qrcode::QrCode;
+use qrcode::render::svg;
+use rand::RngCore;
+use rand::distributions::{Alphanumeric, DistString};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+use crate::common_utils::UserRole;
+use crate::config_variables::{MFA_CHALLENGE_DURATION, RECOVERY_CODE_COUNT};
+use crate::schema::{mfa_challenges, totp_recovery_codes};
+
+/// Length of the challenge handed out after the password step
+const CHALLENGE_LENGTH: usize = 48;
+
+/// Bytes of shared secret, the RFC 4226 recommendation for HMAC-SHA1
+const TOTP_SECRET_BYTES: usize = 20;
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_SECONDS: u64 = 30;
+
+lazy_static! {
+    /// Roles that must use a second factor, from the comma separated
+    /// MFA_REQUIRED_ROLES. Defaults to ADMIN and OPERATOR; set it empty to
+    /// make TOTP optional for everyone.
+    static ref MFA_REQUIRED_ROLES: Vec<UserRole> = std::env::var("MFA_REQUIRED_ROLES")
+        .unwrap_or_else(|_| "ADMIN,OPERATOR".to_owned())
+        .split(',')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(|r| UserRole::from_str(r).unwrap_or_else(|_| panic!("Unknown role {} in MFA_REQUIRED_ROLES", r)))
+        .collect();
+
+    /// Issuer shown in authenticator apps, from TOTP_ISSUER
+    static ref TOTP_ISSUER: String = std::env::var("TOTP_ISSUER")
+        .unwrap_or_else(|_| "Security Classification Converter".to_owned());
+}
+
+/// Whether users with `role` must pass a second factor to sign in
+pub fn mfa_required(role: &str) -> bool {
+    UserRole::from_str(role)
+        .map(|r| MFA_REQUIRED_ROLES.contains(&r))
+        .unwrap_or(false)
+}
+
+fn sha256_hex(value: &str) -> String {
+    format!("{:x}", Sha256::digest(value.as_bytes()))
+}
+
+/// A new base32 TOTP secret
+pub fn generate_totp_secret() -> String {
+    let mut bytes = [0u8; TOTP_SECRET_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    Secret::Raw(bytes.to_vec()).to_encoded().to_string()
+}
+
+fn totp(secret: &str, account: &str) -> Result<TOTP> {
+    let bytes = Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .map_err(|_| Error::new("Invalid TOTP secret"))?;
+
+    // Labels can't contain ':'
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        1,
+        TOTP_STEP_SECONDS,
+        bytes,
+        Some(TOTP_ISSUER.replace(':', "")),
+        account.replace(':', ""),
+    )
+    .map_err(|e| Error::new(format!("Invalid TOTP parameters: {}", e)))
+}
+
+/// The `otpauth://` URI authenticator apps enrol from
+pub fn totp_provisioning_uri(secret: &str, account: &str) -> Result<String> {
+    Ok(totp(secret, account)?.get_url())
+}
+
+/// The provisioning URI as an SVG QR code
+pub fn qr_code_svg(uri: &str) -> Result<String> {
+    let code = QrCode::new(uri.as_bytes()).map_err(|e| Error::new(format!("Unable to render QR code: {}", e)))?;
+    Ok(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
+
+/// The time step `code` is valid for, allowing one step of clock drift either
+/// way. Steps at or before `last_step` are refused so a code can't be
+/// replayed.
+pub fn verify_totp(secret: &str, code: &str, last_step: Option<i64>) -> Result<Option<i64>> {
+    let totp = totp(secret, "")?;
+    let code = code.trim();
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let current = (now / TOTP_STEP_SECONDS) as i64;
+
+    for step in [current - 1, current, current + 1] {
+        if last_step.is_some_and(|last| step <= last) {
+            continue;
+        }
+
+        let expected = totp.generate(step as u64 * TOTP_STEP_SECONDS);
+        if constant_time_eq(expected.as_bytes(), code.as_bytes()) {
+            return Ok(Some(step));
+        }
+    }
+
+    Ok(None)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// New recovery codes, formatted `xxxxx-xxxxx`
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = Alphanumeric.sample_string(&mut OsRng, 10).to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Hash of a recovery code as typed, ignoring case and spacing
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalised: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+
+    sha256_hex(&normalised)
+}
+
+/// A new random sign-in challenge. Only its hash is stored.
+pub fn generate_mfa_challenge() -> String {
+    Alphanumeric.sample_string(&mut OsRng, CHALLENGE_LENGTH)
+}
+
+pub fn hash_mfa_challenge(challenge: &str) -> String {
+    sha256_hex(challenge)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = mfa_challenges)]
+/// A password sign-in waiting for its second factor
+pub struct SignInChallenge {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub challenge_hash: String,
+    pub failed_attempts: i32,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+impl SignInChallenge {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now().naive_utc()
+    }
+
+    pub fn create(conn: &mut PgConnection, challenge: &NewSignInChallenge) -> Result<Self> {
+        let res = diesel::insert_into(mfa_challenges::table)
+            .values(challenge)
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    pub fn get_by_challenge_hash(conn: &mut PgConnection, hash: &str) -> Result<Self> {
+        let res = mfa_challenges::table
+            .filter(mfa_challenges::challenge_hash.eq(hash))
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    /// Counts a wrong code against the challenge
+    pub fn record_failure(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
+        let res = diesel::update(mfa_challenges::table)
+            .filter(mfa_challenges::id.eq(id))
+            .set(mfa_challenges::failed_attempts.eq(mfa_challenges::failed_attempts + 1))
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    /// Removes the challenge, returning whether it was still there. Only one
+    /// of several concurrent uses gets true.
+    pub fn delete(conn: &mut PgConnection, id: &Uuid) -> Result<bool> {
+        let res = diesel::delete(mfa_challenges::table)
+            .filter(mfa_challenges::id.eq(id))
+            .execute(conn)?;
+
+        Ok(res == 1)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Insertable)]
+#[diesel(table_name = mfa_challenges)]
+pub struct NewSignInChallenge {
+    pub user_id: Uuid,
+    pub challenge_hash: String,
+    pub expires_at: NaiveDateTime,
+}
+
+impl NewSignInChallenge {
+    /// A challenge for `user_id` with the hash of `challenge`
+    pub fn new(user_id: Uuid, challenge: &str) -> Self {
+        NewSignInChallenge {
+            user_id,
+            challenge_hash: hash_mfa_challenge(challenge),
+            expires_at: Utc::now().naive_utc() + Duration::seconds(MFA_CHALLENGE_DURATION),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = totp_recovery_codes)]
+pub struct RecoveryCode {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub code_hash: String,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+impl RecoveryCode {
+    /// Replaces every recovery code of a user with `code_hashes`
+    pub fn replace_for_user(conn: &mut PgConnection, user_id: &Uuid, code_hashes: Vec<String>) -> Result<()> {
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            diesel::delete(totp_recovery_codes::table)
+                .filter(totp_recovery_codes::user_id.eq(user_id))
+                .execute(conn)?;
+
+            let rows: Vec<_> = code_hashes
+                .into_iter()
+                .map(|hash| {
+                    (
+                        totp_recovery_codes::user_id.eq(*user_id),
+                        totp_recovery_codes::code_hash.eq(hash),
+                    )
+                })
+                .collect();
+
+            if !rows.is_empty() {
+                diesel::insert_into(totp_recovery_codes::table)
+                    .values(&rows)
+                    .execute(conn)?;
+            }
+
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    /// Marks an unused code of the user as used, returning whether there was one
+    pub fn redeem(conn: &mut PgConnection, user_id: &Uuid, code_hash: &str) -> Result<bool> {
+        let res = diesel::update(totp_recovery_codes::table)
+            .filter(totp_recovery_codes::user_id.eq(user_id))
+            .filter(totp_recovery_codes::code_hash.eq(code_hash))
+            .filter(totp_recovery_codes::used_at.is_null())
+            .set(totp_recovery_codes::used_at.eq(Utc::now().naive_utc()))
+            .execute(conn)?;
+
+        Ok(res == 1)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, SimpleObject)]
+/// What an authenticator app needs to enrol. Shown on each sign-in until the
+/// enrolment is confirmed.
+pub struct TotpEnrolment {
+    /// Base32 secret, for apps that can't scan the QR code
+    pub secret: String,
+    /// `otpauth://` URI encoded in the QR code
+    pub provisioning_uri: String,
+    /// SVG image of the provisioning URI as a QR code
+    pub qr_code_svg: String,
+    /// Single-use codes that stand in for a TOTP code if the device is lost.
+    /// They work once the enrolment is confirmed. Only given out with a new
+    /// secret, and empty when the pending secret is shown again.
+    pub recovery_codes: Vec<String>,
+}
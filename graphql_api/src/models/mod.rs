@@ -1,25 +1,51 @@
-mod access_log;
+mod api_key;
+mod audit;
+mod audit_export;
 mod auth;
+mod authentication;
 mod messages;
+mod mfa;
+mod oidc;
+mod security_event;
+mod session;
+mod sign_in_throttle;
+mod signing_keys;
 mod user;
 
 // App
 mod authority;
+mod authority_member;
 mod classification_schema;
 mod data_object;
 mod metadata;
 mod nation;
 mod conversion_request;
+mod conversion_result;
+mod merkle;
+mod receipt;
 
-pub use self::access_log::*;
+pub use self::api_key::*;
+pub use self::audit::*;
+pub use self::audit_export::*;
+pub use self::mfa::*;
+pub use self::oidc::*;
+pub use self::security_event::*;
+pub use self::session::*;
+pub use self::sign_in_throttle::*;
+pub use self::signing_keys::*;
 pub use self::user::*;
 //pub use messages::*;
 pub use auth::*;
+pub use authentication::*;
 
 // App
 pub use authority::*;
+pub use authority_member::*;
 pub use classification_schema::*;
 pub use data_object::*;
 pub use metadata::*;
 pub use nation::*;
 pub use conversion_request::*;
+pub use conversion_result::*;
+pub use merkle::*;
+pub use receipt::*;
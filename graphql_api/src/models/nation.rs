@@ -2,13 +2,14 @@ use std::fmt::Debug;
 
 use async_graphql::*;
 use chrono::prelude::*;
-use diesel::{self, ExpressionMethods, Insertable, PgTextExpressionMethods, Queryable};
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, Queryable};
 use diesel::{QueryDsl, RunQueryDsl};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::models::{Authority, User};
-use crate::{database, schema::*};
+use crate::graphql::get_repositories_from_context;
+use crate::schema::*;
 
 #[derive(
     Debug, Clone, Deserialize, Serialize, Queryable, Insertable, AsChangeset, SimpleObject,
@@ -28,82 +29,74 @@ pub struct Nation {
 // GraphQL implementation
 #[ComplexObject]
 impl Nation {
-    pub async fn creator(&self) -> Result<User> {
-        User::get_by_id(&self.creator_id)
+    pub async fn creator(&self, ctx: &Context<'_>) -> Result<User> {
+        get_repositories_from_context(ctx).users.get_by_id(self.creator_id).await
     }
 
-    pub async fn authorities(&self) -> Result<Vec<Authority>> {
-        Authority::get_by_nation_id(&self.id)
+    pub async fn authorities(&self, ctx: &Context<'_>) -> Result<Vec<Authority>> {
+        get_repositories_from_context(ctx).authorities.get_by_nation_id(self.id).await
     }
 }
 
 // Non Graphql
 impl Nation {
-    pub fn create(nation: &NewNation) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn create(conn: &mut PgConnection, nation: &NewNation) -> Result<Self> {
 
         let res = diesel::insert_into(nations::table)
             .values(nation)
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(res)
     }
 
-    pub fn get_or_create(nation: &NewNation) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_or_create(conn: &mut PgConnection, nation: &NewNation) -> Result<Self> {
 
         let res = nations::table
             .filter(nations::creator_id.eq(&nation.creator_id))
             .distinct()
-            .first(&mut conn);
+            .first(conn);
 
         let nation = match res {
             Ok(p) => p,
             Err(e) => {
                 // Nation not found
                 println!("{:?}", e);
-                let p = Nation::create(nation).expect("Unable to create nation");
-                p
+                Nation::create(conn, nation).expect("Unable to create nation")
             }
         };
         Ok(nation)
     }
 
-    pub fn get_all() -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
-        let res = nations::table.load::<Nation>(&mut conn)?;
+    pub fn get_all(conn: &mut PgConnection) -> Result<Vec<Self>> {
+        let res = nations::table.load::<Nation>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_id(id: &Uuid) -> Result<Self> {
-        let mut conn = database::connection()?;
-        let res = nations::table.filter(nations::id.eq(id)).first(&mut conn)?;
+    pub fn get_by_id(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
+        let res = nations::table.filter(nations::id.eq(id)).first(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_creator_id(creator_id: Uuid) -> Result<Vec<Self>> {
-        let mut conn = database::connection()?;
+    pub fn get_by_creator_id(conn: &mut PgConnection, creator_id: Uuid) -> Result<Vec<Self>> {
         let res = nations::table
             .filter(nations::creator_id.eq(creator_id))
-            .load::<Nation>(&mut conn)?;
+            .load::<Nation>(conn)?;
         Ok(res)
     }
 
-    pub fn get_by_code(nation_code: &String) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn get_by_code(conn: &mut PgConnection, nation_code: &String) -> Result<Self> {
         let res = nations::table
             .filter(nations::nation_code.eq(nation_code))
-            .first(&mut conn)?;
+            .first(conn)?;
         Ok(res)
     }
 
-    pub fn update(&self) -> Result<Self> {
-        let mut conn = database::connection()?;
+    pub fn update(&self, conn: &mut PgConnection) -> Result<Self> {
 
         let res = diesel::update(nations::table)
             .filter(nations::id.eq(&self.id))
             .set(self)
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(res)
     }
@@ -0,0 +1,341 @@
+//! OpenID Connect single sign-on against a national IdP.
+//!
+//! Configured with OIDC_ISSUER, OIDC_CLIENT_ID, OIDC_CLIENT_SECRET and
+//! OIDC_REDIRECT_URI. The provider's endpoints are discovered at startup.
+//! Users are matched on the `email` claim and created on first sign-in, with
+//! their role, clearance and nationality from the IdP. Only accounts created
+//! this way are matched, never password accounts. On later sign-ins the IdP
+//! can lower their role and clearance but not raise them; that takes an
+//! administrator.
+//!
+//! - OIDC_ROLE_GROUPS="scc-admins:ADMIN,scc-operators:OPERATOR", highest
+//!   matching role wins, USER when none match
+//! - OIDC_ACCESS_LEVEL_GROUPS="scc-secret:SECRET,scc-ts:TOP_SECRET", highest
+//!   matching clearance wins, UNCLASSIFIED when none match
+//! - OIDC_GROUPS_CLAIM names the claim holding the groups, `groups` by default
+//! - OIDC_NATIONALITY_CLAIM names the claim holding the user's nation code,
+//!   `nationality` by default
+
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use async_graphql::{Error, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, NaiveDateTime, Utc};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rand::distributions::{Alphanumeric, DistString};
+use rand::rngs::OsRng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::common_utils::UserRole;
+use crate::models::NatoClassification;
+
+/// How long a user has to complete the IdP login before its state expires
+const LOGIN_TIMEOUT_SECONDS: i64 = 600;
+
+/// Stored in place of a password hash for users provisioned through SSO. It
+/// never parses as a PHC string, so password sign-in always fails for them.
+pub const SSO_ONLY_HASH: &str = "!sso";
+
+/// Subset of the provider's discovery document we rely on
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Login started by `authorization_url` and waiting for the IdP callback
+struct PendingLogin {
+    nonce: String,
+    code_verifier: String,
+    started_at: NaiveDateTime,
+}
+
+/// The identity asserted by a validated ID token, mapped onto our user model
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+    pub email: String,
+    pub name: String,
+    pub role: UserRole,
+    pub access_level: NatoClassification,
+    pub nationality: Option<String>,
+}
+
+/// Client settings for a provider, normally read from the OIDC_* variables
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub groups_claim: String,
+    pub nationality_claim: String,
+    pub role_groups: Vec<(String, UserRole)>,
+    pub access_level_groups: Vec<(String, NatoClassification)>,
+}
+
+impl OidcConfig {
+    /// Reads the OIDC_* variables. Returns None when OIDC_ISSUER is not set.
+    pub fn from_env() -> Option<Self> {
+        let issuer = env::var("OIDC_ISSUER").ok()?;
+
+        Some(OidcConfig {
+            issuer,
+            client_id: env::var("OIDC_CLIENT_ID").expect("Can't read OIDC_CLIENT_ID"),
+            client_secret: env::var("OIDC_CLIENT_SECRET").expect("Can't read OIDC_CLIENT_SECRET"),
+            redirect_uri: env::var("OIDC_REDIRECT_URI").expect("Can't read OIDC_REDIRECT_URI"),
+            groups_claim: env::var("OIDC_GROUPS_CLAIM").unwrap_or_else(|_| "groups".to_owned()),
+            nationality_claim: env::var("OIDC_NATIONALITY_CLAIM")
+                .unwrap_or_else(|_| "nationality".to_owned()),
+            role_groups: group_mapping("OIDC_ROLE_GROUPS", |r| UserRole::from_str(r).ok()),
+            access_level_groups: group_mapping("OIDC_ACCESS_LEVEL_GROUPS", |l| {
+                NatoClassification::from_str(l).ok()
+            }),
+        })
+    }
+}
+
+pub struct OidcProvider {
+    metadata: ProviderMetadata,
+    config: OidcConfig,
+    http: reqwest::Client,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+/// Parses "group:VALUE,group:VALUE" mappings
+fn group_mapping<T>(name: &str, parse: impl Fn(&str) -> Option<T>) -> Vec<(String, T)> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            pair.trim()
+                .rsplit_once(':')
+                .and_then(|(group, value)| Some((group.to_owned(), parse(value)?)))
+                .unwrap_or_else(|| panic!("Unable to parse {} entry \"{}\"", name, pair))
+        })
+        .collect()
+}
+
+fn random_string(len: usize) -> String {
+    Alphanumeric.sample_string(&mut OsRng, len)
+}
+
+impl OidcProvider {
+    /// Builds the provider from the OIDC_* variables and fetches its discovery
+    /// document. Returns None when OIDC_ISSUER is not set.
+    pub async fn from_env() -> Option<Self> {
+        let config = OidcConfig::from_env()?;
+
+        Some(OidcProvider::discover(config).await.unwrap_or_else(|e| panic!("{}", e.message)))
+    }
+
+    /// Fetches the issuer's discovery document and builds the provider
+    pub async fn discover(mut config: OidcConfig) -> Result<Self> {
+        config.issuer = config.issuer.trim_end_matches('/').to_owned();
+
+        let http = reqwest::Client::new();
+
+        let metadata: ProviderMetadata = http
+            .get(format!("{}/.well-known/openid-configuration", config.issuer))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| Error::new(format!("Unable to reach OIDC_ISSUER: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| Error::new(format!("Unable to parse OIDC discovery document: {}", e)))?;
+
+        if metadata.issuer.trim_end_matches('/') != config.issuer {
+            return Err(Error::new("OIDC discovery document is for a different issuer"));
+        }
+
+        Ok(OidcProvider {
+            metadata,
+            config,
+            http,
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Starts a login and returns the IdP URL to send the browser to. The
+    /// request carries a fresh state, nonce and PKCE challenge.
+    pub fn authorization_url(&self) -> String {
+        let state = random_string(32);
+        let nonce = random_string(32);
+        let code_verifier = random_string(64);
+
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+        let mut pending = self.pending.lock().expect("OIDC login lock poisoned");
+
+        let now = Utc::now().naive_utc();
+        pending.retain(|_, p| now - p.started_at < Duration::seconds(LOGIN_TIMEOUT_SECONDS));
+
+        let url = reqwest::Url::parse_with_params(
+            &self.metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", &self.config.redirect_uri),
+                ("scope", "openid email profile"),
+                ("state", &state),
+                ("nonce", &nonce),
+                ("code_challenge", &code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .expect("Invalid OIDC authorization endpoint");
+
+        pending.insert(state, PendingLogin { nonce, code_verifier, started_at: now });
+
+        url.into()
+    }
+
+    /// Completes a login from the IdP callback: exchanges the code, validates
+    /// the ID token and maps its claims
+    pub async fn complete_login(&self, code: &str, state: &str) -> Result<OidcIdentity> {
+        let login = self
+            .pending
+            .lock()
+            .expect("OIDC login lock poisoned")
+            .remove(state)
+            .filter(|p| {
+                Utc::now().naive_utc() - p.started_at < Duration::seconds(LOGIN_TIMEOUT_SECONDS)
+            })
+            .ok_or_else(|| Error::new("Unknown or expired login state"))?;
+
+        let tokens: TokenResponse = self
+            .http
+            .post(&self.metadata.token_endpoint)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.config.redirect_uri),
+                ("code_verifier", &login.code_verifier),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let claims = self.validate_id_token(&tokens.id_token, &login.nonce).await?;
+
+        self.identity(&claims)
+    }
+
+    async fn validate_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<HashMap<String, serde_json::Value>> {
+        let header = decode_header(id_token)?;
+
+        // Only accept asymmetric signatures from the IdP's published keys
+        if !matches!(
+            header.alg,
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 | Algorithm::PS256
+                | Algorithm::ES256 | Algorithm::ES384 | Algorithm::EdDSA
+        ) {
+            return Err(Error::new(format!("Unsupported ID token algorithm {:?}", header.alg)));
+        }
+
+        let jwks: JwkSet = self
+            .http
+            .get(&self.metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let jwk = match header.kid.as_deref() {
+            Some(kid) => jwks.find(kid),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| Error::new("ID token signed with an unknown key"))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(&[&self.metadata.issuer]);
+
+        let claims = decode::<HashMap<String, serde_json::Value>>(
+            id_token,
+            &DecodingKey::from_jwk(jwk)?,
+            &validation,
+        )?
+        .claims;
+
+        if claims.get("nonce").and_then(|n| n.as_str()) != Some(nonce) {
+            return Err(Error::new("ID token nonce does not match the login"));
+        }
+
+        Ok(claims)
+    }
+
+    fn identity(&self, claims: &HashMap<String, serde_json::Value>) -> Result<OidcIdentity> {
+        let email = claims
+            .get("email")
+            .and_then(|e| e.as_str())
+            .ok_or_else(|| Error::new("ID token has no email claim"))?
+            .to_owned();
+
+        if claims.get("email_verified").and_then(|v| v.as_bool()) == Some(false) {
+            return Err(Error::new("IdP email address is not verified"));
+        }
+
+        let name = claims
+            .get("name")
+            .and_then(|n| n.as_str())
+            .unwrap_or(&email)
+            .to_owned();
+
+        let groups: Vec<&str> = match claims.get(&self.config.groups_claim) {
+            Some(serde_json::Value::Array(groups)) => {
+                groups.iter().filter_map(|g| g.as_str()).collect()
+            }
+            Some(serde_json::Value::String(group)) => vec![group.as_str()],
+            _ => Vec::new(),
+        };
+
+        let role = self
+            .config
+            .role_groups
+            .iter()
+            .filter(|(group, _)| groups.contains(&group.as_str()))
+            .map(|(_, role)| *role)
+            .max()
+            .unwrap_or(UserRole::User);
+
+        let access_level = self
+            .config
+            .access_level_groups
+            .iter()
+            .filter(|(group, _)| groups.contains(&group.as_str()))
+            .map(|(_, level)| *level)
+            .max()
+            .unwrap_or(NatoClassification::Unclassified);
+
+        let nationality = claims
+            .get(&self.config.nationality_claim)
+            .and_then(|n| n.as_str())
+            .map(|n| n.to_uppercase());
+
+        Ok(OidcIdentity { email, name, role, access_level, nationality })
+    }
+}
@@ -0,0 +1,432 @@
+//! Signed conversion receipts.
+//!
+//! Every conversion result is stored with a receipt: a canonical JSON
+//! statement of which marking was converted to which, for which request and
+//! data object (and its document's digest, if it was submitted with one),
+//! under which schema versions, and when. With
+//! `RECEIPT_SIGNING_KEY` pointing at an Ed25519 PKCS#8 PEM file the receipt is
+//! signed (the key is required in production; only development runs store
+//! unsigned receipts), so a partner holding the public key (served at
+//! `/.well-known/receipt-keys.json`) can prove the equivalence was issued by
+//! this service without asking it. `verify_receipt` checks one offline, and
+//! the `verify_receipt` binary wraps it for the command line. The same key
+//! signs Merkle anchor roots (see `models/merkle.rs`).
+//!
+//! The canonical form is the `ConversionReceipt` JSON object with its keys in
+//! alphabetical order, no whitespace, and `issued_at` as RFC 3339 UTC to the
+//! microsecond. The signature is over its UTF-8 bytes, base64 encoded.
+//!
+//! Keep the public key of every retired signing key: receipts are never
+//! re-signed.
+
+use std::fs;
+use std::path::Path;
+
+use async_graphql::{Error, Result};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chrono::{SecondsFormat, Utc};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::models::{ClassificationSchema, DataObject, NewConversionResult};
+
+/// Version of the receipt format, signed with the receipt. Version 2 added
+/// the content digest, which version 1 receipts don't have.
+pub const RECEIPT_VERSION: u32 = 2;
+
+lazy_static! {
+    static ref RECEIPT_KEY: Option<ReceiptKey> = std::env::var("RECEIPT_SIGNING_KEY")
+        .ok()
+        .map(|path| load_receipt_key_file(Path::new(&path)));
+}
+
+struct ReceiptKey {
+    kid: String,
+    signing: SigningKey,
+}
+
+fn load_receipt_key_file(path: &Path) -> ReceiptKey {
+    let pem = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Can't read RECEIPT_SIGNING_KEY {}: {}", path.display(), e));
+
+    let signing = SigningKey::from_pkcs8_pem(&pem)
+        .unwrap_or_else(|e| panic!("Invalid RECEIPT_SIGNING_KEY {}: {}", path.display(), e));
+
+    ReceiptKey {
+        kid: receipt_key_id(&signing.verifying_key()),
+        signing,
+    }
+}
+
+/// Loads the receipt key now rather than on the first conversion, so a broken
+/// key stops the server at startup. In production a missing key does too:
+/// unsigned receipts are only for development.
+pub fn load_receipt_key(production: bool) {
+    if RECEIPT_KEY.is_none() {
+        if production {
+            panic!("RECEIPT_SIGNING_KEY must be set in production");
+        }
+
+        println!("RECEIPT_SIGNING_KEY not set, conversion receipts will not be signed");
+    }
+}
+
+/// Identifier of a receipt key: the first 16 hex digits of the SHA-256 of
+/// the raw public key
+pub fn receipt_key_id(key: &VerifyingKey) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))[..16].to_owned()
+}
+
+/// The receipt public key as a JWK set, for `/.well-known/receipt-keys.json`.
+/// Empty when receipts aren't signed.
+pub fn receipt_public_keys() -> JwkSet {
+    let keys = RECEIPT_KEY
+        .iter()
+        .map(|key| Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(KeyAlgorithm::EdDSA),
+                key_id: Some(key.kid.clone()),
+                ..Default::default()
+            },
+            algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(key.signing.verifying_key().to_bytes()),
+            }),
+        })
+        .collect();
+
+    JwkSet { keys }
+}
+
+/// The Ed25519 public key of a JWK from `/.well-known/receipt-keys.json`
+pub fn receipt_verifying_key(jwk: &Jwk) -> std::result::Result<VerifyingKey, String> {
+    let AlgorithmParameters::OctetKeyPair(params) = &jwk.algorithm else {
+        return Err("not an Ed25519 key".to_owned());
+    };
+
+    let bytes: [u8; 32] = URL_SAFE_NO_PAD
+        .decode(&params.x)
+        .map_err(|e| e.to_string())?
+        .try_into()
+        .map_err(|_| "Ed25519 keys are 32 bytes".to_owned())?;
+
+    VerifyingKey::from_bytes(&bytes).map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+/// What a receipt states. Fields are declared in alphabetical order, which
+/// is the order they are serialized and signed in.
+pub struct ConversionReceipt {
+    /// Digest of the converted document's bytes, left out when the data
+    /// object has none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_digest: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_size: Option<i64>,
+    pub conversion_request_id: Uuid,
+    /// `DataObject::record_hash` of the converted object
+    pub data_object_hash: String,
+    pub data_object_id: Uuid,
+    /// DigestAlgorithm of content_digest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest_algorithm: Option<String>,
+    pub issued_at: String,
+    pub nato_classification: String,
+    pub source_marking: String,
+    pub source_nation_code: String,
+    pub source_schema_id: Uuid,
+    pub source_schema_version: String,
+    pub target_marking: String,
+    pub target_nation_code: String,
+    pub target_schema_id: Uuid,
+    pub target_schema_version: String,
+    pub version: u32,
+}
+
+impl ConversionReceipt {
+    /// The receipt for a result of converting `data_object`. `schemas` must
+    /// hold the result's source and target schemas.
+    pub fn issue(
+        result: &NewConversionResult,
+        data_object: &DataObject,
+        schemas: &[ClassificationSchema],
+    ) -> Result<Self> {
+        let version = |id: Uuid| {
+            schemas
+                .iter()
+                .find(|s| s.id == id)
+                .map(|s| s.version.clone())
+                .ok_or_else(|| Error::new(format!("Classification schema {} not found", id)))
+        };
+
+        Ok(ConversionReceipt {
+            content_digest: data_object.content_digest.clone(),
+            content_size: data_object.content_size,
+            conversion_request_id: result.conversion_request_id,
+            data_object_hash: data_object.record_hash(),
+            data_object_id: data_object.id,
+            digest_algorithm: data_object.digest_algorithm.clone(),
+            issued_at: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            nato_classification: result.nato_classification.clone(),
+            source_marking: result.source_marking.clone(),
+            source_nation_code: result.source_nation_code.clone(),
+            source_schema_id: result.source_schema_id,
+            source_schema_version: version(result.source_schema_id)?,
+            target_marking: result.target_marking.clone(),
+            target_nation_code: result.target_nation_code.clone(),
+            target_schema_id: result.target_schema_id,
+            target_schema_version: version(result.target_schema_id)?,
+            version: RECEIPT_VERSION,
+        })
+    }
+
+    pub fn canonical_form(&self) -> String {
+        serde_json::to_string(self).expect("Receipts always serialize")
+    }
+}
+
+/// Base64 signature of `message` by the receipt key, with the key's id, or
+/// None when RECEIPT_SIGNING_KEY is not set
+pub fn sign_with_receipt_key(message: &[u8]) -> Option<(String, String)> {
+    RECEIPT_KEY
+        .as_ref()
+        .map(|key| (STANDARD.encode(key.signing.sign(message).to_bytes()), key.kid.clone()))
+}
+
+/// The key `kid` from receipt public keys, given either as a JWK set saved
+/// from `/.well-known/receipt-keys.json` or as an Ed25519 public key PEM
+pub fn find_receipt_key(keys: &str, kid: &str) -> std::result::Result<VerifyingKey, String> {
+    if keys.trim_start().starts_with("-----BEGIN") {
+        let key = VerifyingKey::from_public_key_pem(keys).map_err(|e| e.to_string())?;
+
+        if receipt_key_id(&key) != kid {
+            return Err(format!("the PEM key is {}, not {}", receipt_key_id(&key), kid));
+        }
+
+        return Ok(key);
+    }
+
+    let jwks: JwkSet = serde_json::from_str(keys).map_err(|e| format!("not a JWK set or PEM key: {}", e))?;
+
+    let jwk = jwks.find(kid).ok_or_else(|| format!("no key {} in the key set", kid))?;
+
+    receipt_verifying_key(jwk)
+}
+
+/// Checks a base64 signature made by `sign_with_receipt_key`
+pub fn verify_receipt_signature(
+    message: &[u8],
+    signature: &str,
+    key: &VerifyingKey,
+) -> std::result::Result<(), String> {
+    let signature = STANDARD
+        .decode(signature)
+        .map_err(|e| format!("signature is not base64: {}", e))?;
+    let signature = Signature::from_slice(&signature).map_err(|e| format!("malformed signature: {}", e))?;
+
+    key.verify_strict(message, &signature)
+        .map_err(|_| "signature does not match".to_owned())
+}
+
+/// Attaches a receipt to each result, signed when RECEIPT_SIGNING_KEY is set
+pub fn issue_receipts(
+    results: Vec<NewConversionResult>,
+    data_object: &DataObject,
+    schemas: &[ClassificationSchema],
+) -> Result<Vec<NewConversionResult>> {
+    results
+        .into_iter()
+        .map(|mut result| {
+            let receipt = ConversionReceipt::issue(&result, data_object, schemas)?.canonical_form();
+
+            if let Some((signature, kid)) = sign_with_receipt_key(receipt.as_bytes()) {
+                result.signature = Some(signature);
+                result.signing_key_id = Some(kid);
+            }
+
+            result.receipt = Some(receipt);
+            Ok(result)
+        })
+        .collect()
+}
+
+/// Checks a receipt and its base64 signature against a receipt public key,
+/// without the database or the service. Returns what the receipt states if
+/// the signature holds and the receipt is in canonical form.
+pub fn verify_receipt(
+    receipt: &str,
+    signature: &str,
+    key: &VerifyingKey,
+) -> std::result::Result<ConversionReceipt, String> {
+    verify_receipt_signature(receipt.as_bytes(), signature, key)?;
+
+    let statement: ConversionReceipt =
+        serde_json::from_str(receipt).map_err(|e| format!("malformed receipt: {}", e))?;
+
+    if statement.canonical_form() != receipt {
+        return Err("receipt is not in canonical form".to_owned());
+    }
+
+    Ok(statement)
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::pkcs8::EncodePublicKey;
+
+    use super::*;
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn sign(key: &SigningKey, receipt: &str) -> String {
+        STANDARD.encode(key.sign(receipt.as_bytes()).to_bytes())
+    }
+
+    fn receipt() -> ConversionReceipt {
+        ConversionReceipt {
+            content_digest: Some("ab".repeat(32)),
+            content_size: Some(1024),
+            conversion_request_id: Uuid::from_u128(1),
+            data_object_hash: "cd".repeat(32),
+            data_object_id: Uuid::from_u128(2),
+            digest_algorithm: Some("SHA256".to_owned()),
+            issued_at: "2026-10-18T12:00:00.000000Z".to_owned(),
+            nato_classification: "SECRET".to_owned(),
+            source_marking: "GBR SECRET".to_owned(),
+            source_nation_code: "GBR".to_owned(),
+            source_schema_id: Uuid::from_u128(3),
+            source_schema_version: "1".to_owned(),
+            target_marking: "USA SECRET".to_owned(),
+            target_nation_code: "USA".to_owned(),
+            target_schema_id: Uuid::from_u128(4),
+            target_schema_version: "2".to_owned(),
+            version: RECEIPT_VERSION,
+        }
+    }
+
+    fn jwks(key: &VerifyingKey) -> String {
+        let x = URL_SAFE_NO_PAD.encode(key.as_bytes());
+
+        format!(
+            r#"{{"keys":[{{"kty":"OKP","crv":"Ed25519","alg":"EdDSA","use":"sig","kid":"{}","x":"{}"}}]}}"#,
+            receipt_key_id(key),
+            x,
+        )
+    }
+
+    #[test]
+    fn valid_signature_verifies() {
+        let key = signing_key(7);
+        let canonical = receipt().canonical_form();
+
+        let statement = verify_receipt(&canonical, &sign(&key, &canonical), &key.verifying_key()).unwrap();
+
+        assert_eq!(statement, receipt());
+    }
+
+    #[test]
+    fn tampered_receipt_is_refused() {
+        let key = signing_key(7);
+        let canonical = receipt().canonical_form();
+        let signature = sign(&key, &canonical);
+
+        let tampered = canonical.replace("USA SECRET", "USA TOP SECRET");
+
+        assert_eq!(
+            verify_receipt(&tampered, &signature, &key.verifying_key()),
+            Err("signature does not match".to_owned()),
+        );
+    }
+
+    #[test]
+    fn signature_by_another_key_is_refused() {
+        let canonical = receipt().canonical_form();
+
+        assert!(verify_receipt(&canonical, &sign(&signing_key(8), &canonical), &signing_key(7).verifying_key()).is_err());
+    }
+
+    #[test]
+    fn non_canonical_key_order_is_refused() {
+        let key = signing_key(7);
+        let mut fields: Vec<(String, serde_json::Value)> = serde_json::to_value(receipt())
+            .unwrap()
+            .as_object()
+            .unwrap()
+            .clone()
+            .into_iter()
+            .collect();
+        fields.reverse();
+
+        let reordered = format!(
+            "{{{}}}",
+            fields
+                .iter()
+                .map(|(k, v)| format!("{}:{}", serde_json::to_string(k).unwrap(), v))
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+        assert_ne!(reordered, receipt().canonical_form());
+
+        // Signed as it is, yet a receipt only counts in its canonical form
+        assert_eq!(
+            verify_receipt(&reordered, &sign(&key, &reordered), &key.verifying_key()),
+            Err("receipt is not in canonical form".to_owned()),
+        );
+    }
+
+    #[test]
+    fn version_1_receipts_without_a_digest_verify() {
+        let key = signing_key(7);
+        let v1 = ConversionReceipt {
+            content_digest: None,
+            content_size: None,
+            digest_algorithm: None,
+            version: 1,
+            ..receipt()
+        }
+        .canonical_form();
+        assert!(!v1.contains("content_digest"));
+
+        let statement = verify_receipt(&v1, &sign(&key, &v1), &key.verifying_key()).unwrap();
+
+        assert_eq!(statement.version, 1);
+        assert_eq!(statement.content_digest, None);
+    }
+
+    #[test]
+    fn receipt_keys_are_found_by_kid() {
+        let key = signing_key(7).verifying_key();
+        let kid = receipt_key_id(&key);
+        let pem = key.to_public_key_pem(Default::default()).unwrap();
+
+        assert_eq!(find_receipt_key(&jwks(&key), &kid), Ok(key));
+        assert_eq!(find_receipt_key(&pem, &kid), Ok(key));
+    }
+
+    #[test]
+    fn wrong_kid_is_not_found() {
+        let key = signing_key(7).verifying_key();
+        let other = receipt_key_id(&signing_key(8).verifying_key());
+        let pem = key.to_public_key_pem(Default::default()).unwrap();
+
+        assert_eq!(find_receipt_key(&jwks(&key), &other), Err(format!("no key {} in the key set", other)));
+        assert_eq!(
+            find_receipt_key(&pem, &other),
+            Err(format!("the PEM key is {}, not {}", receipt_key_id(&key), other)),
+        );
+    }
+}
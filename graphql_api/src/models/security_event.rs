@@ -0,0 +1,120 @@
+use std::str::FromStr;
+
+use async_graphql::*;
+use chrono::prelude::*;
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, QueryDsl, Queryable, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::models::{AuditAction, AuditEntity, NewAuditEntry};
+use crate::repositories::Repositories;
+use crate::schema::*;
+
+/// What happened in a security event
+#[derive(Debug, Display, EnumString, Enum, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum SecurityEventType {
+    /// A session was issued
+    SignInSucceeded,
+    /// A wrong password or second-factor code
+    SignInFailed,
+    /// An attempt refused because the account or address is backing off
+    SignInThrottled,
+    /// Too many failures locked out an account or address
+    SignInLocked,
+    /// An administrator cleared the failures of an account or address
+    SignInUnlocked,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable, SimpleObject)]
+#[diesel(table_name = security_events)]
+/// A sign-in outcome or lockout change. Events are never updated.
+pub struct SecurityEvent {
+    pub id: Uuid,
+    /// SecurityEventType
+    pub event_type: String,
+    /// Account concerned, when the email belongs to one
+    pub user_id: Option<Uuid>,
+    /// Email the attempt was made for
+    pub email: Option<String>,
+    pub ip_address: Option<String>,
+    pub detail: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl SecurityEvent {
+    pub fn create(conn: &mut PgConnection, event: &NewSecurityEvent) -> Result<Self> {
+        let res = diesel::insert_into(security_events::table)
+            .values(event)
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    /// The latest `limit` events, newest first
+    pub fn get_recent(conn: &mut PgConnection, limit: i64) -> Result<Vec<Self>> {
+        let res = security_events::table
+            .order(security_events::created_at.desc())
+            .limit(limit)
+            .load::<SecurityEvent>(conn)?;
+
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Insertable)]
+#[diesel(table_name = security_events)]
+pub struct NewSecurityEvent {
+    pub event_type: String,
+    pub user_id: Option<Uuid>,
+    pub email: Option<String>,
+    pub ip_address: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl NewSecurityEvent {
+    pub fn new(
+        event_type: SecurityEventType,
+        user_id: Option<Uuid>,
+        email: Option<String>,
+        ip_address: Option<String>,
+        detail: Option<String>,
+    ) -> Self {
+        NewSecurityEvent {
+            event_type: event_type.to_string(),
+            user_id,
+            email,
+            ip_address,
+            detail,
+        }
+    }
+}
+
+/// Stores a security event and adds it to the audit log as done by
+/// `actor_id`. A failure to record is logged rather than failing the caller,
+/// so sign-ins don't depend on it.
+pub async fn record_security_event(repos: &Repositories, actor_id: Option<Uuid>, event: NewSecurityEvent) {
+    let event_type = event.event_type.clone();
+
+    let entry = NewAuditEntry {
+        detail: serde_json::json!({
+            "email": event.email,
+            "ip_address": event.ip_address,
+            "detail": event.detail,
+        }),
+        ..NewAuditEntry::new(
+            actor_id,
+            SecurityEventType::from_str(&event_type).map_or(AuditAction::SignInFailed, AuditAction::from),
+            AuditEntity::User,
+            event.user_id,
+        )
+    };
+
+    if let Err(e) = repos.security_events.create(event).await {
+        println!("Unable to record {} security event: {:?}", event_type, e);
+    }
+
+    if let Err(e) = repos.audit_log.append(vec![entry]).await {
+        println!("Unable to audit {} security event: {:?}", event_type, e);
+    }
+}
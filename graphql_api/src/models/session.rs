@@ -0,0 +1,133 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use diesel::prelude::*;
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, QueryDsl, Queryable, RunQueryDsl};
+use rand::distributions::{Alphanumeric, DistString};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use async_graphql::*;
+
+use crate::schema::*;
+use crate::config_variables::REFRESH_TOKEN_DURATION;
+
+/// Length of the opaque refresh tokens handed to clients
+const REFRESH_TOKEN_LENGTH: usize = 48;
+
+/// Session id carried in an access token, inserted into the GraphQL context
+/// next to the user id for requests with a valid token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionId(pub Uuid);
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = user_sessions)]
+/// A signed-in device. Access tokens are only honoured while their session
+/// is active, so revoking it signs the device out immediately.
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// SHA-256 of the current refresh token, which rotates on every refresh
+    pub refresh_token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+impl Session {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at > Utc::now().naive_utc()
+    }
+
+    pub fn create(conn: &mut PgConnection, session: &NewSession) -> Result<Self> {
+        let res = diesel::insert_into(user_sessions::table)
+            .values(session)
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    pub fn get_by_id(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
+        let res = user_sessions::table
+            .filter(user_sessions::id.eq(id))
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    pub fn get_by_refresh_token_hash(conn: &mut PgConnection, hash: &str) -> Result<Self> {
+        let res = user_sessions::table
+            .filter(user_sessions::refresh_token_hash.eq(hash))
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    /// Replace the refresh token of an unrevoked session and extend its
+    /// expiry. Matching on the current hash means a refresh token can only
+    /// be exchanged once, even by concurrent requests.
+    pub fn rotate(conn: &mut PgConnection, current_hash: &str, new_hash: &str) -> Result<Self> {
+        let now = Utc::now().naive_utc();
+
+        let res = diesel::update(user_sessions::table)
+            .filter(user_sessions::refresh_token_hash.eq(current_hash))
+            .filter(user_sessions::revoked_at.is_null())
+            .set((
+                user_sessions::refresh_token_hash.eq(new_hash),
+                user_sessions::last_used_at.eq(now),
+                user_sessions::expires_at.eq(now + Duration::seconds(REFRESH_TOKEN_DURATION)),
+            ))
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    pub fn revoke(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
+        let res = diesel::update(user_sessions::table)
+            .filter(user_sessions::id.eq(id))
+            .set(user_sessions::revoked_at.eq(Utc::now().naive_utc()))
+            .get_result(conn)?;
+
+        Ok(res)
+    }
+
+    /// Revoke every active session of a user, returning how many were revoked
+    pub fn revoke_all_by_user_id(conn: &mut PgConnection, user_id: &Uuid) -> Result<usize> {
+        let res = diesel::update(user_sessions::table)
+            .filter(user_sessions::user_id.eq(user_id))
+            .filter(user_sessions::revoked_at.is_null())
+            .set(user_sessions::revoked_at.eq(Utc::now().naive_utc()))
+            .execute(conn)?;
+
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Insertable)]
+#[diesel(table_name = user_sessions)]
+pub struct NewSession {
+    pub user_id: Uuid,
+    pub refresh_token_hash: String,
+    pub expires_at: NaiveDateTime,
+}
+
+impl NewSession {
+    /// A session for `user_id` with the hash of `refresh_token`
+    pub fn new(user_id: Uuid, refresh_token: &str) -> Self {
+        NewSession {
+            user_id,
+            refresh_token_hash: hash_refresh_token(refresh_token),
+            expires_at: Utc::now().naive_utc() + Duration::seconds(REFRESH_TOKEN_DURATION),
+        }
+    }
+}
+
+/// A new random refresh token. Only its hash is stored.
+pub fn generate_refresh_token() -> String {
+    Alphanumeric.sample_string(&mut OsRng, REFRESH_TOKEN_LENGTH)
+}
+
+pub fn hash_refresh_token(refresh_token: &str) -> String {
+    format!("{:x}", Sha256::digest(refresh_token.as_bytes()))
+}
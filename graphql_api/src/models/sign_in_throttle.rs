@@ -0,0 +1,304 @@
+//! Brute-force protection for sign-in.
+//!
+//! Failed attempts are counted per account and per client address. After a
+//! few free failures each further one makes the next attempt wait twice as
+//! long, up to SIGN_IN_MAX_BACKOFF, and reaching the lockout count refuses
+//! attempts for SIGN_IN_LOCKOUT_DURATION. Failures after a lockout lock
+//! again straight away, until an administrator calls `unlockSignIn` or no
+//! failure is seen for SIGN_IN_FAILURE_WINDOW. A session issued to the
+//! account clears its count; the address count only expires.
+
+use std::str::FromStr;
+
+use async_graphql::*;
+use chrono::{Duration, prelude::*};
+use diesel::prelude::*;
+use diesel::{self, ExpressionMethods, PgConnection, QueryDsl, Queryable, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::common_utils::too_many_requests;
+use crate::config_variables::{
+    IP_FREE_ATTEMPTS, IP_LOCKOUT_ATTEMPTS, SIGN_IN_FAILURE_WINDOW, SIGN_IN_FREE_ATTEMPTS,
+    SIGN_IN_LOCKOUT_ATTEMPTS, SIGN_IN_LOCKOUT_DURATION, SIGN_IN_MAX_BACKOFF,
+};
+use crate::models::{record_security_event, NewSecurityEvent, SecurityEventType};
+use crate::repositories::Repositories;
+use crate::schema::sign_in_throttles;
+
+/// Address of the client making a request, inserted into the GraphQL context
+/// by the HTTP handler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIp(pub String);
+
+/// What failed sign-ins are counted against
+#[derive(Debug, Display, EnumString, Enum, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum ThrottleKind {
+    /// An email address, whether or not it has an account
+    Account,
+    /// A client address
+    Ip,
+}
+
+impl ThrottleKind {
+    fn free_attempts(self) -> i32 {
+        match self {
+            ThrottleKind::Account => SIGN_IN_FREE_ATTEMPTS,
+            ThrottleKind::Ip => IP_FREE_ATTEMPTS,
+        }
+    }
+
+    pub fn lockout_attempts(self) -> i32 {
+        match self {
+            ThrottleKind::Account => SIGN_IN_LOCKOUT_ATTEMPTS,
+            ThrottleKind::Ip => IP_LOCKOUT_ATTEMPTS,
+        }
+    }
+
+    /// How long after the last of `failed_attempts` failures attempts are
+    /// refused, or None while failures are still free
+    pub fn backoff(self, failed_attempts: i32) -> Option<Duration> {
+        if failed_attempts >= self.lockout_attempts() {
+            return Some(Duration::seconds(SIGN_IN_LOCKOUT_DURATION));
+        }
+
+        let doublings = failed_attempts - self.free_attempts();
+        if doublings < 0 {
+            return None;
+        }
+
+        // Capped before shifting so large counts can't overflow
+        let seconds = 1i64 << doublings.min(20);
+        Some(Duration::seconds(seconds.min(SIGN_IN_MAX_BACKOFF)))
+    }
+}
+
+/// Key for an email's throttle. Case is ignored so variants of an address
+/// share one count.
+pub fn account_subject(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, Identifiable, SimpleObject)]
+#[diesel(table_name = sign_in_throttles)]
+#[graphql(complex)]
+/// Recent failed sign-ins of an email address or client address
+pub struct SignInThrottle {
+    pub id: Uuid,
+    /// ThrottleKind: ACCOUNT or IP
+    pub kind: String,
+    /// Lowercased email or client address
+    pub subject: String,
+    pub failed_attempts: i32,
+    pub first_failed_at: NaiveDateTime,
+    pub last_failed_at: NaiveDateTime,
+}
+
+#[ComplexObject]
+impl SignInThrottle {
+    #[graphql(name = "blockedUntil")]
+    /// When attempts are allowed again, or null if they are now
+    pub async fn blocked_until_field(&self) -> Option<NaiveDateTime> {
+        self.blocked_until().filter(|until| *until > Utc::now().naive_utc())
+    }
+
+    /// Whether the failures reached the lockout count
+    pub async fn locked(&self) -> bool {
+        self.is_locked()
+    }
+}
+
+impl SignInThrottle {
+    pub fn throttle_kind(&self) -> ThrottleKind {
+        ThrottleKind::from_str(&self.kind).unwrap_or(ThrottleKind::Account)
+    }
+
+    /// End of the backoff or lockout from the last failure, which may have
+    /// passed already
+    pub fn blocked_until(&self) -> Option<NaiveDateTime> {
+        self.throttle_kind()
+            .backoff(self.failed_attempts)
+            .map(|backoff| self.last_failed_at + backoff)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.failed_attempts >= self.throttle_kind().lockout_attempts()
+    }
+
+    pub fn get(conn: &mut PgConnection, kind: ThrottleKind, subject: &str) -> Result<Option<Self>> {
+        let res = sign_in_throttles::table
+            .filter(sign_in_throttles::kind.eq(kind.to_string()))
+            .filter(sign_in_throttles::subject.eq(subject))
+            .get_result(conn)
+            .optional()?;
+
+        Ok(res)
+    }
+
+    /// Every throttle, latest failure first
+    pub fn get_all(conn: &mut PgConnection) -> Result<Vec<Self>> {
+        let res = sign_in_throttles::table
+            .order(sign_in_throttles::last_failed_at.desc())
+            .load::<SignInThrottle>(conn)?;
+
+        Ok(res)
+    }
+
+    /// Counts a failure, starting over if the last one is older than
+    /// SIGN_IN_FAILURE_WINDOW. Concurrent failures are all counted.
+    pub fn record_failure(conn: &mut PgConnection, kind: ThrottleKind, subject: &str) -> Result<Self> {
+        let now = Utc::now().naive_utc();
+        let kind = kind.to_string();
+
+        let res = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            diesel::delete(sign_in_throttles::table)
+                .filter(sign_in_throttles::kind.eq(&kind))
+                .filter(sign_in_throttles::subject.eq(subject))
+                .filter(sign_in_throttles::last_failed_at.lt(now - Duration::seconds(SIGN_IN_FAILURE_WINDOW)))
+                .execute(conn)?;
+
+            diesel::insert_into(sign_in_throttles::table)
+                .values((
+                    sign_in_throttles::kind.eq(&kind),
+                    sign_in_throttles::subject.eq(subject),
+                    sign_in_throttles::failed_attempts.eq(1),
+                    sign_in_throttles::first_failed_at.eq(now),
+                    sign_in_throttles::last_failed_at.eq(now),
+                ))
+                .on_conflict((sign_in_throttles::kind, sign_in_throttles::subject))
+                .do_update()
+                .set((
+                    sign_in_throttles::failed_attempts.eq(sign_in_throttles::failed_attempts + 1),
+                    sign_in_throttles::last_failed_at.eq(now),
+                ))
+                .get_result(conn)
+        })?;
+
+        Ok(res)
+    }
+
+    /// Forgets the failures of a subject, returning whether there were any
+    pub fn clear(conn: &mut PgConnection, kind: ThrottleKind, subject: &str) -> Result<bool> {
+        let res = diesel::delete(sign_in_throttles::table)
+            .filter(sign_in_throttles::kind.eq(kind.to_string()))
+            .filter(sign_in_throttles::subject.eq(subject))
+            .execute(conn)?;
+
+        Ok(res > 0)
+    }
+}
+
+/// A sign-in attempt for an email from a client address, checked and counted
+/// against both
+pub struct SignInAttempt {
+    email: String,
+    ip_address: Option<String>,
+}
+
+impl SignInAttempt {
+    pub fn new(email: &str, ip_address: Option<&ClientIp>) -> Self {
+        SignInAttempt {
+            email: email.trim().to_owned(),
+            ip_address: ip_address.map(|ip| ip.0.clone()),
+        }
+    }
+
+    fn subjects(&self) -> Vec<(ThrottleKind, String)> {
+        let mut subjects = vec![(ThrottleKind::Account, account_subject(&self.email))];
+
+        if let Some(ip) = &self.ip_address {
+            subjects.push((ThrottleKind::Ip, ip.clone()));
+        }
+
+        subjects
+    }
+
+    /// Refuses the attempt while the email or address is backing off or
+    /// locked out. Checked before the password, so the answer doesn't reveal
+    /// whether it was right.
+    pub async fn ensure_allowed(&self, repos: &Repositories) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        let mut blocked_until = None;
+
+        for (kind, subject) in self.subjects() {
+            let until = repos
+                .sign_in_throttles
+                .get(kind, subject)
+                .await?
+                .and_then(|t| t.blocked_until())
+                .filter(|until| *until > now);
+
+            blocked_until = blocked_until.max(until);
+        }
+
+        let Some(until) = blocked_until else {
+            return Ok(());
+        };
+
+        self.record(repos, SecurityEventType::SignInThrottled, None, Some(format!("Refused until {}", until)))
+            .await;
+
+        // Round up so clients don't retry a moment too early
+        let retry_after = (until - now).num_seconds() + 1;
+
+        Err(too_many_requests(
+            format!("Too many failed sign-in attempts, try again in {} seconds", retry_after),
+            retry_after,
+        ))
+    }
+
+    /// Counts a failed password or code against the email and address
+    pub async fn failed(&self, repos: &Repositories, user_id: Option<Uuid>, detail: &str) -> Result<()> {
+        self.record(repos, SecurityEventType::SignInFailed, user_id, Some(detail.to_owned()))
+            .await;
+
+        for (kind, subject) in self.subjects() {
+            let throttle = repos.sign_in_throttles.record_failure(kind, subject).await?;
+
+            if throttle.failed_attempts == kind.lockout_attempts() {
+                let detail = format!(
+                    "{} locked for {} minutes after {} failed attempts",
+                    kind,
+                    SIGN_IN_LOCKOUT_DURATION / 60,
+                    throttle.failed_attempts,
+                );
+                self.record(repos, SecurityEventType::SignInLocked, user_id, Some(detail)).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records a session issued to the account and clears its failures
+    pub async fn succeeded(&self, repos: &Repositories, user_id: Uuid, detail: &str) -> Result<()> {
+        repos
+            .sign_in_throttles
+            .clear(ThrottleKind::Account, account_subject(&self.email))
+            .await?;
+
+        self.record(repos, SecurityEventType::SignInSucceeded, Some(user_id), Some(detail.to_owned()))
+            .await;
+
+        Ok(())
+    }
+
+    /// Records an event about this attempt
+    pub async fn record(
+        &self,
+        repos: &Repositories,
+        event_type: SecurityEventType,
+        user_id: Option<Uuid>,
+        detail: Option<String>,
+    ) {
+        let event = NewSecurityEvent::new(
+            event_type,
+            user_id,
+            Some(self.email.clone()),
+            self.ip_address.clone(),
+            detail,
+        );
+
+        record_security_event(repos, user_id, event).await;
+    }
+}
@@ -0,0 +1,343 @@
+//! Keys used to sign and verify access tokens.
+//!
+//! With `JWT_SIGNING_KEYS` pointing at a keyring file, tokens are signed with
+//! RS256 or EdDSA keys identified by `kid` and the public halves are served
+//! at `/.well-known/jwks.json`, so partner nodes can verify our tokens without
+//! holding a secret. Without it, tokens fall back to HS256 with
+//! `JWT_SECRET_KEY`.
+//!
+//! The keyring is a JSON array, newest key last:
+//!
+//! ```json
+//! [
+//!   { "kid": "2026-10", "alg": "EdDSA", "private_key": "keys/2026-10.pem",
+//!     "retire_at": "2027-01-01T00:00:00Z" },
+//!   { "kid": "2027-01", "alg": "RS256", "private_key": "keys/2027-01.pem",
+//!     "active_from": "2027-01-01T00:00:00Z" }
+//! ]
+//! ```
+//!
+//! Rotation is scheduled through the time windows: the newest key whose
+//! `active_from` has passed signs new tokens, and every key is published
+//! from the moment it is listed until `TOKEN_DURATION` after its `retire_at`,
+//! so verifiers can cache upcoming keys and tokens signed just before a
+//! rotation stay valid.
+
+use std::fs;
+use std::path::Path;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use lazy_static::lazy_static;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::traits::PublicKeyParts;
+use serde::Deserialize;
+
+use crate::config_variables::TOKEN_DURATION;
+
+lazy_static! {
+    static ref TOKEN_KEYS: TokenKeys = match std::env::var("JWT_SIGNING_KEYS") {
+        Ok(path) => TokenKeys::Keyring(load_keyring(Path::new(&path))),
+        Err(_) => {
+            let secret = std::env::var("JWT_SECRET_KEY").expect("Can't read JWT_SECRET_KEY");
+            TokenKeys::Shared(
+                EncodingKey::from_secret(secret.as_ref()),
+                DecodingKey::from_secret(secret.as_ref()),
+            )
+        }
+    };
+}
+
+enum TokenKeys {
+    /// Legacy HS256 secret shared with every verifier
+    Shared(EncodingKey, DecodingKey),
+    Keyring(Vec<SigningKey>),
+}
+
+/// One keyring entry as written in the `JWT_SIGNING_KEYS` file
+#[derive(Deserialize)]
+struct KeyConfig {
+    kid: String,
+    /// RS256 or EdDSA
+    alg: String,
+    /// PEM private key, relative to the keyring file. PKCS#8, or PKCS#1 for RSA.
+    private_key: String,
+    active_from: Option<DateTime<Utc>>,
+    retire_at: Option<DateTime<Utc>>,
+}
+
+struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Jwk,
+    active_from: Option<DateTime<Utc>>,
+    retire_at: Option<DateTime<Utc>>,
+}
+
+impl SigningKey {
+    fn signs_at(&self, now: DateTime<Utc>) -> bool {
+        self.active_from.is_none_or(|t| t <= now) && self.retire_at.is_none_or(|t| now < t)
+    }
+
+    /// Retired keys stay published until every token they signed has expired
+    fn published_at(&self, now: DateTime<Utc>) -> bool {
+        self.retire_at
+            .is_none_or(|t| now < t + Duration::seconds(TOKEN_DURATION))
+    }
+}
+
+/// Loads the token keys now rather than on the first request, so a broken
+/// keyring, or one with no key active yet, stops the server at startup
+pub fn load_token_keys() {
+    lazy_static::initialize(&TOKEN_KEYS);
+
+    assert!(
+        current_signing_key().is_some(),
+        "No JWT signing key is active, check JWT_SIGNING_KEYS"
+    );
+}
+
+fn load_keyring(path: &Path) -> Vec<SigningKey> {
+    let file = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Can't read JWT_SIGNING_KEYS {}: {}", path.display(), e));
+
+    let configs: Vec<KeyConfig> = serde_json::from_str(&file)
+        .unwrap_or_else(|e| panic!("Can't parse JWT_SIGNING_KEYS {}: {}", path.display(), e));
+
+    assert!(!configs.is_empty(), "JWT_SIGNING_KEYS {} has no keys", path.display());
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    configs
+        .into_iter()
+        .map(|config| {
+            let pem_path = dir.join(&config.private_key);
+            let pem = fs::read_to_string(&pem_path)
+                .unwrap_or_else(|e| panic!("Can't read key {}: {}", pem_path.display(), e));
+
+            signing_key(config, &pem)
+                .unwrap_or_else(|e| panic!("Invalid key {}: {}", pem_path.display(), e))
+        })
+        .collect()
+}
+
+fn signing_key(config: KeyConfig, pem: &str) -> Result<SigningKey, String> {
+    let (algorithm, encoding, decoding, key_algorithm, parameters) = match config.alg.as_str() {
+        "RS256" => {
+            let private = rsa::RsaPrivateKey::from_pkcs8_pem(pem)
+                .or_else(|_| rsa::RsaPrivateKey::from_pkcs1_pem(pem))
+                .map_err(|e| e.to_string())?;
+
+            let n = URL_SAFE_NO_PAD.encode(private.n().to_bytes_be());
+            let e = URL_SAFE_NO_PAD.encode(private.e().to_bytes_be());
+
+            (
+                Algorithm::RS256,
+                EncodingKey::from_rsa_pem(pem.as_bytes()).map_err(|e| e.to_string())?,
+                DecodingKey::from_rsa_components(&n, &e).map_err(|e| e.to_string())?,
+                KeyAlgorithm::RS256,
+                AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n,
+                    e,
+                }),
+            )
+        }
+        "EdDSA" => {
+            let private = ed25519_dalek::SigningKey::from_pkcs8_pem(pem)
+                .map_err(|e| e.to_string())?;
+
+            let x = URL_SAFE_NO_PAD.encode(private.verifying_key().to_bytes());
+
+            (
+                Algorithm::EdDSA,
+                EncodingKey::from_ed_pem(pem.as_bytes()).map_err(|e| e.to_string())?,
+                DecodingKey::from_ed_components(&x).map_err(|e| e.to_string())?,
+                KeyAlgorithm::EdDSA,
+                AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x,
+                }),
+            )
+        }
+        other => return Err(format!("unsupported alg {}, expected RS256 or EdDSA", other)),
+    };
+
+    let jwk = Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            key_id: Some(config.kid.clone()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    };
+
+    Ok(SigningKey {
+        kid: config.kid,
+        algorithm,
+        encoding,
+        decoding,
+        jwk,
+        active_from: config.active_from,
+        retire_at: config.retire_at,
+    })
+}
+
+/// Header and key to sign a token issued now. None once every key in the
+/// keyring has retired.
+pub fn current_signing_key() -> Option<(Header, &'static EncodingKey)> {
+    match &*TOKEN_KEYS {
+        TokenKeys::Shared(encoding, _) => Some((Header::default(), encoding)),
+        TokenKeys::Keyring(keys) => {
+            let key = signing_key_at(keys, Utc::now())?;
+
+            let mut header = Header::new(key.algorithm);
+            header.kid = Some(key.kid.clone());
+
+            Some((header, &key.encoding))
+        }
+    }
+}
+
+/// Key and validation rules for a token with the given header. Tokens must
+/// name a published key and use that key's algorithm.
+pub fn verifying_key(header: &Header) -> Option<(DecodingKey, Validation)> {
+    match &*TOKEN_KEYS {
+        TokenKeys::Shared(_, decoding) => (header.alg == Algorithm::HS256)
+            .then(|| (decoding.clone(), Validation::default())),
+        TokenKeys::Keyring(keys) => verifying_key_at(keys, header, Utc::now())
+            .map(|k| (k.decoding.clone(), Validation::new(k.algorithm))),
+    }
+}
+
+/// The newest key whose window covers `now`
+fn signing_key_at(keys: &[SigningKey], now: DateTime<Utc>) -> Option<&SigningKey> {
+    keys.iter()
+        .filter(|k| k.signs_at(now))
+        .max_by_key(|k| k.active_from)
+}
+
+/// The published key named by the header's `kid`, if it uses the header's
+/// algorithm
+fn verifying_key_at<'a>(keys: &'a [SigningKey], header: &Header, now: DateTime<Utc>) -> Option<&'a SigningKey> {
+    let kid = header.kid.as_deref()?;
+
+    keys.iter()
+        .find(|k| k.kid == kid && k.published_at(now) && k.algorithm == header.alg)
+}
+
+/// Public keys for `/.well-known/jwks.json`. Empty when tokens use the
+/// shared secret.
+pub fn public_jwks() -> JwkSet {
+    let keys = match &*TOKEN_KEYS {
+        TokenKeys::Shared(..) => Vec::new(),
+        TokenKeys::Keyring(keys) => {
+            let now = Utc::now();
+
+            keys.iter()
+                .filter(|k| k.published_at(now))
+                .map(|k| k.jwk.clone())
+                .collect()
+        }
+    };
+
+    JwkSet { keys }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::pkcs8::spki::der::pem::LineEnding;
+    use ed25519_dalek::pkcs8::EncodePrivateKey;
+
+    use super::*;
+
+    fn key(kid: &str, active_from: Option<DateTime<Utc>>, retire_at: Option<DateTime<Utc>>) -> SigningKey {
+        let seed = kid.bytes().fold(0u8, |a, b| a.wrapping_add(b));
+        let pem = ed25519_dalek::SigningKey::from_bytes(&[seed; 32])
+            .to_pkcs8_pem(LineEnding::LF)
+            .unwrap();
+
+        let config = KeyConfig {
+            kid: kid.to_owned(),
+            alg: "EdDSA".to_owned(),
+            private_key: String::new(),
+            active_from,
+            retire_at,
+        };
+
+        signing_key(config, &pem).unwrap()
+    }
+
+    fn header(alg: Algorithm, kid: Option<&str>) -> Header {
+        let mut header = Header::new(alg);
+        header.kid = kid.map(str::to_owned);
+        header
+    }
+
+    #[test]
+    fn the_newest_key_in_its_window_signs() {
+        let rotation = Utc::now();
+        let hour = Duration::hours(1);
+
+        let keys = [
+            key("old", None, Some(rotation)),
+            key("new", Some(rotation), None),
+            key("next", Some(rotation + hour), None),
+        ];
+        let kid_at = |now| signing_key_at(&keys, now).map(|k| k.kid.as_str());
+
+        assert_eq!(kid_at(rotation - hour), Some("old"));
+        assert_eq!(kid_at(rotation), Some("new"));
+        assert_eq!(kid_at(rotation + hour), Some("next"));
+
+        let retired = [key("old", None, Some(rotation))];
+        assert!(signing_key_at(&retired, rotation).is_none());
+    }
+
+    #[test]
+    fn retired_keys_stay_published_until_their_tokens_expire() {
+        let rotation = Utc::now();
+        let expiry = rotation + Duration::seconds(TOKEN_DURATION);
+        let old = key("old", None, Some(rotation));
+
+        assert!(old.published_at(rotation));
+        assert!(old.published_at(expiry - Duration::seconds(1)));
+        assert!(!old.published_at(expiry));
+
+        // Upcoming keys are published before they sign
+        assert!(key("next", Some(expiry), None).published_at(rotation));
+    }
+
+    #[test]
+    fn tokens_must_use_the_algorithm_of_their_key() {
+        let now = Utc::now();
+        let keys = [key("current", None, None)];
+
+        assert!(verifying_key_at(&keys, &header(Algorithm::EdDSA, Some("current")), now).is_some());
+        assert!(verifying_key_at(&keys, &header(Algorithm::RS256, Some("current")), now).is_none());
+        assert!(verifying_key_at(&keys, &header(Algorithm::HS256, Some("current")), now).is_none());
+        assert!(verifying_key_at(&keys, &header(Algorithm::EdDSA, Some("unknown")), now).is_none());
+        assert!(verifying_key_at(&keys, &header(Algorithm::EdDSA, None), now).is_none());
+    }
+
+    #[test]
+    fn retired_keys_stop_verifying_once_unpublished() {
+        let rotation = Utc::now();
+        let keys = [key("old", None, Some(rotation))];
+        let old = header(Algorithm::EdDSA, Some("old"));
+
+        assert!(verifying_key_at(&keys, &old, rotation).is_some());
+        assert!(verifying_key_at(&keys, &old, rotation + Duration::seconds(TOKEN_DURATION)).is_none());
+    }
+}
@@ -1,15 +1,27 @@
 // Modelled off https://github.com/clifinger/canduma/blob/master/src/user
 
+use std::str::FromStr;
+
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
-use diesel::{self, ExpressionMethods, Insertable, QueryDsl, Queryable, RunQueryDsl};
+use diesel::{self, ExpressionMethods, Insertable, PgConnection, QueryDsl, Queryable, RunQueryDsl};
 use uuid::Uuid;
 use async_graphql::*;
 
 use crate::{schema::*};
-use crate::common_utils::{is_admin, RoleGuard, UserRole};
-use crate::models::hash_password;
-use crate::database::connection;
+use crate::common_utils::{can_manage_users, forbidden, Permission, PermissionGuard};
+use crate::models::{hash_password, NatoClassification, OidcIdentity, SSO_ONLY_HASH};
+
+/// Where an account is in its lifecycle. Only approved accounts can sign in.
+#[derive(Debug, Display, EnumString, Enum, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum AccountStatus {
+    /// Waiting for approval by a second administrator
+    Pending,
+    Approved,
+    /// Signed out and blocked until approved again
+    Suspended,
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct UserInstance {
@@ -17,95 +29,152 @@ pub struct UserInstance {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, SimpleObject, Queryable, AsChangeset)]
+#[diesel(treat_none_as_null = true)]
 pub struct User {
     #[graphql(
-        guard = "RoleGuard::new(UserRole::Admin)",
-        visible = "is_admin",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
     )]
     pub id: Uuid,
     #[graphql(skip)]
     pub hash: String,
 
     #[graphql(
-        guard = "RoleGuard::new(UserRole::Admin)",
-        visible = "is_admin",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
     )]
     pub email: String,
     pub role: String,
 
     #[graphql(
-        guard = "RoleGuard::new(UserRole::Admin)",
-        visible = "is_admin",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
     )]
     pub name: String,
-    pub access_level: String, // AccessLevelEnum
+    /// Clearance on the NATO scale: UNCLASSIFIED to TOP_SECRET
+    pub access_level: String,
     pub created_at: NaiveDateTime,
     #[graphql(
-        guard = "RoleGuard::new(UserRole::Admin)",
-        visible = "is_admin",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
     )]
 
     pub updated_at: NaiveDateTime,
     #[graphql(
-        guard = "RoleGuard::new(UserRole::Admin)",
-        visible = "is_admin",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
     )]
-
     /// Access Level: Admin
-    pub access_key: String,
+    pub approved_by_user_uid: Option<Uuid>,
+
+    /// Nation code, data objects are only shown to nations they are released to
+    pub nationality: Option<String>,
 
     #[graphql(
-        guard = "RoleGuard::new(UserRole::Admin)",
-        visible = "is_admin",
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
     )]
-    /// Access Level: Admin
-    pub approved_by_user_uid: Option<Uuid>,
+    /// AccountStatus: PENDING, APPROVED or SUSPENDED
+    pub account_status: String,
+
+    #[graphql(
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
+    )]
+    /// Administrator who created the account, if any
+    pub created_by_user_uid: Option<Uuid>,
+
+    #[graphql(
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
+    )]
+    /// When the user last proved they control their email address
+    pub email_verified_at: Option<NaiveDateTime>,
+
+    #[graphql(skip)]
+    pub totp_secret: Option<String>,
+
+    #[graphql(
+        guard = "PermissionGuard::new(Permission::ManageUsers)",
+        visible = "can_manage_users",
+    )]
+    /// When TOTP was confirmed, or null without a second factor
+    pub totp_enabled_at: Option<NaiveDateTime>,
+
+    #[graphql(skip)]
+    /// Time step of the last accepted TOTP code
+    pub totp_last_step: Option<i64>,
 }
 
 impl User {
+    pub fn totp_enabled(&self) -> bool {
+        self.totp_enabled_at.is_some() && self.totp_secret.is_some()
+    }
+
+    pub fn status(&self) -> AccountStatus {
+        AccountStatus::from_str(&self.account_status).unwrap_or(AccountStatus::Pending)
+    }
+
+    /// Refuses accounts that are not approved
+    pub fn ensure_approved(&self) -> Result<()> {
+        match self.status() {
+            AccountStatus::Approved => Ok(()),
+            AccountStatus::Pending => Err(forbidden("Account is pending approval")),
+            AccountStatus::Suspended => Err(forbidden("Account is suspended")),
+        }
+    }
+
+    /// The user's clearance. Unrecognised levels clear nothing above UNCLASSIFIED.
+    pub fn clearance(&self) -> NatoClassification {
+        NatoClassification::from_str(&self.access_level).unwrap_or(NatoClassification::Unclassified)
+    }
 
-    pub fn get_by_id(id: &Uuid) -> Result<Self> {
-        let mut conn = connection()?;
+    pub fn get_by_id(conn: &mut PgConnection, id: &Uuid) -> Result<Self> {
         let user = users::table
             .filter(users::id.eq(id))
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(user)
     }
 
-    pub fn get_by_email(email: &String) -> Result<Self> {
-        let mut conn = connection()?;
+    pub fn get_by_email(conn: &mut PgConnection, email: &String) -> Result<Self> {
         let user = users::table
             .filter(users::email.eq(email))
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(user)
     }
 
-    pub fn create(user: InsertableUser) -> Result<Self> {
-        let mut conn = connection()?;
+    pub fn create(conn: &mut PgConnection, user: InsertableUser) -> Result<Self> {
         let user = diesel::insert_into(users::table)
             .values(&user)
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(user)
     }
 
-    pub fn get_all() -> Result<Vec<Self>> {
-        let mut conn = connection()?;
-        let users = users::table.load::<User>(&mut conn)?;
+    pub fn get_all(conn: &mut PgConnection) -> Result<Vec<Self>> {
+        let users = users::table.load::<User>(conn)?;
         Ok(users)
     }
 
-    pub fn update(&mut self) -> Result<Self> {
-        let mut conn = connection()?;
+    /// Deletes a user, returning whether there was one
+    pub fn delete(conn: &mut PgConnection, id: &Uuid) -> Result<bool> {
+        let res = diesel::delete(users::table)
+            .filter(users::id.eq(id))
+            .execute(conn)?;
+
+        Ok(res > 0)
+    }
+
+    pub fn update(&mut self, conn: &mut PgConnection) -> Result<Self> {
 
         self.updated_at = chrono::Utc::now().naive_utc();
 
         let user = diesel::update(users::table)
             .filter(users::id.eq(&self.id))
             .set(self.clone())
-            .get_result(&mut conn)?;
+            .get_result(conn)?;
 
         Ok(user)
     }
@@ -118,11 +187,14 @@ pub struct InsertableUser {
     pub email: String,
     pub role: String,
     pub name: String,
-    pub access_level: String, // AccessLevelEnum
+    pub access_level: String, // NatoClassification
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
-    pub access_key: String,
     pub approved_by_user_uid: Option<Uuid>,
+    pub nationality: Option<String>,
+    pub account_status: String, // AccountStatus
+    pub created_by_user_uid: Option<Uuid>,
+    pub email_verified_at: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Deserialize, Serialize, InputObject)]
@@ -131,8 +203,12 @@ pub struct UserData {
     pub name: String,
     pub email: String,
     pub password: String,
-    /// UserRole in system: USER, OPERATOR, ANALYST, ADMIN
+    /// UserRole in system: USER, AUDITOR, ANALYST, OPERATOR, ADMIN
     pub role: String,
+    /// Defaults to UNCLASSIFIED
+    pub clearance: Option<NatoClassification>,
+    /// Nation code of the user's nationality
+    pub nationality: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, InputObject)]
@@ -142,8 +218,10 @@ pub struct UserUpdate {
     pub name: Option<String>,
     pub email: Option<String>,
     pub password: Option<String>,
-    /// UserRole in system: USER, OPERATOR, ANALYST, ADMIN
+    /// UserRole in system: USER, AUDITOR, ANALYST, OPERATOR, ADMIN
     pub role: Option<String>,
+    pub clearance: Option<NatoClassification>,
+    pub nationality: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, SimpleObject)]
@@ -173,7 +251,8 @@ impl From<UserData> for InsertableUser {
             email,
             password,
             role,
-            ..
+            clearance,
+            nationality,
         } = user_data;
         
         let hash = hash_password(&password)
@@ -187,9 +266,12 @@ impl From<UserData> for InsertableUser {
             updated_at,
             name,
             role,
-            access_key: "".to_owned(),
-            access_level: "detailed".to_owned(),
+            access_level: clearance.unwrap_or(NatoClassification::Unclassified).to_string(),
             approved_by_user_uid: None,
+            nationality,
+            account_status: AccountStatus::Pending.to_string(),
+            created_by_user_uid: None,
+            email_verified_at: None,
         }
     }
 }
@@ -217,4 +299,26 @@ impl From<User> for SlimUser {
 pub struct LoginQuery {
     pub email: String,
     pub password: String,
-}
\ No newline at end of file
+}
+impl From<OidcIdentity> for InsertableUser {
+    /// A user provisioned on first single sign-on. It has no password and
+    /// waits for approval like any other new account.
+    fn from(identity: OidcIdentity) -> Self {
+        let now = chrono::Utc::now().naive_utc();
+
+        Self {
+            email: identity.email,
+            hash: SSO_ONLY_HASH.to_owned(),
+            created_at: now,
+            updated_at: now,
+            name: identity.name,
+            role: identity.role.to_string(),
+            access_level: identity.access_level.to_string(),
+            approved_by_user_uid: None,
+            nationality: identity.nationality,
+            account_status: AccountStatus::Pending.to_string(),
+            created_by_user_uid: None,
+            email_verified_at: None,
+        }
+    }
+}
@@ -3,6 +3,7 @@
 // Invoking the constructor will print the start message (and set the total count)
 // increment() will print a dot every 10 times it is called, and a message every 100 times
 // done() will print the final message
+#[allow(clippy::module_inception)]
 pub mod progress {
     use std::io::{self, Write};
 
@@ -21,12 +22,12 @@ pub mod progress {
         pub fn increment(&mut self) {
             self.count += 1;
 
-            if self.count % 100 == 0 {
+            if self.count.is_multiple_of(100) {
                 println!("... {} ({}/{})", self.message, self.count, self.total);
-            } else if self.count % 10 == 0 {
+            } else if self.count.is_multiple_of(10) {
                 print!(".");
                 // flush stdout so we can see progress
-                io::stdout().flush().ok().expect("Could not flush stdout");
+                io::stdout().flush().expect("Could not flush stdout");
         }
         }
         pub fn done(&mut self) {
@@ -0,0 +1,525 @@
+//! Audit logging of reads and writes.
+//!
+//! `Repositories::audited` wraps the repositories whose use has to be on the
+//! record: data objects returned, conversion requests submitted and
+//! completed, and changes to nations, authorities, classification schemas and
+//! authority membership. Each call appends its entries to the audit log
+//! before the result is handed back, so if the log can't be written the
+//! caller gets an error instead of unaudited data. Submissions are appended
+//! in the same transaction as the request, through
+//! `ConversionRequestRepo::process_payload_audited`, and with its results
+//! through `process_and_complete`.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use async_graphql::Result;
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::models::{
+    AuditAction, AuditEntity, Authority, AuthorityMember, ClassificationSchema, ConversionRequest,
+    ConversionResult, DataObject, DataObjectMarking, DataObjectSearchResult, InsertableConversionRequest, InsertableMetadata, Metadata, Nation, NewAuditEntry,
+    NewAuthority, NewAuthorityMember, NewClassificationSchema, NewConversionResult, NewDataObject, NewNation,
+};
+use crate::repositories::clearance::REDACTED_TITLE;
+use crate::repositories::{
+    AuditEntriesFor, AuditRepo, AuthorityMemberRepo, AuthorityRepo, CompletedConversion, ConversionRequestRepo,
+    ConversionResultRepo, ConversionResultsFor, DataObjectRepo, NationRepo, Repositories, SchemaRepo, SearchVisibility,
+    TenantScope,
+};
+
+impl Repositories {
+    /// These repositories with reads of data objects and changes to
+    /// conversions and reference data recorded against `actor_id`, or
+    /// against the system if None
+    pub fn audited(&self, actor_id: Option<Uuid>) -> Repositories {
+        let auditor = Arc::new(Auditor {
+            log: self.audit_log.clone(),
+            actor_id,
+        });
+
+        Repositories {
+            nations: Arc::new(AuditedNations { inner: self.nations.clone(), auditor: auditor.clone() }),
+            authorities: Arc::new(AuditedAuthorities {
+                inner: self.authorities.clone(),
+                nations: self.nations.clone(),
+                auditor: auditor.clone(),
+            }),
+            authority_members: Arc::new(AuditedAuthorityMembers {
+                inner: self.authority_members.clone(),
+                auditor: auditor.clone(),
+            }),
+            schemas: Arc::new(AuditedSchemas { inner: self.schemas.clone(), auditor: auditor.clone() }),
+            data_objects: Arc::new(AuditedDataObjects { inner: self.data_objects.clone(), auditor: auditor.clone() }),
+            conversion_requests: Arc::new(AuditedConversionRequests {
+                inner: self.conversion_requests.clone(),
+                auditor: auditor.clone(),
+            }),
+            conversion_results: Arc::new(AuditedConversionResults { inner: self.conversion_results.clone(), auditor }),
+            ..self.clone()
+        }
+    }
+}
+
+struct Auditor {
+    log: Arc<dyn AuditRepo>,
+    actor_id: Option<Uuid>,
+}
+
+impl Auditor {
+    fn entry(&self, action: AuditAction, entity_type: AuditEntity, entity_id: Uuid) -> NewAuditEntry {
+        NewAuditEntry::new(self.actor_id, action, entity_type, Some(entity_id))
+    }
+
+    async fn record(&self, entries: Vec<NewAuditEntry>) -> Result<()> {
+        if !entries.is_empty() {
+            self.log.append(entries).await?;
+        }
+        Ok(())
+    }
+
+    fn submitted(&self, request: &ConversionRequest) -> NewAuditEntry {
+        NewAuditEntry {
+            nation_code: Some(request.source_nation_code.clone()),
+            detail: serde_json::json!({
+                "authority_id": request.authority_id,
+                "data_object_id": request.data_object_id,
+                "target_nation_codes": request.target_nation_codes,
+            }),
+            ..self.entry(AuditAction::ConversionSubmitted, AuditEntity::ConversionRequest, request.id)
+        }
+    }
+
+    fn completed(&self, results: &[ConversionResult]) -> Vec<NewAuditEntry> {
+        results
+            .iter()
+            .map(|r| NewAuditEntry {
+                nation_code: Some(r.target_nation_code.clone()),
+                detail: serde_json::json!({
+                    "conversion_request_id": r.conversion_request_id,
+                    "source_nation_code": r.source_nation_code,
+                    "source_marking": r.source_marking,
+                    "source_schema_id": r.source_schema_id,
+                    "nato_classification": r.nato_classification,
+                    "target_marking": r.target_marking,
+                    "target_schema_id": r.target_schema_id,
+                }),
+                ..self.entry(AuditAction::ConversionCompleted, AuditEntity::ConversionResult, r.id)
+            })
+            .collect()
+    }
+
+    async fn read(&self, data_objects: &[&DataObject]) -> Result<()> {
+        let entries = data_objects
+            .iter()
+            .map(|d| NewAuditEntry {
+                detail: serde_json::json!({ "redacted": d.title == REDACTED_TITLE }),
+                ..self.entry(AuditAction::DataObjectRead, AuditEntity::DataObject, d.id)
+            })
+            .collect();
+
+        self.record(entries).await
+    }
+}
+
+struct AuditedDataObjects {
+    inner: Arc<dyn DataObjectRepo>,
+    auditor: Arc<Auditor>,
+}
+
+#[async_trait]
+impl DataObjectRepo for AuditedDataObjects {
+    async fn create(&self, data_object: NewDataObject) -> Result<DataObject> {
+        self.inner.create(data_object).await
+    }
+
+    async fn create_with_metadata(
+        &self,
+        data_object: NewDataObject,
+        metadata: InsertableMetadata,
+    ) -> Result<(DataObject, Metadata)> {
+        let (data_object, metadata) = self.inner.create_with_metadata(data_object, metadata).await?;
+
+        self.auditor
+            .record(vec![NewAuditEntry {
+                detail: serde_json::json!({
+                    "content_digest": data_object.content_digest,
+                    "content_size": data_object.content_size,
+                    "media_type": data_object.media_type,
+                    "domain": metadata.domain,
+                }),
+                ..self.auditor.entry(AuditAction::DataObjectCreated, AuditEntity::DataObject, data_object.id)
+            }])
+            .await?;
+
+        Ok((data_object, metadata))
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<DataObject> {
+        let data_object = self.inner.get_by_id(id).await?;
+        self.auditor.read(&[&data_object]).await?;
+        Ok(data_object)
+    }
+
+    async fn get_by_ids(&self, ids: Vec<Uuid>) -> Result<Vec<DataObject>> {
+        let data_objects = self.inner.get_by_ids(ids).await?;
+        self.auditor.read(&data_objects.iter().collect::<Vec<_>>()).await?;
+        Ok(data_objects)
+    }
+
+    async fn get_by_title(&self, title: String) -> Result<Vec<DataObject>> {
+        let data_objects = self.inner.get_by_title(title).await?;
+        self.auditor.read(&data_objects.iter().collect::<Vec<_>>()).await?;
+        Ok(data_objects)
+    }
+
+    async fn get_by_content_digest(&self, content_digest: String) -> Result<Vec<DataObject>> {
+        let data_objects = self.inner.get_by_content_digest(content_digest).await?;
+        self.auditor.read(&data_objects.iter().collect::<Vec<_>>()).await?;
+        Ok(data_objects)
+    }
+
+    async fn search(
+        &self,
+        query: String,
+        domains: Option<Vec<String>>,
+        tags: Option<Vec<String>>,
+        visibility: SearchVisibility,
+    ) -> Result<Vec<DataObjectSearchResult>> {
+        let results = self.inner.search(query, domains, tags, visibility).await?;
+        self.auditor.read(&results.iter().map(|r| &r.data_object).collect::<Vec<_>>()).await?;
+        Ok(results)
+    }
+
+    async fn get_all(&self) -> Result<Vec<DataObject>> {
+        let data_objects = self.inner.get_all().await?;
+        self.auditor.read(&data_objects.iter().collect::<Vec<_>>()).await?;
+        Ok(data_objects)
+    }
+
+    async fn count(&self) -> Result<i64> {
+        self.inner.count().await
+    }
+
+    async fn is_redacted(&self, id: Uuid) -> Result<bool> {
+        self.inner.is_redacted(id).await
+    }
+}
+
+struct AuditedConversionRequests {
+    inner: Arc<dyn ConversionRequestRepo>,
+    auditor: Arc<Auditor>,
+}
+
+#[async_trait]
+impl ConversionRequestRepo for AuditedConversionRequests {
+    /// The entry is appended with the request, so a request is never kept
+    /// unaudited
+    async fn process_payload(&self, payload: InsertableConversionRequest) -> Result<ConversionRequest> {
+        self.process_payload_audited(payload, Box::new(|_| Vec::new())).await
+    }
+
+    async fn process_payload_audited(
+        &self,
+        payload: InsertableConversionRequest,
+        audit: AuditEntriesFor<ConversionRequest>,
+    ) -> Result<ConversionRequest> {
+        let auditor = self.auditor.clone();
+
+        let audit: AuditEntriesFor<ConversionRequest> = Box::new(move |request| {
+            let mut entries = audit(request);
+            entries.push(auditor.submitted(request));
+            entries
+        });
+
+        self.inner.process_payload_audited(payload, audit).await
+    }
+
+    /// The submission and its results are appended with them
+    async fn process_and_complete(
+        &self,
+        payload: InsertableConversionRequest,
+        convert: ConversionResultsFor,
+        audit: AuditEntriesFor<CompletedConversion>,
+    ) -> Result<CompletedConversion> {
+        let auditor = self.auditor.clone();
+
+        let audit: AuditEntriesFor<CompletedConversion> = Box::new(move |completed| {
+            let mut entries = audit(completed);
+            entries.push(auditor.submitted(&completed.0));
+            entries.extend(auditor.completed(&completed.1));
+            entries
+        });
+
+        self.inner.process_and_complete(payload, convert, audit).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<ConversionRequest> {
+        self.inner.get_by_id(id).await
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<ConversionRequest>> {
+        self.inner.get_by_creator_id(creator_id).await
+    }
+
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<ConversionRequest>> {
+        self.inner.get_by_authority_id(authority_id).await
+    }
+
+    async fn get_by_data_object_id(&self, data_object_id: Uuid) -> Result<Vec<ConversionRequest>> {
+        self.inner.get_by_data_object_id(data_object_id).await
+    }
+
+    async fn get_by_source_nation_code(&self, nation_code: String) -> Result<Vec<ConversionRequest>> {
+        self.inner.get_by_source_nation_code(nation_code).await
+    }
+
+    async fn get_pending(&self) -> Result<Vec<ConversionRequest>> {
+        self.inner.get_pending().await
+    }
+
+    async fn get_completed(&self) -> Result<Vec<ConversionRequest>> {
+        self.inner.get_completed().await
+    }
+
+    async fn get_all(&self) -> Result<Vec<ConversionRequest>> {
+        self.inner.get_all().await
+    }
+
+    async fn get_limited(&self, count: i64) -> Result<Vec<ConversionRequest>> {
+        self.inner.get_limited(count).await
+    }
+
+    async fn get_in_scope(&self, scopes: &[TenantScope], limit: Option<i64>) -> Result<Vec<ConversionRequest>> {
+        self.inner.get_in_scope(scopes, limit).await
+    }
+
+    async fn data_object_ids_in_scope(
+        &self,
+        scope: &TenantScope,
+        data_object_ids: Option<Vec<Uuid>>,
+    ) -> Result<HashSet<Uuid>> {
+        self.inner.data_object_ids_in_scope(scope, data_object_ids).await
+    }
+
+    async fn get_markings(&self, data_object_ids: Vec<Uuid>) -> Result<HashMap<Uuid, DataObjectMarking>> {
+        self.inner.get_markings(data_object_ids).await
+    }
+}
+
+struct AuditedConversionResults {
+    inner: Arc<dyn ConversionResultRepo>,
+    auditor: Arc<Auditor>,
+}
+
+#[async_trait]
+impl ConversionResultRepo for AuditedConversionResults {
+    async fn complete_request(
+        &self,
+        conversion_request_id: Uuid,
+        results: Vec<NewConversionResult>,
+    ) -> Result<(ConversionRequest, Vec<ConversionResult>)> {
+        let (request, results) = self.inner.complete_request(conversion_request_id, results).await?;
+
+        let entries = self.auditor.completed(&results);
+        self.auditor.record(entries).await?;
+
+        Ok((request, results))
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<ConversionResult> {
+        self.inner.get_by_id(id).await
+    }
+
+    async fn get_by_conversion_request_id(&self, conversion_request_id: Uuid) -> Result<Vec<ConversionResult>> {
+        self.inner.get_by_conversion_request_id(conversion_request_id).await
+    }
+}
+
+struct AuditedNations {
+    inner: Arc<dyn NationRepo>,
+    auditor: Arc<Auditor>,
+}
+
+#[async_trait]
+impl NationRepo for AuditedNations {
+    async fn create(&self, nation: NewNation) -> Result<Nation> {
+        let nation = self.inner.create(nation).await?;
+
+        self.auditor
+            .record(vec![NewAuditEntry {
+                nation_code: Some(nation.nation_code.clone()),
+                detail: serde_json::json!({ "nation_name": nation.nation_name }),
+                ..self.auditor.entry(AuditAction::NationCreated, AuditEntity::Nation, nation.id)
+            }])
+            .await?;
+
+        Ok(nation)
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Nation> {
+        self.inner.get_by_id(id).await
+    }
+
+    async fn get_by_code(&self, nation_code: String) -> Result<Nation> {
+        self.inner.get_by_code(nation_code).await
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<Nation>> {
+        self.inner.get_by_creator_id(creator_id).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<Nation>> {
+        self.inner.get_all().await
+    }
+}
+
+struct AuditedAuthorities {
+    inner: Arc<dyn AuthorityRepo>,
+    nations: Arc<dyn NationRepo>,
+    auditor: Arc<Auditor>,
+}
+
+#[async_trait]
+impl AuthorityRepo for AuditedAuthorities {
+    async fn create(&self, authority: NewAuthority) -> Result<Authority> {
+        let authority = self.inner.create(authority).await?;
+        let nation = self.nations.get_by_id(authority.nation_id).await?;
+
+        self.auditor
+            .record(vec![NewAuditEntry {
+                nation_code: Some(nation.nation_code),
+                detail: serde_json::json!({ "name": authority.name, "email": authority.email }),
+                ..self.auditor.entry(AuditAction::AuthorityCreated, AuditEntity::Authority, authority.id)
+            }])
+            .await?;
+
+        Ok(authority)
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Authority> {
+        self.inner.get_by_id(id).await
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<Authority>> {
+        self.inner.get_by_creator_id(creator_id).await
+    }
+
+    async fn get_by_nation_id(&self, nation_id: Uuid) -> Result<Vec<Authority>> {
+        self.inner.get_by_nation_id(nation_id).await
+    }
+
+    async fn get_by_nation_code(&self, nation_code: String) -> Result<Vec<Authority>> {
+        self.inner.get_by_nation_code(nation_code).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<Authority>> {
+        self.inner.get_all().await
+    }
+}
+
+struct AuditedAuthorityMembers {
+    inner: Arc<dyn AuthorityMemberRepo>,
+    auditor: Arc<Auditor>,
+}
+
+impl AuditedAuthorityMembers {
+    fn entry(&self, action: AuditAction, user_id: Uuid, authority_id: Uuid) -> NewAuditEntry {
+        NewAuditEntry {
+            detail: serde_json::json!({ "user_id": user_id }),
+            ..self.auditor.entry(action, AuditEntity::Authority, authority_id)
+        }
+    }
+}
+
+#[async_trait]
+impl AuthorityMemberRepo for AuditedAuthorityMembers {
+    async fn add(&self, member: NewAuthorityMember) -> Result<AuthorityMember> {
+        let member = self.inner.add(member).await?;
+
+        self.auditor
+            .record(vec![self.entry(AuditAction::AuthorityMemberAdded, member.user_id, member.authority_id)])
+            .await?;
+
+        Ok(member)
+    }
+
+    async fn remove(&self, user_id: Uuid, authority_id: Uuid) -> Result<bool> {
+        let removed = self.inner.remove(user_id, authority_id).await?;
+
+        if removed {
+            self.auditor
+                .record(vec![self.entry(AuditAction::AuthorityMemberRemoved, user_id, authority_id)])
+                .await?;
+        }
+
+        Ok(removed)
+    }
+
+    async fn get_by_user_id(&self, user_id: Uuid) -> Result<Vec<AuthorityMember>> {
+        self.inner.get_by_user_id(user_id).await
+    }
+
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<AuthorityMember>> {
+        self.inner.get_by_authority_id(authority_id).await
+    }
+}
+
+struct AuditedSchemas {
+    inner: Arc<dyn SchemaRepo>,
+    auditor: Arc<Auditor>,
+}
+
+#[async_trait]
+impl SchemaRepo for AuditedSchemas {
+    async fn create(&self, schema: NewClassificationSchema) -> Result<ClassificationSchema> {
+        let schema = self.inner.create(schema).await?;
+
+        self.auditor
+            .record(vec![NewAuditEntry {
+                nation_code: Some(schema.nation_code.clone()),
+                detail: serde_json::json!({ "version": schema.version }),
+                ..self.auditor.entry(AuditAction::ClassificationSchemaCreated, AuditEntity::ClassificationSchema, schema.id)
+            }])
+            .await?;
+
+        Ok(schema)
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<ClassificationSchema> {
+        self.inner.get_by_id(id).await
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<ClassificationSchema>> {
+        self.inner.get_by_creator_id(creator_id).await
+    }
+
+    async fn get_by_nation_code(&self, nation_code: String) -> Result<Vec<ClassificationSchema>> {
+        self.inner.get_by_nation_code(nation_code).await
+    }
+
+    async fn get_by_nation_code_and_version(
+        &self,
+        nation_code: String,
+        version: String,
+    ) -> Result<ClassificationSchema> {
+        self.inner.get_by_nation_code_and_version(nation_code, version).await
+    }
+
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<ClassificationSchema>> {
+        self.inner.get_by_authority_id(authority_id).await
+    }
+
+    async fn get_latest_by_nation_code(&self, nation_code: String) -> Result<ClassificationSchema> {
+        self.inner.get_latest_by_nation_code(nation_code).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<ClassificationSchema>> {
+        self.inner.get_all().await
+    }
+
+    async fn count(&self) -> Result<i64> {
+        self.inner.count().await
+    }
+}
@@ -0,0 +1,176 @@
+//! Clearance-aware redaction of data objects.
+//!
+//! A data object is classified at the highest NATO level its conversions
+//! produced, and released to the source and target nations of the requests
+//! carrying it. Objects with no conversion results yet are treated as
+//! TOP_SECRET. Viewers below the classification, or not of a released nation,
+//! get a placeholder with the title and description redacted and the
+//! document's digest, size and type cleared, and no metadata. Lookups by
+//! content (title, digest and full-text search) leave such objects out, since
+//! a match would itself disclose the content.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_graphql::Result;
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::models::{
+    DataObject, DataObjectMarking, DataObjectSearchResult, InsertableMetadata, Metadata, NatoClassification,
+    NewDataObject, User,
+};
+use crate::repositories::{ConversionRequestRepo, DataObjectRepo, SearchVisibility};
+
+pub const REDACTED_TITLE: &str = "[REDACTED]";
+
+/// What a caller is cleared to read, inserted into the GraphQL context of
+/// authenticated requests
+#[derive(Debug, Clone)]
+pub struct Clearance {
+    pub level: NatoClassification,
+    /// Nation code; callers without one are not released anything
+    pub nationality: Option<String>,
+}
+
+impl Clearance {
+    /// The clearance of a user, or of the service user an API key acts as
+    pub fn for_user(user: &User) -> Self {
+        Clearance {
+            level: user.clearance(),
+            nationality: user.nationality.clone(),
+        }
+    }
+
+    /// Whether the caller may read a data object marked `marking`
+    pub fn may_read(&self, marking: &DataObjectMarking) -> bool {
+        self.denial(marking).is_none()
+    }
+
+    /// Why the caller may not read a data object marked `marking`, or None if
+    /// they may
+    fn denial(&self, marking: &DataObjectMarking) -> Option<String> {
+        let classification = marking.classification.unwrap_or(NatoClassification::TopSecret);
+
+        if classification > self.level {
+            return Some(format!("{} exceeds your clearance", classification));
+        }
+
+        match &self.nationality {
+            None => Some("no nationality on record".to_owned()),
+            Some(n) if !marking.nations.contains(n) => Some(format!("not releasable to {}", n)),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Data objects as the caller is cleared to read them
+pub(super) struct ClearedDataObjects {
+    pub(super) inner: Arc<dyn DataObjectRepo>,
+    /// Unscoped, so every request carrying an object counts towards its marking
+    pub(super) conversion_requests: Arc<dyn ConversionRequestRepo>,
+    pub(super) clearance: Arc<Clearance>,
+}
+
+impl ClearedDataObjects {
+    /// Why the caller may not read each of `ids`, with the markings of all of
+    /// them loaded at once. Ids missing from the map are readable.
+    async fn denials(&self, ids: Vec<Uuid>) -> Result<HashMap<Uuid, String>> {
+        let mut markings = self.conversion_requests.get_markings(ids.clone()).await?;
+
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| {
+                let marking = markings.remove(&id).unwrap_or_default();
+
+                self.clearance.denial(&marking).map(|reason| (id, reason))
+            })
+            .collect())
+    }
+
+    async fn redact(&self, data_object: DataObject) -> Result<DataObject> {
+        let mut redacted = self.redact_all(vec![data_object]).await?;
+        Ok(redacted.remove(0))
+    }
+
+    async fn redact_all(&self, data_objects: Vec<DataObject>) -> Result<Vec<DataObject>> {
+        let mut denials = self.denials(data_objects.iter().map(|d| d.id).collect()).await?;
+
+        Ok(data_objects
+            .into_iter()
+            .map(|data_object| match denials.remove(&data_object.id) {
+                // The digest would let a caller confirm a guessed document
+                Some(reason) => DataObject {
+                    title: REDACTED_TITLE.to_owned(),
+                    description: format!("[REDACTED: {}]", reason),
+                    content_digest: None,
+                    digest_algorithm: None,
+                    content_size: None,
+                    media_type: None,
+                    ..data_object
+                },
+                None => data_object,
+            })
+            .collect())
+    }
+
+    async fn readable(&self, data_objects: Vec<DataObject>) -> Result<Vec<DataObject>> {
+        let denials = self.denials(data_objects.iter().map(|d| d.id).collect()).await?;
+        Ok(data_objects.into_iter().filter(|d| !denials.contains_key(&d.id)).collect())
+    }
+}
+
+#[async_trait]
+impl DataObjectRepo for ClearedDataObjects {
+    async fn create(&self, data_object: NewDataObject) -> Result<DataObject> {
+        self.inner.create(data_object).await
+    }
+
+    async fn create_with_metadata(
+        &self,
+        data_object: NewDataObject,
+        metadata: InsertableMetadata,
+    ) -> Result<(DataObject, Metadata)> {
+        self.inner.create_with_metadata(data_object, metadata).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<DataObject> {
+        self.redact(self.inner.get_by_id(id).await?).await
+    }
+
+    async fn get_by_ids(&self, ids: Vec<Uuid>) -> Result<Vec<DataObject>> {
+        self.redact_all(self.inner.get_by_ids(ids).await?).await
+    }
+
+    async fn get_by_title(&self, title: String) -> Result<Vec<DataObject>> {
+        self.readable(self.inner.get_by_title(title).await?).await
+    }
+
+    async fn get_by_content_digest(&self, content_digest: String) -> Result<Vec<DataObject>> {
+        self.readable(self.inner.get_by_content_digest(content_digest).await?).await
+    }
+
+    async fn search(
+        &self,
+        query: String,
+        domains: Option<Vec<String>>,
+        tags: Option<Vec<String>>,
+        mut visibility: SearchVisibility,
+    ) -> Result<Vec<DataObjectSearchResult>> {
+        visibility.clearances.push((*self.clearance).clone());
+        self.inner.search(query, domains, tags, visibility).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<DataObject>> {
+        self.redact_all(self.inner.get_all().await?).await
+    }
+
+    /// Redacted objects are listed as placeholders, so they count
+    async fn count(&self) -> Result<i64> {
+        self.inner.count().await
+    }
+
+    async fn is_redacted(&self, id: Uuid) -> Result<bool> {
+        Ok(self.inner.is_redacted(id).await? || self.denials(vec![id]).await?.contains_key(&id))
+    }
+}
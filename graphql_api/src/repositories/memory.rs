@@ -0,0 +1,1628 @@
+//! In-memory storage for tests and offline demos.
+//!
+//! Tables are plain vectors behind a lock, so results come back in insertion
+//! order like an unordered Postgres scan would. Unique indexes from the
+//! migrations are enforced; full-text search is approximated (see `search`).
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use async_graphql::{Error, Result};
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDateTime, Utc};
+use uuid::Uuid;
+
+use crate::config_variables::{REFRESH_TOKEN_DURATION, SEARCH_RESULT_LIMIT, SIGN_IN_FAILURE_WINDOW};
+use crate::models::{
+    issue_receipts, ApiKey, AuditEntry, AuditFilter, AuditPage, Authority, AuthorityMember, ClassificationSchema, ConversionRequest, ConversionResult,
+    ConversionSubject, DataObject, DataObjectMarking, DataObjectSearchResult, EmailVerification, InsertableConversionRequest,
+    InsertableMetadata, InsertablePasswordResetToken,
+    InsertableUser, InsertableVerification, MerkleAnchor, MerkleLeaf, Metadata, Nation, NewApiKey, NewAuditEntry, NewAuthority,
+    NewAuthorityMember, NewClassificationSchema, NewConversionResult, NewDataObject, NewMetadata, NewNation,
+    NewSecurityEvent, NewSession, NewSignInChallenge, PasswordResetToken, RecoveryCode, SecurityEvent, Session,
+    SignInChallenge, SignInThrottle, ThrottleKind, User,
+};
+use crate::repositories::{
+    ApiKeyRepo, AuditEntriesFor, AuditRepo, AuthorityMemberRepo, AuthorityRepo, CompletedConversion, ConversionRequestRepo,
+    ConversionResultRepo, ConversionResultsFor, DataObjectRepo, EmailVerificationRepo, MerkleAnchorRepo, MetadataRepo,
+    MfaChallengeRepo, NationRepo, PasswordResetRepo, RecoveryCodeRepo, SchemaRepo, SearchVisibility, SecurityEventRepo,
+    SessionRepo, SignInThrottleRepo, TenantScope, UserRepo,
+};
+
+#[derive(Default)]
+struct Store {
+    users: Vec<User>,
+    sessions: Vec<Session>,
+    api_keys: Vec<ApiKey>,
+    email_verifications: Vec<EmailVerification>,
+    password_resets: Vec<PasswordResetToken>,
+    mfa_challenges: Vec<SignInChallenge>,
+    recovery_codes: Vec<RecoveryCode>,
+    sign_in_throttles: Vec<SignInThrottle>,
+    security_events: Vec<SecurityEvent>,
+    audit_log: Vec<AuditEntry>,
+    merkle_anchors: Vec<MerkleAnchor>,
+    merkle_leaves: Vec<MerkleLeaf>,
+    nations: Vec<Nation>,
+    authorities: Vec<Authority>,
+    authority_members: Vec<AuthorityMember>,
+    schemas: Vec<ClassificationSchema>,
+    data_objects: Vec<DataObject>,
+    metadata: Vec<Metadata>,
+    conversion_requests: Vec<ConversionRequest>,
+    conversion_results: Vec<ConversionResult>,
+}
+
+impl Store {
+    /// Appends entries after the last one in the audit log
+    fn audit(&mut self, entries: Vec<NewAuditEntry>) -> Vec<AuditEntry> {
+        let mut appended = Vec::with_capacity(entries.len());
+
+        for entry in entries {
+            let entry = entry.chain(self.audit_log.last());
+            self.audit_log.push(entry.clone());
+            appended.push(entry);
+        }
+
+        appended
+    }
+
+    /// The request `payload` describes and its data object, with the
+    /// metadata of a new one, checked but not yet stored
+    fn prepare_request(
+        &self,
+        payload: InsertableConversionRequest,
+    ) -> Result<(ConversionRequest, DataObject, Option<Metadata>)> {
+        if !self.users.iter().any(|u| u.id == payload.user_id)
+            || !self.authorities.iter().any(|a| a.id == payload.authority_id)
+        {
+            return Err(Error::new(
+                "insert or update on table \"conversion_requests\" violates foreign key constraint",
+            ));
+        }
+
+        let (data_object, metadata) = match payload.subject()? {
+            ConversionSubject::New(data_object, metadata) => {
+                let data_object = new_data_object(*data_object);
+                let metadata = new_metadata(data_object.id, metadata);
+                (data_object, Some(metadata))
+            }
+            ConversionSubject::Uploaded(id) => {
+                let data_object = first(&self.data_objects, |d| d.id == id)?;
+                data_object.ensure_uploaded_by(payload.user_id)?;
+                (data_object, None)
+            }
+        };
+
+        let conversion_request = ConversionRequest {
+            id: Uuid::new_v4(),
+            creator_id: payload.user_id,
+            authority_id: payload.authority_id,
+            data_object_id: data_object.id,
+            source_nation_code: payload.source_nation_code,
+            target_nation_codes: payload.target_nation_codes.into_iter().map(Some).collect(),
+            created_at: now(),
+            updated_at: now(),
+            completed_at: None,
+        };
+
+        Ok((conversion_request, data_object, metadata))
+    }
+
+    /// Stores what `prepare_request` returned
+    fn insert_request(&mut self, request: ConversionRequest, data_object: DataObject, metadata: Option<Metadata>) {
+        if let Some(metadata) = metadata {
+            self.metadata.push(metadata);
+            self.data_objects.push(data_object);
+        }
+        self.conversion_requests.push(request);
+    }
+
+    /// `results` with their receipts for `data_object`, ready to be stored
+    fn issue_results(&self, results: Vec<NewConversionResult>, data_object: &DataObject) -> Result<Vec<ConversionResult>> {
+        let results = issue_receipts(results, data_object, &self.schemas)?;
+
+        Ok(results
+            .into_iter()
+            .map(|r| ConversionResult {
+                id: Uuid::new_v4(),
+                conversion_request_id: r.conversion_request_id,
+                source_nation_code: r.source_nation_code,
+                source_marking: r.source_marking,
+                source_schema_id: r.source_schema_id,
+                nato_classification: r.nato_classification,
+                target_nation_code: r.target_nation_code,
+                target_marking: r.target_marking,
+                target_schema_id: r.target_schema_id,
+                created_at: now(),
+                receipt: r.receipt,
+                signature: r.signature,
+                signing_key_id: r.signing_key_id,
+            })
+            .collect())
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryRepository {
+    store: RwLock<Store>,
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Store> {
+        self.store.read().expect("In-memory store lock poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Store> {
+        self.store.write().expect("In-memory store lock poisoned")
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+fn new_data_object(data_object: NewDataObject) -> DataObject {
+    DataObject {
+        id: Uuid::new_v4(),
+        creator_id: data_object.creator_id,
+        title: data_object.title,
+        description: data_object.description,
+        created_at: now(),
+        updated_at: now(),
+        content_digest: data_object.content_digest,
+        digest_algorithm: data_object.digest_algorithm,
+        content_size: data_object.content_size,
+        storage_key: data_object.storage_key,
+        media_type: data_object.media_type,
+    }
+}
+
+fn new_metadata(data_object_id: Uuid, metadata: InsertableMetadata) -> Metadata {
+    Metadata {
+        id: Uuid::new_v4(),
+        data_object_id,
+        domain: metadata.domain,
+        tags: metadata.tags,
+        created_at: now(),
+        updated_at: now(),
+    }
+}
+
+fn not_found() -> Error {
+    Error::new("Record not found")
+}
+
+fn first<T: Clone>(rows: &[T], pred: impl Fn(&T) -> bool) -> Result<T> {
+    rows.iter().find(|r| pred(r)).cloned().ok_or_else(not_found)
+}
+
+fn filter<T: Clone>(rows: &[T], pred: impl Fn(&T) -> bool) -> Vec<T> {
+    rows.iter().filter(|r| pred(r)).cloned().collect()
+}
+
+#[async_trait]
+impl UserRepo for InMemoryRepository {
+    async fn create(&self, user: InsertableUser) -> Result<User> {
+        let mut store = self.write();
+
+        if store.users.iter().any(|u| u.email == user.email) {
+            return Err(Error::new(
+                "duplicate key value violates unique constraint \"users__email_idx\"",
+            ));
+        }
+
+        let user = User {
+            id: Uuid::new_v4(),
+            hash: user.hash,
+            email: user.email,
+            role: user.role,
+            name: user.name,
+            access_level: user.access_level,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            approved_by_user_uid: user.approved_by_user_uid,
+            nationality: user.nationality,
+            account_status: user.account_status,
+            created_by_user_uid: user.created_by_user_uid,
+            email_verified_at: user.email_verified_at,
+            totp_secret: None,
+            totp_enabled_at: None,
+            totp_last_step: None,
+        };
+
+        store.users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<User> {
+        first(&self.read().users, |u| u.id == id)
+    }
+
+    async fn get_by_email(&self, email: String) -> Result<User> {
+        first(&self.read().users, |u| u.email == email)
+    }
+
+    async fn get_all(&self) -> Result<Vec<User>> {
+        Ok(self.read().users.clone())
+    }
+
+    async fn update(&self, mut user: User) -> Result<User> {
+        let mut store = self.write();
+
+        if store.users.iter().any(|u| u.email == user.email && u.id != user.id) {
+            return Err(Error::new(
+                "duplicate key value violates unique constraint \"users__email_idx\"",
+            ));
+        }
+
+        let row = store.users.iter_mut().find(|u| u.id == user.id).ok_or_else(not_found)?;
+        let old_email = std::mem::replace(&mut row.email, user.email.clone());
+
+        user.updated_at = now();
+        *row = user.clone();
+
+        // ON UPDATE CASCADE on the email foreign keys
+        for v in store.email_verifications.iter_mut().filter(|v| v.email_address == old_email) {
+            v.email_address = user.email.clone();
+        }
+        for r in store.password_resets.iter_mut().filter(|r| r.email_address == old_email) {
+            r.email_address = user.email.clone();
+        }
+
+        Ok(user)
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<bool> {
+        let mut store = self.write();
+
+        let referenced = store.api_keys.iter().any(|k| k.created_by == id)
+            || store.nations.iter().any(|n| n.creator_id == id)
+            || store.authorities.iter().any(|a| a.creator_id == id)
+            || store.schemas.iter().any(|s| s.creator_id == id)
+            || store.data_objects.iter().any(|d| d.creator_id == id)
+            || store.conversion_requests.iter().any(|r| r.creator_id == id);
+
+        if referenced {
+            return Err(Error::new(
+                "update or delete on table \"users\" violates foreign key constraint",
+            ));
+        }
+
+        let Some(email) = store.users.iter().find(|u| u.id == id).map(|u| u.email.clone()) else {
+            return Ok(false);
+        };
+
+        store.users.retain(|u| u.id != id);
+
+        store.sessions.retain(|s| s.user_id != id);
+        store.api_keys.retain(|k| k.user_id != id);
+        store.authority_members.retain(|m| m.user_id != id);
+        store.email_verifications.retain(|v| v.email_address != email);
+        store.password_resets.retain(|r| r.email_address != email);
+        store.mfa_challenges.retain(|c| c.user_id != id);
+        store.recovery_codes.retain(|c| c.user_id != id);
+
+        // ON DELETE SET NULL
+        for event in store.security_events.iter_mut().filter(|e| e.user_id == Some(id)) {
+            event.user_id = None;
+        }
+
+        for user in store.users.iter_mut().filter(|u| u.created_by_user_uid == Some(id)) {
+            user.created_by_user_uid = None;
+        }
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl SessionRepo for InMemoryRepository {
+    async fn create(&self, session: NewSession) -> Result<Session> {
+        let mut store = self.write();
+
+        if !store.users.iter().any(|u| u.id == session.user_id) {
+            return Err(Error::new(
+                "insert or update on table \"user_sessions\" violates foreign key constraint",
+            ));
+        }
+
+        if store.sessions.iter().any(|s| s.refresh_token_hash == session.refresh_token_hash) {
+            return Err(Error::new(
+                "duplicate key value violates unique constraint \"user_sessions__refresh_token_hash_idx\"",
+            ));
+        }
+
+        let session = Session {
+            id: Uuid::new_v4(),
+            user_id: session.user_id,
+            refresh_token_hash: session.refresh_token_hash,
+            created_at: now(),
+            last_used_at: now(),
+            expires_at: session.expires_at,
+            revoked_at: None,
+        };
+
+        store.sessions.push(session.clone());
+        Ok(session)
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Session> {
+        first(&self.read().sessions, |s| s.id == id)
+    }
+
+    async fn get_by_refresh_token_hash(&self, refresh_token_hash: String) -> Result<Session> {
+        first(&self.read().sessions, |s| s.refresh_token_hash == refresh_token_hash)
+    }
+
+    async fn rotate(&self, current_hash: String, new_hash: String) -> Result<Session> {
+        let mut store = self.write();
+
+        let row = store
+            .sessions
+            .iter_mut()
+            .find(|s| s.refresh_token_hash == current_hash && s.revoked_at.is_none())
+            .ok_or_else(not_found)?;
+
+        row.refresh_token_hash = new_hash;
+        row.last_used_at = now();
+        row.expires_at = now() + Duration::seconds(REFRESH_TOKEN_DURATION);
+        Ok(row.clone())
+    }
+
+    async fn revoke(&self, id: Uuid) -> Result<Session> {
+        let mut store = self.write();
+
+        let row = store.sessions.iter_mut().find(|s| s.id == id).ok_or_else(not_found)?;
+
+        row.revoked_at = Some(now());
+        Ok(row.clone())
+    }
+
+    async fn revoke_all_by_user_id(&self, user_id: Uuid) -> Result<usize> {
+        let mut store = self.write();
+
+        let mut revoked = 0;
+        for row in store.sessions.iter_mut() {
+            if row.user_id == user_id && row.revoked_at.is_none() {
+                row.revoked_at = Some(now());
+                revoked += 1;
+            }
+        }
+
+        Ok(revoked)
+    }
+}
+
+fn unknown_email() -> Error {
+    Error::new("insert or update violates foreign key constraint on \"email_address\"")
+}
+
+#[async_trait]
+impl EmailVerificationRepo for InMemoryRepository {
+    async fn create(&self, verification: InsertableVerification) -> Result<EmailVerification> {
+        let mut store = self.write();
+
+        if !store.users.iter().any(|u| u.email == verification.email_address) {
+            return Err(unknown_email());
+        }
+
+        store.email_verifications.retain(|v| v.email_address != verification.email_address);
+
+        let verification = EmailVerification {
+            id: Uuid::new_v4(),
+            email_address: verification.email_address,
+            activation_code_hash: verification.activation_code_hash,
+            expires_on: verification.expires_on,
+        };
+
+        store.email_verifications.push(verification.clone());
+        Ok(verification)
+    }
+
+    async fn consume(&self, activation_code_hash: String) -> Result<EmailVerification> {
+        let mut store = self.write();
+
+        let index = store
+            .email_verifications
+            .iter()
+            .position(|v| v.activation_code_hash == activation_code_hash)
+            .ok_or_else(not_found)?;
+
+        Ok(store.email_verifications.remove(index))
+    }
+}
+
+#[async_trait]
+impl PasswordResetRepo for InMemoryRepository {
+    async fn create(&self, reset: InsertablePasswordResetToken) -> Result<PasswordResetToken> {
+        let mut store = self.write();
+
+        if !store.users.iter().any(|u| u.email == reset.email_address) {
+            return Err(unknown_email());
+        }
+
+        store.password_resets.retain(|r| r.email_address != reset.email_address);
+
+        let reset = PasswordResetToken {
+            id: Uuid::new_v4(),
+            email_address: reset.email_address,
+            reset_token_hash: reset.reset_token_hash,
+            expires_on: reset.expires_on,
+        };
+
+        store.password_resets.push(reset.clone());
+        Ok(reset)
+    }
+
+    async fn consume(&self, reset_token_hash: String) -> Result<PasswordResetToken> {
+        let mut store = self.write();
+
+        let index = store
+            .password_resets
+            .iter()
+            .position(|r| r.reset_token_hash == reset_token_hash)
+            .ok_or_else(not_found)?;
+
+        Ok(store.password_resets.remove(index))
+    }
+
+    async fn delete_by_email(&self, email_address: String) -> Result<usize> {
+        let mut store = self.write();
+        let before = store.password_resets.len();
+
+        store.password_resets.retain(|r| r.email_address != email_address);
+
+        Ok(before - store.password_resets.len())
+    }
+}
+
+#[async_trait]
+impl MfaChallengeRepo for InMemoryRepository {
+    async fn create(&self, challenge: NewSignInChallenge) -> Result<SignInChallenge> {
+        let mut store = self.write();
+
+        if store.mfa_challenges.iter().any(|c| c.challenge_hash == challenge.challenge_hash) {
+            return Err(Error::new(
+                "duplicate key value violates unique constraint \"mfa_challenges__challenge_hash_idx\"",
+            ));
+        }
+
+        let challenge = SignInChallenge {
+            id: Uuid::new_v4(),
+            user_id: challenge.user_id,
+            challenge_hash: challenge.challenge_hash,
+            failed_attempts: 0,
+            created_at: now(),
+            expires_at: challenge.expires_at,
+        };
+
+        store.mfa_challenges.push(challenge.clone());
+        Ok(challenge)
+    }
+
+    async fn get_by_challenge_hash(&self, challenge_hash: String) -> Result<SignInChallenge> {
+        first(&self.read().mfa_challenges, |c| c.challenge_hash == challenge_hash)
+    }
+
+    async fn record_failure(&self, id: Uuid) -> Result<SignInChallenge> {
+        let mut store = self.write();
+        let row = store.mfa_challenges.iter_mut().find(|c| c.id == id).ok_or_else(not_found)?;
+
+        row.failed_attempts += 1;
+        Ok(row.clone())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<bool> {
+        let mut store = self.write();
+        let before = store.mfa_challenges.len();
+
+        store.mfa_challenges.retain(|c| c.id != id);
+        Ok(store.mfa_challenges.len() < before)
+    }
+}
+
+#[async_trait]
+impl RecoveryCodeRepo for InMemoryRepository {
+    async fn replace_for_user(&self, user_id: Uuid, code_hashes: Vec<String>) -> Result<()> {
+        let mut store = self.write();
+
+        store.recovery_codes.retain(|c| c.user_id != user_id);
+        store.recovery_codes.extend(code_hashes.into_iter().map(|code_hash| RecoveryCode {
+            id: Uuid::new_v4(),
+            user_id,
+            code_hash,
+            used_at: None,
+        }));
+
+        Ok(())
+    }
+
+    async fn redeem(&self, user_id: Uuid, code_hash: String) -> Result<bool> {
+        let mut store = self.write();
+
+        match store
+            .recovery_codes
+            .iter_mut()
+            .find(|c| c.user_id == user_id && c.code_hash == code_hash && c.used_at.is_none())
+        {
+            Some(code) => {
+                code.used_at = Some(now());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl SignInThrottleRepo for InMemoryRepository {
+    async fn get(&self, kind: ThrottleKind, subject: String) -> Result<Option<SignInThrottle>> {
+        let kind = kind.to_string();
+        Ok(first(&self.read().sign_in_throttles, |t| t.kind == kind && t.subject == subject).ok())
+    }
+
+    async fn get_all(&self) -> Result<Vec<SignInThrottle>> {
+        let mut throttles = self.read().sign_in_throttles.clone();
+        throttles.sort_by_key(|t| std::cmp::Reverse(t.last_failed_at));
+        Ok(throttles)
+    }
+
+    async fn record_failure(&self, kind: ThrottleKind, subject: String) -> Result<SignInThrottle> {
+        let mut store = self.write();
+        let kind = kind.to_string();
+        let cutoff = now() - Duration::seconds(SIGN_IN_FAILURE_WINDOW);
+
+        store
+            .sign_in_throttles
+            .retain(|t| !(t.kind == kind && t.subject == subject && t.last_failed_at < cutoff));
+
+        if let Some(row) = store.sign_in_throttles.iter_mut().find(|t| t.kind == kind && t.subject == subject) {
+            row.failed_attempts += 1;
+            row.last_failed_at = now();
+            return Ok(row.clone());
+        }
+
+        let throttle = SignInThrottle {
+            id: Uuid::new_v4(),
+            kind,
+            subject,
+            failed_attempts: 1,
+            first_failed_at: now(),
+            last_failed_at: now(),
+        };
+
+        store.sign_in_throttles.push(throttle.clone());
+        Ok(throttle)
+    }
+
+    async fn clear(&self, kind: ThrottleKind, subject: String) -> Result<bool> {
+        let mut store = self.write();
+        let kind = kind.to_string();
+        let before = store.sign_in_throttles.len();
+
+        store.sign_in_throttles.retain(|t| !(t.kind == kind && t.subject == subject));
+        Ok(store.sign_in_throttles.len() < before)
+    }
+}
+
+#[async_trait]
+impl SecurityEventRepo for InMemoryRepository {
+    async fn create(&self, event: NewSecurityEvent) -> Result<SecurityEvent> {
+        let mut store = self.write();
+
+        if event.user_id.is_some_and(|id| !store.users.iter().any(|u| u.id == id)) {
+            return Err(Error::new(
+                "insert or update on table \"security_events\" violates foreign key constraint",
+            ));
+        }
+
+        let event = SecurityEvent {
+            id: Uuid::new_v4(),
+            event_type: event.event_type,
+            user_id: event.user_id,
+            email: event.email,
+            ip_address: event.ip_address,
+            detail: event.detail,
+            created_at: now(),
+        };
+
+        store.security_events.push(event.clone());
+        Ok(event)
+    }
+
+    async fn get_recent(&self, limit: i64) -> Result<Vec<SecurityEvent>> {
+        Ok(self
+            .read()
+            .security_events
+            .iter()
+            .rev()
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl AuditRepo for InMemoryRepository {
+    async fn append(&self, entries: Vec<NewAuditEntry>) -> Result<Vec<AuditEntry>> {
+        Ok(self.write().audit(entries))
+    }
+
+    async fn get_page(&self, after_sequence: i64, limit: i64) -> Result<Vec<AuditEntry>> {
+        Ok(self
+            .read()
+            .audit_log
+            .iter()
+            .filter(|e| e.sequence > after_sequence)
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn search(&self, filter: AuditFilter, page: AuditPage) -> Result<Vec<AuditEntry>> {
+        let store = self.read();
+        let in_page = |e: &&AuditEntry| {
+            page.after_sequence.is_none_or(|after| e.sequence > after)
+                && page.before_sequence.is_none_or(|before| e.sequence < before)
+                && filter.matches(e)
+        };
+        let limit = page.limit.max(0) as usize;
+
+        let entries: Vec<AuditEntry> = if page.newest_first {
+            store.audit_log.iter().rev().filter(in_page).take(limit).cloned().collect()
+        } else {
+            store.audit_log.iter().filter(in_page).take(limit).cloned().collect()
+        };
+
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl MerkleAnchorRepo for InMemoryRepository {
+    async fn create_batch(&self, audit_limit: i64, receipt_limit: i64) -> Result<Option<MerkleAnchor>> {
+        let mut store = self.write();
+
+        let last_sequence = store
+            .merkle_anchors
+            .iter()
+            .filter_map(|a| a.last_audit_sequence)
+            .max()
+            .unwrap_or(0);
+
+        let audit_entries: Vec<AuditEntry> = store
+            .audit_log
+            .iter()
+            .filter(|e| e.sequence > last_sequence)
+            .take(audit_limit.max(0) as usize)
+            .cloned()
+            .collect();
+
+        let anchored: HashSet<Uuid> = store.merkle_leaves.iter().filter_map(|l| l.receipt_id).collect();
+
+        let receipts: Vec<(Uuid, String)> = store
+            .conversion_results
+            .iter()
+            .filter(|r| !anchored.contains(&r.id))
+            .filter_map(|r| r.receipt.clone().map(|receipt| (r.id, receipt)))
+            .take(receipt_limit.max(0) as usize)
+            .collect();
+
+        let Some((anchor, leaves)) = MerkleAnchor::new_batch(&audit_entries, &receipts) else {
+            return Ok(None);
+        };
+
+        store.merkle_anchors.push(anchor.clone());
+        store.merkle_leaves.extend(leaves);
+
+        Ok(Some(anchor))
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<MerkleAnchor> {
+        first(&self.read().merkle_anchors, |a| a.id == id)
+    }
+
+    async fn get_unpublished(&self) -> Result<Vec<MerkleAnchor>> {
+        Ok(filter(&self.read().merkle_anchors, |a| a.published_at.is_none()))
+    }
+
+    async fn mark_published(&self, id: Uuid) -> Result<MerkleAnchor> {
+        let mut store = self.write();
+        let anchor = store.merkle_anchors.iter_mut().find(|a| a.id == id).ok_or_else(not_found)?;
+
+        anchor.published_at = Some(now());
+        Ok(anchor.clone())
+    }
+
+    async fn get_leaf_by_receipt_id(&self, receipt_id: Uuid) -> Result<Option<MerkleLeaf>> {
+        Ok(self
+            .read()
+            .merkle_leaves
+            .iter()
+            .find(|l| l.receipt_id == Some(receipt_id))
+            .cloned())
+    }
+
+    async fn get_leaves(&self, anchor_id: Uuid) -> Result<Vec<MerkleLeaf>> {
+        // Leaves are pushed in position order
+        Ok(filter(&self.read().merkle_leaves, |l| l.anchor_id == anchor_id))
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepo for InMemoryRepository {
+    async fn create(&self, api_key: NewApiKey) -> Result<ApiKey> {
+        let mut store = self.write();
+
+        let users_exist = [api_key.user_id, api_key.created_by]
+            .iter()
+            .all(|id| store.users.iter().any(|u| u.id == *id));
+
+        if !users_exist || !store.authorities.iter().any(|a| a.id == api_key.authority_id) {
+            return Err(Error::new(
+                "insert or update on table \"api_keys\" violates foreign key constraint",
+            ));
+        }
+
+        if store.api_keys.iter().any(|k| k.key_hash == api_key.key_hash) {
+            return Err(Error::new(
+                "duplicate key value violates unique constraint \"api_keys__key_hash_idx\"",
+            ));
+        }
+
+        let api_key = ApiKey {
+            id: Uuid::new_v4(),
+            user_id: api_key.user_id,
+            authority_id: api_key.authority_id,
+            name: api_key.name,
+            key_prefix: api_key.key_prefix,
+            key_hash: api_key.key_hash,
+            role: api_key.role,
+            scopes: api_key.scopes,
+            created_by: api_key.created_by,
+            created_at: now(),
+            expires_at: api_key.expires_at,
+            last_used_at: None,
+            revoked_at: None,
+        };
+
+        store.api_keys.push(api_key.clone());
+        Ok(api_key)
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<ApiKey> {
+        first(&self.read().api_keys, |k| k.id == id)
+    }
+
+    async fn get_by_key_hash(&self, key_hash: String) -> Result<ApiKey> {
+        first(&self.read().api_keys, |k| k.key_hash == key_hash)
+    }
+
+    async fn get_all(&self) -> Result<Vec<ApiKey>> {
+        let mut keys = self.read().api_keys.clone();
+        keys.sort_by_key(|k| std::cmp::Reverse(k.created_at));
+        Ok(keys)
+    }
+
+    async fn expire_by(&self, id: Uuid, expires_at: NaiveDateTime) -> Result<ApiKey> {
+        let mut store = self.write();
+
+        let row = store.api_keys.iter_mut().find(|k| k.id == id).ok_or_else(not_found)?;
+
+        row.expires_at = row.expires_at.min(expires_at);
+        Ok(row.clone())
+    }
+
+    async fn revoke(&self, id: Uuid) -> Result<ApiKey> {
+        let mut store = self.write();
+
+        let row = store.api_keys.iter_mut().find(|k| k.id == id).ok_or_else(not_found)?;
+
+        row.revoked_at = Some(now());
+        Ok(row.clone())
+    }
+
+    async fn touch(&self, id: Uuid) -> Result<()> {
+        let mut store = self.write();
+
+        let row = store.api_keys.iter_mut().find(|k| k.id == id).ok_or_else(not_found)?;
+
+        row.last_used_at = Some(now());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl NationRepo for InMemoryRepository {
+    async fn create(&self, nation: NewNation) -> Result<Nation> {
+        let nation = Nation {
+            id: Uuid::new_v4(),
+            creator_id: nation.creator_id,
+            nation_code: nation.nation_code,
+            nation_name: nation.nation_name,
+            created_at: now(),
+            updated_at: now(),
+        };
+
+        self.write().nations.push(nation.clone());
+        Ok(nation)
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Nation> {
+        first(&self.read().nations, |n| n.id == id)
+    }
+
+    async fn get_by_code(&self, nation_code: String) -> Result<Nation> {
+        first(&self.read().nations, |n| n.nation_code == nation_code)
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<Nation>> {
+        Ok(filter(&self.read().nations, |n| n.creator_id == creator_id))
+    }
+
+    async fn get_all(&self) -> Result<Vec<Nation>> {
+        Ok(self.read().nations.clone())
+    }
+}
+
+#[async_trait]
+impl AuthorityRepo for InMemoryRepository {
+    async fn create(&self, authority: NewAuthority) -> Result<Authority> {
+        let authority = Authority {
+            id: Uuid::new_v4(),
+            creator_id: authority.creator_id,
+            nation_id: authority.nation_id,
+            name: authority.name,
+            email: authority.email,
+            phone: authority.phone,
+            created_at: now(),
+            updated_at: now(),
+            expires_at: authority.expires_at,
+        };
+
+        self.write().authorities.push(authority.clone());
+        Ok(authority)
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Authority> {
+        first(&self.read().authorities, |a| a.id == id)
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<Authority>> {
+        Ok(filter(&self.read().authorities, |a| a.creator_id == creator_id))
+    }
+
+    async fn get_by_nation_id(&self, nation_id: Uuid) -> Result<Vec<Authority>> {
+        Ok(filter(&self.read().authorities, |a| a.nation_id == nation_id))
+    }
+
+    async fn get_by_nation_code(&self, nation_code: String) -> Result<Vec<Authority>> {
+        let store = self.read();
+        let nation_id = first(&store.nations, |n| n.nation_code == nation_code)?.id;
+
+        Ok(filter(&store.authorities, |a| a.nation_id == nation_id))
+    }
+
+    async fn get_all(&self) -> Result<Vec<Authority>> {
+        Ok(self.read().authorities.clone())
+    }
+}
+
+#[async_trait]
+impl AuthorityMemberRepo for InMemoryRepository {
+    async fn add(&self, member: NewAuthorityMember) -> Result<AuthorityMember> {
+        let mut store = self.write();
+
+        if let Ok(existing) = first(&store.authority_members, |m| {
+            m.user_id == member.user_id && m.authority_id == member.authority_id
+        }) {
+            return Ok(existing);
+        }
+
+        if !store.users.iter().any(|u| u.id == member.user_id)
+            || !store.authorities.iter().any(|a| a.id == member.authority_id)
+        {
+            return Err(Error::new(
+                "insert or update on table \"authority_members\" violates foreign key constraint",
+            ));
+        }
+
+        let member = AuthorityMember {
+            user_id: member.user_id,
+            authority_id: member.authority_id,
+            created_at: now(),
+        };
+
+        store.authority_members.push(member.clone());
+        Ok(member)
+    }
+
+    async fn remove(&self, user_id: Uuid, authority_id: Uuid) -> Result<bool> {
+        let mut store = self.write();
+        let before = store.authority_members.len();
+
+        store
+            .authority_members
+            .retain(|m| !(m.user_id == user_id && m.authority_id == authority_id));
+
+        Ok(store.authority_members.len() < before)
+    }
+
+    async fn get_by_user_id(&self, user_id: Uuid) -> Result<Vec<AuthorityMember>> {
+        Ok(filter(&self.read().authority_members, |m| m.user_id == user_id))
+    }
+
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<AuthorityMember>> {
+        Ok(filter(&self.read().authority_members, |m| m.authority_id == authority_id))
+    }
+}
+
+#[async_trait]
+impl SchemaRepo for InMemoryRepository {
+    async fn create(&self, schema: NewClassificationSchema) -> Result<ClassificationSchema> {
+        let mut store = self.write();
+
+        if store
+            .schemas
+            .iter()
+            .any(|s| s.nation_code == schema.nation_code && s.version == schema.version)
+        {
+            return Err(Error::new(
+                "duplicate key value violates unique constraint \"classification_schemas__nation_version_idx\"",
+            ));
+        }
+
+        let schema = ClassificationSchema {
+            id: Uuid::new_v4(),
+            creator_id: schema.creator_id,
+            nation_code: schema.nation_code,
+            to_nato_unclassified: schema.to_nato_unclassified,
+            to_nato_restricted: schema.to_nato_restricted,
+            to_nato_confidential: schema.to_nato_confidential,
+            to_nato_secret: schema.to_nato_secret,
+            to_nato_top_secret: schema.to_nato_top_secret,
+            from_nato_unclassified: schema.from_nato_unclassified,
+            from_nato_restricted: schema.from_nato_restricted,
+            from_nato_confidential: schema.from_nato_confidential,
+            from_nato_secret: schema.from_nato_secret,
+            from_nato_top_secret: schema.from_nato_top_secret,
+            caveats: schema.caveats,
+            version: schema.version,
+            authority_id: schema.authority_id,
+            created_at: now(),
+            updated_at: now(),
+            expires_at: schema.expires_at,
+        };
+
+        store.schemas.push(schema.clone());
+        Ok(schema)
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<ClassificationSchema> {
+        first(&self.read().schemas, |s| s.id == id)
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<ClassificationSchema>> {
+        Ok(filter(&self.read().schemas, |s| s.creator_id == creator_id))
+    }
+
+    async fn get_by_nation_code(&self, nation_code: String) -> Result<Vec<ClassificationSchema>> {
+        Ok(filter(&self.read().schemas, |s| s.nation_code == nation_code))
+    }
+
+    async fn get_by_nation_code_and_version(
+        &self,
+        nation_code: String,
+        version: String,
+    ) -> Result<ClassificationSchema> {
+        first(&self.read().schemas, |s| {
+            s.nation_code == nation_code && s.version == version
+        })
+    }
+
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<ClassificationSchema>> {
+        Ok(filter(&self.read().schemas, |s| s.authority_id == authority_id))
+    }
+
+    async fn get_latest_by_nation_code(&self, nation_code: String) -> Result<ClassificationSchema> {
+        self.read()
+            .schemas
+            .iter()
+            .filter(|s| s.nation_code == nation_code)
+            .max_by_key(|s| s.created_at)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    async fn get_all(&self) -> Result<Vec<ClassificationSchema>> {
+        Ok(self.read().schemas.clone())
+    }
+
+    async fn count(&self) -> Result<i64> {
+        Ok(self.read().schemas.len() as i64)
+    }
+}
+
+#[async_trait]
+impl DataObjectRepo for InMemoryRepository {
+    async fn create(&self, data_object: NewDataObject) -> Result<DataObject> {
+        let data_object = new_data_object(data_object);
+
+        self.write().data_objects.push(data_object.clone());
+        Ok(data_object)
+    }
+
+    async fn create_with_metadata(
+        &self,
+        data_object: NewDataObject,
+        metadata: InsertableMetadata,
+    ) -> Result<(DataObject, Metadata)> {
+        let data_object = new_data_object(data_object);
+        let metadata = new_metadata(data_object.id, metadata);
+
+        let mut store = self.write();
+        store.data_objects.push(data_object.clone());
+        store.metadata.push(metadata.clone());
+
+        Ok((data_object, metadata))
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<DataObject> {
+        first(&self.read().data_objects, |d| d.id == id)
+    }
+
+    async fn get_by_ids(&self, ids: Vec<Uuid>) -> Result<Vec<DataObject>> {
+        let ids: HashSet<Uuid> = ids.into_iter().collect();
+
+        Ok(filter(&self.read().data_objects, |d| ids.contains(&d.id)))
+    }
+
+    async fn get_by_title(&self, title: String) -> Result<Vec<DataObject>> {
+        let title = title.to_lowercase();
+
+        Ok(filter(&self.read().data_objects, |d| {
+            d.title.to_lowercase().contains(&title)
+        }))
+    }
+
+    async fn get_by_content_digest(&self, content_digest: String) -> Result<Vec<DataObject>> {
+        let content_digest = content_digest.to_lowercase();
+
+        let mut data_objects = filter(&self.read().data_objects, |d| {
+            d.content_digest.as_ref() == Some(&content_digest)
+        });
+        data_objects.sort_by_key(|d| d.created_at);
+
+        Ok(data_objects)
+    }
+
+    async fn search(
+        &self,
+        query: String,
+        domains: Option<Vec<String>>,
+        tags: Option<Vec<String>>,
+        visibility: SearchVisibility,
+    ) -> Result<Vec<DataObjectSearchResult>> {
+        let query = query.trim();
+
+        if query.is_empty() {
+            return Err(Error::new("Search query cannot be empty"));
+        }
+
+        let clauses = parse_search_query(query);
+        let lowercase = |values: Vec<String>| -> Vec<String> {
+            values.iter().map(|v| v.to_lowercase()).collect()
+        };
+        let domains = domains.map(lowercase);
+        let tags = tags.map(lowercase);
+
+        let ids = self.read().data_objects.iter().map(|d| d.id).collect();
+        let markings = self.get_markings(ids).await?;
+        let unmarked = DataObjectMarking::default();
+
+        let store = self.read();
+
+        let mut results: Vec<DataObjectSearchResult> = store
+            .data_objects
+            .iter()
+            .filter_map(|d| {
+                let requests: Vec<&ConversionRequest> = store
+                    .conversion_requests
+                    .iter()
+                    .filter(|c| c.data_object_id == d.id)
+                    .collect();
+
+                if !visibility.allows(&requests, markings.get(&d.id).unwrap_or(&unmarked)) {
+                    return None;
+                }
+
+                let metadata: Vec<&Metadata> = store
+                    .metadata
+                    .iter()
+                    .filter(|m| m.data_object_id == d.id)
+                    .collect();
+
+                if let Some(domains) = &domains
+                    && !metadata.iter().any(|m| domains.contains(&m.domain.to_lowercase()))
+                {
+                    return None;
+                }
+
+                if let Some(tags) = &tags
+                    && !metadata.iter().any(|m| {
+                        m.tags
+                            .iter()
+                            .flatten()
+                            .any(|t| tags.contains(&t.to_lowercase()))
+                    })
+                {
+                    return None;
+                }
+
+                let keywords: Vec<&str> = metadata
+                    .iter()
+                    .flat_map(|m| {
+                        std::iter::once(m.domain.as_str())
+                            .chain(m.tags.iter().flatten().map(String::as_str))
+                    })
+                    .collect();
+
+                let document = SearchDocument::new(&d.title, &keywords.join(" "), &d.description);
+                let rank = document.rank(&clauses)?;
+
+                Some(DataObjectSearchResult {
+                    data_object: d.clone(),
+                    rank,
+                    title_highlight: highlight(&d.title, &clauses),
+                    description_highlight: highlight(&d.description, &clauses),
+                })
+            })
+            .collect();
+
+        results.sort_by(|a, b| {
+            b.rank
+                .total_cmp(&a.rank)
+                .then(b.data_object.updated_at.cmp(&a.data_object.updated_at))
+        });
+        results.truncate(SEARCH_RESULT_LIMIT as usize);
+
+        Ok(results)
+    }
+
+    async fn get_all(&self) -> Result<Vec<DataObject>> {
+        Ok(self.read().data_objects.clone())
+    }
+
+    async fn count(&self) -> Result<i64> {
+        Ok(self.read().data_objects.len() as i64)
+    }
+
+    async fn is_redacted(&self, _id: Uuid) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+#[async_trait]
+impl MetadataRepo for InMemoryRepository {
+    async fn create(&self, metadata: NewMetadata) -> Result<Metadata> {
+        let mut store = self.write();
+
+        if !store.data_objects.iter().any(|d| d.id == metadata.data_object_id) {
+            return Err(Error::new(
+                "insert or update on table \"metadata\" violates foreign key constraint",
+            ));
+        }
+
+        let metadata = Metadata {
+            id: Uuid::new_v4(),
+            data_object_id: metadata.data_object_id,
+            domain: metadata.domain,
+            tags: metadata.tags,
+            created_at: now(),
+            updated_at: now(),
+        };
+
+        store.metadata.push(metadata.clone());
+        Ok(metadata)
+    }
+
+    async fn get_by_data_object_id(&self, data_object_id: Uuid) -> Result<Metadata> {
+        first(&self.read().metadata, |m| m.data_object_id == data_object_id)
+    }
+
+    async fn get_data_object_ids_by_domain(&self, domain: String) -> Result<Vec<Uuid>> {
+        Ok(self
+            .read()
+            .metadata
+            .iter()
+            .filter(|m| m.domain == domain)
+            .map(|m| m.data_object_id)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl ConversionRequestRepo for InMemoryRepository {
+    async fn process_payload(&self, payload: InsertableConversionRequest) -> Result<ConversionRequest> {
+        self.process_payload_audited(payload, Box::new(|_| Vec::new())).await
+    }
+
+    async fn process_payload_audited(
+        &self,
+        payload: InsertableConversionRequest,
+        audit: AuditEntriesFor<ConversionRequest>,
+    ) -> Result<ConversionRequest> {
+        // One write lock for all the inserts and the audit entries, so
+        // readers never see a half-created or unaudited request
+        let mut store = self.write();
+
+        let (conversion_request, data_object, metadata) = store.prepare_request(payload)?;
+
+        store.audit(audit(&conversion_request));
+        store.insert_request(conversion_request.clone(), data_object, metadata);
+
+        Ok(conversion_request)
+    }
+
+    async fn process_and_complete(
+        &self,
+        payload: InsertableConversionRequest,
+        convert: ConversionResultsFor,
+        audit: AuditEntriesFor<CompletedConversion>,
+    ) -> Result<CompletedConversion> {
+        // Everything that can fail comes before the first write
+        let mut store = self.write();
+
+        let (mut conversion_request, data_object, metadata) = store.prepare_request(payload)?;
+        let results = store.issue_results(convert(&conversion_request), &data_object)?;
+        conversion_request.completed_at = Some(now());
+
+        let completed = (conversion_request, results);
+
+        store.audit(audit(&completed));
+        store.insert_request(completed.0.clone(), data_object, metadata);
+        store.conversion_results.extend(completed.1.iter().cloned());
+
+        Ok(completed)
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<ConversionRequest> {
+        first(&self.read().conversion_requests, |c| c.id == id)
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<ConversionRequest>> {
+        Ok(filter(&self.read().conversion_requests, |c| c.creator_id == creator_id))
+    }
+
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<ConversionRequest>> {
+        Ok(filter(&self.read().conversion_requests, |c| c.authority_id == authority_id))
+    }
+
+    async fn get_by_data_object_id(&self, data_object_id: Uuid) -> Result<Vec<ConversionRequest>> {
+        Ok(filter(&self.read().conversion_requests, |c| c.data_object_id == data_object_id))
+    }
+
+    async fn get_by_source_nation_code(&self, nation_code: String) -> Result<Vec<ConversionRequest>> {
+        Ok(filter(&self.read().conversion_requests, |c| c.source_nation_code == nation_code))
+    }
+
+    async fn get_pending(&self) -> Result<Vec<ConversionRequest>> {
+        Ok(filter(&self.read().conversion_requests, |c| c.completed_at.is_none()))
+    }
+
+    async fn get_completed(&self) -> Result<Vec<ConversionRequest>> {
+        Ok(filter(&self.read().conversion_requests, |c| c.completed_at.is_some()))
+    }
+
+    async fn get_all(&self) -> Result<Vec<ConversionRequest>> {
+        Ok(self.read().conversion_requests.clone())
+    }
+
+    async fn get_limited(&self, count: i64) -> Result<Vec<ConversionRequest>> {
+        let count = usize::try_from(count).map_err(|_| Error::new("LIMIT must not be negative"))?;
+
+        Ok(self.read().conversion_requests.iter().take(count).cloned().collect())
+    }
+
+    async fn get_in_scope(&self, scopes: &[TenantScope], limit: Option<i64>) -> Result<Vec<ConversionRequest>> {
+        let limit = match limit {
+            Some(limit) => usize::try_from(limit).map_err(|_| Error::new("LIMIT must not be negative"))?,
+            None => usize::MAX,
+        };
+
+        Ok(self
+            .read()
+            .conversion_requests
+            .iter()
+            .filter(|c| scopes.iter().all(|scope| scope.allows(c)))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn data_object_ids_in_scope(
+        &self,
+        scope: &TenantScope,
+        data_object_ids: Option<Vec<Uuid>>,
+    ) -> Result<HashSet<Uuid>> {
+        let among: Option<HashSet<Uuid>> = data_object_ids.map(|ids| ids.into_iter().collect());
+
+        Ok(self
+            .read()
+            .conversion_requests
+            .iter()
+            .filter(|c| scope.allows(c))
+            .map(|c| c.data_object_id)
+            .filter(|id| among.as_ref().is_none_or(|among| among.contains(id)))
+            .collect())
+    }
+
+    async fn get_markings(&self, data_object_ids: Vec<Uuid>) -> Result<HashMap<Uuid, DataObjectMarking>> {
+        let ids: HashSet<Uuid> = data_object_ids.into_iter().collect();
+        let store = self.read();
+
+        let mut markings: HashMap<Uuid, DataObjectMarking> = HashMap::new();
+        for request in store.conversion_requests.iter().filter(|c| ids.contains(&c.data_object_id)) {
+            let marking = markings.entry(request.data_object_id).or_default();
+            let results: Vec<&ConversionResult> = store
+                .conversion_results
+                .iter()
+                .filter(|r| r.conversion_request_id == request.id)
+                .collect();
+
+            if results.is_empty() {
+                marking.add(request.source_nation_code.clone(), request.target_nation_codes.clone(), None);
+            }
+            for result in results {
+                marking.add(
+                    request.source_nation_code.clone(),
+                    request.target_nation_codes.clone(),
+                    Some(&result.nato_classification),
+                );
+            }
+        }
+
+        Ok(markings)
+    }
+}
+
+#[async_trait]
+impl ConversionResultRepo for InMemoryRepository {
+    async fn complete_request(
+        &self,
+        conversion_request_id: Uuid,
+        results: Vec<NewConversionResult>,
+    ) -> Result<(ConversionRequest, Vec<ConversionResult>)> {
+        let mut store = self.write();
+
+        let request = first(&store.conversion_requests, |c| c.id == conversion_request_id)?;
+        let data_object = first(&store.data_objects, |d| d.id == request.data_object_id)?;
+        let results = store.issue_results(results, &data_object)?;
+
+        let request = store
+            .conversion_requests
+            .iter_mut()
+            .find(|c| c.id == conversion_request_id)
+            .ok_or_else(not_found)?;
+
+        request.completed_at = Some(now());
+        let request = request.clone();
+
+        store.conversion_results.extend(results.iter().cloned());
+
+        Ok((request, results))
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<ConversionResult> {
+        first(&self.read().conversion_results, |r| r.id == id)
+    }
+
+    async fn get_by_conversion_request_id(
+        &self,
+        conversion_request_id: Uuid,
+    ) -> Result<Vec<ConversionResult>> {
+        Ok(filter(&self.read().conversion_results, |r| {
+            r.conversion_request_id == conversion_request_id
+        }))
+    }
+}
+
+// Search
+//
+// A small stand-in for websearch_to_tsquery and ts_rank_cd. Words are
+// lowercased and lightly stemmed, terms are ANDed unless joined by OR,
+// "quoted phrases" must appear consecutively and -term excludes. Matches score
+// by field using the same A/B/C weights as the Postgres search document: title
+// 1.0, metadata domain and tags 0.4, description 0.2.
+
+const TITLE_WEIGHT: f32 = 1.0;
+const KEYWORD_WEIGHT: f32 = 0.4;
+const DESCRIPTION_WEIGHT: f32 = 0.2;
+
+/// A word or phrase from the query, as stemmed words
+struct SearchTerm {
+    words: Vec<String>,
+    negated: bool,
+}
+
+/// Alternatives joined by OR; at least one must match
+type SearchClause = Vec<SearchTerm>;
+
+fn stem(word: &str) -> String {
+    let word = word.to_lowercase();
+
+    match word.strip_suffix('s') {
+        Some(s) if s.len() > 2 && !s.ends_with('s') => s.to_owned(),
+        _ => word,
+    }
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(stem)
+        .collect()
+}
+
+fn parse_search_query(query: &str) -> Vec<SearchClause> {
+    let mut clauses: Vec<SearchClause> = Vec::new();
+    let mut join_next = false;
+    let mut rest = query;
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+
+        let negated = rest.starts_with('-');
+        if negated {
+            rest = &rest[1..];
+        }
+
+        let token = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let phrase = &quoted[..end];
+            rest = quoted.get(end + 1..).unwrap_or("");
+            phrase
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let word = &rest[..end];
+            rest = &rest[end..];
+
+            if !negated && word.eq_ignore_ascii_case("or") {
+                join_next = !clauses.is_empty();
+                continue;
+            }
+            word
+        };
+
+        let term = SearchTerm {
+            words: words(token),
+            negated,
+        };
+
+        if term.words.is_empty() {
+            continue;
+        }
+
+        match clauses.last_mut() {
+            Some(last) if join_next && !negated => last.push(term),
+            _ => clauses.push(vec![term]),
+        }
+        join_next = false;
+    }
+
+    clauses
+}
+
+struct SearchDocument {
+    fields: Vec<(Vec<String>, f32)>,
+}
+
+impl SearchDocument {
+    fn new(title: &str, keywords: &str, description: &str) -> Self {
+        SearchDocument {
+            fields: vec![
+                (words(title), TITLE_WEIGHT),
+                (words(keywords), KEYWORD_WEIGHT),
+                (words(description), DESCRIPTION_WEIGHT),
+            ],
+        }
+    }
+
+    /// Weighted count of the term's occurrences across fields
+    fn score(&self, term: &SearchTerm) -> f32 {
+        self.fields
+            .iter()
+            .map(|(field, weight)| {
+                let hits = field
+                    .windows(term.words.len())
+                    .filter(|w| *w == term.words.as_slice())
+                    .count();
+                hits as f32 * weight
+            })
+            .sum()
+    }
+
+    /// Returns the rank if every clause is satisfied, None otherwise
+    fn rank(&self, clauses: &[SearchClause]) -> Option<f32> {
+        let mut rank = 0.0;
+
+        for clause in clauses {
+            let mut matched = false;
+
+            for term in clause {
+                let score = self.score(term);
+
+                if term.negated {
+                    if score > 0.0 {
+                        return None;
+                    }
+                    matched = true;
+                } else if score > 0.0 {
+                    matched = true;
+                    rank += score;
+                }
+            }
+
+            if !matched {
+                return None;
+            }
+        }
+
+        (rank > 0.0).then_some(rank)
+    }
+}
+
+/// Escapes `text` as HTML and wraps words matching any positive query term
+/// in <mark> tags
+fn highlight(text: &str, clauses: &[SearchClause]) -> String {
+    let marked: HashSet<&String> = clauses
+        .iter()
+        .flatten()
+        .filter(|t| !t.negated)
+        .flat_map(|t| t.words.iter())
+        .collect();
+
+    let mut out = String::with_capacity(text.len());
+    let mut word = String::new();
+
+    let flush = |word: &mut String, out: &mut String| {
+        if word.is_empty() {
+            return;
+        }
+        if marked.contains(&stem(word)) {
+            out.push_str("<mark>");
+            out.push_str(word);
+            out.push_str("</mark>");
+        } else {
+            out.push_str(word);
+        }
+        word.clear();
+    };
+
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut out);
+            match c {
+                '&' => out.push_str("&amp;"),
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '"' => out.push_str("&quot;"),
+                '\'' => out.push_str("&#39;"),
+                _ => out.push(c),
+            }
+        }
+    }
+    flush(&mut word, &mut out);
+
+    out
+}
@@ -0,0 +1,421 @@
+//! Storage access for the GraphQL layer.
+//!
+//! Resolvers never open database connections themselves. They reach storage
+//! through the repository traits below, bundled as `Repositories` and injected
+//! into the schema data, so tests and alternate deployments can supply their
+//! own pool or backend without `DATABASE_URL` being set at process start.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use async_graphql::Result;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+use crate::database::PostgresPool;
+use crate::models::{
+    ApiKey, AuditEntry, AuditFilter, AuditPage, Authority, AuthorityMember, ClassificationSchema, ConversionRequest, ConversionResult,
+    DataObject, DataObjectMarking, DataObjectSearchResult, EmailVerification, InsertableConversionRequest, InsertableMetadata, InsertablePasswordResetToken,
+    InsertableUser, InsertableVerification, MerkleAnchor, MerkleLeaf, Metadata, Nation, NewApiKey, NewAuditEntry, NewAuthority,
+    NewAuthorityMember, NewClassificationSchema, NewConversionResult, NewDataObject, NewMetadata, NewNation,
+    NewSecurityEvent, NewSession, NewSignInChallenge, PasswordResetToken, SecurityEvent, Session, SignInChallenge,
+    SignInThrottle, ThrottleKind, User,
+};
+
+mod postgres;
+#[cfg(feature = "in-memory")]
+mod memory;
+mod scoped;
+mod clearance;
+mod audited;
+
+pub use self::postgres::PostgresRepository;
+pub use self::scoped::TenantScope;
+pub use self::clearance::{Clearance, REDACTED_TITLE};
+#[cfg(feature = "in-memory")]
+pub use self::memory::InMemoryRepository;
+
+#[async_trait]
+pub trait UserRepo: Send + Sync {
+    async fn create(&self, user: InsertableUser) -> Result<User>;
+    async fn get_by_id(&self, id: Uuid) -> Result<User>;
+    async fn get_by_email(&self, email: String) -> Result<User>;
+    async fn get_all(&self) -> Result<Vec<User>>;
+    async fn update(&self, user: User) -> Result<User>;
+    /// Deletes a user, returning whether there was one
+    async fn delete(&self, id: Uuid) -> Result<bool>;
+}
+
+#[async_trait]
+pub trait NationRepo: Send + Sync {
+    async fn create(&self, nation: NewNation) -> Result<Nation>;
+    async fn get_by_id(&self, id: Uuid) -> Result<Nation>;
+    async fn get_by_code(&self, nation_code: String) -> Result<Nation>;
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<Nation>>;
+    async fn get_all(&self) -> Result<Vec<Nation>>;
+}
+
+#[async_trait]
+pub trait AuthorityRepo: Send + Sync {
+    async fn create(&self, authority: NewAuthority) -> Result<Authority>;
+    async fn get_by_id(&self, id: Uuid) -> Result<Authority>;
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<Authority>>;
+    async fn get_by_nation_id(&self, nation_id: Uuid) -> Result<Vec<Authority>>;
+    async fn get_by_nation_code(&self, nation_code: String) -> Result<Vec<Authority>>;
+    async fn get_all(&self) -> Result<Vec<Authority>>;
+}
+
+/// Which authorities each user acts for
+#[async_trait]
+pub trait AuthorityMemberRepo: Send + Sync {
+    /// Adds a membership, returning the existing one if there is one
+    async fn add(&self, member: NewAuthorityMember) -> Result<AuthorityMember>;
+    /// Removes a membership, returning whether there was one
+    async fn remove(&self, user_id: Uuid, authority_id: Uuid) -> Result<bool>;
+    async fn get_by_user_id(&self, user_id: Uuid) -> Result<Vec<AuthorityMember>>;
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<AuthorityMember>>;
+}
+
+/// Classification schemas, versioned per nation
+#[async_trait]
+pub trait SchemaRepo: Send + Sync {
+    async fn create(&self, schema: NewClassificationSchema) -> Result<ClassificationSchema>;
+    async fn get_by_id(&self, id: Uuid) -> Result<ClassificationSchema>;
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<ClassificationSchema>>;
+    async fn get_by_nation_code(&self, nation_code: String) -> Result<Vec<ClassificationSchema>>;
+    async fn get_by_nation_code_and_version(
+        &self,
+        nation_code: String,
+        version: String,
+    ) -> Result<ClassificationSchema>;
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<ClassificationSchema>>;
+    async fn get_latest_by_nation_code(&self, nation_code: String) -> Result<ClassificationSchema>;
+    async fn get_all(&self) -> Result<Vec<ClassificationSchema>>;
+    async fn count(&self) -> Result<i64>;
+}
+
+/// Whom a data object search runs for. The scoped and clearance decorators
+/// add their caller's restrictions, so the backend applies them before it
+/// limits the results.
+#[derive(Debug, Clone, Default)]
+pub struct SearchVisibility {
+    /// Objects carried by a request visible in each of these scopes
+    pub scopes: Vec<TenantScope>,
+    /// Objects each of these clearances may read
+    pub clearances: Vec<Clearance>,
+}
+
+impl SearchVisibility {
+    /// Whether an object carried by `requests` and marked `marking` is visible
+    pub fn allows(&self, requests: &[&ConversionRequest], marking: &DataObjectMarking) -> bool {
+        self.scopes.iter().all(|scope| requests.iter().any(|r| scope.allows(r)))
+            && self.clearances.iter().all(|clearance| clearance.may_read(marking))
+    }
+}
+
+#[async_trait]
+pub trait DataObjectRepo: Send + Sync {
+    async fn create(&self, data_object: NewDataObject) -> Result<DataObject>;
+    /// Creates a data object and its metadata as a single unit
+    async fn create_with_metadata(
+        &self,
+        data_object: NewDataObject,
+        metadata: InsertableMetadata,
+    ) -> Result<(DataObject, Metadata)>;
+    async fn get_by_id(&self, id: Uuid) -> Result<DataObject>;
+    async fn get_by_ids(&self, ids: Vec<Uuid>) -> Result<Vec<DataObject>>;
+    async fn get_by_title(&self, title: String) -> Result<Vec<DataObject>>;
+    /// Data objects whose document has this digest, oldest first
+    async fn get_by_content_digest(&self, content_digest: String) -> Result<Vec<DataObject>>;
+    /// Full-text search, limited to `SEARCH_RESULT_LIMIT` objects `visibility`
+    /// allows
+    async fn search(
+        &self,
+        query: String,
+        domains: Option<Vec<String>>,
+        tags: Option<Vec<String>>,
+        visibility: SearchVisibility,
+    ) -> Result<Vec<DataObjectSearchResult>>;
+    async fn get_all(&self) -> Result<Vec<DataObject>>;
+    /// Objects `get_all` lists, counting redacted placeholders
+    async fn count(&self) -> Result<i64>;
+    /// Whether the caller only gets a redacted placeholder of the object.
+    /// Always false without a clearance applied.
+    async fn is_redacted(&self, id: Uuid) -> Result<bool>;
+}
+
+#[async_trait]
+pub trait MetadataRepo: Send + Sync {
+    async fn create(&self, metadata: NewMetadata) -> Result<Metadata>;
+    async fn get_by_data_object_id(&self, data_object_id: Uuid) -> Result<Metadata>;
+    async fn get_data_object_ids_by_domain(&self, domain: String) -> Result<Vec<Uuid>>;
+}
+
+/// Makes the audit entries of a row as it is written
+pub type AuditEntriesFor<T> = Box<dyn FnOnce(&T) -> Vec<NewAuditEntry> + Send>;
+
+/// Makes the results of a conversion request as it is written
+pub type ConversionResultsFor = Box<dyn FnOnce(&ConversionRequest) -> Vec<NewConversionResult> + Send>;
+
+/// A completed conversion request and its results
+pub type CompletedConversion = (ConversionRequest, Vec<ConversionResult>);
+
+#[async_trait]
+pub trait ConversionRequestRepo: Send + Sync {
+    /// Creates the DataObject, Metadata and ConversionRequest described by a
+    /// submitted payload as a single unit
+    async fn process_payload(&self, payload: InsertableConversionRequest) -> Result<ConversionRequest>;
+    /// `process_payload`, appending the audit entries `audit` makes of the new
+    /// request in the same unit, so neither is kept without the other
+    async fn process_payload_audited(
+        &self,
+        payload: InsertableConversionRequest,
+        audit: AuditEntriesFor<ConversionRequest>,
+    ) -> Result<ConversionRequest>;
+    /// `process_payload` and `ConversionResultRepo::complete_request` with
+    /// the results `convert` makes of the new request, and the audit entries
+    /// `audit` makes of the outcome, as a single unit, so a request is never
+    /// kept without its results
+    async fn process_and_complete(
+        &self,
+        payload: InsertableConversionRequest,
+        convert: ConversionResultsFor,
+        audit: AuditEntriesFor<CompletedConversion>,
+    ) -> Result<CompletedConversion>;
+    async fn get_by_id(&self, id: Uuid) -> Result<ConversionRequest>;
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<ConversionRequest>>;
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<ConversionRequest>>;
+    async fn get_by_data_object_id(&self, data_object_id: Uuid) -> Result<Vec<ConversionRequest>>;
+    async fn get_by_source_nation_code(&self, nation_code: String) -> Result<Vec<ConversionRequest>>;
+    async fn get_pending(&self) -> Result<Vec<ConversionRequest>>;
+    async fn get_completed(&self) -> Result<Vec<ConversionRequest>>;
+    async fn get_all(&self) -> Result<Vec<ConversionRequest>>;
+    /// Returns at most `count` conversion requests
+    async fn get_limited(&self, count: i64) -> Result<Vec<ConversionRequest>>;
+    /// Conversion requests visible in each of `scopes`, at most `limit` of them
+    async fn get_in_scope(&self, scopes: &[TenantScope], limit: Option<i64>) -> Result<Vec<ConversionRequest>>;
+    /// Ids of the data objects carried by a conversion request visible in
+    /// `scope`, only out of `data_object_ids` when given
+    async fn data_object_ids_in_scope(
+        &self,
+        scope: &TenantScope,
+        data_object_ids: Option<Vec<Uuid>>,
+    ) -> Result<HashSet<Uuid>>;
+    /// Markings of the data objects in `data_object_ids` that have requests,
+    /// from every request carrying them
+    async fn get_markings(&self, data_object_ids: Vec<Uuid>) -> Result<HashMap<Uuid, DataObjectMarking>>;
+}
+
+#[async_trait]
+pub trait ConversionResultRepo: Send + Sync {
+    /// Stores the results and marks the conversion request completed as a
+    /// single unit
+    async fn complete_request(
+        &self,
+        conversion_request_id: Uuid,
+        results: Vec<NewConversionResult>,
+    ) -> Result<(ConversionRequest, Vec<ConversionResult>)>;
+    async fn get_by_id(&self, id: Uuid) -> Result<ConversionResult>;
+    async fn get_by_conversion_request_id(
+        &self,
+        conversion_request_id: Uuid,
+    ) -> Result<Vec<ConversionResult>>;
+}
+
+/// Sign-in sessions backing access and refresh tokens
+#[async_trait]
+pub trait SessionRepo: Send + Sync {
+    async fn create(&self, session: NewSession) -> Result<Session>;
+    async fn get_by_id(&self, id: Uuid) -> Result<Session>;
+    async fn get_by_refresh_token_hash(&self, refresh_token_hash: String) -> Result<Session>;
+    /// Swaps the refresh token hash of an unrevoked session and extends its
+    /// expiry. Fails if `current_hash` was already exchanged.
+    async fn rotate(&self, current_hash: String, new_hash: String) -> Result<Session>;
+    async fn revoke(&self, id: Uuid) -> Result<Session>;
+    /// Revokes every active session of a user, returning how many there were
+    async fn revoke_all_by_user_id(&self, user_id: Uuid) -> Result<usize>;
+}
+
+/// Keys for machine-to-machine clients
+#[async_trait]
+pub trait ApiKeyRepo: Send + Sync {
+    async fn create(&self, api_key: NewApiKey) -> Result<ApiKey>;
+    async fn get_by_id(&self, id: Uuid) -> Result<ApiKey>;
+    async fn get_by_key_hash(&self, key_hash: String) -> Result<ApiKey>;
+    /// Newest first
+    async fn get_all(&self) -> Result<Vec<ApiKey>>;
+    /// Brings the expiry forward to `expires_at` if it is later
+    async fn expire_by(&self, id: Uuid, expires_at: NaiveDateTime) -> Result<ApiKey>;
+    async fn revoke(&self, id: Uuid) -> Result<ApiKey>;
+    /// Records that the key was just used
+    async fn touch(&self, id: Uuid) -> Result<()>;
+}
+
+/// Email verification codes, stored hashed
+#[async_trait]
+pub trait EmailVerificationRepo: Send + Sync {
+    /// Replaces any pending verification for the same address
+    async fn create(&self, verification: InsertableVerification) -> Result<EmailVerification>;
+    /// Removes and returns the verification with this code hash
+    async fn consume(&self, activation_code_hash: String) -> Result<EmailVerification>;
+}
+
+/// Password reset tokens, stored hashed
+#[async_trait]
+pub trait PasswordResetRepo: Send + Sync {
+    /// Replaces any pending reset for the same address
+    async fn create(&self, reset: InsertablePasswordResetToken) -> Result<PasswordResetToken>;
+    /// Removes and returns the reset with this token hash
+    async fn consume(&self, reset_token_hash: String) -> Result<PasswordResetToken>;
+    /// Drops any pending reset for the address, returning how many
+    async fn delete_by_email(&self, email_address: String) -> Result<usize>;
+}
+
+/// Second-step challenges of password sign-ins
+#[async_trait]
+pub trait MfaChallengeRepo: Send + Sync {
+    async fn create(&self, challenge: NewSignInChallenge) -> Result<SignInChallenge>;
+    async fn get_by_challenge_hash(&self, challenge_hash: String) -> Result<SignInChallenge>;
+    /// Counts a wrong code against the challenge
+    async fn record_failure(&self, id: Uuid) -> Result<SignInChallenge>;
+    /// Removes the challenge, returning whether it was still there
+    async fn delete(&self, id: Uuid) -> Result<bool>;
+}
+
+/// TOTP recovery codes, stored hashed
+#[async_trait]
+pub trait RecoveryCodeRepo: Send + Sync {
+    /// Replaces every code of the user; an empty list removes them
+    async fn replace_for_user(&self, user_id: Uuid, code_hashes: Vec<String>) -> Result<()>;
+    /// Uses up an unused code, returning whether there was one
+    async fn redeem(&self, user_id: Uuid, code_hash: String) -> Result<bool>;
+}
+
+/// Failed sign-in counts per email and client address
+#[async_trait]
+pub trait SignInThrottleRepo: Send + Sync {
+    async fn get(&self, kind: ThrottleKind, subject: String) -> Result<Option<SignInThrottle>>;
+    /// Latest failure first
+    async fn get_all(&self) -> Result<Vec<SignInThrottle>>;
+    /// Counts a failure, starting over after SIGN_IN_FAILURE_WINDOW without one
+    async fn record_failure(&self, kind: ThrottleKind, subject: String) -> Result<SignInThrottle>;
+    /// Forgets the failures, returning whether there were any
+    async fn clear(&self, kind: ThrottleKind, subject: String) -> Result<bool>;
+}
+
+/// Append-only record of sign-in outcomes and lockouts
+#[async_trait]
+pub trait SecurityEventRepo: Send + Sync {
+    async fn create(&self, event: NewSecurityEvent) -> Result<SecurityEvent>;
+    /// The latest `limit` events, newest first
+    async fn get_recent(&self, limit: i64) -> Result<Vec<SecurityEvent>>;
+}
+
+/// The hash-chained audit trail. Entries can only be appended.
+#[async_trait]
+pub trait AuditRepo: Send + Sync {
+    /// Chains the entries after the current last one, in order
+    async fn append(&self, entries: Vec<NewAuditEntry>) -> Result<Vec<AuditEntry>>;
+    /// Up to `limit` entries after `after_sequence`, in chain order
+    async fn get_page(&self, after_sequence: i64, limit: i64) -> Result<Vec<AuditEntry>>;
+    /// Up to `page.limit` entries matching `filter` within the page's window
+    async fn search(&self, filter: AuditFilter, page: AuditPage) -> Result<Vec<AuditEntry>>;
+}
+
+/// Merkle anchors over the audit trail and conversion receipts
+#[async_trait]
+pub trait MerkleAnchorRepo: Send + Sync {
+    /// Anchors the oldest `audit_limit` audit entries and `receipt_limit`
+    /// receipts not yet anchored, or returns None if there are none
+    async fn create_batch(&self, audit_limit: i64, receipt_limit: i64) -> Result<Option<MerkleAnchor>>;
+    async fn get_by_id(&self, id: Uuid) -> Result<MerkleAnchor>;
+    /// Anchors not yet published, oldest first
+    async fn get_unpublished(&self) -> Result<Vec<MerkleAnchor>>;
+    async fn mark_published(&self, id: Uuid) -> Result<MerkleAnchor>;
+    /// The leaf of a receipt, if it has been anchored
+    async fn get_leaf_by_receipt_id(&self, receipt_id: Uuid) -> Result<Option<MerkleLeaf>>;
+    /// Every leaf of an anchor in tree order
+    async fn get_leaves(&self, anchor_id: Uuid) -> Result<Vec<MerkleLeaf>>;
+}
+
+/// The full set of repositories available to resolvers and handlers
+#[derive(Clone)]
+pub struct Repositories {
+    pub users: Arc<dyn UserRepo>,
+    pub sessions: Arc<dyn SessionRepo>,
+    pub api_keys: Arc<dyn ApiKeyRepo>,
+    pub email_verifications: Arc<dyn EmailVerificationRepo>,
+    pub password_resets: Arc<dyn PasswordResetRepo>,
+    pub mfa_challenges: Arc<dyn MfaChallengeRepo>,
+    pub recovery_codes: Arc<dyn RecoveryCodeRepo>,
+    pub sign_in_throttles: Arc<dyn SignInThrottleRepo>,
+    pub security_events: Arc<dyn SecurityEventRepo>,
+    pub audit_log: Arc<dyn AuditRepo>,
+    pub merkle_anchors: Arc<dyn MerkleAnchorRepo>,
+    pub nations: Arc<dyn NationRepo>,
+    pub authorities: Arc<dyn AuthorityRepo>,
+    pub authority_members: Arc<dyn AuthorityMemberRepo>,
+    pub schemas: Arc<dyn SchemaRepo>,
+    pub data_objects: Arc<dyn DataObjectRepo>,
+    pub metadata: Arc<dyn MetadataRepo>,
+    pub conversion_requests: Arc<dyn ConversionRequestRepo>,
+    pub conversion_results: Arc<dyn ConversionResultRepo>,
+}
+
+impl Repositories {
+    /// Repositories backed by Diesel models over the given Postgres pool
+    pub fn postgres(pool: PostgresPool) -> Self {
+        let repo = Arc::new(PostgresRepository::new(pool));
+
+        Repositories {
+            users: repo.clone(),
+            sessions: repo.clone(),
+            api_keys: repo.clone(),
+            email_verifications: repo.clone(),
+            password_resets: repo.clone(),
+            mfa_challenges: repo.clone(),
+            recovery_codes: repo.clone(),
+            sign_in_throttles: repo.clone(),
+            security_events: repo.clone(),
+            audit_log: repo.clone(),
+            merkle_anchors: repo.clone(),
+            nations: repo.clone(),
+            authorities: repo.clone(),
+            authority_members: repo.clone(),
+            schemas: repo.clone(),
+            data_objects: repo.clone(),
+            metadata: repo.clone(),
+            conversion_requests: repo.clone(),
+            conversion_results: repo,
+        }
+    }
+
+    /// Repositories backed by process memory. Nothing is persisted.
+    #[cfg(feature = "in-memory")]
+    pub fn in_memory() -> Self {
+        let repo = Arc::new(InMemoryRepository::new());
+
+        Repositories {
+            users: repo.clone(),
+            sessions: repo.clone(),
+            api_keys: repo.clone(),
+            email_verifications: repo.clone(),
+            password_resets: repo.clone(),
+            mfa_challenges: repo.clone(),
+            recovery_codes: repo.clone(),
+            sign_in_throttles: repo.clone(),
+            security_events: repo.clone(),
+            audit_log: repo.clone(),
+            merkle_anchors: repo.clone(),
+            nations: repo.clone(),
+            authorities: repo.clone(),
+            authority_members: repo.clone(),
+            schemas: repo.clone(),
+            data_objects: repo.clone(),
+            metadata: repo.clone(),
+            conversion_requests: repo.clone(),
+            conversion_results: repo,
+        }
+    }
+}
@@ -0,0 +1,555 @@
+use std::collections::{HashMap, HashSet};
+
+use async_graphql::Result;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use diesel::PgConnection;
+use uuid::Uuid;
+
+use crate::database::{run_blocking, PostgresPool};
+use crate::models::{
+    ApiKey, AuditEntry, AuditFilter, AuditPage, Authority, AuthorityMember, ClassificationSchema, ConversionRequest, ConversionResult,
+    DataObject, DataObjectMarking, DataObjectSearchResult, EmailVerification, InsertableConversionRequest, InsertableMetadata, InsertablePasswordResetToken,
+    InsertableUser, InsertableVerification, MerkleAnchor, MerkleLeaf, Metadata, Nation, NewApiKey, NewAuditEntry, NewAuthority,
+    NewAuthorityMember, NewClassificationSchema, NewConversionResult, NewDataObject, NewMetadata, NewNation,
+    NewSecurityEvent, NewSession, NewSignInChallenge, PasswordResetToken, RecoveryCode, SecurityEvent, Session,
+    SignInChallenge, SignInThrottle, ThrottleKind, User,
+};
+use crate::repositories::{
+    ApiKeyRepo, AuditEntriesFor, AuditRepo, AuthorityMemberRepo, AuthorityRepo, CompletedConversion, ConversionRequestRepo,
+    ConversionResultRepo, ConversionResultsFor, DataObjectRepo, EmailVerificationRepo, MerkleAnchorRepo, MetadataRepo,
+    MfaChallengeRepo, NationRepo, PasswordResetRepo, RecoveryCodeRepo, SchemaRepo, SearchVisibility, SecurityEventRepo,
+    SessionRepo, SignInThrottleRepo, TenantScope, UserRepo,
+};
+
+/// Repository implementation over the Diesel models. Every call checks out a
+/// pooled connection and runs on the blocking thread pool.
+#[derive(Clone)]
+pub struct PostgresRepository {
+    pool: PostgresPool,
+}
+
+impl PostgresRepository {
+    pub fn new(pool: PostgresPool) -> Self {
+        PostgresRepository { pool }
+    }
+
+    async fn run<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut PgConnection) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+
+        run_blocking(move || {
+            let mut conn = pool.get()?;
+            f(&mut conn)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl UserRepo for PostgresRepository {
+    async fn create(&self, user: InsertableUser) -> Result<User> {
+        self.run(move |conn| User::create(conn, user)).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<User> {
+        self.run(move |conn| User::get_by_id(conn, &id)).await
+    }
+
+    async fn get_by_email(&self, email: String) -> Result<User> {
+        self.run(move |conn| User::get_by_email(conn, &email)).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<User>> {
+        self.run(User::get_all).await
+    }
+
+    async fn update(&self, mut user: User) -> Result<User> {
+        self.run(move |conn| user.update(conn)).await
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<bool> {
+        self.run(move |conn| User::delete(conn, &id)).await
+    }
+}
+
+#[async_trait]
+impl SessionRepo for PostgresRepository {
+    async fn create(&self, session: NewSession) -> Result<Session> {
+        self.run(move |conn| Session::create(conn, &session)).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Session> {
+        self.run(move |conn| Session::get_by_id(conn, &id)).await
+    }
+
+    async fn get_by_refresh_token_hash(&self, refresh_token_hash: String) -> Result<Session> {
+        self.run(move |conn| Session::get_by_refresh_token_hash(conn, &refresh_token_hash)).await
+    }
+
+    async fn rotate(&self, current_hash: String, new_hash: String) -> Result<Session> {
+        self.run(move |conn| Session::rotate(conn, &current_hash, &new_hash)).await
+    }
+
+    async fn revoke(&self, id: Uuid) -> Result<Session> {
+        self.run(move |conn| Session::revoke(conn, &id)).await
+    }
+
+    async fn revoke_all_by_user_id(&self, user_id: Uuid) -> Result<usize> {
+        self.run(move |conn| Session::revoke_all_by_user_id(conn, &user_id)).await
+    }
+}
+
+#[async_trait]
+impl EmailVerificationRepo for PostgresRepository {
+    async fn create(&self, verification: InsertableVerification) -> Result<EmailVerification> {
+        self.run(move |conn| EmailVerification::create(conn, &verification)).await
+    }
+
+    async fn consume(&self, activation_code_hash: String) -> Result<EmailVerification> {
+        self.run(move |conn| EmailVerification::consume(conn, &activation_code_hash)).await
+    }
+}
+
+#[async_trait]
+impl PasswordResetRepo for PostgresRepository {
+    async fn create(&self, reset: InsertablePasswordResetToken) -> Result<PasswordResetToken> {
+        self.run(move |conn| PasswordResetToken::create(conn, &reset)).await
+    }
+
+    async fn consume(&self, reset_token_hash: String) -> Result<PasswordResetToken> {
+        self.run(move |conn| PasswordResetToken::consume(conn, &reset_token_hash)).await
+    }
+
+    async fn delete_by_email(&self, email_address: String) -> Result<usize> {
+        self.run(move |conn| PasswordResetToken::delete_by_email(conn, &email_address)).await
+    }
+}
+
+#[async_trait]
+impl MfaChallengeRepo for PostgresRepository {
+    async fn create(&self, challenge: NewSignInChallenge) -> Result<SignInChallenge> {
+        self.run(move |conn| SignInChallenge::create(conn, &challenge)).await
+    }
+
+    async fn get_by_challenge_hash(&self, challenge_hash: String) -> Result<SignInChallenge> {
+        self.run(move |conn| SignInChallenge::get_by_challenge_hash(conn, &challenge_hash)).await
+    }
+
+    async fn record_failure(&self, id: Uuid) -> Result<SignInChallenge> {
+        self.run(move |conn| SignInChallenge::record_failure(conn, &id)).await
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<bool> {
+        self.run(move |conn| SignInChallenge::delete(conn, &id)).await
+    }
+}
+
+#[async_trait]
+impl RecoveryCodeRepo for PostgresRepository {
+    async fn replace_for_user(&self, user_id: Uuid, code_hashes: Vec<String>) -> Result<()> {
+        self.run(move |conn| RecoveryCode::replace_for_user(conn, &user_id, code_hashes)).await
+    }
+
+    async fn redeem(&self, user_id: Uuid, code_hash: String) -> Result<bool> {
+        self.run(move |conn| RecoveryCode::redeem(conn, &user_id, &code_hash)).await
+    }
+}
+
+#[async_trait]
+impl SignInThrottleRepo for PostgresRepository {
+    async fn get(&self, kind: ThrottleKind, subject: String) -> Result<Option<SignInThrottle>> {
+        self.run(move |conn| SignInThrottle::get(conn, kind, &subject)).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<SignInThrottle>> {
+        self.run(SignInThrottle::get_all).await
+    }
+
+    async fn record_failure(&self, kind: ThrottleKind, subject: String) -> Result<SignInThrottle> {
+        self.run(move |conn| SignInThrottle::record_failure(conn, kind, &subject)).await
+    }
+
+    async fn clear(&self, kind: ThrottleKind, subject: String) -> Result<bool> {
+        self.run(move |conn| SignInThrottle::clear(conn, kind, &subject)).await
+    }
+}
+
+#[async_trait]
+impl SecurityEventRepo for PostgresRepository {
+    async fn create(&self, event: NewSecurityEvent) -> Result<SecurityEvent> {
+        self.run(move |conn| SecurityEvent::create(conn, &event)).await
+    }
+
+    async fn get_recent(&self, limit: i64) -> Result<Vec<SecurityEvent>> {
+        self.run(move |conn| SecurityEvent::get_recent(conn, limit)).await
+    }
+}
+
+#[async_trait]
+impl AuditRepo for PostgresRepository {
+    async fn append(&self, entries: Vec<NewAuditEntry>) -> Result<Vec<AuditEntry>> {
+        self.run(move |conn| AuditEntry::append(conn, entries)).await
+    }
+
+    async fn get_page(&self, after_sequence: i64, limit: i64) -> Result<Vec<AuditEntry>> {
+        self.run(move |conn| AuditEntry::get_page(conn, after_sequence, limit)).await
+    }
+
+    async fn search(&self, filter: AuditFilter, page: AuditPage) -> Result<Vec<AuditEntry>> {
+        self.run(move |conn| AuditEntry::search(conn, &filter, page)).await
+    }
+}
+
+#[async_trait]
+impl MerkleAnchorRepo for PostgresRepository {
+    async fn create_batch(&self, audit_limit: i64, receipt_limit: i64) -> Result<Option<MerkleAnchor>> {
+        self.run(move |conn| MerkleAnchor::create_batch(conn, audit_limit, receipt_limit)).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<MerkleAnchor> {
+        self.run(move |conn| MerkleAnchor::get_by_id(conn, &id)).await
+    }
+
+    async fn get_unpublished(&self) -> Result<Vec<MerkleAnchor>> {
+        self.run(MerkleAnchor::get_unpublished).await
+    }
+
+    async fn mark_published(&self, id: Uuid) -> Result<MerkleAnchor> {
+        self.run(move |conn| MerkleAnchor::mark_published(conn, &id)).await
+    }
+
+    async fn get_leaf_by_receipt_id(&self, receipt_id: Uuid) -> Result<Option<MerkleLeaf>> {
+        self.run(move |conn| MerkleLeaf::get_by_receipt_id(conn, &receipt_id)).await
+    }
+
+    async fn get_leaves(&self, anchor_id: Uuid) -> Result<Vec<MerkleLeaf>> {
+        self.run(move |conn| MerkleLeaf::get_by_anchor_id(conn, &anchor_id)).await
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepo for PostgresRepository {
+    async fn create(&self, api_key: NewApiKey) -> Result<ApiKey> {
+        self.run(move |conn| ApiKey::create(conn, &api_key)).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<ApiKey> {
+        self.run(move |conn| ApiKey::get_by_id(conn, &id)).await
+    }
+
+    async fn get_by_key_hash(&self, key_hash: String) -> Result<ApiKey> {
+        self.run(move |conn| ApiKey::get_by_key_hash(conn, &key_hash)).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<ApiKey>> {
+        self.run(ApiKey::get_all).await
+    }
+
+    async fn expire_by(&self, id: Uuid, expires_at: NaiveDateTime) -> Result<ApiKey> {
+        self.run(move |conn| ApiKey::expire_by(conn, &id, expires_at)).await
+    }
+
+    async fn revoke(&self, id: Uuid) -> Result<ApiKey> {
+        self.run(move |conn| ApiKey::revoke(conn, &id)).await
+    }
+
+    async fn touch(&self, id: Uuid) -> Result<()> {
+        self.run(move |conn| ApiKey::touch(conn, &id)).await
+    }
+}
+
+#[async_trait]
+impl NationRepo for PostgresRepository {
+    async fn create(&self, nation: NewNation) -> Result<Nation> {
+        self.run(move |conn| Nation::create(conn, &nation)).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Nation> {
+        self.run(move |conn| Nation::get_by_id(conn, &id)).await
+    }
+
+    async fn get_by_code(&self, nation_code: String) -> Result<Nation> {
+        self.run(move |conn| Nation::get_by_code(conn, &nation_code)).await
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<Nation>> {
+        self.run(move |conn| Nation::get_by_creator_id(conn, creator_id)).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<Nation>> {
+        self.run(Nation::get_all).await
+    }
+}
+
+#[async_trait]
+impl AuthorityRepo for PostgresRepository {
+    async fn create(&self, authority: NewAuthority) -> Result<Authority> {
+        self.run(move |conn| Authority::create(conn, &authority)).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<Authority> {
+        self.run(move |conn| Authority::get_by_id(conn, &id)).await
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<Authority>> {
+        self.run(move |conn| Authority::get_by_creator_id(conn, creator_id)).await
+    }
+
+    async fn get_by_nation_id(&self, nation_id: Uuid) -> Result<Vec<Authority>> {
+        self.run(move |conn| Authority::get_by_nation_id(conn, &nation_id)).await
+    }
+
+    async fn get_by_nation_code(&self, nation_code: String) -> Result<Vec<Authority>> {
+        self.run(move |conn| Authority::get_by_nation_code(conn, &nation_code)).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<Authority>> {
+        self.run(Authority::get_all).await
+    }
+}
+
+#[async_trait]
+impl AuthorityMemberRepo for PostgresRepository {
+    async fn add(&self, member: NewAuthorityMember) -> Result<AuthorityMember> {
+        self.run(move |conn| AuthorityMember::add(conn, &member)).await
+    }
+
+    async fn remove(&self, user_id: Uuid, authority_id: Uuid) -> Result<bool> {
+        self.run(move |conn| AuthorityMember::remove(conn, &user_id, &authority_id)).await
+    }
+
+    async fn get_by_user_id(&self, user_id: Uuid) -> Result<Vec<AuthorityMember>> {
+        self.run(move |conn| AuthorityMember::get_by_user_id(conn, &user_id)).await
+    }
+
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<AuthorityMember>> {
+        self.run(move |conn| AuthorityMember::get_by_authority_id(conn, &authority_id)).await
+    }
+}
+
+#[async_trait]
+impl SchemaRepo for PostgresRepository {
+    async fn create(&self, schema: NewClassificationSchema) -> Result<ClassificationSchema> {
+        self.run(move |conn| ClassificationSchema::create(conn, &schema)).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<ClassificationSchema> {
+        self.run(move |conn| ClassificationSchema::get_by_id(conn, &id)).await
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<ClassificationSchema>> {
+        self.run(move |conn| ClassificationSchema::get_by_creator_id(conn, creator_id)).await
+    }
+
+    async fn get_by_nation_code(&self, nation_code: String) -> Result<Vec<ClassificationSchema>> {
+        self.run(move |conn| ClassificationSchema::get_by_nation_code(conn, &nation_code)).await
+    }
+
+    async fn get_by_nation_code_and_version(
+        &self,
+        nation_code: String,
+        version: String,
+    ) -> Result<ClassificationSchema> {
+        self.run(move |conn| {
+            ClassificationSchema::get_by_nation_code_and_version(conn, &nation_code, &version)
+        })
+        .await
+    }
+
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<ClassificationSchema>> {
+        self.run(move |conn| ClassificationSchema::get_by_authority_id(conn, &authority_id)).await
+    }
+
+    async fn get_latest_by_nation_code(&self, nation_code: String) -> Result<ClassificationSchema> {
+        self.run(move |conn| ClassificationSchema::get_latest_by_nation_code(conn, &nation_code))
+            .await
+    }
+
+    async fn get_all(&self) -> Result<Vec<ClassificationSchema>> {
+        self.run(ClassificationSchema::get_all).await
+    }
+
+    async fn count(&self) -> Result<i64> {
+        self.run(ClassificationSchema::get_count).await
+    }
+}
+
+#[async_trait]
+impl DataObjectRepo for PostgresRepository {
+    async fn create(&self, data_object: NewDataObject) -> Result<DataObject> {
+        self.run(move |conn| DataObject::create(conn, &data_object)).await
+    }
+
+    async fn create_with_metadata(
+        &self,
+        data_object: NewDataObject,
+        metadata: InsertableMetadata,
+    ) -> Result<(DataObject, Metadata)> {
+        self.run(move |conn| DataObject::create_with_metadata(conn, &data_object, &metadata)).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<DataObject> {
+        self.run(move |conn| DataObject::get_by_id(conn, &id)).await
+    }
+
+    async fn get_by_ids(&self, ids: Vec<Uuid>) -> Result<Vec<DataObject>> {
+        self.run(move |conn| DataObject::get_by_ids(conn, ids)).await
+    }
+
+    async fn get_by_title(&self, title: String) -> Result<Vec<DataObject>> {
+        self.run(move |conn| DataObject::get_by_title(conn, &title)).await
+    }
+
+    async fn get_by_content_digest(&self, content_digest: String) -> Result<Vec<DataObject>> {
+        self.run(move |conn| DataObject::get_by_content_digest(conn, &content_digest)).await
+    }
+
+    async fn search(
+        &self,
+        query: String,
+        domains: Option<Vec<String>>,
+        tags: Option<Vec<String>>,
+        visibility: SearchVisibility,
+    ) -> Result<Vec<DataObjectSearchResult>> {
+        self.run(move |conn| DataObject::search(conn, &query, domains, tags, &visibility)).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<DataObject>> {
+        self.run(DataObject::get_all).await
+    }
+
+    async fn count(&self) -> Result<i64> {
+        self.run(DataObject::get_count).await
+    }
+
+    async fn is_redacted(&self, _id: Uuid) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+#[async_trait]
+impl MetadataRepo for PostgresRepository {
+    async fn create(&self, metadata: NewMetadata) -> Result<Metadata> {
+        self.run(move |conn| Metadata::create(conn, &metadata)).await
+    }
+
+    async fn get_by_data_object_id(&self, data_object_id: Uuid) -> Result<Metadata> {
+        self.run(move |conn| Metadata::get_by_data_object_id(conn, &data_object_id)).await
+    }
+
+    async fn get_data_object_ids_by_domain(&self, domain: String) -> Result<Vec<Uuid>> {
+        self.run(move |conn| Metadata::get_data_object_ids_by_domain(conn, domain)).await
+    }
+}
+
+#[async_trait]
+impl ConversionRequestRepo for PostgresRepository {
+    async fn process_payload(&self, payload: InsertableConversionRequest) -> Result<ConversionRequest> {
+        self.process_payload_audited(payload, Box::new(|_| Vec::new())).await
+    }
+
+    async fn process_payload_audited(
+        &self,
+        payload: InsertableConversionRequest,
+        audit: AuditEntriesFor<ConversionRequest>,
+    ) -> Result<ConversionRequest> {
+        self.run(move |conn| ConversionRequest::process_payload(conn, &payload, audit)).await
+    }
+
+    async fn process_and_complete(
+        &self,
+        payload: InsertableConversionRequest,
+        convert: ConversionResultsFor,
+        audit: AuditEntriesFor<CompletedConversion>,
+    ) -> Result<CompletedConversion> {
+        self.run(move |conn| ConversionRequest::process_and_complete(conn, &payload, convert, audit)).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<ConversionRequest> {
+        self.run(move |conn| ConversionRequest::get_by_id(conn, &id)).await
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<ConversionRequest>> {
+        self.run(move |conn| ConversionRequest::get_by_creator_id(conn, &creator_id)).await
+    }
+
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<ConversionRequest>> {
+        self.run(move |conn| ConversionRequest::get_by_authority_id(conn, &authority_id)).await
+    }
+
+    async fn get_by_data_object_id(&self, data_object_id: Uuid) -> Result<Vec<ConversionRequest>> {
+        self.run(move |conn| ConversionRequest::get_by_data_object_id(conn, &data_object_id))
+            .await
+    }
+
+    async fn get_by_source_nation_code(&self, nation_code: String) -> Result<Vec<ConversionRequest>> {
+        self.run(move |conn| ConversionRequest::get_by_source_nation_code(conn, &nation_code))
+            .await
+    }
+
+    async fn get_pending(&self) -> Result<Vec<ConversionRequest>> {
+        self.run(ConversionRequest::get_pending).await
+    }
+
+    async fn get_completed(&self) -> Result<Vec<ConversionRequest>> {
+        self.run(ConversionRequest::get_completed).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<ConversionRequest>> {
+        self.run(ConversionRequest::get_all).await
+    }
+
+    async fn get_limited(&self, count: i64) -> Result<Vec<ConversionRequest>> {
+        self.run(move |conn| ConversionRequest::get_count(conn, count)).await
+    }
+
+    async fn get_in_scope(&self, scopes: &[TenantScope], limit: Option<i64>) -> Result<Vec<ConversionRequest>> {
+        let scopes = scopes.to_vec();
+        self.run(move |conn| ConversionRequest::get_in_scope(conn, &scopes, limit)).await
+    }
+
+    async fn data_object_ids_in_scope(
+        &self,
+        scope: &TenantScope,
+        data_object_ids: Option<Vec<Uuid>>,
+    ) -> Result<HashSet<Uuid>> {
+        let scope = scope.clone();
+        let ids = self
+            .run(move |conn| ConversionRequest::get_data_object_ids_in_scope(conn, &scope, data_object_ids))
+            .await?;
+
+        Ok(ids.into_iter().collect())
+    }
+
+    async fn get_markings(&self, data_object_ids: Vec<Uuid>) -> Result<HashMap<Uuid, DataObjectMarking>> {
+        self.run(move |conn| ConversionRequest::get_markings(conn, data_object_ids)).await
+    }
+}
+
+#[async_trait]
+impl ConversionResultRepo for PostgresRepository {
+    async fn complete_request(
+        &self,
+        conversion_request_id: Uuid,
+        results: Vec<NewConversionResult>,
+    ) -> Result<(ConversionRequest, Vec<ConversionResult>)> {
+        self.run(move |conn| ConversionResult::complete_request(conn, &conversion_request_id, results))
+            .await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<ConversionResult> {
+        self.run(move |conn| ConversionResult::get_by_id(conn, &id)).await
+    }
+
+    async fn get_by_conversion_request_id(
+        &self,
+        conversion_request_id: Uuid,
+    ) -> Result<Vec<ConversionResult>> {
+        self.run(move |conn| ConversionResult::get_by_conversion_request_id(conn, &conversion_request_id))
+            .await
+    }
+}
@@ -0,0 +1,343 @@
+//! Authority tenancy.
+//!
+//! Each authenticated request gets its own `Repositories` whose conversion
+//! request and data object repositories only see rows inside the caller's
+//! `TenantScope`. Resolvers keep calling `get_repositories_from_context` and
+//! are scoped without knowing it, whichever backend is underneath.
+//!
+//! Listings, counts and full-text search are filtered by the backend through
+//! `ConversionRequestRepo::get_in_scope`, `data_object_ids_in_scope` and
+//! `SearchVisibility`, in SQL for Postgres, before any limit applies. Lookups by id or by a narrower key are filtered after
+//! loading. Lookups of rows outside the scope fail as if the row did not exist.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use async_graphql::{Error, Result};
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::common_utils::{forbidden, Permission, UserRole};
+use crate::models::{
+    ApiKeyAuth, ConversionRequest, DataObject, DataObjectMarking, DataObjectSearchResult, InsertableConversionRequest,
+    InsertableMetadata, Metadata, NewDataObject,
+};
+use crate::repositories::clearance::{Clearance, ClearedDataObjects};
+use crate::repositories::{
+    AuditEntriesFor, CompletedConversion, ConversionRequestRepo, ConversionResultsFor, DataObjectRepo, Repositories,
+    SearchVisibility,
+};
+
+/// Which conversion requests a caller may see, inserted into the GraphQL
+/// context of authenticated requests
+#[derive(Debug, Clone)]
+pub enum TenantScope {
+    /// Callers with Permission::AccessAllAuthorities
+    All,
+    /// Requests submitted by one of `authority_ids`, or from or to one of
+    /// `nation_codes`, the nations of those authorities
+    Members {
+        authority_ids: HashSet<Uuid>,
+        nation_codes: HashSet<String>,
+    },
+}
+
+/// A scope that sees nothing, for callers whose scope is unknown
+impl Default for TenantScope {
+    fn default() -> Self {
+        TenantScope::Members {
+            authority_ids: HashSet::new(),
+            nation_codes: HashSet::new(),
+        }
+    }
+}
+
+impl TenantScope {
+    /// The scope of a user through their authority memberships, or of an API
+    /// key through the authority it was issued for
+    pub async fn for_caller(
+        repos: &Repositories,
+        role: UserRole,
+        user_id: Uuid,
+        api_key: Option<&ApiKeyAuth>,
+    ) -> Result<Self> {
+        let authority_ids: HashSet<Uuid> = match api_key {
+            Some(api_key) => HashSet::from([api_key.authority_id]),
+            None if role.has_permission(Permission::AccessAllAuthorities) => {
+                return Ok(TenantScope::All)
+            }
+            None => repos
+                .authority_members
+                .get_by_user_id(user_id)
+                .await?
+                .into_iter()
+                .map(|m| m.authority_id)
+                .collect(),
+        };
+
+        let mut nation_codes = HashSet::new();
+
+        for authority_id in &authority_ids {
+            let authority = repos.authorities.get_by_id(*authority_id).await?;
+            let nation = repos.nations.get_by_id(authority.nation_id).await?;
+            nation_codes.insert(nation.nation_code);
+        }
+
+        Ok(TenantScope::Members { authority_ids, nation_codes })
+    }
+
+    pub fn allows(&self, request: &ConversionRequest) -> bool {
+        match self {
+            TenantScope::All => true,
+            TenantScope::Members { authority_ids, nation_codes } => {
+                authority_ids.contains(&request.authority_id)
+                    || nation_codes.contains(&request.source_nation_code)
+                    || request
+                        .target_nation_codes
+                        .iter()
+                        .flatten()
+                        .any(|code| nation_codes.contains(code))
+            }
+        }
+    }
+
+    /// Whether the caller may submit conversion requests for an authority
+    pub fn can_act_for(&self, authority_id: Uuid) -> bool {
+        match self {
+            TenantScope::All => true,
+            TenantScope::Members { authority_ids, .. } => authority_ids.contains(&authority_id),
+        }
+    }
+}
+
+impl Repositories {
+    /// These repositories restricted to `scope`, with data objects redacted
+    /// to what `clearance` may read
+    pub fn scoped(&self, scope: &TenantScope, clearance: &Clearance) -> Repositories {
+        let (conversion_requests, data_objects): (Arc<dyn ConversionRequestRepo>, Arc<dyn DataObjectRepo>) =
+            match scope {
+                TenantScope::All => (self.conversion_requests.clone(), self.data_objects.clone()),
+                TenantScope::Members { .. } => {
+                    let scope = Arc::new(scope.clone());
+
+                    (
+                        Arc::new(ScopedConversionRequests {
+                            inner: self.conversion_requests.clone(),
+                            scope: scope.clone(),
+                        }),
+                        Arc::new(ScopedDataObjects {
+                            inner: self.data_objects.clone(),
+                            conversion_requests: self.conversion_requests.clone(),
+                            scope,
+                        }),
+                    )
+                }
+            };
+
+        Repositories {
+            conversion_requests,
+            data_objects: Arc::new(ClearedDataObjects {
+                inner: data_objects,
+                conversion_requests: self.conversion_requests.clone(),
+                clearance: Arc::new(clearance.clone()),
+            }),
+            ..self.clone()
+        }
+    }
+}
+
+fn not_found() -> Error {
+    Error::new("Record not found")
+}
+
+struct ScopedConversionRequests {
+    inner: Arc<dyn ConversionRequestRepo>,
+    scope: Arc<TenantScope>,
+}
+
+impl ScopedConversionRequests {
+    fn keep(&self, requests: Vec<ConversionRequest>) -> Vec<ConversionRequest> {
+        requests.into_iter().filter(|r| self.scope.allows(r)).collect()
+    }
+}
+
+#[async_trait]
+impl ConversionRequestRepo for ScopedConversionRequests {
+    async fn process_payload(&self, payload: InsertableConversionRequest) -> Result<ConversionRequest> {
+        if !self.scope.can_act_for(payload.authority_id) {
+            return Err(forbidden("Not a member of this authority"));
+        }
+
+        self.inner.process_payload(payload).await
+    }
+
+    async fn process_payload_audited(
+        &self,
+        payload: InsertableConversionRequest,
+        audit: AuditEntriesFor<ConversionRequest>,
+    ) -> Result<ConversionRequest> {
+        if !self.scope.can_act_for(payload.authority_id) {
+            return Err(forbidden("Not a member of this authority"));
+        }
+
+        self.inner.process_payload_audited(payload, audit).await
+    }
+
+    async fn process_and_complete(
+        &self,
+        payload: InsertableConversionRequest,
+        convert: ConversionResultsFor,
+        audit: AuditEntriesFor<CompletedConversion>,
+    ) -> Result<CompletedConversion> {
+        if !self.scope.can_act_for(payload.authority_id) {
+            return Err(forbidden("Not a member of this authority"));
+        }
+
+        self.inner.process_and_complete(payload, convert, audit).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<ConversionRequest> {
+        let request = self.inner.get_by_id(id).await?;
+
+        if self.scope.allows(&request) {
+            Ok(request)
+        } else {
+            Err(not_found())
+        }
+    }
+
+    async fn get_by_creator_id(&self, creator_id: Uuid) -> Result<Vec<ConversionRequest>> {
+        Ok(self.keep(self.inner.get_by_creator_id(creator_id).await?))
+    }
+
+    async fn get_by_authority_id(&self, authority_id: Uuid) -> Result<Vec<ConversionRequest>> {
+        Ok(self.keep(self.inner.get_by_authority_id(authority_id).await?))
+    }
+
+    async fn get_by_data_object_id(&self, data_object_id: Uuid) -> Result<Vec<ConversionRequest>> {
+        Ok(self.keep(self.inner.get_by_data_object_id(data_object_id).await?))
+    }
+
+    async fn get_by_source_nation_code(&self, nation_code: String) -> Result<Vec<ConversionRequest>> {
+        Ok(self.keep(self.inner.get_by_source_nation_code(nation_code).await?))
+    }
+
+    async fn get_pending(&self) -> Result<Vec<ConversionRequest>> {
+        Ok(self.keep(self.inner.get_pending().await?))
+    }
+
+    async fn get_completed(&self) -> Result<Vec<ConversionRequest>> {
+        Ok(self.keep(self.inner.get_completed().await?))
+    }
+
+    async fn get_all(&self) -> Result<Vec<ConversionRequest>> {
+        self.get_in_scope(&[], None).await
+    }
+
+    async fn get_limited(&self, count: i64) -> Result<Vec<ConversionRequest>> {
+        self.get_in_scope(&[], Some(count)).await
+    }
+
+    async fn get_in_scope(&self, scopes: &[TenantScope], limit: Option<i64>) -> Result<Vec<ConversionRequest>> {
+        let mut scopes = scopes.to_vec();
+        scopes.push((*self.scope).clone());
+
+        self.inner.get_in_scope(&scopes, limit).await
+    }
+
+    async fn data_object_ids_in_scope(
+        &self,
+        scope: &TenantScope,
+        data_object_ids: Option<Vec<Uuid>>,
+    ) -> Result<HashSet<Uuid>> {
+        let ids = self.inner.data_object_ids_in_scope(scope, data_object_ids).await?;
+
+        self.inner
+            .data_object_ids_in_scope(&self.scope, Some(ids.into_iter().collect()))
+            .await
+    }
+
+    async fn get_markings(&self, data_object_ids: Vec<Uuid>) -> Result<HashMap<Uuid, DataObjectMarking>> {
+        let visible = self.inner.data_object_ids_in_scope(&self.scope, Some(data_object_ids)).await?;
+        self.inner.get_markings(visible.into_iter().collect()).await
+    }
+}
+
+/// Data objects are visible through the conversion requests that carry them
+struct ScopedDataObjects {
+    inner: Arc<dyn DataObjectRepo>,
+    /// Unscoped, to find the requests carrying a data object
+    conversion_requests: Arc<dyn ConversionRequestRepo>,
+    scope: Arc<TenantScope>,
+}
+
+impl ScopedDataObjects {
+    /// Which of `ids` are visible, or every visible id when None
+    async fn visible_ids(&self, ids: Option<Vec<Uuid>>) -> Result<HashSet<Uuid>> {
+        self.conversion_requests.data_object_ids_in_scope(&self.scope, ids).await
+    }
+
+    async fn keep(&self, data_objects: Vec<DataObject>) -> Result<Vec<DataObject>> {
+        let visible = self.visible_ids(Some(data_objects.iter().map(|d| d.id).collect())).await?;
+        Ok(data_objects.into_iter().filter(|d| visible.contains(&d.id)).collect())
+    }
+}
+
+#[async_trait]
+impl DataObjectRepo for ScopedDataObjects {
+    async fn create(&self, data_object: NewDataObject) -> Result<DataObject> {
+        self.inner.create(data_object).await
+    }
+
+    async fn create_with_metadata(
+        &self,
+        data_object: NewDataObject,
+        metadata: InsertableMetadata,
+    ) -> Result<(DataObject, Metadata)> {
+        self.inner.create_with_metadata(data_object, metadata).await
+    }
+
+    async fn get_by_id(&self, id: Uuid) -> Result<DataObject> {
+        if self.visible_ids(Some(vec![id])).await?.contains(&id) {
+            self.inner.get_by_id(id).await
+        } else {
+            Err(not_found())
+        }
+    }
+
+    async fn get_by_ids(&self, ids: Vec<Uuid>) -> Result<Vec<DataObject>> {
+        self.keep(self.inner.get_by_ids(ids).await?).await
+    }
+
+    async fn get_by_title(&self, title: String) -> Result<Vec<DataObject>> {
+        self.keep(self.inner.get_by_title(title).await?).await
+    }
+
+    async fn get_by_content_digest(&self, content_digest: String) -> Result<Vec<DataObject>> {
+        self.keep(self.inner.get_by_content_digest(content_digest).await?).await
+    }
+
+    async fn search(
+        &self,
+        query: String,
+        domains: Option<Vec<String>>,
+        tags: Option<Vec<String>>,
+        mut visibility: SearchVisibility,
+    ) -> Result<Vec<DataObjectSearchResult>> {
+        visibility.scopes.push((*self.scope).clone());
+        self.inner.search(query, domains, tags, visibility).await
+    }
+
+    async fn get_all(&self) -> Result<Vec<DataObject>> {
+        let visible = self.visible_ids(None).await?;
+        self.inner.get_by_ids(visible.into_iter().collect()).await
+    }
+
+    async fn count(&self) -> Result<i64> {
+        Ok(self.visible_ids(None).await?.len() as i64)
+    }
+
+    async fn is_redacted(&self, id: Uuid) -> Result<bool> {
+        self.inner.is_redacted(id).await
+    }
+}
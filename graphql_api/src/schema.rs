@@ -1,5 +1,61 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tsvector", schema = "pg_catalog"))]
+    pub struct Tsvector;
+}
+
+diesel::table! {
+    api_keys (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        authority_id -> Uuid,
+        #[max_length = 128]
+        name -> Varchar,
+        #[max_length = 16]
+        key_prefix -> Varchar,
+        #[max_length = 64]
+        key_hash -> Varchar,
+        #[max_length = 64]
+        role -> Varchar,
+        scopes -> Array<Nullable<Text>>,
+        created_by -> Uuid,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    audit_log (sequence) {
+        sequence -> Int8,
+        occurred_at -> Timestamp,
+        actor_id -> Nullable<Uuid>,
+        #[max_length = 64]
+        action -> Varchar,
+        #[max_length = 64]
+        entity_type -> Varchar,
+        entity_id -> Nullable<Uuid>,
+        #[max_length = 3]
+        nation_code -> Nullable<Varchar>,
+        detail -> Text,
+        #[max_length = 64]
+        prev_hash -> Varchar,
+        #[max_length = 64]
+        entry_hash -> Varchar,
+    }
+}
+
+diesel::table! {
+    authority_members (user_id, authority_id) {
+        user_id -> Uuid,
+        authority_id -> Uuid,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     authorities (id) {
         id -> Uuid,
@@ -68,6 +124,42 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    conversion_results (id) {
+        id -> Uuid,
+        conversion_request_id -> Uuid,
+        #[max_length = 3]
+        source_nation_code -> Varchar,
+        #[max_length = 128]
+        source_marking -> Varchar,
+        source_schema_id -> Uuid,
+        #[max_length = 32]
+        nato_classification -> Varchar,
+        #[max_length = 3]
+        target_nation_code -> Varchar,
+        #[max_length = 128]
+        target_marking -> Varchar,
+        target_schema_id -> Uuid,
+        created_at -> Timestamp,
+        receipt -> Nullable<Text>,
+        #[max_length = 128]
+        signature -> Nullable<Varchar>,
+        #[max_length = 64]
+        signing_key_id -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Tsvector;
+
+    data_object_search (data_object_id) {
+        data_object_id -> Uuid,
+        document -> Tsvector,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     data_objects (id) {
         id -> Uuid,
@@ -77,6 +169,55 @@ diesel::table! {
         description -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        #[max_length = 128]
+        content_digest -> Nullable<Varchar>,
+        #[max_length = 16]
+        digest_algorithm -> Nullable<Varchar>,
+        content_size -> Nullable<Int8>,
+        #[max_length = 512]
+        storage_key -> Nullable<Varchar>,
+        #[max_length = 128]
+        media_type -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    email_verification_code (id) {
+        id -> Uuid,
+        #[max_length = 128]
+        email_address -> Varchar,
+        #[max_length = 64]
+        activation_code_hash -> Varchar,
+        expires_on -> Timestamp,
+    }
+}
+
+diesel::table! {
+    merkle_anchors (id) {
+        id -> Uuid,
+        #[max_length = 64]
+        root_hash -> Varchar,
+        leaf_count -> Int4,
+        first_audit_sequence -> Nullable<Int8>,
+        last_audit_sequence -> Nullable<Int8>,
+        statement -> Text,
+        #[max_length = 128]
+        signature -> Nullable<Varchar>,
+        #[max_length = 64]
+        signing_key_id -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        published_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    merkle_leaves (anchor_id, position) {
+        anchor_id -> Uuid,
+        position -> Int4,
+        #[max_length = 64]
+        leaf_hash -> Varchar,
+        audit_sequence -> Nullable<Int8>,
+        receipt_id -> Nullable<Uuid>,
     }
 }
 
@@ -92,6 +233,18 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    mfa_challenges (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        #[max_length = 64]
+        challenge_hash -> Varchar,
+        failed_attempts -> Int4,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     nations (id) {
         id -> Uuid,
@@ -105,6 +258,68 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    password_reset_token (id) {
+        id -> Uuid,
+        #[max_length = 128]
+        email_address -> Varchar,
+        #[max_length = 64]
+        reset_token_hash -> Varchar,
+        expires_on -> Timestamp,
+    }
+}
+
+diesel::table! {
+    security_events (id) {
+        id -> Uuid,
+        #[max_length = 32]
+        event_type -> Varchar,
+        user_id -> Nullable<Uuid>,
+        #[max_length = 255]
+        email -> Nullable<Varchar>,
+        #[max_length = 64]
+        ip_address -> Nullable<Varchar>,
+        detail -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    sign_in_throttles (id) {
+        id -> Uuid,
+        #[max_length = 16]
+        kind -> Varchar,
+        #[max_length = 255]
+        subject -> Varchar,
+        failed_attempts -> Int4,
+        first_failed_at -> Timestamp,
+        last_failed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    totp_recovery_codes (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        #[max_length = 64]
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    user_sessions (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        #[max_length = 64]
+        refresh_token_hash -> Varchar,
+        created_at -> Timestamp,
+        last_used_at -> Timestamp,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Uuid,
@@ -120,9 +335,17 @@ diesel::table! {
         access_level -> Varchar,
         created_at -> Timestamp,
         updated_at -> Timestamp,
-        #[max_length = 256]
-        access_key -> Varchar,
         approved_by_user_uid -> Nullable<Uuid>,
+        #[max_length = 3]
+        nationality -> Nullable<Varchar>,
+        #[max_length = 16]
+        account_status -> Varchar,
+        created_by_user_uid -> Nullable<Uuid>,
+        email_verified_at -> Nullable<Timestamp>,
+        #[max_length = 64]
+        totp_secret -> Nullable<Varchar>,
+        totp_enabled_at -> Nullable<Timestamp>,
+        totp_last_step -> Nullable<Int8>,
     }
 }
 
@@ -133,6 +356,10 @@ diesel::table! {
     }
 }
 
+diesel::joinable!(api_keys -> authorities (authority_id));
+diesel::joinable!(api_keys -> valid_roles (role));
+diesel::joinable!(authority_members -> authorities (authority_id));
+diesel::joinable!(authority_members -> users (user_id));
 diesel::joinable!(authorities -> nations (nation_id));
 diesel::joinable!(authorities -> users (creator_id));
 diesel::joinable!(classification_schemas -> authorities (authority_id));
@@ -140,18 +367,40 @@ diesel::joinable!(classification_schemas -> users (creator_id));
 diesel::joinable!(conversion_requests -> authorities (authority_id));
 diesel::joinable!(conversion_requests -> data_objects (data_object_id));
 diesel::joinable!(conversion_requests -> users (creator_id));
+diesel::joinable!(conversion_results -> conversion_requests (conversion_request_id));
+diesel::joinable!(data_object_search -> data_objects (data_object_id));
 diesel::joinable!(data_objects -> users (creator_id));
+diesel::joinable!(merkle_leaves -> conversion_results (receipt_id));
+diesel::joinable!(merkle_leaves -> merkle_anchors (anchor_id));
 diesel::joinable!(metadata -> data_objects (data_object_id));
+diesel::joinable!(mfa_challenges -> users (user_id));
 diesel::joinable!(nations -> users (creator_id));
+diesel::joinable!(security_events -> users (user_id));
+diesel::joinable!(totp_recovery_codes -> users (user_id));
+diesel::joinable!(user_sessions -> users (user_id));
 diesel::joinable!(users -> valid_roles (role));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_log,
+    authority_members,
     authorities,
     classification_schemas,
     conversion_requests,
+    conversion_results,
+    data_object_search,
     data_objects,
+    email_verification_code,
+    merkle_anchors,
+    merkle_leaves,
     metadata,
+    mfa_challenges,
     nations,
+    password_reset_token,
+    security_events,
+    sign_in_throttles,
+    totp_recovery_codes,
+    user_sessions,
     users,
     valid_roles,
 );
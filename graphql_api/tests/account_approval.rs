@@ -0,0 +1,98 @@
+//! `approveUser` needs an administrator other than the account's creator,
+//! except while the creator is the only approved administrator, which is how
+//! a second one is brought in.
+
+use async_graphql::{Request, Response, Value};
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::graphql::create_schema_with_context;
+use graphql_api::models::{AccountStatus, AuditAction};
+use graphql_api::repositories::Repositories;
+
+mod common;
+use common::create_user;
+
+async fn execute(repos: &Repositories, role: UserRole, caller: Uuid, query: &str) -> Response {
+    create_schema_with_context(repos.clone())
+        .execute(Request::new(query).data(role).data(caller))
+        .await
+}
+
+/// Creates a pending administrator as `admin_id` and returns its id
+async fn create_pending(repos: &Repositories, admin_id: Uuid, email: &str) -> Uuid {
+    let query = format!(
+        r#"mutation {{
+            createUser(userData: {{name: "Ada", email: "{}", password: "correct-horse-battery", role: "ADMIN"}}) {{ id }}
+        }}"#,
+        email,
+    );
+    let response = execute(repos, UserRole::Admin, admin_id, &query).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let data = response.data.into_json().unwrap();
+    data["createUser"]["id"].as_str().unwrap().parse().unwrap()
+}
+
+fn approve(id: Uuid) -> String {
+    format!(r#"mutation {{ approveUser(id: "{}") {{ accountStatus approvedByUserUid }} }}"#, id)
+}
+
+fn assert_forbidden(response: &Response) {
+    let code = response.errors[0].extensions.as_ref().and_then(|x| x.get("code"));
+    assert_eq!(code, Some(&Value::from("FORBIDDEN")), "{:?}", response.errors);
+}
+
+#[actix_rt::test]
+async fn the_sole_admin_brings_in_a_second_who_then_approves() {
+    let repos = Repositories::in_memory();
+    let admin = create_user(&repos, "admin@example.org", UserRole::Admin, None, None).await;
+    let second = create_pending(&repos, admin, "ada@example.org").await;
+
+    // The only approved administrator, so the exception applies and is audited
+    let response = execute(&repos, UserRole::Admin, admin, &approve(second)).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(repos.users.get_by_id(second).await.unwrap().approved_by_user_uid, Some(admin));
+
+    let audited = repos.audit_log.get_page(0, 100).await.unwrap();
+    let exception = audited
+        .iter()
+        .find(|e| e.action == AuditAction::BootstrapApproval.to_string())
+        .expect("the bootstrap approval");
+    assert_eq!((exception.actor_id, exception.entity_id), (Some(admin), Some(second)));
+
+    // With two approved, the creator can no longer approve their own accounts
+    let third = create_pending(&repos, admin, "bo@example.org").await;
+    assert_forbidden(&execute(&repos, UserRole::Admin, admin, &approve(third)).await);
+    assert_eq!(repos.users.get_by_id(third).await.unwrap().status(), AccountStatus::Pending);
+
+    let response = execute(&repos, UserRole::Admin, second, &approve(third)).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data.into_json().unwrap()["approveUser"]["approvedByUserUid"], second.to_string());
+}
+
+#[actix_rt::test]
+async fn users_cannot_approve_themselves() {
+    let repos = Repositories::in_memory();
+    let admin = create_user(&repos, "admin@example.org", UserRole::Admin, None, None).await;
+    let user = create_pending(&repos, admin, "ada@example.org").await;
+
+    let response = execute(&repos, UserRole::Admin, user, &approve(user)).await;
+
+    assert_forbidden(&response);
+}
+
+#[actix_rt::test]
+async fn another_admin_approves() {
+    let repos = Repositories::in_memory();
+    let creator = create_user(&repos, "creator@example.org", UserRole::Admin, None, None).await;
+    let approver = create_user(&repos, "approver@example.org", UserRole::Admin, None, None).await;
+    let user = create_pending(&repos, creator, "ada@example.org").await;
+
+    let response = execute(&repos, UserRole::Admin, approver, &approve(user)).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let data = response.data.into_json().unwrap();
+    assert_eq!(data["approveUser"]["accountStatus"], "APPROVED");
+    assert_eq!(data["approveUser"]["approvedByUserUid"], approver.to_string());
+}
@@ -0,0 +1,103 @@
+//! Audit entries and receipts are anchored under separate limits, so a busy
+//! audit trail doesn't keep receipts out of anchors.
+
+use uuid::Uuid;
+
+use graphql_api::anchoring::Anchorer;
+use graphql_api::common_utils::UserRole;
+use graphql_api::models::{
+    merkle_leaf_hash, merkle_root, AuditAction, AuditEntity, NatoClassification, NewAuditEntry, NewAuthority, NewNation,
+};
+use graphql_api::repositories::Repositories;
+
+mod common;
+use common::{classification_schema, conversion_request, conversion_result, create_user};
+
+/// Appends `count` audit entries
+async fn audit(repos: &Repositories, count: usize) {
+    let entries = (0..count)
+        .map(|_| NewAuditEntry::new(None, AuditAction::SignInFailed, AuditEntity::User, None))
+        .collect();
+
+    repos.audit_log.append(entries).await.unwrap();
+}
+
+/// Completes a conversion request with `count` results and returns their ids
+/// and receipts
+async fn convert(repos: &Repositories, count: usize) -> Vec<(Uuid, String)> {
+    let user = create_user(repos, &format!("{}@example.org", Uuid::new_v4()), UserRole::Operator, None, None).await;
+
+    let nation = repos.nations.create(NewNation::new(user, "GBR".to_owned(), "United Kingdom".to_owned())).await.unwrap();
+    let authority = repos
+        .authorities
+        .create(NewAuthority::new(user, nation.id, "UK NSA".to_owned(), "nsa@example.org".to_owned(), "0".to_owned(), None))
+        .await
+        .unwrap()
+        .id;
+
+    let source_schema = repos.schemas.create(classification_schema(user, authority, "GBR")).await.unwrap().id;
+    let target_schema = repos.schemas.create(classification_schema(user, authority, "USA")).await.unwrap().id;
+
+    let request = repos
+        .conversion_requests
+        .process_payload(conversion_request(user, authority, "plan", "GBR", "USA"))
+        .await
+        .unwrap();
+
+    let results = (0..count)
+        .map(|_| conversion_result(request.id, source_schema, target_schema, NatoClassification::Secret))
+        .collect();
+
+    let (_, results) = repos.conversion_results.complete_request(request.id, results).await.unwrap();
+
+    results.into_iter().map(|r| (r.id, r.receipt.unwrap())).collect()
+}
+
+#[actix_rt::test]
+async fn receipts_are_anchored_when_audit_entries_fill_their_limit() {
+    let repos = Repositories::in_memory();
+    audit(&repos, 5).await;
+    let receipts = convert(&repos, 3).await;
+
+    let anchor = repos.merkle_anchors.create_batch(2, 2).await.unwrap().unwrap();
+    assert_eq!(anchor.leaf_count, 4);
+    assert_eq!((anchor.first_audit_sequence, anchor.last_audit_sequence), (Some(1), Some(2)));
+    assert_eq!(anchor.audit_entry_count(), 2);
+
+    // The oldest receipts, after the audit entries
+    let leaves = repos.merkle_anchors.get_leaves(anchor.id).await.unwrap();
+    let anchored: Vec<Option<Uuid>> = leaves.iter().map(|l| l.receipt_id).collect();
+    assert_eq!(anchored, vec![None, None, Some(receipts[0].0), Some(receipts[1].0)]);
+
+    let hashes: Vec<String> = leaves.iter().map(|l| l.leaf_hash.clone()).collect();
+    assert_eq!(merkle_root(&hashes).as_ref(), Some(&anchor.root_hash));
+
+    let anchor = repos.merkle_anchors.create_batch(2, 2).await.unwrap().unwrap();
+    assert_eq!((anchor.first_audit_sequence, anchor.last_audit_sequence), (Some(3), Some(4)));
+    assert_eq!(anchor.leaf_count, 3);
+
+    let (id, receipt) = &receipts[2];
+    let leaf = repos.merkle_anchors.get_leaf_by_receipt_id(*id).await.unwrap().unwrap();
+    assert_eq!(leaf.anchor_id, anchor.id);
+    assert_eq!(leaf.leaf_hash, merkle_leaf_hash(receipt.as_bytes()));
+
+    let anchor = repos.merkle_anchors.create_batch(2, 2).await.unwrap().unwrap();
+    assert_eq!(anchor.leaf_count, 1);
+
+    assert!(repos.merkle_anchors.create_batch(2, 2).await.unwrap().is_none());
+}
+
+#[actix_rt::test]
+async fn anchorer_anchors_audit_entries_and_receipts_together() {
+    let repos = Repositories::in_memory();
+    audit(&repos, 3).await;
+    let receipts = convert(&repos, 2).await;
+
+    let anchors = Anchorer::new(repos.clone(), None).run_once().await.unwrap();
+    assert_eq!(anchors.len(), 1);
+    assert_eq!(anchors[0].leaf_count, 5);
+
+    for (id, _) in receipts {
+        assert!(repos.merkle_anchors.get_leaf_by_receipt_id(id).await.unwrap().is_some());
+    }
+}
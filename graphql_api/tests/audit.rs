@@ -0,0 +1,114 @@
+//! Conversion submissions are written to the audit log in the same unit as
+//! the request they record, so neither is kept without the other, and
+//! `process_and_complete` keeps a request only with its results.
+
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::models::{AuditAction, AuditEntry, NatoClassification, NewAuthority, NewNation};
+use graphql_api::repositories::Repositories;
+
+mod common;
+use common::{classification_schema, conversion_request, conversion_result, create_user};
+
+async fn submissions(repos: &Repositories) -> Vec<AuditEntry> {
+    repos
+        .audit_log
+        .get_page(0, 100)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|e| e.action == AuditAction::ConversionSubmitted.to_string())
+        .collect()
+}
+
+/// An operator and the authority they submit for
+async fn authority(repos: &Repositories) -> (Uuid, Uuid) {
+    let operator = create_user(repos, "op@example.org", UserRole::Operator, None, None).await;
+    let nation = repos.nations.create(NewNation::new(operator, "GBR".to_owned(), "United Kingdom".to_owned())).await.unwrap();
+    let authority = repos
+        .authorities
+        .create(NewAuthority::new(operator, nation.id, "UK NSA".to_owned(), "nsa@example.org".to_owned(), "0".to_owned(), None))
+        .await
+        .unwrap()
+        .id;
+
+    (operator, authority)
+}
+
+#[actix_rt::test]
+async fn submissions_are_recorded_with_the_request() {
+    let repos = Repositories::in_memory();
+    let (operator, authority) = authority(&repos).await;
+
+    let audited = repos.audited(Some(operator));
+    let payload = conversion_request(operator, authority, "plan", "GBR", "USA");
+    let request = audited.conversion_requests.process_payload(payload).await.unwrap();
+
+    let entries = submissions(&repos).await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].entity_id, Some(request.id));
+    assert_eq!(entries[0].actor_id, Some(operator));
+    assert_eq!(entries[0].nation_code.as_deref(), Some("GBR"));
+}
+
+#[actix_rt::test]
+async fn rejected_submissions_leave_no_entry() {
+    let repos = Repositories::in_memory();
+    let operator = create_user(&repos, "op@example.org", UserRole::Operator, None, None).await;
+
+    let audited = repos.audited(Some(operator));
+    let payload = conversion_request(operator, Uuid::new_v4(), "plan", "GBR", "USA");
+    assert!(audited.conversion_requests.process_payload(payload).await.is_err());
+
+    assert!(submissions(&repos).await.is_empty());
+    assert!(repos.conversion_requests.get_all().await.unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn requests_are_kept_with_their_results_and_entries() {
+    let repos = Repositories::in_memory();
+    let (operator, authority) = authority(&repos).await;
+    let source = repos.schemas.create(classification_schema(operator, authority, "GBR")).await.unwrap().id;
+    let target = repos.schemas.create(classification_schema(operator, authority, "USA")).await.unwrap().id;
+
+    let audited = repos.audited(Some(operator));
+    let (request, results) = audited
+        .conversion_requests
+        .process_and_complete(
+            conversion_request(operator, authority, "plan", "GBR", "USA"),
+            Box::new(move |r| vec![conversion_result(r.id, source, target, NatoClassification::Secret)]),
+            Box::new(|_| Vec::new()),
+        )
+        .await
+        .unwrap();
+
+    assert!(request.completed_at.is_some());
+    assert_eq!(repos.conversion_results.get_by_conversion_request_id(request.id).await.unwrap().len(), 1);
+
+    let actions: Vec<String> = repos.audit_log.get_page(0, 100).await.unwrap().into_iter().map(|e| e.action).collect();
+    assert_eq!(actions, vec![AuditAction::ConversionSubmitted.to_string(), AuditAction::ConversionCompleted.to_string()]);
+    assert_eq!(results[0].conversion_request_id, request.id);
+}
+
+#[actix_rt::test]
+async fn requests_whose_results_fail_are_not_kept() {
+    let repos = Repositories::in_memory();
+    let (operator, authority) = authority(&repos).await;
+
+    // No schemas to issue the receipt against
+    let audited = repos.audited(Some(operator));
+    let completed = audited
+        .conversion_requests
+        .process_and_complete(
+            conversion_request(operator, authority, "plan", "GBR", "USA"),
+            Box::new(|r| vec![conversion_result(r.id, Uuid::new_v4(), Uuid::new_v4(), NatoClassification::Secret)]),
+            Box::new(|_| Vec::new()),
+        )
+        .await;
+    assert!(completed.is_err());
+
+    assert!(repos.conversion_requests.get_all().await.unwrap().is_empty());
+    assert!(repos.data_objects.get_all().await.unwrap().is_empty());
+    assert!(repos.audit_log.get_page(0, 100).await.unwrap().is_empty());
+}
@@ -0,0 +1,321 @@
+//! Data objects above the caller's clearance, or not released to their
+//! nation, come back redacted and are left out of lookups by content.
+//!
+//! A British authority converts a SECRET and a RESTRICTED plan from GBR to
+//! USA, and leaves a third plan unconverted. Callers read them through
+//! `Repositories::scoped` with every authority in scope, so only clearance
+//! applies.
+
+use std::time::Duration;
+
+use async_graphql::Request;
+use futures::StreamExt;
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::config_variables::SEARCH_RESULT_LIMIT;
+use graphql_api::graphql::{create_schema_with_context, ConversionBroker};
+use graphql_api::models::{
+    ConversionCompleted, ConversionRequest, ConversionResult, DigestAlgorithm, NatoClassification, NewAuthority, NewNation,
+};
+use graphql_api::repositories::{Clearance, Repositories, SearchVisibility, TenantScope, REDACTED_TITLE};
+
+mod common;
+use common::{classification_schema, conversion_request, conversion_result, create_user};
+
+struct Fixture {
+    repos: Repositories,
+    secret: Uuid,
+    restricted: Uuid,
+    unconverted: Uuid,
+    /// The completed requests and their results, as `conversionCompleted`
+    /// publishes them
+    completed: Vec<ConversionCompleted>,
+}
+
+async fn fixture() -> Fixture {
+    let repos = Repositories::in_memory();
+
+    let creator = create_user(&repos, "creator@example.org", UserRole::Operator, Some(NatoClassification::TopSecret), Some("GBR")).await;
+
+    let gbr = repos.nations.create(NewNation::new(creator, "GBR".to_owned(), "United Kingdom".to_owned())).await.unwrap();
+    repos.nations.create(NewNation::new(creator, "USA".to_owned(), "United States".to_owned())).await.unwrap();
+
+    let authority = repos
+        .authorities
+        .create(NewAuthority::new(creator, gbr.id, "UK NSA".to_owned(), "nsa@example.org".to_owned(), "0".to_owned(), None))
+        .await
+        .unwrap()
+        .id;
+
+    let source_schema = repos.schemas.create(classification_schema(creator, authority, "GBR")).await.unwrap().id;
+    let target_schema = repos.schemas.create(classification_schema(creator, authority, "USA")).await.unwrap().id;
+
+    let submit = |title: &'static str| {
+        let repos = repos.clone();
+        let mut payload = conversion_request(creator, authority, title, "GBR", "USA");
+        // Only the secret plan came with a document
+        if title == "secret plan" {
+            let data_object = payload.data_object.as_mut().unwrap();
+            data_object.content_digest = Some("ab".repeat(32));
+            data_object.digest_algorithm = Some(DigestAlgorithm::Sha256);
+            data_object.content_size = Some(1024);
+        }
+        async move { repos.conversion_requests.process_payload(payload).await.unwrap() }
+    };
+
+    let convert = |request: ConversionRequest, level: NatoClassification| {
+        let repos = repos.clone();
+        let request_id = request.id;
+        async move {
+            let (conversion_request, results): (ConversionRequest, Vec<ConversionResult>) = repos
+                .conversion_results
+                .complete_request(
+                    request_id,
+                    vec![conversion_result(request_id, source_schema, target_schema, level)],
+                )
+                .await
+                .unwrap();
+
+            ConversionCompleted { conversion_request, results }
+        }
+    };
+
+    let secret = submit("secret plan").await;
+    let restricted = submit("restricted plan").await;
+    let unconverted = submit("unconverted plan").await;
+
+    let completed = vec![
+        convert(secret.clone(), NatoClassification::Secret).await,
+        convert(restricted.clone(), NatoClassification::Restricted).await,
+    ];
+
+    Fixture {
+        secret: secret.data_object_id,
+        restricted: restricted.data_object_id,
+        unconverted: unconverted.data_object_id,
+        completed,
+        repos,
+    }
+}
+
+fn cleared(fixture: &Fixture, level: NatoClassification, nationality: &str) -> Repositories {
+    let clearance = Clearance {
+        level,
+        nationality: Some(nationality.to_owned()),
+    };
+
+    fixture.repos.scoped(&TenantScope::All, &clearance)
+}
+
+#[actix_rt::test]
+async fn objects_above_clearance_are_redacted() {
+    let fixture = fixture().await;
+    let repos = cleared(&fixture, NatoClassification::Confidential, "GBR");
+
+    let secret = repos.data_objects.get_by_id(fixture.secret).await.unwrap();
+    assert_eq!(secret.title, REDACTED_TITLE);
+    assert_eq!(secret.description, "[REDACTED: SECRET exceeds your clearance]");
+    assert_eq!(secret.content_digest, None);
+    assert_eq!(secret.digest_algorithm, None);
+    assert_eq!(secret.content_size, None);
+    assert!(repos.data_objects.is_redacted(fixture.secret).await.unwrap());
+    assert!(!repos.data_objects.is_redacted(fixture.restricted).await.unwrap());
+
+    let restricted = repos.data_objects.get_by_id(fixture.restricted).await.unwrap();
+    assert_eq!(restricted.title, "restricted plan");
+
+    // Unconverted objects count as TOP_SECRET
+    let unconverted = repos.data_objects.get_by_id(fixture.unconverted).await.unwrap();
+    assert_eq!(unconverted.title, REDACTED_TITLE);
+
+    let titles: Vec<(Uuid, String)> = repos
+        .data_objects
+        .get_all()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|d| (d.id, d.title))
+        .collect();
+    assert_eq!(titles.len(), 3);
+    for (id, title) in titles {
+        assert_eq!(title == REDACTED_TITLE, id != fixture.restricted, "{}", title);
+    }
+}
+
+#[actix_rt::test]
+async fn objects_above_clearance_are_dropped_from_title_search() {
+    let fixture = fixture().await;
+    let repos = cleared(&fixture, NatoClassification::Confidential, "GBR");
+
+    let found: Vec<Uuid> = repos.data_objects.get_by_title("plan".to_owned()).await.unwrap().into_iter().map(|d| d.id).collect();
+    assert_eq!(found, vec![fixture.restricted]);
+
+    assert!(repos.data_objects.get_by_title("secret".to_owned()).await.unwrap().is_empty());
+    assert!(repos.data_objects.get_by_content_digest("ab".repeat(32)).await.unwrap().is_empty());
+
+    let searched: Vec<Uuid> = repos
+        .data_objects
+        .search("plan".to_owned(), None, None, SearchVisibility::default())
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.data_object.id)
+        .collect();
+    assert_eq!(searched, vec![fixture.restricted]);
+}
+
+#[actix_rt::test]
+async fn search_results_are_not_crowded_out_by_unreadable_matches() {
+    let fixture = fixture().await;
+    let repos = cleared(&fixture, NatoClassification::Confidential, "GBR");
+
+    // Newer unconverted plans rank with the restricted one and would fill
+    // the result limit ahead of it
+    let template = fixture.repos.conversion_requests.get_by_data_object_id(fixture.restricted).await.unwrap().remove(0);
+    for _ in 0..SEARCH_RESULT_LIMIT {
+        fixture
+            .repos
+            .conversion_requests
+            .process_payload(conversion_request(template.creator_id, template.authority_id, "plan", "GBR", "USA"))
+            .await
+            .unwrap();
+    }
+
+    let searched: Vec<Uuid> = repos
+        .data_objects
+        .search("plan".to_owned(), None, None, SearchVisibility::default())
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.data_object.id)
+        .collect();
+    assert_eq!(searched, vec![fixture.restricted]);
+}
+
+#[actix_rt::test]
+async fn cleared_callers_read_everything_released_to_them() {
+    let fixture = fixture().await;
+    let repos = cleared(&fixture, NatoClassification::TopSecret, "USA");
+
+    let secret = repos.data_objects.get_by_id(fixture.secret).await.unwrap();
+    assert_eq!(secret.title, "secret plan");
+    assert_eq!(secret.content_digest, Some("ab".repeat(32)));
+
+    assert_eq!(repos.data_objects.get_by_title("plan".to_owned()).await.unwrap().len(), 3);
+}
+
+#[actix_rt::test]
+async fn objects_not_released_to_the_callers_nation_are_redacted() {
+    let fixture = fixture().await;
+    let repos = cleared(&fixture, NatoClassification::TopSecret, "FRA");
+
+    let restricted = repos.data_objects.get_by_id(fixture.restricted).await.unwrap();
+    assert_eq!(restricted.title, REDACTED_TITLE);
+    assert_eq!(restricted.description, "[REDACTED: not releasable to FRA]");
+
+    assert!(repos.data_objects.get_by_title("plan".to_owned()).await.unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn redacted_objects_have_no_metadata() {
+    let fixture = fixture().await;
+    let repos = cleared(&fixture, NatoClassification::Confidential, "GBR");
+
+    let schema = create_schema_with_context(fixture.repos.clone());
+    let execute = |id: Uuid| {
+        let query = format!(r#"{{ dataObjectById(id: "{}") {{ title contentDigest metadata {{ domain }} }} }}"#, id);
+        schema.execute(Request::new(query).data(UserRole::Analyst).data(Uuid::new_v4()).data(repos.clone()))
+    };
+
+    let response = execute(fixture.secret).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let data = response.data.into_json().unwrap();
+    assert_eq!(data["dataObjectById"]["title"], REDACTED_TITLE);
+    assert!(data["dataObjectById"]["contentDigest"].is_null());
+    assert!(data["dataObjectById"]["metadata"].is_null());
+
+    let data = execute(fixture.restricted).await.data.into_json().unwrap();
+    assert_eq!(data["dataObjectById"]["metadata"]["domain"], "OPERATIONS");
+}
+
+#[actix_rt::test]
+async fn conversion_requests_of_redacted_objects_have_no_metadata() {
+    let fixture = fixture().await;
+    let repos = cleared(&fixture, NatoClassification::Confidential, "GBR");
+
+    let schema = create_schema_with_context(fixture.repos.clone());
+    let execute = |id: Uuid| {
+        let query = format!(r#"{{ conversionRequestsByDataObjectId(dataObjectId: "{}") {{ metadata {{ domain }} }} }}"#, id);
+        schema.execute(Request::new(query).data(UserRole::Analyst).data(Uuid::new_v4()).data(repos.clone()))
+    };
+
+    let response = execute(fixture.secret).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let data = response.data.into_json().unwrap();
+    assert!(data["conversionRequestsByDataObjectId"][0]["metadata"].is_null());
+
+    let data = execute(fixture.restricted).await.data.into_json().unwrap();
+    assert_eq!(data["conversionRequestsByDataObjectId"][0]["metadata"]["domain"], "OPERATIONS");
+}
+
+#[actix_rt::test]
+async fn receipts_of_redacted_objects_are_withheld() {
+    let fixture = fixture().await;
+    let repos = cleared(&fixture, NatoClassification::Confidential, "GBR");
+
+    let schema = create_schema_with_context(fixture.repos.clone());
+    let execute = |id: Uuid| {
+        let query = format!(
+            r#"{{ conversionRequestsByDataObjectId(dataObjectId: "{}") {{ results {{ id receipt }} }} }}"#,
+            id,
+        );
+        schema.execute(Request::new(query).data(UserRole::Analyst).data(Uuid::new_v4()).data(repos.clone()))
+    };
+
+    // The receipt states the secret plan's digest and size
+    let response = execute(fixture.secret).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let data = response.data.into_json().unwrap();
+    let result = &data["conversionRequestsByDataObjectId"][0]["results"][0];
+    assert_eq!(result["id"], fixture.completed[0].results[0].id.to_string());
+    assert!(result["receipt"].is_null());
+
+    let data = execute(fixture.restricted).await.data.into_json().unwrap();
+    let receipt = data["conversionRequestsByDataObjectId"][0]["results"][0]["receipt"].as_str().unwrap();
+    assert_eq!(Some(receipt), fixture.completed[1].results[0].receipt.as_deref());
+}
+
+#[actix_rt::test]
+async fn subscribers_only_hear_of_conversions_they_may_read() {
+    let fixture = fixture().await;
+    let repos = cleared(&fixture, NatoClassification::Confidential, "GBR");
+    let broker = ConversionBroker::default();
+
+    let request = Request::new("subscription { conversionCompleted { conversionRequest { dataObjectId } } }")
+        .data(UserRole::Analyst)
+        .data(Uuid::new_v4())
+        .data(repos)
+        .data(TenantScope::All)
+        .data(broker.clone());
+    let schema = create_schema_with_context(fixture.repos.clone());
+
+    // Lets the subscription start listening before anything is published
+    let first = actix_rt::spawn(async move { schema.execute_stream(request).next().await });
+    actix_rt::time::sleep(Duration::from_millis(50)).await;
+
+    for event in fixture.completed {
+        broker.publish(event);
+    }
+
+    let response = actix_rt::time::timeout(Duration::from_secs(5), first)
+        .await
+        .expect("an event is delivered")
+        .unwrap()
+        .unwrap();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    // The SECRET conversion was published first but isn't delivered
+    let data = response.data.into_json().unwrap();
+    assert_eq!(data["conversionCompleted"]["conversionRequest"]["dataObjectId"], fixture.restricted.to_string());
+}
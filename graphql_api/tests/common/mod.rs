@@ -0,0 +1,125 @@
+//! Fixtures shared by the integration tests. Every test binary compiles its
+//! own copy and uses only some of them.
+
+#![allow(dead_code)]
+
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::models::{
+    AccountStatus, InsertableConversionRequest, InsertableDataObject, InsertableMetadata, InsertableUser,
+    NatoClassification, NewClassificationSchema, NewConversionResult, UserData,
+};
+use graphql_api::repositories::Repositories;
+
+/// Password of every user created here
+pub const PASSWORD: &str = "correct-horse-battery";
+
+/// An approved user with `PASSWORD`, named after their email
+pub fn new_user(
+    email: &str,
+    role: UserRole,
+    clearance: Option<NatoClassification>,
+    nationality: Option<&str>,
+) -> InsertableUser {
+    let mut user = InsertableUser::from(UserData {
+        name: email.to_owned(),
+        email: email.to_owned(),
+        password: PASSWORD.to_owned(),
+        role: role.to_string(),
+        clearance,
+        nationality: nationality.map(str::to_owned),
+    });
+    user.account_status = AccountStatus::Approved.to_string();
+
+    user
+}
+
+/// Stores a `new_user` and returns its id
+pub async fn create_user(
+    repos: &Repositories,
+    email: &str,
+    role: UserRole,
+    clearance: Option<NatoClassification>,
+    nationality: Option<&str>,
+) -> Uuid {
+    let user = new_user(email, role, clearance, nationality);
+
+    repos.users.create(user).await.unwrap().id
+}
+
+/// A schema whose markings are the nation code followed by the NATO level,
+/// e.g. "GBR TOP SECRET", both ways
+pub fn classification_schema(creator_id: Uuid, authority_id: Uuid, nation_code: &str) -> NewClassificationSchema {
+    let marking = |level: &str| format!("{} {}", nation_code, level);
+
+    NewClassificationSchema {
+        creator_id,
+        nation_code: nation_code.to_owned(),
+        to_nato_unclassified: marking("UNCLASSIFIED"),
+        to_nato_restricted: marking("RESTRICTED"),
+        to_nato_confidential: marking("CONFIDENTIAL"),
+        to_nato_secret: marking("SECRET"),
+        to_nato_top_secret: marking("TOP SECRET"),
+        from_nato_unclassified: marking("UNCLASSIFIED"),
+        from_nato_restricted: marking("RESTRICTED"),
+        from_nato_confidential: marking("CONFIDENTIAL"),
+        from_nato_secret: marking("SECRET"),
+        from_nato_top_secret: marking("TOP SECRET"),
+        caveats: String::new(),
+        version: "1".to_owned(),
+        authority_id,
+        expires_at: None,
+    }
+}
+
+/// A payload creating a data object titled `title`, without a document, with
+/// OPERATIONS metadata and one target nation
+pub fn conversion_request(
+    user_id: Uuid,
+    authority_id: Uuid,
+    title: &str,
+    source_nation_code: &str,
+    target_nation_code: &str,
+) -> InsertableConversionRequest {
+    InsertableConversionRequest {
+        user_id,
+        authority_id,
+        data_object_id: None,
+        data_object: Some(InsertableDataObject {
+            title: title.to_owned(),
+            description: "the plan".to_owned(),
+            content_digest: None,
+            digest_algorithm: None,
+            content_size: None,
+        }),
+        metadata: Some(InsertableMetadata {
+            domain: "OPERATIONS".to_owned(),
+            tags: Vec::new(),
+        }),
+        source_nation_code: source_nation_code.to_owned(),
+        target_nation_codes: vec![target_nation_code.to_owned()],
+    }
+}
+
+/// A GBR to USA result at `level` between two `classification_schema`s
+pub fn conversion_result(
+    conversion_request_id: Uuid,
+    source_schema_id: Uuid,
+    target_schema_id: Uuid,
+    level: NatoClassification,
+) -> NewConversionResult {
+    NewConversionResult {
+        conversion_request_id,
+        source_nation_code: "GBR".to_owned(),
+        source_marking: format!("GBR {}", level),
+        source_schema_id,
+        nato_classification: level.to_string(),
+        target_nation_code: "USA".to_owned(),
+        target_marking: format!("USA {}", level),
+        target_schema_id,
+        receipt: None,
+        signature: None,
+        signing_key_id: None,
+    }
+}
@@ -0,0 +1,177 @@
+//! Concurrent resolvers don't serialize on blocking storage work.
+//!
+//! The resolvers run over `Repositories::postgres`, so every query goes
+//! through `PostgresRepository::run` and a pooled libpq connection, to a
+//! stand-in server speaking just enough of the Postgres protocol. The server
+//! holds each `nations` query until `QUERIES` of them are in flight at once,
+//! which only happens if the single threaded runtime hands the blocking work
+//! off rather than waiting on each query in turn.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use async_graphql::Request;
+use diesel::r2d2::ConnectionManager;
+use futures::future::join_all;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::database::PostgresPool;
+use graphql_api::graphql::create_schema_with_context;
+use graphql_api::repositories::Repositories;
+
+const QUERIES: usize = 8;
+
+/// How long a query waits for the others before it is answered anyway
+const RENDEZVOUS_WAIT: Duration = Duration::from_secs(2);
+
+/// Queries in flight on the server, and the most there have been at once
+#[derive(Default)]
+struct InFlight {
+    counts: Mutex<(usize, usize)>,
+    changed: Condvar,
+}
+
+impl InFlight {
+    /// Waits until `QUERIES` queries have been in flight together, or
+    /// `RENDEZVOUS_WAIT` passes
+    fn rendezvous(&self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.0 += 1;
+        counts.1 = counts.1.max(counts.0);
+        self.changed.notify_all();
+
+        let (mut counts, _) = self
+            .changed
+            .wait_timeout_while(counts, RENDEZVOUS_WAIT, |(_, peak)| *peak < QUERIES)
+            .unwrap();
+        counts.0 -= 1;
+    }
+
+    fn peak(&self) -> usize {
+        self.counts.lock().unwrap().1
+    }
+}
+
+/// Listens on a free local port, answering every statement with no rows
+fn serve(in_flight: Arc<InFlight>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let in_flight = in_flight.clone();
+            std::thread::spawn(move || {
+                // The client hanging up ends the session
+                let _ = session(stream.unwrap(), &in_flight);
+            });
+        }
+    });
+
+    format!("postgres://test@127.0.0.1:{}/test?sslmode=disable&gssencmode=disable", port)
+}
+
+fn send(stream: &mut TcpStream, tag: u8, body: &[u8]) -> std::io::Result<()> {
+    let mut message = vec![tag];
+    message.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+    message.extend_from_slice(body);
+    stream.write_all(&message)
+}
+
+fn read_body(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+
+    let mut body = vec![0; i32::from_be_bytes(len) as usize - 4];
+    stream.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// The null-terminated strings at the start of `body`
+fn strings(body: &[u8]) -> Vec<String> {
+    body.split(|b| *b == 0).map(|s| String::from_utf8_lossy(s).into_owned()).collect()
+}
+
+fn session(mut stream: TcpStream, in_flight: &InFlight) -> std::io::Result<()> {
+    // Decline SSL and GSS encryption until the startup message arrives
+    while read_body(&mut stream)?[..4] != 196608_i32.to_be_bytes() {
+        stream.write_all(b"N")?;
+    }
+
+    send(&mut stream, b'R', &0_i32.to_be_bytes())?;
+    for (name, value) in [
+        ("server_version", "16.0"),
+        ("client_encoding", "UTF8"),
+        ("standard_conforming_strings", "on"),
+        ("integer_datetimes", "on"),
+    ] {
+        send(&mut stream, b'S', format!("{}\0{}\0", name, value).as_bytes())?;
+    }
+    send(&mut stream, b'K', &[0; 8])?;
+    send(&mut stream, b'Z', b"I")?;
+
+    let mut statements: HashMap<String, String> = HashMap::new();
+    let mut bound = String::new();
+
+    loop {
+        let mut tag = [0];
+        stream.read_exact(&mut tag)?;
+        let body = read_body(&mut stream)?;
+
+        match tag[0] {
+            b'P' => {
+                let parsed = strings(&body);
+                statements.insert(parsed[0].clone(), parsed[1].clone());
+                send(&mut stream, b'1', &[])?;
+            }
+            b'B' => {
+                bound = statements.get(&strings(&body)[1]).cloned().unwrap_or_default();
+                send(&mut stream, b'2', &[])?;
+            }
+            b'D' => {
+                if body[0] == b'S' {
+                    send(&mut stream, b't', &0_i16.to_be_bytes())?;
+                }
+                send(&mut stream, b'n', &[])?;
+            }
+            b'E' => {
+                if bound.contains("\"nations\"") {
+                    in_flight.rendezvous();
+                }
+                send(&mut stream, b'C', b"SELECT 0\0")?;
+            }
+            b'Q' => {
+                send(&mut stream, b'C', b"SELECT 0\0")?;
+                send(&mut stream, b'Z', b"I")?;
+            }
+            b'C' => send(&mut stream, b'3', &[])?,
+            b'S' => send(&mut stream, b'Z', b"I")?,
+            b'X' => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn postgres_queries_run_concurrently() {
+    let in_flight = Arc::new(InFlight::default());
+
+    let pool = PostgresPool::builder()
+        .max_size(QUERIES as u32)
+        .build(ConnectionManager::new(serve(in_flight.clone())))
+        .unwrap();
+    let schema = create_schema_with_context(Repositories::postgres(pool));
+
+    let responses = join_all((0..QUERIES).map(|_| {
+        schema.execute(Request::new("{ nations { nationCode } }").data(UserRole::User))
+    }))
+    .await;
+
+    for response in responses {
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+    }
+
+    assert_eq!(in_flight.peak(), QUERIES, "queries were not in flight together");
+}
@@ -0,0 +1,110 @@
+//! `conversionCompleted` delivers the events matching its `authorityId` and
+//! `nationCode` filters and drops the rest.
+//!
+//! Three conversions are published: one submitted by a British authority for
+//! the USA, and two by a French authority for Germany and for Italy.
+
+use std::time::Duration;
+
+use async_graphql::Request;
+use chrono::Utc;
+use futures::StreamExt;
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::graphql::{create_schema_with_context, ConversionBroker};
+use graphql_api::models::{ConversionCompleted, ConversionRequest};
+use graphql_api::repositories::{Repositories, TenantScope};
+
+const GBR_AUTHORITY: Uuid = Uuid::from_u128(1);
+const FRA_AUTHORITY: Uuid = Uuid::from_u128(2);
+
+fn completed(authority_id: Uuid, source: &str, target: &str) -> ConversionCompleted {
+    let now = Utc::now().naive_utc();
+
+    ConversionCompleted {
+        conversion_request: ConversionRequest {
+            id: Uuid::new_v4(),
+            creator_id: Uuid::new_v4(),
+            authority_id,
+            data_object_id: Uuid::new_v4(),
+            source_nation_code: source.to_owned(),
+            target_nation_codes: vec![Some(target.to_owned())],
+            created_at: now,
+            updated_at: now,
+            completed_at: Some(now),
+        },
+        results: Vec::new(),
+    }
+}
+
+/// Subscribes with `arguments`, publishes every event and returns the target
+/// nation of each event delivered
+async fn delivered(arguments: &str) -> Vec<String> {
+    let broker = ConversionBroker::default();
+    let schema = create_schema_with_context(Repositories::in_memory());
+
+    let query = format!(
+        "subscription {{ conversionCompleted{} {{ conversionRequest {{ targetNationCodes }} }} }}",
+        arguments
+    );
+    let request = Request::new(query)
+        .data(UserRole::Analyst)
+        .data(Uuid::new_v4())
+        .data(TenantScope::All)
+        .data(broker.clone());
+
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let listener = actix_rt::spawn(async move {
+        let mut stream = schema.execute_stream(request);
+        while let Some(response) = stream.next().await {
+            if sender.send(response).is_err() {
+                break;
+            }
+        }
+    });
+
+    // Lets the subscription start listening before anything is published
+    actix_rt::time::sleep(Duration::from_millis(50)).await;
+
+    broker.publish(completed(GBR_AUTHORITY, "GBR", "USA"));
+    broker.publish(completed(FRA_AUTHORITY, "FRA", "DEU"));
+    broker.publish(completed(FRA_AUTHORITY, "FRA", "ITA"));
+
+    let mut targets = Vec::new();
+    while let Ok(Some(response)) = actix_rt::time::timeout(Duration::from_millis(200), receiver.recv()).await {
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+        let data = response.data.into_json().unwrap();
+        targets.push(data["conversionCompleted"]["conversionRequest"]["targetNationCodes"][0].as_str().unwrap().to_owned());
+    }
+    listener.abort();
+
+    targets
+}
+
+#[actix_rt::test]
+async fn without_filters_every_event_is_delivered() {
+    assert_eq!(delivered("").await, vec!["USA", "DEU", "ITA"]);
+}
+
+#[actix_rt::test]
+async fn authority_filter_delivers_only_that_authoritys_requests() {
+    assert_eq!(delivered(&format!(r#"(authorityId: "{}")"#, GBR_AUTHORITY)).await, vec!["USA"]);
+    assert_eq!(delivered(&format!(r#"(authorityId: "{}")"#, FRA_AUTHORITY)).await, vec!["DEU", "ITA"]);
+    assert!(delivered(&format!(r#"(authorityId: "{}")"#, Uuid::new_v4())).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn nation_filter_delivers_only_conversions_targeting_that_nation() {
+    assert_eq!(delivered(r#"(nationCode: "DEU")"#).await, vec!["DEU"]);
+    // The source nation isn't a target
+    assert!(delivered(r#"(nationCode: "FRA")"#).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn events_matching_either_filter_are_delivered() {
+    let arguments = format!(r#"(authorityId: "{}", nationCode: "ITA")"#, GBR_AUTHORITY);
+
+    assert_eq!(delivered(&arguments).await, vec!["USA", "ITA"]);
+}
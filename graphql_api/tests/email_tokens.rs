@@ -0,0 +1,104 @@
+//! The tokens of `resetPassword` and `verifyEmail` work once and only until
+//! they expire, and changing a user's email drops the resets sent to the old
+//! address.
+
+use async_graphql::Request;
+use chrono::{Duration, Utc};
+use tera::Tera;
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::graphql::create_schema_with_context;
+use graphql_api::mail::Mailer;
+use graphql_api::models::{InsertablePasswordResetToken, InsertableVerification};
+use graphql_api::repositories::Repositories;
+
+mod common;
+use common::create_user;
+
+/// Runs `query` with a mailer that sends nothing and returns its errors
+async fn errors(repos: &Repositories, caller: Option<(UserRole, Uuid)>, query: &str) -> Vec<String> {
+    let mailer = Mailer::new(None, "converter@example.org".parse().unwrap(), Tera::default());
+    let mut request = Request::new(query).data(mailer);
+    if let Some((role, id)) = caller {
+        request = request.data(role).data(id);
+    }
+
+    let response = create_schema_with_context(repos.clone()).execute(request).await;
+
+    response.errors.into_iter().map(|e| e.message).collect()
+}
+
+fn reset_password(token: &str) -> String {
+    format!(r#"mutation {{ resetPassword(token: "{}", newPassword: "another-horse-battery") }}"#, token)
+}
+
+fn verify_email(token: &str) -> String {
+    format!(r#"mutation {{ verifyEmail(token: "{}") }}"#, token)
+}
+
+#[actix_rt::test]
+async fn a_reset_token_works_once() {
+    let repos = Repositories::in_memory();
+    create_user(&repos, "ada@example.org", UserRole::User, None, None).await;
+
+    let (reset, token) = InsertablePasswordResetToken::new("ada@example.org");
+    repos.password_resets.create(reset).await.unwrap();
+
+    assert!(errors(&repos, None, &reset_password(&token)).await.is_empty());
+    assert_eq!(errors(&repos, None, &reset_password(&token)).await, vec!["Invalid or expired reset token"]);
+}
+
+#[actix_rt::test]
+async fn an_expired_reset_token_is_refused() {
+    let repos = Repositories::in_memory();
+    create_user(&repos, "ada@example.org", UserRole::User, None, None).await;
+
+    let (mut reset, token) = InsertablePasswordResetToken::new("ada@example.org");
+    reset.expires_on = Utc::now().naive_utc() - Duration::seconds(1);
+    repos.password_resets.create(reset).await.unwrap();
+
+    assert_eq!(errors(&repos, None, &reset_password(&token)).await, vec!["Invalid or expired reset token"]);
+}
+
+#[actix_rt::test]
+async fn a_verification_token_works_once() {
+    let repos = Repositories::in_memory();
+    create_user(&repos, "ada@example.org", UserRole::User, None, None).await;
+
+    let (verification, token) = InsertableVerification::new("ada@example.org");
+    repos.email_verifications.create(verification).await.unwrap();
+
+    assert!(errors(&repos, None, &verify_email(&token)).await.is_empty());
+    assert_eq!(errors(&repos, None, &verify_email(&token)).await, vec!["Invalid or expired verification token"]);
+}
+
+#[actix_rt::test]
+async fn an_expired_verification_token_is_refused() {
+    let repos = Repositories::in_memory();
+    create_user(&repos, "ada@example.org", UserRole::User, None, None).await;
+
+    let (mut verification, token) = InsertableVerification::new("ada@example.org");
+    verification.expires_on = Utc::now().naive_utc() - Duration::seconds(1);
+    repos.email_verifications.create(verification).await.unwrap();
+
+    assert_eq!(errors(&repos, None, &verify_email(&token)).await, vec!["Invalid or expired verification token"]);
+
+    let user = repos.users.get_by_email("ada@example.org".to_owned()).await.unwrap();
+    assert!(user.email_verified_at.is_none());
+}
+
+#[actix_rt::test]
+async fn changing_the_email_drops_pending_resets() {
+    let repos = Repositories::in_memory();
+    let admin = create_user(&repos, "admin@example.org", UserRole::Admin, None, None).await;
+    let user = create_user(&repos, "ada@example.org", UserRole::User, None, None).await;
+
+    let (reset, token) = InsertablePasswordResetToken::new("ada@example.org");
+    repos.password_resets.create(reset).await.unwrap();
+
+    let update = format!(r#"mutation {{ updateUser(userData: {{id: "{}", email: "ada@example.net"}}) {{ id }} }}"#, user);
+    assert!(errors(&repos, Some((UserRole::Admin, admin)), &update).await.is_empty());
+
+    assert_eq!(errors(&repos, None, &reset_password(&token)).await, vec!["Invalid or expired reset token"]);
+}
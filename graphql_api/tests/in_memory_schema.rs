@@ -0,0 +1,100 @@
+//! The schema runs over injected repositories, with no DATABASE_URL and no
+//! global pool: a mutation and a query through `Schema::execute` against
+//! `Repositories::in_memory()`, and the same store read back directly.
+
+use async_graphql::Request;
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::graphql::create_schema_with_context;
+use graphql_api::models::NewDataObject;
+use graphql_api::repositories::{Repositories, SearchVisibility};
+
+/// Runs `query` as a signed-in admin and returns the data, failing on errors
+async fn execute_as_admin(repos: &Repositories, admin_id: Uuid, query: &str) -> serde_json::Value {
+    let schema = create_schema_with_context(repos.clone());
+
+    let response = schema
+        .execute(Request::new(query).data(UserRole::Admin).data(admin_id))
+        .await;
+
+    assert!(response.errors.is_empty(), "{}: {:?}", query, response.errors);
+
+    response.data.into_json().expect("GraphQL data is JSON")
+}
+
+#[actix_rt::test]
+async fn mutation_and_query_run_against_in_memory_repositories() {
+    let repos = Repositories::in_memory();
+    let admin_id = Uuid::new_v4();
+
+    let created = execute_as_admin(
+        &repos,
+        admin_id,
+        r#"mutation {
+            createUser(userData: {name: "Ada", email: "ada@example.org", password: "correct-horse-battery", role: "ANALYST"}) {
+                id email role
+            }
+        }"#,
+    )
+    .await;
+
+    let user = &created["createUser"];
+    assert_eq!(user["email"], "ada@example.org");
+    assert_eq!(user["role"], "ANALYST");
+
+    let found = execute_as_admin(&repos, admin_id, r#"{ userByEmail(email: "ada@example.org") { id name } }"#).await;
+    assert_eq!(found["userByEmail"]["id"], user["id"]);
+    assert_eq!(found["userByEmail"]["name"], "Ada");
+
+    // The schema wrote to the store the test injected
+    let stored = repos
+        .users
+        .get_by_email("ada@example.org".to_owned())
+        .await
+        .expect("user is in the injected store");
+    assert_eq!(serde_json::json!(stored.id), user["id"]);
+}
+
+#[actix_rt::test]
+async fn separate_in_memory_repositories_do_not_share_data() {
+    let first = Repositories::in_memory();
+    let second = Repositories::in_memory();
+    let admin_id = Uuid::new_v4();
+
+    execute_as_admin(
+        &first,
+        admin_id,
+        r#"mutation {
+            createUser(userData: {name: "Bo", email: "bo@example.org", password: "correct-horse-battery", role: "USER"}) { id }
+        }"#,
+    )
+    .await;
+
+    let users = execute_as_admin(&second, admin_id, "{ allUsers { email } }").await;
+    assert_eq!(users["allUsers"], serde_json::json!([]));
+}
+
+#[actix_rt::test]
+async fn search_highlights_escape_the_object_text() {
+    let repos = Repositories::in_memory();
+    let title = r#"Plan <img src=x onerror="alert('x')"> & more"#;
+    repos
+        .data_objects
+        .create(NewDataObject::new(Uuid::new_v4(), title.to_owned(), "<b>plan</b>".to_owned()))
+        .await
+        .unwrap();
+
+    let results = repos
+        .data_objects
+        .search("plan".to_owned(), None, None, SearchVisibility::default())
+        .await
+        .unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[0].title_highlight,
+        "<mark>Plan</mark> &lt;img src=x onerror=&quot;alert(&#39;x&#39;)&quot;&gt; &amp; more",
+    );
+    assert_eq!(results[0].description_highlight, "&lt;b&gt;<mark>plan</mark>&lt;/b&gt;");
+}
@@ -0,0 +1,251 @@
+//! Notices sent by the mutations, written through `FileDropTransport` to a
+//! fresh directory and read back as `.eml` files: recipient, sender, subject
+//! and the rendered Tera text part.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_graphql::Request;
+use tera::Tera;
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::graphql::create_schema_with_context;
+use graphql_api::mail::{FileDropTransport, Mailer};
+use graphql_api::models::{AccountStatus, InsertableUser, NatoClassification, NewAuthority, NewNation};
+use graphql_api::repositories::Repositories;
+
+mod common;
+use common::{classification_schema, create_user, new_user};
+
+const FROM: &str = "Converter <converter@example.org>";
+
+/// How long to wait for a dispatched message to be written
+const DELIVERY_WAIT: Duration = Duration::from_secs(5);
+
+/// A mailer writing to its own directory, removed when dropped
+struct MailDrop {
+    dir: PathBuf,
+    mailer: Mailer,
+}
+
+impl MailDrop {
+    fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("scc-mail-{}", Uuid::new_v4()));
+        let templates = Tera::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../templates/**/*")).unwrap();
+        let transport = FileDropTransport::new(&dir).unwrap();
+
+        MailDrop {
+            mailer: Mailer::new(Some(Arc::new(transport)), FROM.parse().unwrap(), templates),
+            dir,
+        }
+    }
+
+    /// The one message written, once it arrives
+    async fn message(&self) -> Email {
+        let started = std::time::Instant::now();
+
+        loop {
+            let messages = eml_files(&self.dir);
+
+            if !messages.is_empty() {
+                assert_eq!(messages.len(), 1, "{:?}", messages);
+                return Email::parse(&std::fs::read_to_string(&messages[0]).unwrap());
+            }
+
+            assert!(started.elapsed() < DELIVERY_WAIT, "no message in {:?}", self.dir);
+            actix_rt::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+impl Drop for MailDrop {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn eml_files(dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "eml"))
+        .collect()
+}
+
+struct Email {
+    headers: Vec<(String, String)>,
+    /// Decoded text/plain part
+    text: String,
+}
+
+impl Email {
+    fn parse(eml: &str) -> Self {
+        let (head, body) = eml.split_once("\r\n\r\n").unwrap();
+
+        let headers = head
+            .split("\r\n")
+            .filter_map(|line| line.split_once(": "))
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+
+        // The text part comes first in multipart/alternative
+        let text_part = body
+            .split("Content-Type: text/plain")
+            .nth(1)
+            .expect("a text/plain part");
+        let (part_head, part_body) = text_part.split_once("\r\n\r\n").unwrap();
+        let part_body = part_body.split("\r\n--").next().unwrap();
+
+        let text = if part_head.contains("quoted-printable") {
+            decode_quoted_printable(part_body)
+        } else {
+            part_body.replace("\r\n", "\n")
+        };
+
+        Email { headers, text }
+    }
+
+    fn header(&self, name: &str) -> &str {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+            .unwrap_or_else(|| panic!("no {} header", name))
+    }
+}
+
+fn decode_quoted_printable(encoded: &str) -> String {
+    let joined = encoded.replace("=\r\n", "").replace("\r\n", "\n");
+    let mut bytes = Vec::new();
+    let mut chars = joined.bytes();
+
+    while let Some(b) = chars.next() {
+        if b == b'=' {
+            let hex = [chars.next().unwrap(), chars.next().unwrap()];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).unwrap(), 16).unwrap());
+        } else {
+            bytes.push(b);
+        }
+    }
+
+    String::from_utf8(bytes).unwrap()
+}
+
+/// Stores a `new_user` named `name`
+async fn create_named_user(repos: &Repositories, mut user: InsertableUser, name: &str) -> Uuid {
+    user.name = name.to_owned();
+
+    repos.users.create(user).await.unwrap().id
+}
+
+async fn execute(repos: &Repositories, mail: &MailDrop, caller: Option<(UserRole, Uuid)>, query: &str) -> serde_json::Value {
+    let mut request = Request::new(query).data(mail.mailer.clone());
+    if let Some((role, id)) = caller {
+        request = request.data(role).data(id);
+    }
+
+    let response = create_schema_with_context(repos.clone()).execute(request).await;
+    assert!(response.errors.is_empty(), "{}: {:?}", query, response.errors);
+
+    response.data.into_json().unwrap()
+}
+
+#[actix_rt::test]
+async fn password_reset_is_mailed_to_the_user() {
+    let repos = Repositories::in_memory();
+    let mail = MailDrop::new();
+    create_named_user(&repos, new_user("ada@example.org", UserRole::User, None, None), "Ada").await;
+
+    execute(&repos, &mail, None, r#"mutation { requestPasswordReset(email: "ada@example.org") }"#).await;
+
+    let email = mail.message().await;
+    assert_eq!(email.header("To"), "ada@example.org");
+    assert_eq!(email.header("From"), FROM);
+    assert_eq!(email.header("Subject"), "Reset your password");
+    assert!(email.text.starts_with("Hello Ada,\n"), "{}", email.text);
+    assert!(email.text.contains("within 30 minutes"), "{}", email.text);
+
+    // The mailed token is the one that resets the password
+    let token = email.text.lines().skip_while(|l| !l.ends_with("new password:")).nth(2).unwrap();
+    let reset = execute(
+        &repos,
+        &mail,
+        None,
+        &format!(r#"mutation {{ resetPassword(token: "{}", newPassword: "another-horse-battery") }}"#, token),
+    )
+    .await;
+    assert_eq!(reset["resetPassword"], true);
+}
+
+#[actix_rt::test]
+async fn approval_is_mailed_to_the_user() {
+    let repos = Repositories::in_memory();
+    let mail = MailDrop::new();
+    let admin = create_user(&repos, "admin@example.org", UserRole::Admin, None, None).await;
+    let mut pending = new_user("bo@example.org", UserRole::User, None, None);
+    pending.account_status = AccountStatus::Pending.to_string();
+    let user = create_named_user(&repos, pending, "Bo").await;
+
+    execute(
+        &repos,
+        &mail,
+        Some((UserRole::Admin, admin)),
+        &format!(r#"mutation {{ approveUser(id: "{}") {{ accountStatus }} }}"#, user),
+    )
+    .await;
+
+    let email = mail.message().await;
+    assert_eq!(email.header("To"), "bo@example.org");
+    assert_eq!(email.header("From"), FROM);
+    assert_eq!(email.header("Subject"), "Your account has been approved");
+    assert_eq!(email.text.trim(), "Hello Bo,\n\nYour account has been approved and you can now sign in.");
+}
+
+#[actix_rt::test]
+async fn completed_conversion_is_mailed_to_the_authority() {
+    let repos = Repositories::in_memory();
+    let mail = MailDrop::new();
+    let operator = create_user(&repos, "op@example.org", UserRole::Operator, Some(NatoClassification::TopSecret), Some("GBR")).await;
+
+    let gbr = repos.nations.create(NewNation::new(operator, "GBR".to_owned(), "United Kingdom".to_owned())).await.unwrap();
+    repos.nations.create(NewNation::new(operator, "USA".to_owned(), "United States".to_owned())).await.unwrap();
+
+    let authority = repos
+        .authorities
+        .create(NewAuthority::new(operator, gbr.id, "UK NSA".to_owned(), "nsa@example.org".to_owned(), "0".to_owned(), None))
+        .await
+        .unwrap()
+        .id;
+    repos.schemas.create(classification_schema(operator, authority, "GBR")).await.unwrap();
+    repos.schemas.create(classification_schema(operator, authority, "USA")).await.unwrap();
+
+    let submitted = execute(
+        &repos,
+        &mail,
+        Some((UserRole::Operator, operator)),
+        &format!(
+            r#"mutation {{ submitConversionRequest(input: {{
+                authorityId: "{}", dataObject: {{title: "plan", description: "the plan"}},
+                metadata: {{domain: "OPERATIONS", tags: []}}, sourceNationCode: "GBR",
+                sourceMarking: "GBR SECRET", targetNationCodes: ["USA"]
+            }}) {{ conversionRequest {{ id }} }} }}"#,
+            authority,
+        ),
+    )
+    .await;
+    let request_id = submitted["submitConversionRequest"]["conversionRequest"]["id"].as_str().unwrap();
+
+    let email = mail.message().await;
+    assert_eq!(email.header("To"), "nsa@example.org");
+    assert_eq!(email.header("From"), FROM);
+    assert_eq!(email.header("Subject"), format!("Conversion request {} completed", request_id));
+    assert_eq!(
+        email.text.trim(),
+        format!(
+            "Conversion request {} for UK NSA has completed.\n\nSource: GBR SECRET (GBR), NATO SECRET\n\nUSA: USA SECRET",
+            request_id,
+        ),
+    );
+}
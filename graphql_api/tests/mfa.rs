@@ -0,0 +1,229 @@
+//! TOTP second factor: enrolment takes effect with the first code, codes and
+//! recovery codes work once, and sign-in challenges are refused once they
+//! expire or after MFA_MAX_ATTEMPTS wrong codes.
+//!
+//! Users here have the USER role, for which TOTP is optional, so they sign
+//! in with the password alone until they enrol.
+
+use std::sync::Once;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_graphql::{Request, Response};
+use chrono::{Duration, Utc};
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::config_variables::{MFA_MAX_ATTEMPTS, RECOVERY_CODE_COUNT};
+use graphql_api::graphql::create_schema_with_context;
+use graphql_api::models::{
+    account_subject, generate_totp_secret, verify_totp, NewSignInChallenge, SessionId, ThrottleKind,
+};
+use graphql_api::repositories::Repositories;
+
+mod common;
+use common::create_user;
+
+const EMAIL: &str = "ada@example.org";
+
+static ENV: Once = Once::new();
+
+/// Session tokens are signed with JWT_SECRET_KEY
+fn set_env() {
+    ENV.call_once(|| {
+        // SAFETY: runs once, before any test reads the environment
+        unsafe { std::env::set_var("JWT_SECRET_KEY", "mfa-test-secret-mfa-test-secret-mfa") };
+    });
+}
+
+fn current_step() -> i64 {
+    (SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() / 30) as i64
+}
+
+/// The code an authenticator app shows for `secret` during `step`
+fn code_at(secret: &str, step: i64) -> String {
+    let bytes = Secret::Encoded(secret.to_owned()).to_bytes().unwrap();
+
+    TOTP::new_unchecked(Algorithm::SHA1, 6, 1, 30, bytes, None, String::new()).generate(step as u64 * 30)
+}
+
+async fn execute(repos: &Repositories, caller: Option<Uuid>, query: &str) -> Response {
+    let mut request = Request::new(query);
+    if let Some(id) = caller {
+        request = request.data(UserRole::User).data(id).data(SessionId(Uuid::new_v4()));
+    }
+
+    create_schema_with_context(repos.clone()).execute(request).await
+}
+
+fn messages(response: Response) -> Vec<String> {
+    response.errors.into_iter().map(|e| e.message).collect()
+}
+
+/// Signs in with the password, returning the challenge or None for tokens
+async fn sign_in(repos: &Repositories) -> Option<String> {
+    let response = execute(
+        repos,
+        None,
+        &format!(
+            r#"mutation {{ signIn(input: {{email: "{}", password: "correct-horse-battery"}}) {{
+                ... on UserResponse {{ bearer }}
+                ... on MfaChallenge {{ challenge }}
+            }} }}"#,
+            EMAIL,
+        ),
+    )
+    .await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let data = response.data.into_json().unwrap();
+    data["signIn"]["challenge"].as_str().map(str::to_owned)
+}
+
+async fn verify_mfa(repos: &Repositories, challenge: &str, code: &str) -> Response {
+    execute(
+        repos,
+        None,
+        &format!(r#"mutation {{ verifyMfa(challenge: "{}", code: "{}") {{ bearer }} }}"#, challenge, code),
+    )
+    .await
+}
+
+/// Creates a user and starts their enrolment, returning the secret and
+/// recovery codes
+async fn enrol(repos: &Repositories) -> (Uuid, String, Vec<String>) {
+    set_env();
+    let user_id = create_user(repos, EMAIL, UserRole::User, None, None).await;
+
+    let response = execute(repos, Some(user_id), "mutation { enrolTotp { secret recoveryCodes } }").await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let data = response.data.into_json().unwrap();
+    let secret = data["enrolTotp"]["secret"].as_str().unwrap().to_owned();
+    let recovery_codes = data["enrolTotp"]["recoveryCodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c.as_str().unwrap().to_owned())
+        .collect();
+
+    (user_id, secret, recovery_codes)
+}
+
+/// A user with TOTP enabled, returning the secret, recovery codes and the
+/// step whose code confirmed the enrolment
+async fn enrolled(repos: &Repositories) -> (String, Vec<String>, i64) {
+    let (user_id, secret, recovery_codes) = enrol(repos).await;
+    let step = current_step();
+
+    let confirm = format!(r#"mutation {{ confirmTotp(code: "{}") }}"#, code_at(&secret, step));
+    let response = execute(repos, Some(user_id), &confirm).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    (secret, recovery_codes, step)
+}
+
+#[actix_rt::test]
+async fn enrolment_takes_effect_once_confirmed() {
+    let repos = Repositories::in_memory();
+    let (user_id, secret, recovery_codes) = enrol(&repos).await;
+    assert_eq!(recovery_codes.len(), RECOVERY_CODE_COUNT);
+
+    // Pending until confirmed, so the password alone still signs in
+    assert!(sign_in(&repos).await.is_none());
+
+    let wrong = execute(&repos, Some(user_id), r#"mutation { confirmTotp(code: "000000") }"#).await;
+    assert_eq!(messages(wrong), vec!["Invalid code"]);
+    assert!(sign_in(&repos).await.is_none());
+
+    let confirm = format!(r#"mutation {{ confirmTotp(code: "{}") }}"#, code_at(&secret, current_step()));
+    assert!(execute(&repos, Some(user_id), &confirm).await.errors.is_empty());
+
+    let challenge = sign_in(&repos).await.expect("a challenge once TOTP is enabled");
+    let response = verify_mfa(&repos, &challenge, &code_at(&secret, current_step() + 1)).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+}
+
+#[test]
+fn codes_at_or_before_the_last_step_are_refused() {
+    let secret = generate_totp_secret();
+    let step = current_step();
+    let code = code_at(&secret, step);
+
+    assert_eq!(verify_totp(&secret, &code, None).unwrap(), Some(step));
+    assert_eq!(verify_totp(&secret, &code, Some(step - 1)).unwrap(), Some(step));
+    assert_eq!(verify_totp(&secret, &code, Some(step)).unwrap(), None);
+    assert_eq!(verify_totp(&secret, &code_at(&secret, step - 1), Some(step)).unwrap(), None);
+
+    // One step of drift either way, no more
+    assert_eq!(verify_totp(&secret, &code_at(&secret, step + 1), Some(step)).unwrap(), Some(step + 1));
+    assert_eq!(verify_totp(&secret, &code_at(&secret, step + 3), None).unwrap(), None);
+}
+
+#[actix_rt::test]
+async fn a_replayed_code_is_refused() {
+    let repos = Repositories::in_memory();
+    let (secret, _, confirmed_step) = enrolled(&repos).await;
+
+    // The code that confirmed the enrolment
+    let challenge = sign_in(&repos).await.unwrap();
+    let replayed = verify_mfa(&repos, &challenge, &code_at(&secret, confirmed_step)).await;
+    assert_eq!(messages(replayed), vec!["Invalid code"]);
+
+    let next = code_at(&secret, confirmed_step + 1);
+    assert!(verify_mfa(&repos, &challenge, &next).await.errors.is_empty());
+
+    let challenge = sign_in(&repos).await.unwrap();
+    assert_eq!(messages(verify_mfa(&repos, &challenge, &next).await), vec!["Invalid code"]);
+}
+
+#[actix_rt::test]
+async fn a_recovery_code_works_once() {
+    let repos = Repositories::in_memory();
+    let (_, recovery_codes, _) = enrolled(&repos).await;
+
+    let challenge = sign_in(&repos).await.unwrap();
+    assert!(verify_mfa(&repos, &challenge, &recovery_codes[0]).await.errors.is_empty());
+
+    let challenge = sign_in(&repos).await.unwrap();
+    assert_eq!(messages(verify_mfa(&repos, &challenge, &recovery_codes[0]).await), vec!["Invalid code"]);
+
+    // Typed in upper case, the others still work
+    let response = verify_mfa(&repos, &challenge, &recovery_codes[1].to_uppercase()).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+}
+
+#[actix_rt::test]
+async fn the_challenge_is_discarded_after_too_many_wrong_codes() {
+    let repos = Repositories::in_memory();
+    let (secret, _, _) = enrolled(&repos).await;
+    let challenge = sign_in(&repos).await.unwrap();
+
+    for attempt in 1..=MFA_MAX_ATTEMPTS {
+        let expected = match attempt {
+            MFA_MAX_ATTEMPTS => "Too many invalid codes, sign in again",
+            _ => "Invalid code",
+        };
+        assert_eq!(messages(verify_mfa(&repos, &challenge, "000000").await), vec![expected]);
+
+        // So the account's sign-in backoff doesn't refuse the next code first
+        repos.sign_in_throttles.clear(ThrottleKind::Account, account_subject(EMAIL)).await.unwrap();
+    }
+
+    let right = verify_mfa(&repos, &challenge, &code_at(&secret, current_step() + 1)).await;
+    assert_eq!(messages(right), vec!["Invalid or expired sign-in challenge"]);
+}
+
+#[actix_rt::test]
+async fn an_expired_challenge_is_refused() {
+    let repos = Repositories::in_memory();
+    let (secret, _, _) = enrolled(&repos).await;
+    let user_id = repos.users.get_by_email(EMAIL.to_owned()).await.unwrap().id;
+
+    let mut expired = NewSignInChallenge::new(user_id, "expired-challenge");
+    expired.expires_at = Utc::now().naive_utc() - Duration::seconds(1);
+    repos.mfa_challenges.create(expired).await.unwrap();
+
+    let response = verify_mfa(&repos, "expired-challenge", &code_at(&secret, current_step() + 1)).await;
+    assert_eq!(messages(response), vec!["Invalid or expired sign-in challenge"]);
+}
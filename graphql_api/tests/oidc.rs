@@ -0,0 +1,414 @@
+//! The OIDC authorization-code flow against a mock IdP.
+//!
+//! The IdP serves discovery, a JWKS holding one Ed25519 key and a token
+//! endpoint that checks the PKCE verifier and signs whatever claims the test
+//! queued. Each test starts at `/auth/oidc/login`, reads state, nonce and code
+//! challenge off the redirect, and finishes at `/auth/oidc/callback`. Users
+//! whose role requires TOTP finish with `verifyMfa`.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, Once};
+
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{test, web, App, HttpResponse, HttpServer};
+use async_graphql::Request;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use ed25519_dalek::pkcs8::EncodePrivateKey;
+use ed25519_dalek::SigningKey;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use totp_rs::TOTP;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::graphql::create_schema_with_context;
+use graphql_api::handlers::{oidc_callback, oidc_login};
+use graphql_api::models::{AccountStatus, NatoClassification, OidcConfig, OidcProvider};
+use graphql_api::repositories::Repositories;
+
+mod common;
+use common::new_user;
+
+const CLIENT_ID: &str = "scc-test";
+
+const KID: &str = "mock-idp";
+
+static ENV: Once = Once::new();
+
+/// Session tokens are signed with JWT_SECRET_KEY
+fn set_env() {
+    ENV.call_once(|| {
+        // SAFETY: runs once, before any test reads the environment
+        unsafe { std::env::set_var("JWT_SECRET_KEY", "oidc-test-secret-oidc-test-secret") };
+    });
+}
+
+/// What the token endpoint hands out next
+#[derive(Default)]
+struct Pending {
+    code_challenge: Option<String>,
+    claims: Option<serde_json::Value>,
+}
+
+struct MockIdp {
+    issuer: String,
+    key: EncodingKey,
+    jwk: serde_json::Value,
+    pending: Mutex<Pending>,
+}
+
+async fn discovery(idp: web::Data<MockIdp>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "issuer": idp.issuer,
+        "authorization_endpoint": format!("{}/authorize", idp.issuer),
+        "token_endpoint": format!("{}/token", idp.issuer),
+        "jwks_uri": format!("{}/jwks", idp.issuer),
+    }))
+}
+
+async fn jwks(idp: web::Data<MockIdp>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "keys": [idp.jwk] }))
+}
+
+async fn token(idp: web::Data<MockIdp>, form: web::Form<HashMap<String, String>>) -> HttpResponse {
+    let pending = std::mem::take(&mut *idp.pending.lock().unwrap());
+
+    let verifier = form.get("code_verifier").map(String::as_str).unwrap_or_default();
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+    if form.get("grant_type").map(String::as_str) != Some("authorization_code")
+        || pending.code_challenge.as_deref() != Some(challenge.as_str())
+    {
+        return HttpResponse::BadRequest().json(json!({ "error": "invalid_grant" }));
+    }
+
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(KID.to_owned());
+
+    let id_token = encode(&header, &pending.claims.expect("claims queued"), &idp.key).unwrap();
+
+    HttpResponse::Ok().json(json!({ "id_token": id_token, "token_type": "Bearer" }))
+}
+
+/// Starts the IdP on a free port
+fn start_idp() -> web::Data<MockIdp> {
+    let signing_key = SigningKey::from_bytes(&rand::random());
+    let der = signing_key.to_pkcs8_der().unwrap();
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+    let idp = web::Data::new(MockIdp {
+        issuer,
+        key: EncodingKey::from_ed_der(der.as_bytes()),
+        jwk: json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "alg": "EdDSA",
+            "use": "sig",
+            "kid": KID,
+            "x": URL_SAFE_NO_PAD.encode(signing_key.verifying_key().as_bytes()),
+        }),
+        pending: Mutex::new(Pending::default()),
+    });
+
+    let data = idp.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(data.clone())
+            .route("/.well-known/openid-configuration", web::get().to(discovery))
+            .route("/jwks", web::get().to(jwks))
+            .route("/token", web::post().to(token))
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+
+    actix_rt::spawn(server);
+
+    idp
+}
+
+async fn provider(idp: &MockIdp) -> OidcProvider {
+    OidcProvider::discover(OidcConfig {
+        issuer: idp.issuer.clone(),
+        client_id: CLIENT_ID.to_owned(),
+        client_secret: "secret".to_owned(),
+        redirect_uri: "http://localhost/auth/oidc/callback".to_owned(),
+        groups_claim: "groups".to_owned(),
+        nationality_claim: "nationality".to_owned(),
+        role_groups: vec![
+            ("scc-analysts".to_owned(), UserRole::Analyst),
+            ("scc-admins".to_owned(), UserRole::Admin),
+        ],
+        access_level_groups: vec![
+            ("scc-secret".to_owned(), NatoClassification::Secret),
+            ("scc-confidential".to_owned(), NatoClassification::Confidential),
+        ],
+    })
+    .await
+    .expect("mock IdP discovery")
+}
+
+/// Claims for a well-formed ID token answering `nonce`
+fn claims(idp: &MockIdp, nonce: &str, groups: &[&str]) -> serde_json::Value {
+    let now = Utc::now().timestamp();
+
+    json!({
+        "iss": idp.issuer,
+        "aud": CLIENT_ID,
+        "sub": "ada-1",
+        "email": "ada@example.org",
+        "name": "Ada",
+        "nonce": nonce,
+        "groups": groups,
+        "nationality": "gbr",
+        "iat": now,
+        "exp": now + 300,
+    })
+}
+
+/// Parameters of the IdP redirect returned by `/auth/oidc/login`
+async fn start_login<S>(app: &S) -> HashMap<String, String>
+where
+    S: Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+{
+    let response = test::call_service(app, test::TestRequest::get().uri("/auth/oidc/login").to_request()).await;
+    assert_eq!(response.status(), 302);
+
+    let location = response.headers().get("Location").unwrap().to_str().unwrap();
+
+    reqwest::Url::parse(location).unwrap().query_pairs().into_owned().collect()
+}
+
+/// Queues `claims` at the IdP and completes the login started with `login`
+async fn callback<S>(
+    app: &S,
+    idp: &MockIdp,
+    login: &HashMap<String, String>,
+    state: &str,
+    claims: serde_json::Value,
+) -> (u16, serde_json::Value)
+where
+    S: Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+{
+    *idp.pending.lock().unwrap() = Pending {
+        code_challenge: login.get("code_challenge").cloned(),
+        claims: Some(claims),
+    };
+
+    let request = test::TestRequest::get()
+        .uri(&format!("/auth/oidc/callback?code=abc&state={}", state))
+        .to_request();
+    let response = test::call_service(app, request).await;
+    let status = response.status().as_u16();
+
+    (status, test::read_body_json(response).await)
+}
+
+macro_rules! init_app {
+    ($repos:expr, $idp:expr) => {
+        test::init_service(
+            App::new()
+                .app_data(web::Data::new($repos.clone()))
+                .app_data(web::Data::new(provider(&$idp).await))
+                .route("/auth/oidc/login", web::get().to(oidc_login))
+                .route("/auth/oidc/callback", web::get().to(oidc_callback)),
+        )
+        .await
+    };
+}
+
+#[actix_rt::test]
+async fn first_sign_in_provisions_a_pending_user_with_mapped_groups() {
+    set_env();
+    let idp = start_idp();
+    let repos = Repositories::in_memory();
+    let app = init_app!(repos, idp);
+
+    let login = start_login(&app).await;
+    assert_eq!(login["client_id"], CLIENT_ID);
+    assert_eq!(login["code_challenge_method"], "S256");
+
+    let groups = ["scc-analysts", "scc-admins", "scc-confidential", "unrelated"];
+    let (status, body) = callback(&app, &idp, &login, &login["state"], claims(&idp, &login["nonce"], &groups)).await;
+
+    // Provisioned, but not signed in until an admin approves the account
+    assert_eq!(status, 403, "{}", body);
+
+    let user = repos.users.get_by_email("ada@example.org".to_owned()).await.unwrap();
+    assert_eq!(user.name, "Ada");
+    assert_eq!(user.role, UserRole::Admin.to_string());
+    assert_eq!(user.access_level, NatoClassification::Confidential.to_string());
+    assert_eq!(user.nationality.as_deref(), Some("GBR"));
+    assert_eq!(user.status(), AccountStatus::Pending);
+}
+
+#[actix_rt::test]
+async fn approved_user_signs_in_and_follows_idp_groups() {
+    set_env();
+    let idp = start_idp();
+    let repos = Repositories::in_memory();
+    let app = init_app!(repos, idp);
+
+    let login = start_login(&app).await;
+    let (status, _) = callback(&app, &idp, &login, &login["state"], claims(&idp, &login["nonce"], &["scc-secret"])).await;
+    assert_eq!(status, 403);
+
+    let mut user = repos.users.get_by_email("ada@example.org".to_owned()).await.unwrap();
+    assert_eq!(user.role, UserRole::User.to_string());
+    assert_eq!(user.access_level, NatoClassification::Secret.to_string());
+
+    user.account_status = AccountStatus::Approved.to_string();
+    repos.users.update(user).await.unwrap();
+
+    // No matching groups: back to the defaults
+    let login = start_login(&app).await;
+    let (status, body) = callback(&app, &idp, &login, &login["state"], claims(&idp, &login["nonce"], &[])).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["role"], UserRole::User.to_string());
+    assert!(body["bearer"].as_str().is_some_and(|b| !b.is_empty()));
+
+    let user = repos.users.get_by_email("ada@example.org".to_owned()).await.unwrap();
+    assert_eq!(user.access_level, NatoClassification::Unclassified.to_string());
+
+    // The IdP can't raise them again
+    let login = start_login(&app).await;
+    let groups = ["scc-admins", "scc-secret"];
+    let (status, body) = callback(&app, &idp, &login, &login["state"], claims(&idp, &login["nonce"], &groups)).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["role"], UserRole::User.to_string());
+
+    let user = repos.users.get_by_email("ada@example.org".to_owned()).await.unwrap();
+    assert_eq!(user.role, UserRole::User.to_string());
+    assert_eq!(user.access_level, NatoClassification::Unclassified.to_string());
+}
+
+#[actix_rt::test]
+async fn password_accounts_are_not_linked() {
+    set_env();
+    let idp = start_idp();
+    let repos = Repositories::in_memory();
+    let app = init_app!(repos, idp);
+
+    let local = new_user("ada@example.org", UserRole::User, None, None);
+    let local = repos.users.create(local).await.unwrap();
+
+    let login = start_login(&app).await;
+    let groups = ["scc-admins", "scc-secret"];
+    let (status, body) = callback(&app, &idp, &login, &login["state"], claims(&idp, &login["nonce"], &groups)).await;
+
+    assert_eq!(status, 403);
+    assert_eq!(body["error"], "This account signs in with a password");
+
+    let user = repos.users.get_by_id(local.id).await.unwrap();
+    assert_eq!(user.hash, local.hash);
+    assert_eq!(user.role, UserRole::User.to_string());
+    assert_eq!(user.access_level, NatoClassification::Unclassified.to_string());
+}
+
+#[actix_rt::test]
+async fn unknown_state_is_refused() {
+    set_env();
+    let idp = start_idp();
+    let repos = Repositories::in_memory();
+    let app = init_app!(repos, idp);
+
+    let login = start_login(&app).await;
+    let (status, body) = callback(&app, &idp, &login, "forged", claims(&idp, &login["nonce"], &[])).await;
+
+    assert_eq!(status, 401);
+    assert_eq!(body["error"], "Unknown or expired login state");
+
+    // The state is single use
+    let (status, _) = callback(&app, &idp, &login, &login["state"], claims(&idp, &login["nonce"], &[])).await;
+    assert_eq!(status, 403);
+    let (status, _) = callback(&app, &idp, &login, &login["state"], claims(&idp, &login["nonce"], &[])).await;
+    assert_eq!(status, 401);
+}
+
+#[actix_rt::test]
+async fn wrong_nonce_is_refused() {
+    set_env();
+    let idp = start_idp();
+    let repos = Repositories::in_memory();
+    let app = init_app!(repos, idp);
+
+    let login = start_login(&app).await;
+    let (status, body) = callback(&app, &idp, &login, &login["state"], claims(&idp, "replayed", &[])).await;
+
+    assert_eq!(status, 401);
+    assert_eq!(body["error"], "ID token nonce does not match the login");
+    assert!(repos.users.get_by_email("ada@example.org".to_owned()).await.is_err());
+}
+
+#[actix_rt::test]
+async fn wrong_audience_is_refused() {
+    set_env();
+    let idp = start_idp();
+    let repos = Repositories::in_memory();
+    let app = init_app!(repos, idp);
+
+    let login = start_login(&app).await;
+    let mut claims = claims(&idp, &login["nonce"], &[]);
+    claims["aud"] = json!("another-client");
+
+    let (status, _) = callback(&app, &idp, &login, &login["state"], claims).await;
+
+    assert_eq!(status, 401);
+    assert!(repos.users.get_by_email("ada@example.org".to_owned()).await.is_err());
+}
+
+#[actix_rt::test]
+async fn users_who_must_use_mfa_get_a_challenge() {
+    set_env();
+    let idp = start_idp();
+    let repos = Repositories::in_memory();
+    let app = init_app!(repos, idp);
+
+    let login = start_login(&app).await;
+    let (status, _) = callback(&app, &idp, &login, &login["state"], claims(&idp, &login["nonce"], &["scc-admins"])).await;
+    assert_eq!(status, 403);
+
+    let mut user = repos.users.get_by_email("ada@example.org".to_owned()).await.unwrap();
+    user.account_status = AccountStatus::Approved.to_string();
+    repos.users.update(user).await.unwrap();
+
+    // Admins must use TOTP, so the IdP sign-in alone issues no tokens
+    let login = start_login(&app).await;
+    let (status, body) = callback(&app, &idp, &login, &login["state"], claims(&idp, &login["nonce"], &["scc-admins"])).await;
+    assert_eq!(status, 200, "{}", body);
+    assert!(body.get("bearer").is_none(), "{}", body);
+
+    let enrolment = &body["enrolment"];
+    assert_eq!(enrolment["recovery_codes"].as_array().unwrap().len(), 10);
+
+    // Signing in again shows the same pending secret, without new recovery codes
+    let login = start_login(&app).await;
+    let (_, again) = callback(&app, &idp, &login, &login["state"], claims(&idp, &login["nonce"], &["scc-admins"])).await;
+    assert_eq!(again["enrolment"]["secret"], enrolment["secret"]);
+    assert!(again["enrolment"]["recovery_codes"].as_array().unwrap().is_empty());
+
+    let code = TOTP::from_url(enrolment["provisioning_uri"].as_str().unwrap())
+        .unwrap()
+        .generate_current()
+        .unwrap();
+
+    let response = create_schema_with_context(repos.clone())
+        .execute(Request::new(format!(
+            r#"mutation {{ verifyMfa(challenge: "{}", code: "{}") {{ bearer role }} }}"#,
+            again["challenge"].as_str().unwrap(),
+            code,
+        )))
+        .await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let data = response.data.into_json().unwrap();
+    assert_eq!(data["verifyMfa"]["role"], UserRole::Admin.to_string());
+
+    let user = repos.users.get_by_email("ada@example.org".to_owned()).await.unwrap();
+    assert!(user.totp_enabled());
+}
@@ -0,0 +1,114 @@
+//! Passwords are hashed with per-user salts and the PASSWORD_SECRET_KEY
+//! pepper. Hashes from before that (the key as a shared salt, default Argon2
+//! parameters, no pepper) or with outdated parameters still verify, and
+//! `signIn` replaces them.
+//!
+//! The environment is read once per process, so every test here runs with
+//! a pepper and ARGON2_MEMORY_KIB below the argon2 crate default.
+
+use std::sync::Once;
+
+use argon2::password_hash::{PasswordHasher, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use async_graphql::Request;
+use rand::rngs::OsRng;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::graphql::create_schema_with_context;
+use graphql_api::models::{hash_password, verify_password, NatoClassification, PasswordCheck};
+use graphql_api::repositories::Repositories;
+
+mod common;
+use common::new_user;
+
+const PEPPER: &str = "cGFzc3dvcmRzLXRlc3Q";
+
+static ENV: Once = Once::new();
+
+fn set_env() {
+    ENV.call_once(|| {
+        // SAFETY: runs once, before any test reads the environment
+        unsafe {
+            std::env::set_var("PASSWORD_SECRET_KEY", PEPPER);
+            std::env::set_var("ARGON2_MEMORY_KIB", (Params::DEFAULT_M_COST / 4).to_string());
+            std::env::set_var("JWT_SECRET_KEY", "passwords-test-secret-passwords-test");
+        };
+    });
+}
+
+/// A hash as `hash_password` made them before per-user salts
+fn legacy_hash(password: &str) -> String {
+    let salt = SaltString::from_b64(PEPPER).unwrap();
+
+    Argon2::default().hash_password(password.as_bytes(), &salt).unwrap().to_string()
+}
+
+#[test]
+fn the_same_password_hashes_differently_for_each_user() {
+    set_env();
+
+    let first = hash_password("correct-horse-battery").unwrap();
+    let second = hash_password("correct-horse-battery").unwrap();
+    assert_ne!(first, second);
+
+    assert_eq!(verify_password(&first, "correct-horse-battery").unwrap(), PasswordCheck::Match);
+    assert_eq!(verify_password(&second, "correct-horse-battery").unwrap(), PasswordCheck::Match);
+}
+
+#[test]
+fn legacy_hashes_verify_and_need_rehashing() {
+    set_env();
+    let hash = legacy_hash("correct-horse-battery");
+
+    assert_eq!(verify_password(&hash, "correct-horse-battery").unwrap(), PasswordCheck::MatchNeedsRehash);
+    assert_eq!(verify_password(&hash, "wrong-horse-battery").unwrap(), PasswordCheck::Mismatch);
+}
+
+#[test]
+fn hashes_with_outdated_parameters_need_rehashing() {
+    set_env();
+
+    // Peppered with a per-user salt, but at the default memory cost
+    let hash = Argon2::new_with_secret(PEPPER.as_bytes(), Algorithm::Argon2id, Version::V0x13, Params::default())
+        .unwrap()
+        .hash_password(b"correct-horse-battery", &SaltString::generate(&mut OsRng))
+        .unwrap()
+        .to_string();
+
+    assert_eq!(verify_password(&hash, "correct-horse-battery").unwrap(), PasswordCheck::MatchNeedsRehash);
+}
+
+#[test]
+fn wrong_passwords_against_peppered_hashes_mismatch() {
+    set_env();
+    let hash = hash_password("correct-horse-battery").unwrap();
+
+    assert_eq!(verify_password(&hash, "wrong-horse-battery").unwrap(), PasswordCheck::Mismatch);
+    assert_eq!(verify_password(&hash, "").unwrap(), PasswordCheck::Mismatch);
+}
+
+#[actix_rt::test]
+async fn sign_in_replaces_legacy_hashes() {
+    set_env();
+    let repos = Repositories::in_memory();
+
+    let user = new_user("legacy@example.org", UserRole::User, Some(NatoClassification::Secret), Some("GBR"));
+    let mut user = repos.users.create(user).await.unwrap();
+
+    let legacy = legacy_hash("correct-horse-battery");
+    user.hash = legacy.clone();
+    repos.users.update(user).await.unwrap();
+
+    let response = create_schema_with_context(repos.clone())
+        .execute(Request::new(
+            r#"mutation { signIn(input: {email: "legacy@example.org", password: "correct-horse-battery"}) {
+                ... on UserResponse { bearer }
+            } }"#,
+        ))
+        .await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let stored = repos.users.get_by_email("legacy@example.org".to_owned()).await.unwrap();
+    assert_ne!(stored.hash, legacy);
+    assert_eq!(verify_password(&stored.hash, "correct-horse-battery").unwrap(), PasswordCheck::Match);
+}
@@ -0,0 +1,248 @@
+//! Checks every guarded field against every role.
+//!
+//! `EXPECTED_ROLES` spells out which roles hold each permission, inherited
+//! ones included. The grants are checked against it, then the schema is built
+//! over an in-memory store and one request per guarded field is run as an
+//! anonymous caller and as each `UserRole`. A field counts as allowed when the
+//! request gets past its guard and visibility check, even if the resolver then
+//! fails on the placeholder arguments.
+//!
+//! ```bash
+//! cargo test --features in-memory --test permission_matrix
+//! ```
+//!
+//! Add a row to `GUARDED_FIELDS` when guarding a new field.
+
+use std::time::Duration;
+
+use actix_rt::time::timeout;
+use async_graphql::{Request, Value};
+use futures::StreamExt;
+use strum::IntoEnumIterator;
+use uuid::Uuid;
+
+use graphql_api::common_utils::{Permission, UserRole};
+use graphql_api::database;
+use graphql_api::graphql::{create_schema_with_context, AppSchema};
+use graphql_api::repositories::Repositories;
+
+const NIL: &str = "00000000-0000-0000-0000-000000000000";
+
+const SUBSCRIPTION_WAIT: Duration = Duration::from_millis(200);
+
+/// Roles holding each permission
+const EXPECTED_ROLES: &[(Permission, &[UserRole])] = &[
+    (
+        Permission::ReadReferenceData,
+        &[UserRole::User, UserRole::Auditor, UserRole::Analyst, UserRole::Operator, UserRole::Admin],
+    ),
+    (Permission::ReadConversions, &[UserRole::Analyst, UserRole::Operator, UserRole::Admin]),
+    (Permission::SubmitConversions, &[UserRole::Operator, UserRole::Admin]),
+    (Permission::ManageUsers, &[UserRole::Admin]),
+    (Permission::ManageApiKeys, &[UserRole::Admin]),
+    (Permission::AccessAllAuthorities, &[UserRole::Admin]),
+    (Permission::ReadAuditLog, &[UserRole::Auditor, UserRole::Admin]),
+];
+
+fn expected(role: UserRole, permission: Permission) -> bool {
+    EXPECTED_ROLES
+        .iter()
+        .find(|(p, _)| *p == permission)
+        .is_some_and(|(_, roles)| roles.contains(&role))
+}
+
+/// Field, the permission it requires and a request that reaches it. `{role}`
+/// is replaced with the caller's role to keep created rows unique.
+const GUARDED_FIELDS: &[(&str, Permission, &str)] = &[
+    ("Query.allUsers", Permission::ManageUsers, "{ allUsers { role } }"),
+    ("Query.userByEmail", Permission::ManageUsers, r#"{ userByEmail(email: "nobody@example.com") { role } }"#),
+    ("Query.userById", Permission::ManageUsers, r#"{ userById(id: "{nil}") { role } }"#),
+    ("User.id", Permission::ManageUsers, "{ allUsers { id } }"),
+    ("User.email", Permission::ManageUsers, "{ allUsers { email } }"),
+    ("User.name", Permission::ManageUsers, "{ allUsers { name } }"),
+    ("User.updatedAt", Permission::ManageUsers, "{ allUsers { updatedAt } }"),
+    ("User.approvedByUserUid", Permission::ManageUsers, "{ allUsers { approvedByUserUid } }"),
+    ("User.accountStatus", Permission::ManageUsers, "{ allUsers { accountStatus } }"),
+    ("User.createdByUserUid", Permission::ManageUsers, "{ allUsers { createdByUserUid } }"),
+    ("User.emailVerifiedAt", Permission::ManageUsers, "{ allUsers { emailVerifiedAt } }"),
+    ("User.totpEnabledAt", Permission::ManageUsers, "{ allUsers { totpEnabledAt } }"),
+    ("Query.nationCount", Permission::ReadReferenceData, "{ nationCount }"),
+    ("Query.nationById", Permission::ReadReferenceData, r#"{ nationById(id: "{nil}") { nationCode } }"#),
+    ("Query.nationByCode", Permission::ReadReferenceData, r#"{ nationByCode(nationCode: "GBR") { nationCode } }"#),
+    ("Query.nationsByCreatorId", Permission::ReadReferenceData, r#"{ nationsByCreatorId(creatorId: "{nil}") { nationCode } }"#),
+    ("Query.nations", Permission::ReadReferenceData, "{ nations { nationCode } }"),
+    ("Query.authorityCount", Permission::ReadReferenceData, "{ authorityCount }"),
+    ("Query.authorityById", Permission::ReadReferenceData, r#"{ authorityById(id: "{nil}") { id } }"#),
+    ("Query.authoritiesByCreatorId", Permission::ReadReferenceData, r#"{ authoritiesByCreatorId(creatorId: "{nil}") { id } }"#),
+    ("Query.authoritiesByNationCode", Permission::ReadReferenceData, r#"{ authoritiesByNationCode(nationCode: "GBR") { id } }"#),
+    ("Query.authorities", Permission::ReadReferenceData, "{ authorities { id } }"),
+    ("Query.classificationSchemaCount", Permission::ReadReferenceData, "{ classificationSchemaCount }"),
+    ("Query.classificationSchemaById", Permission::ReadReferenceData, r#"{ classificationSchemaById(id: "{nil}") { id } }"#),
+    ("Query.classificationSchemasByCreatorId", Permission::ReadReferenceData, r#"{ classificationSchemasByCreatorId(creatorId: "{nil}") { id } }"#),
+    ("Query.classificationSchemasByNationCode", Permission::ReadReferenceData, r#"{ classificationSchemasByNationCode(nationCode: "GBR") { id } }"#),
+    (
+        "Query.classificationSchemaByNationCodeAndVersion",
+        Permission::ReadReferenceData,
+        r#"{ classificationSchemaByNationCodeAndVersion(nationCode: "GBR", version: "1") { id } }"#,
+    ),
+    ("Query.classificationSchemasByAuthorityId", Permission::ReadReferenceData, r#"{ classificationSchemasByAuthorityId(authorityId: "{nil}") { id } }"#),
+    ("Query.classificationSchemaLatestByNationCode", Permission::ReadReferenceData, r#"{ classificationSchemaLatestByNationCode(nationCode: "GBR") { id } }"#),
+    ("Query.classificationSchemas", Permission::ReadReferenceData, "{ classificationSchemas { id } }"),
+    ("Query.myAuthorities", Permission::ReadReferenceData, "{ myAuthorities { id } }"),
+    ("Query.conversionRequestCount", Permission::ReadConversions, "{ conversionRequestCount }"),
+    ("Query.conversionRequestById", Permission::ReadConversions, r#"{ conversionRequestById(id: "{nil}") { id } }"#),
+    ("Query.conversionRequestsByCreatorId", Permission::ReadConversions, r#"{ conversionRequestsByCreatorId(creatorId: "{nil}") { id } }"#),
+    ("Query.conversionRequestsByAuthorityId", Permission::ReadConversions, r#"{ conversionRequestsByAuthorityId(authorityId: "{nil}") { id } }"#),
+    ("Query.conversionRequestsByDataObjectId", Permission::ReadConversions, r#"{ conversionRequestsByDataObjectId(dataObjectId: "{nil}") { id } }"#),
+    ("Query.conversionRequestsBySourceNationCode", Permission::ReadConversions, r#"{ conversionRequestsBySourceNationCode(nationCode: "GBR") { id } }"#),
+    ("Query.conversionRequestsPending", Permission::ReadConversions, "{ conversionRequestsPending { id } }"),
+    ("Query.conversionRequestsCompleted", Permission::ReadConversions, "{ conversionRequestsCompleted { id } }"),
+    ("Query.conversionRequests", Permission::ReadConversions, "{ conversionRequests { id } }"),
+    ("Query.conversionRequestsCount", Permission::ReadConversions, "{ conversionRequestsCount(count: 1) { id } }"),
+    ("Query.dataObjectCount", Permission::ReadConversions, "{ dataObjectCount }"),
+    ("Query.dataObjectById", Permission::ReadConversions, r#"{ dataObjectById(id: "{nil}") { id } }"#),
+    ("Query.dataObjectsByTitle", Permission::ReadConversions, r#"{ dataObjectsByTitle(title: "plan") { id } }"#),
+    ("Query.dataObjectsByContentDigest", Permission::ReadConversions, r#"{ dataObjectsByContentDigest(contentDigest: "00") { id } }"#),
+    ("Query.searchDataObjects", Permission::ReadConversions, r#"{ searchDataObjects(query: "plan") { rank } }"#),
+    ("Query.dataObjectCountsByMetadataDomain", Permission::ReadConversions, r#"{ dataObjectCountsByMetadataDomain(domain: "OPERATIONS") { id } }"#),
+    ("Query.dataObjects", Permission::ReadConversions, "{ dataObjects { id } }"),
+    ("Query.merkleProof", Permission::ReadConversions, r#"{ merkleProof(receiptId: "{nil}") { leafHash } }"#),
+    ("Query.apiKeys", Permission::ManageApiKeys, "{ apiKeys { name } }"),
+    ("Query.securityEvents", Permission::ManageUsers, "{ securityEvents { eventType } }"),
+    ("Query.verifyAuditChain", Permission::ReadAuditLog, "{ verifyAuditChain { valid } }"),
+    ("Query.auditEvents", Permission::ReadAuditLog, "{ auditEvents(first: 1) { edges { cursor } } }"),
+    ("Query.signInLockouts", Permission::ManageUsers, "{ signInLockouts { subject } }"),
+    (
+        "Mutation.createUser",
+        Permission::ManageUsers,
+        r#"mutation { createUser(userData: {name: "m", email: "{role}@matrix.local", password: "matrix-password", role: "USER"}) { role } }"#,
+    ),
+    ("Mutation.updateUser", Permission::ManageUsers, r#"mutation { updateUser(userData: {id: "{nil}"}) { role } }"#),
+    ("Mutation.approveUser", Permission::ManageUsers, r#"mutation { approveUser(id: "{nil}") { role } }"#),
+    ("Mutation.rejectUser", Permission::ManageUsers, r#"mutation { rejectUser(id: "{nil}") }"#),
+    ("Mutation.suspendUser", Permission::ManageUsers, r#"mutation { suspendUser(id: "{nil}") { role } }"#),
+    ("Mutation.unlockSignIn", Permission::ManageUsers, r#"mutation { unlockSignIn(email: "nobody@example.com") }"#),
+    ("Mutation.resetTotp", Permission::ManageUsers, r#"mutation { resetTotp(id: "{nil}") { role } }"#),
+    ("Mutation.revokeAllSessions", Permission::ManageUsers, r#"mutation { revokeAllSessions(userId: "{nil}") }"#),
+    (
+        "Mutation.createApiKey",
+        Permission::ManageApiKeys,
+        r#"mutation { createApiKey(input: {userId: "{nil}", authorityId: "{nil}", name: "m", role: "USER", scopes: [READ]}) { key } }"#,
+    ),
+    ("Mutation.rotateApiKey", Permission::ManageApiKeys, r#"mutation { rotateApiKey(id: "{nil}") { key } }"#),
+    ("Mutation.revokeApiKey", Permission::ManageApiKeys, r#"mutation { revokeApiKey(id: "{nil}") { name } }"#),
+    (
+        "Mutation.addAuthorityMember",
+        Permission::ManageUsers,
+        r#"mutation { addAuthorityMember(userId: "{nil}", authorityId: "{nil}") { userId } }"#,
+    ),
+    (
+        "Mutation.removeAuthorityMember",
+        Permission::ManageUsers,
+        r#"mutation { removeAuthorityMember(userId: "{nil}", authorityId: "{nil}") }"#,
+    ),
+    (
+        "Mutation.submitConversionRequest",
+        Permission::SubmitConversions,
+        r#"mutation { submitConversionRequest(input: {authorityId: "{nil}", dataObject: {title: "t", description: "d"}, metadata: {domain: "OPERATIONS", tags: []}, sourceNationCode: "GBR", sourceMarking: "SECRET", targetNationCodes: ["USA"]}) { results { targetMarking } } }"#,
+    ),
+    ("Subscription.conversionCompleted", Permission::ReadConversions, "subscription { conversionCompleted { results { targetMarking } } }"),
+];
+
+/// Whether the request got past the field's guard and visibility check.
+/// Fields hidden from the caller fail validation as unknown fields.
+async fn allowed(schema: &AppSchema, role: Option<UserRole>, query: &str) -> bool {
+    let caller = role.map_or("anonymous".to_owned(), |r| r.to_string());
+    let query = query.replace("{nil}", NIL).replace("{role}", &caller);
+
+    let subscription = query.starts_with("subscription");
+
+    let mut request = Request::new(query);
+    if let Some(role) = role {
+        request = request.data(role).data(Uuid::new_v4());
+    }
+
+    let response = if subscription {
+        // An accepted subscription waits for events instead of responding
+        let mut stream = schema.execute_stream(request);
+        match timeout(SUBSCRIPTION_WAIT, stream.next()).await {
+            Ok(Some(response)) => response,
+            _ => return true,
+        }
+    } else {
+        schema.execute(request).await
+    };
+
+    !response.errors.iter().any(|e| {
+        let code = e.extensions.as_ref().and_then(|x| x.get("code"));
+
+        e.message.starts_with("Unknown field")
+            || code == Some(&Value::from("UNAUTHENTICATED"))
+            || code == Some(&Value::from("FORBIDDEN"))
+    })
+}
+
+#[test]
+fn grants_match_expected_roles() {
+    assert_eq!(EXPECTED_ROLES.len(), Permission::iter().count(), "every permission has a row");
+
+    for role in UserRole::iter() {
+        for permission in Permission::iter() {
+            assert_eq!(
+                role.has_permission(permission),
+                expected(role, permission),
+                "{} {:?}",
+                role,
+                permission,
+            );
+        }
+    }
+}
+
+#[test]
+fn roles_inherit_the_roles_below_them() {
+    assert_eq!(UserRole::Analyst.inherits(), Some(UserRole::User));
+    assert_eq!(UserRole::Auditor.inherits(), Some(UserRole::User));
+    assert_eq!(UserRole::Operator.inherits(), Some(UserRole::Analyst));
+    assert_eq!(UserRole::Admin.inherits(), Some(UserRole::Operator));
+
+    // Operator gets Analyst's and User's grants, Admin gets all three below it
+    for permission in [Permission::ReadReferenceData, Permission::ReadConversions] {
+        assert!(UserRole::Operator.has_permission(permission), "{:?}", permission);
+    }
+    for permission in [Permission::ReadReferenceData, Permission::ReadConversions, Permission::SubmitConversions] {
+        assert!(UserRole::Admin.has_permission(permission), "{:?}", permission);
+    }
+
+    // Nothing flows sideways or down
+    assert!(!UserRole::Auditor.has_permission(Permission::ReadConversions));
+    assert!(!UserRole::Operator.has_permission(Permission::ReadAuditLog));
+    assert!(!UserRole::Analyst.has_permission(Permission::SubmitConversions));
+}
+
+#[actix_rt::test]
+async fn guards_match_expected_roles() {
+    let repos = Repositories::in_memory();
+    database::init_in_memory(&repos).await;
+    let schema = create_schema_with_context(repos);
+
+    let callers: Vec<Option<UserRole>> =
+        std::iter::once(None).chain(UserRole::iter().map(Some)).collect();
+
+    let mut mismatches = Vec::new();
+
+    for (field, permission, query) in GUARDED_FIELDS {
+        for caller in &callers {
+            let expected = caller.is_some_and(|r| expected(r, *permission));
+            let actual = allowed(&schema, *caller, query).await;
+
+            if expected != actual {
+                let caller = caller.map_or("ANONYMOUS".to_owned(), |r| r.to_string());
+                let outcome = if actual { "allowed" } else { "denied" };
+                mismatches.push(format!("{} {} {}", field, caller, outcome));
+            }
+        }
+    }
+
+    assert!(mismatches.is_empty(), "cells differ from the expected roles:\n{}", mismatches.join("\n"));
+}
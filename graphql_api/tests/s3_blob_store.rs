@@ -0,0 +1,187 @@
+//! `S3BlobStore` against a mock S3 endpoint that records each request: small
+//! blobs go up in one signed PUT, larger ones as a multipart upload that is
+//! completed on commit or aborted.
+
+use std::sync::{Arc, Mutex};
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use sha2::{Digest, Sha256};
+
+use graphql_api::blob_store::{BlobStore, S3BlobStore};
+use graphql_api::config_variables::UPLOAD_PART_SIZE;
+
+#[derive(Debug, Clone)]
+struct Recorded {
+    method: String,
+    path: String,
+    query: String,
+    content_sha256: String,
+    authorization: String,
+    body: Vec<u8>,
+}
+
+type Log = Arc<Mutex<Vec<Recorded>>>;
+
+fn header(request: &HttpRequest, name: &str) -> String {
+    request
+        .headers()
+        .get(name)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .unwrap_or_default()
+}
+
+/// Answers like S3 would, refusing any object named `refused`
+async fn record(request: HttpRequest, body: web::Bytes, log: web::Data<Log>) -> HttpResponse {
+    let query = request.query_string().to_owned();
+
+    log.lock().unwrap().push(Recorded {
+        method: request.method().to_string(),
+        path: request.path().to_owned(),
+        query: query.clone(),
+        content_sha256: header(&request, "x-amz-content-sha256"),
+        authorization: header(&request, "authorization"),
+        body: body.to_vec(),
+    });
+
+    if request.path().ends_with("/refused") {
+        return HttpResponse::Forbidden().body("<Error><Code>AccessDenied</Code></Error>");
+    }
+
+    match request.method().as_str() {
+        "POST" if query == "uploads=" => HttpResponse::Ok()
+            .body("<InitiateMultipartUploadResult><UploadId>up-1</UploadId></InitiateMultipartUploadResult>"),
+        "PUT" if query.starts_with("partNumber=") => {
+            let part = query.split('&').next().unwrap().trim_start_matches("partNumber=").to_owned();
+            HttpResponse::Ok().insert_header(("ETag", format!("\"etag-{}\"", part))).finish()
+        }
+        "DELETE" => HttpResponse::NoContent().finish(),
+        _ => HttpResponse::Ok().finish(),
+    }
+}
+
+/// A store for the bucket `docs` behind a mock endpoint, and its request log
+fn start_s3() -> (S3BlobStore, Log) {
+    let log = Log::default();
+    let data = web::Data::new(log.clone());
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}/s3", listener.local_addr().unwrap());
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(data.clone())
+            .app_data(web::PayloadConfig::new(2 * UPLOAD_PART_SIZE))
+            .default_service(web::to(record))
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+
+    actix_rt::spawn(server);
+
+    (S3BlobStore::new(&endpoint, "docs", "eu-west-2", "AKIDEXAMPLE", "secret"), log)
+}
+
+fn requests(log: &Log) -> Vec<(String, String, String)> {
+    log.lock()
+        .unwrap()
+        .iter()
+        .map(|r| (r.method.clone(), r.path.clone(), r.query.clone()))
+        .collect()
+}
+
+fn owned(method: &str, path: &str, query: &str) -> (String, String, String) {
+    (method.to_owned(), path.to_owned(), query.to_owned())
+}
+
+#[actix_rt::test]
+async fn small_blobs_are_sent_in_one_signed_put() {
+    let (store, log) = start_s3();
+
+    let mut writer = store.create("small").await.unwrap();
+    writer.write(b"the ").await.unwrap();
+    writer.write(b"plan").await.unwrap();
+    assert!(log.lock().unwrap().is_empty());
+
+    writer.commit().await.unwrap();
+
+    let recorded = log.lock().unwrap().clone();
+    assert_eq!(requests(&log), vec![owned("PUT", "/s3/docs/small", "")]);
+    assert_eq!(recorded[0].body, b"the plan");
+    assert_eq!(recorded[0].content_sha256, format!("{:x}", Sha256::digest(b"the plan")));
+
+    let authorization = &recorded[0].authorization;
+    assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"), "{}", authorization);
+    let scope = "/eu-west-2/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature=";
+    assert!(authorization.contains(scope), "{}", authorization);
+}
+
+#[actix_rt::test]
+async fn large_blobs_are_sent_as_a_multipart_upload() {
+    let (store, log) = start_s3();
+
+    let mut writer = store.create("large").await.unwrap();
+    for _ in 0..UPLOAD_PART_SIZE / (1024 * 1024) {
+        writer.write(&[7u8; 1024 * 1024]).await.unwrap();
+    }
+    writer.write(b"tail").await.unwrap();
+
+    // The first part goes up as soon as it is full
+    assert_eq!(
+        requests(&log),
+        vec![
+            owned("POST", "/s3/docs/large", "uploads="),
+            owned("PUT", "/s3/docs/large", "partNumber=1&uploadId=up-1"),
+        ],
+    );
+
+    writer.commit().await.unwrap();
+
+    let recorded = log.lock().unwrap().clone();
+    assert_eq!(recorded.len(), 4);
+    assert_eq!(recorded[1].body.len(), UPLOAD_PART_SIZE);
+    assert_eq!((recorded[2].query.as_str(), recorded[2].body.as_slice()), ("partNumber=2&uploadId=up-1", &b"tail"[..]));
+
+    assert_eq!((recorded[3].method.as_str(), recorded[3].query.as_str()), ("POST", "uploadId=up-1"));
+    assert_eq!(
+        String::from_utf8(recorded[3].body.clone()).unwrap(),
+        "<CompleteMultipartUpload>\
+            <Part><PartNumber>1</PartNumber><ETag>\"etag-1\"</ETag></Part>\
+            <Part><PartNumber>2</PartNumber><ETag>\"etag-2\"</ETag></Part>\
+        </CompleteMultipartUpload>",
+    );
+}
+
+#[actix_rt::test]
+async fn aborting_cancels_a_started_multipart_upload() {
+    let (store, log) = start_s3();
+
+    // Nothing sent yet, so nothing to cancel
+    let mut writer = store.create("unsent").await.unwrap();
+    writer.write(b"the plan").await.unwrap();
+    writer.abort().await;
+    assert!(log.lock().unwrap().is_empty());
+
+    let mut writer = store.create("started").await.unwrap();
+    writer.write(&vec![7u8; UPLOAD_PART_SIZE]).await.unwrap();
+    writer.abort().await;
+
+    assert_eq!(
+        requests(&log).last(),
+        Some(&owned("DELETE", "/s3/docs/started", "uploadId=up-1")),
+    );
+}
+
+#[actix_rt::test]
+async fn delete_removes_the_object_and_errors_are_reported() {
+    let (store, log) = start_s3();
+
+    store.delete("old").await.unwrap();
+    assert_eq!(requests(&log), vec![owned("DELETE", "/s3/docs/old", "")]);
+
+    let mut writer = store.create("refused").await.unwrap();
+    writer.write(b"the plan").await.unwrap();
+    let error = writer.commit().await.unwrap_err();
+    assert!(error.message.contains("403"), "{}", error.message);
+}
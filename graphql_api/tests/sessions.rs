@@ -0,0 +1,150 @@
+//! Signed-in sessions don't outlive a change to the user's privileges:
+//! access tokens stop verifying once the role or clearance changes, and open
+//! subscriptions end with an error once the session is revoked.
+
+use std::sync::Once;
+use std::time::Duration;
+
+use async_graphql::{Request, Response};
+use chrono::Utc;
+use futures::StreamExt;
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::graphql::{create_schema_with_context, ConversionBroker};
+use graphql_api::models::{
+    verify_token, ConversionCompleted, ConversionRequest, NatoClassification,
+};
+use graphql_api::repositories::{Repositories, TenantScope};
+
+mod common;
+use common::create_user;
+
+static ENV: Once = Once::new();
+
+/// Session tokens are signed with JWT_SECRET_KEY
+fn set_env() {
+    ENV.call_once(|| {
+        // SAFETY: runs once, before any test reads the environment
+        unsafe { std::env::set_var("JWT_SECRET_KEY", "sessions-test-secret-sessions-test") };
+    });
+}
+
+async fn execute(repos: &Repositories, caller: Option<(UserRole, Uuid)>, query: &str) -> Response {
+    let mut request = Request::new(query);
+    if let Some((role, id)) = caller {
+        request = request.data(role).data(id);
+    }
+
+    create_schema_with_context(repos.clone()).execute(request).await
+}
+
+/// Signs in with the password and returns the access token
+async fn sign_in(repos: &Repositories, email: &str) -> String {
+    let response = execute(
+        repos,
+        None,
+        &format!(
+            r#"mutation {{ signIn(input: {{email: "{}", password: "correct-horse-battery"}}) {{
+                ... on UserResponse {{ bearer }}
+            }} }}"#,
+            email,
+        ),
+    )
+    .await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let data = response.data.into_json().unwrap();
+    data["signIn"]["bearer"].as_str().expect("tokens, not a challenge").to_owned()
+}
+
+async fn update_user(repos: &Repositories, admin: Uuid, fields: &str) {
+    let response = execute(
+        repos,
+        Some((UserRole::Admin, admin)),
+        &format!("mutation {{ updateUser(userData: {{{}}}) {{ id }} }}", fields),
+    )
+    .await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+}
+
+#[actix_rt::test]
+async fn privilege_changes_sign_the_user_out() {
+    set_env();
+    let repos = Repositories::in_memory();
+    let admin = create_user(&repos, "admin@example.org", UserRole::Admin, Some(NatoClassification::Secret), Some("GBR")).await;
+    let user = create_user(&repos, "user@example.org", UserRole::User, Some(NatoClassification::Secret), Some("GBR")).await;
+
+    let token = sign_in(&repos, "user@example.org").await;
+    update_user(&repos, admin, &format!(r#"id: "{}", name: "Renamed""#, user)).await;
+    assert!(verify_token(&token, &repos).await.is_ok());
+
+    update_user(&repos, admin, &format!(r#"id: "{}", role: "ANALYST""#, user)).await;
+    assert!(verify_token(&token, &repos).await.is_err());
+
+    let token = sign_in(&repos, "user@example.org").await;
+    update_user(&repos, admin, &format!(r#"id: "{}", clearance: RESTRICTED"#, user)).await;
+    assert!(verify_token(&token, &repos).await.is_err());
+}
+
+#[actix_rt::test]
+async fn tokens_naming_an_old_role_are_refused() {
+    set_env();
+    let repos = Repositories::in_memory();
+    let user = create_user(&repos, "user@example.org", UserRole::Analyst, Some(NatoClassification::Secret), Some("GBR")).await;
+
+    let token = sign_in(&repos, "user@example.org").await;
+    assert_eq!(verify_token(&token, &repos).await.unwrap().0, UserRole::Analyst);
+
+    // Demoted without the sessions being revoked
+    let mut demoted = repos.users.get_by_id(user).await.unwrap();
+    demoted.role = UserRole::User.to_string();
+    repos.users.update(demoted).await.unwrap();
+
+    assert!(verify_token(&token, &repos).await.is_err());
+}
+
+#[actix_rt::test]
+async fn subscriptions_end_when_the_session_is_revoked() {
+    set_env();
+    let repos = Repositories::in_memory();
+    let user = create_user(&repos, "user@example.org", UserRole::Analyst, Some(NatoClassification::Secret), Some("GBR")).await;
+    let (role, _, _, session_id) = verify_token(&sign_in(&repos, "user@example.org").await, &repos).await.unwrap();
+
+    let broker = ConversionBroker::default();
+    let request = Request::new("subscription { conversionCompleted { conversionRequest { id } } }")
+        .data(role)
+        .data(user)
+        .data(session_id)
+        .data(TenantScope::All)
+        .data(broker.clone());
+    let schema = create_schema_with_context(repos.clone());
+
+    // Lets the subscription start listening before anything is published
+    let first = actix_rt::spawn(async move { schema.execute_stream(request).next().await });
+    actix_rt::time::sleep(Duration::from_millis(50)).await;
+
+    repos.sessions.revoke_all_by_user_id(user).await.unwrap();
+    broker.publish(ConversionCompleted {
+        conversion_request: ConversionRequest {
+            id: Uuid::new_v4(),
+            creator_id: user,
+            authority_id: Uuid::new_v4(),
+            data_object_id: Uuid::new_v4(),
+            source_nation_code: "GBR".to_owned(),
+            target_nation_codes: vec![Some("USA".to_owned())],
+            created_at: Utc::now().naive_utc(),
+            updated_at: Utc::now().naive_utc(),
+            completed_at: Some(Utc::now().naive_utc()),
+        },
+        results: Vec::new(),
+    });
+
+    let response = actix_rt::time::timeout(Duration::from_secs(5), first)
+        .await
+        .expect("the subscription ends")
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.errors.len(), 1, "{:?}", response.errors);
+    assert_eq!(response.errors[0].message, "Session has ended");
+}
@@ -0,0 +1,262 @@
+//! Failed sign-ins back off and lock out per email and per client address,
+//! `unlockSignIn` lifts that, and each outcome is recorded as a security
+//! event.
+
+use std::sync::Once;
+
+use async_graphql::{Request, Response};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::config_variables::{
+    IP_FREE_ATTEMPTS, IP_LOCKOUT_ATTEMPTS, SIGN_IN_FREE_ATTEMPTS, SIGN_IN_LOCKOUT_ATTEMPTS, SIGN_IN_LOCKOUT_DURATION,
+    SIGN_IN_MAX_BACKOFF,
+};
+use graphql_api::graphql::create_schema_with_context;
+use graphql_api::models::{
+    account_subject, ClientIp, SecurityEventType, SignInAttempt, SignInThrottle, ThrottleKind,
+};
+use graphql_api::repositories::Repositories;
+
+mod common;
+use common::create_user;
+
+const EMAIL: &str = "ada@example.org";
+const IP: &str = "203.0.113.7";
+
+static ENV: Once = Once::new();
+
+/// Session tokens are signed with JWT_SECRET_KEY
+fn set_env() {
+    ENV.call_once(|| {
+        // SAFETY: runs once, before any test reads the environment
+        unsafe { std::env::set_var("JWT_SECRET_KEY", "throttle-test-secret-throttle-test") };
+    });
+}
+
+fn attempt(email: &str) -> SignInAttempt {
+    SignInAttempt::new(email, Some(&ClientIp(IP.to_owned())))
+}
+
+async fn fail(repos: &Repositories, email: &str, times: i32) {
+    for _ in 0..times {
+        attempt(email).failed(repos, None, "Wrong password").await.unwrap();
+    }
+}
+
+async fn sign_in(repos: &Repositories, email: &str, password: &str) -> Response {
+    let query = format!(
+        r#"mutation {{ signIn(input: {{email: "{}", password: "{}"}}) {{ ... on UserResponse {{ bearer }} }} }}"#,
+        email, password,
+    );
+
+    create_schema_with_context(repos.clone())
+        .execute(Request::new(query).data(ClientIp(IP.to_owned())))
+        .await
+}
+
+/// Clears the failures of `email` and of IP as `admin`
+async fn unlock(repos: &Repositories, admin: Uuid, email: &str) -> Response {
+    let query = format!(r#"mutation {{ unlockSignIn(email: "{}", ipAddress: "{}") }}"#, email, IP);
+
+    create_schema_with_context(repos.clone())
+        .execute(Request::new(query).data(UserRole::Admin).data(admin))
+        .await
+}
+
+fn refused(response: &Response) -> bool {
+    response
+        .errors
+        .iter()
+        .any(|e| e.message.starts_with("Too many failed sign-in attempts"))
+}
+
+async fn events(repos: &Repositories, event_type: SecurityEventType) -> usize {
+    repos
+        .security_events
+        .get_recent(1000)
+        .await
+        .unwrap()
+        .iter()
+        .filter(|e| e.event_type == event_type.to_string())
+        .count()
+}
+
+#[test]
+fn backoff_is_free_then_doubles_up_to_the_cap() {
+    for failures in 0..SIGN_IN_FREE_ATTEMPTS {
+        assert_eq!(ThrottleKind::Account.backoff(failures), None);
+    }
+
+    assert_eq!(ThrottleKind::Account.backoff(SIGN_IN_FREE_ATTEMPTS), Some(Duration::seconds(1)));
+    assert_eq!(ThrottleKind::Account.backoff(SIGN_IN_FREE_ATTEMPTS + 1), Some(Duration::seconds(2)));
+    assert_eq!(ThrottleKind::Account.backoff(SIGN_IN_FREE_ATTEMPTS + 2), Some(Duration::seconds(4)));
+
+    // Addresses get more attempts, enough to reach the cap before locking
+    assert_eq!(ThrottleKind::Ip.backoff(IP_FREE_ATTEMPTS - 1), None);
+    assert_eq!(ThrottleKind::Ip.backoff(IP_FREE_ATTEMPTS), Some(Duration::seconds(1)));
+    assert_eq!(ThrottleKind::Ip.backoff(IP_LOCKOUT_ATTEMPTS - 1), Some(Duration::seconds(SIGN_IN_MAX_BACKOFF)));
+}
+
+#[test]
+fn the_lockout_count_locks_for_the_lockout_duration() {
+    let lockout = Some(Duration::seconds(SIGN_IN_LOCKOUT_DURATION));
+
+    assert!(ThrottleKind::Account.backoff(SIGN_IN_LOCKOUT_ATTEMPTS - 1) < lockout);
+    assert_eq!(ThrottleKind::Account.backoff(SIGN_IN_LOCKOUT_ATTEMPTS), lockout);
+    assert_eq!(ThrottleKind::Ip.backoff(IP_LOCKOUT_ATTEMPTS), lockout);
+    assert_eq!(ThrottleKind::Account.backoff(i32::MAX), lockout);
+}
+
+#[test]
+fn a_failure_after_a_lockout_locks_again_straight_away() {
+    let now = Utc::now().naive_utc();
+    let mut throttle = SignInThrottle {
+        id: Uuid::new_v4(),
+        kind: ThrottleKind::Account.to_string(),
+        subject: account_subject(EMAIL),
+        failed_attempts: SIGN_IN_LOCKOUT_ATTEMPTS,
+        first_failed_at: now - Duration::hours(2),
+        last_failed_at: now - Duration::seconds(SIGN_IN_LOCKOUT_DURATION + 1),
+    };
+
+    // The lockout has run out
+    assert!(throttle.is_locked());
+    assert!(throttle.blocked_until().unwrap() < now);
+
+    throttle.failed_attempts += 1;
+    throttle.last_failed_at = now;
+
+    assert_eq!(throttle.blocked_until(), Some(now + Duration::seconds(SIGN_IN_LOCKOUT_DURATION)));
+}
+
+#[actix_rt::test]
+async fn failures_within_the_free_attempts_are_not_delayed() {
+    let repos = Repositories::in_memory();
+
+    for _ in 0..SIGN_IN_FREE_ATTEMPTS {
+        attempt(EMAIL).ensure_allowed(&repos).await.unwrap();
+        fail(&repos, EMAIL, 1).await;
+    }
+
+    assert!(attempt(EMAIL).ensure_allowed(&repos).await.is_err());
+    assert_eq!(events(&repos, SecurityEventType::SignInThrottled).await, 1);
+
+    // Other emails aren't held up by this one
+    attempt("bo@example.org").ensure_allowed(&repos).await.unwrap();
+}
+
+#[actix_rt::test]
+async fn record_failure_counts_and_locks() {
+    let repos = Repositories::in_memory();
+    let subject = account_subject(" Ada@Example.org ");
+
+    for count in 1..=SIGN_IN_LOCKOUT_ATTEMPTS {
+        let throttle = repos.sign_in_throttles.record_failure(ThrottleKind::Account, subject.clone()).await.unwrap();
+
+        assert_eq!(throttle.failed_attempts, count);
+        assert_eq!(throttle.is_locked(), count == SIGN_IN_LOCKOUT_ATTEMPTS);
+    }
+
+    // Counted on past the lockout, so each further failure locks again
+    let throttle = repos.sign_in_throttles.record_failure(ThrottleKind::Account, subject).await.unwrap();
+    assert_eq!(throttle.failed_attempts, SIGN_IN_LOCKOUT_ATTEMPTS + 1);
+    assert_eq!(
+        throttle.blocked_until(),
+        Some(throttle.last_failed_at + Duration::seconds(SIGN_IN_LOCKOUT_DURATION)),
+    );
+}
+
+#[actix_rt::test]
+async fn reaching_the_lockout_count_refuses_even_the_right_password() {
+    set_env();
+    let repos = Repositories::in_memory();
+    create_user(&repos, EMAIL, UserRole::User, None, None).await;
+
+    fail(&repos, EMAIL, SIGN_IN_LOCKOUT_ATTEMPTS).await;
+
+    let response = sign_in(&repos, EMAIL, "correct-horse-battery").await;
+    assert!(refused(&response), "{:?}", response.errors);
+
+    let locked = repos.sign_in_throttles.get(ThrottleKind::Account, account_subject(EMAIL)).await.unwrap().unwrap();
+    assert!(locked.is_locked());
+    assert_eq!(events(&repos, SecurityEventType::SignInLocked).await, 1);
+}
+
+#[actix_rt::test]
+async fn the_address_counter_blocks_across_emails() {
+    set_env();
+    let repos = Repositories::in_memory();
+    create_user(&repos, EMAIL, UserRole::User, None, None).await;
+
+    for n in 0..IP_FREE_ATTEMPTS {
+        let response = sign_in(&repos, &format!("guess{}@example.org", n), "correct-horse-battery").await;
+        assert!(!refused(&response), "{:?}", response.errors);
+    }
+
+    // No failures for this email, but the address is backing off
+    let response = sign_in(&repos, EMAIL, "correct-horse-battery").await;
+    assert!(refused(&response), "{:?}", response.errors);
+}
+
+#[actix_rt::test]
+async fn unlock_sign_in_clears_the_count() {
+    set_env();
+    let repos = Repositories::in_memory();
+    let admin = create_user(&repos, "admin@example.org", UserRole::Admin, None, None).await;
+    create_user(&repos, EMAIL, UserRole::User, None, None).await;
+
+    fail(&repos, EMAIL, SIGN_IN_LOCKOUT_ATTEMPTS).await;
+    assert!(refused(&sign_in(&repos, EMAIL, "correct-horse-battery").await));
+
+    let response = unlock(&repos, admin, &EMAIL.to_uppercase()).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data.into_json().unwrap()["unlockSignIn"], true);
+
+    assert!(repos.sign_in_throttles.get(ThrottleKind::Account, account_subject(EMAIL)).await.unwrap().is_none());
+    assert!(repos.sign_in_throttles.get(ThrottleKind::Ip, IP.to_owned()).await.unwrap().is_none());
+    assert_eq!(events(&repos, SecurityEventType::SignInUnlocked).await, 1);
+
+    let response = sign_in(&repos, EMAIL, "correct-horse-battery").await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    // Nothing left to clear
+    assert_eq!(unlock(&repos, admin, EMAIL).await.data.into_json().unwrap()["unlockSignIn"], false);
+}
+
+#[actix_rt::test]
+async fn sign_in_outcomes_are_recorded() {
+    set_env();
+    let repos = Repositories::in_memory();
+    let user_id = create_user(&repos, EMAIL, UserRole::User, None, None).await;
+
+    assert!(!sign_in(&repos, EMAIL, "wrong-horse-battery").await.errors.is_empty());
+    assert!(sign_in(&repos, EMAIL, "correct-horse-battery").await.errors.is_empty());
+    fail(&repos, EMAIL, SIGN_IN_LOCKOUT_ATTEMPTS).await;
+
+    let recorded = repos.security_events.get_recent(1000).await.unwrap();
+
+    let failed = recorded
+        .iter()
+        .find(|e| e.event_type == SecurityEventType::SignInFailed.to_string() && e.user_id.is_some())
+        .expect("the failed password");
+    assert_eq!(failed.user_id, Some(user_id));
+    assert_eq!(failed.email.as_deref(), Some(EMAIL));
+    assert_eq!(failed.ip_address.as_deref(), Some(IP));
+
+    let succeeded = recorded
+        .iter()
+        .find(|e| e.event_type == SecurityEventType::SignInSucceeded.to_string())
+        .expect("the session issued");
+    assert_eq!(succeeded.user_id, Some(user_id));
+    assert_eq!(succeeded.ip_address.as_deref(), Some(IP));
+
+    // The success cleared the account count, so it locks after a full run
+    let locked = recorded
+        .iter()
+        .find(|e| e.event_type == SecurityEventType::SignInLocked.to_string())
+        .expect("the lockout");
+    assert_eq!(locked.email.as_deref(), Some(EMAIL));
+    assert!(locked.detail.as_deref().unwrap().starts_with("ACCOUNT locked for 30 minutes"), "{:?}", locked.detail);
+}
@@ -0,0 +1,170 @@
+//! Access tokens signed from a `JWT_SIGNING_KEYS` keyring.
+//!
+//! The keyring holds an RS256 key that retired a minute ago and the EdDSA key
+//! that replaced it. `signIn` signs with the EdDSA key, tokens the RS256 key
+//! signed before retiring still verify, and the JWKS publishes both without
+//! their private parts.
+
+use std::sync::OnceLock;
+
+use async_graphql::Request;
+use chrono::{Duration, Utc};
+use ed25519_dalek::pkcs8::EncodePrivateKey as _;
+use jsonwebtoken::{decode_header, encode, Algorithm, EncodingKey, Header};
+use rsa::pkcs8::LineEnding;
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::graphql::create_schema_with_context;
+use graphql_api::models::{public_jwks, verify_token, Claims, NatoClassification};
+use graphql_api::repositories::Repositories;
+
+mod common;
+use common::create_user;
+
+const RSA_KID: &str = "rsa-retired";
+const ED_KID: &str = "ed-current";
+
+struct Keys {
+    rsa: EncodingKey,
+    ed: EncodingKey,
+}
+
+static KEYS: OnceLock<Keys> = OnceLock::new();
+
+/// Writes the keyring and points JWT_SIGNING_KEYS at it
+fn keys() -> &'static Keys {
+    KEYS.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("scc-signing-keys-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let rsa_pem = rsa::RsaPrivateKey::new(&mut rand::rngs::OsRng, 2048)
+            .unwrap()
+            .to_pkcs8_pem(LineEnding::LF)
+            .unwrap();
+        let ed_pem = ed25519_dalek::SigningKey::from_bytes(&rand::random())
+            .to_pkcs8_pem(LineEnding::LF)
+            .unwrap();
+
+        std::fs::write(dir.join("rsa.pem"), rsa_pem.as_bytes()).unwrap();
+        std::fs::write(dir.join("ed.pem"), ed_pem.as_bytes()).unwrap();
+
+        let rotation = Utc::now() - Duration::minutes(1);
+        let keyring = serde_json::json!([
+            { "kid": RSA_KID, "alg": "RS256", "private_key": "rsa.pem", "retire_at": rotation },
+            { "kid": ED_KID, "alg": "EdDSA", "private_key": "ed.pem", "active_from": rotation },
+        ]);
+        std::fs::write(dir.join("keyring.json"), keyring.to_string()).unwrap();
+
+        // SAFETY: runs once, before any test reads the environment
+        unsafe { std::env::set_var("JWT_SIGNING_KEYS", dir.join("keyring.json")) };
+
+        Keys {
+            rsa: EncodingKey::from_rsa_pem(rsa_pem.as_bytes()).unwrap(),
+            ed: EncodingKey::from_ed_pem(ed_pem.as_bytes()).unwrap(),
+        }
+    })
+}
+
+/// Signs in a new user and returns the repositories and access token
+async fn sign_in() -> (Repositories, String) {
+    keys();
+    let repos = Repositories::in_memory();
+
+    create_user(&repos, "analyst@example.org", UserRole::Analyst, Some(NatoClassification::Secret), Some("GBR")).await;
+
+    let response = create_schema_with_context(repos.clone())
+        .execute(Request::new(
+            r#"mutation { signIn(input: {email: "analyst@example.org", password: "correct-horse-battery"}) {
+                ... on UserResponse { bearer }
+            } }"#,
+        ))
+        .await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let data = response.data.into_json().unwrap();
+    let token = data["signIn"]["bearer"].as_str().unwrap().to_owned();
+
+    (repos, token)
+}
+
+/// The claims of a verified token, re-signed with `key` under `alg`/`kid`
+async fn resign(repos: &Repositories, token: &str, alg: Algorithm, kid: &str, key: &EncodingKey) -> String {
+    let (role, user_id, exp, session_id) = verify_token(token, repos).await.unwrap();
+
+    let claims = Claims {
+        sub: user_id.to_string(),
+        exp,
+        role: role.to_string(),
+        sid: session_id.0.to_string(),
+    };
+
+    let mut header = Header::new(alg);
+    header.kid = Some(kid.to_owned());
+
+    encode(&header, &claims, key).unwrap()
+}
+
+#[actix_rt::test]
+async fn sign_in_signs_with_the_active_eddsa_key() {
+    let (repos, token) = sign_in().await;
+
+    let header = decode_header(&token).unwrap();
+    assert_eq!(header.alg, Algorithm::EdDSA);
+    assert_eq!(header.kid.as_deref(), Some(ED_KID));
+
+    let (role, ..) = verify_token(&token, &repos).await.unwrap();
+    assert_eq!(role, UserRole::Analyst);
+}
+
+#[actix_rt::test]
+async fn tokens_from_the_retired_rs256_key_still_verify() {
+    let (repos, token) = sign_in().await;
+
+    let rs256 = resign(&repos, &token, Algorithm::RS256, RSA_KID, &keys().rsa).await;
+
+    let (role, ..) = verify_token(&rs256, &repos).await.unwrap();
+    assert_eq!(role, UserRole::Analyst);
+}
+
+#[actix_rt::test]
+async fn tokens_whose_alg_does_not_match_their_kid_are_rejected() {
+    let (repos, token) = sign_in().await;
+
+    let ed_as_rsa = resign(&repos, &token, Algorithm::EdDSA, RSA_KID, &keys().ed).await;
+    assert!(verify_token(&ed_as_rsa, &repos).await.is_err());
+
+    let rsa_as_ed = resign(&repos, &token, Algorithm::RS256, ED_KID, &keys().rsa).await;
+    assert!(verify_token(&rsa_as_ed, &repos).await.is_err());
+
+    let unknown = resign(&repos, &token, Algorithm::EdDSA, "unknown", &keys().ed).await;
+    assert!(verify_token(&unknown, &repos).await.is_err());
+}
+
+#[actix_rt::test]
+async fn the_jwks_holds_only_public_parameters() {
+    keys();
+    let jwks = serde_json::to_value(public_jwks()).unwrap();
+
+    let keys = jwks["keys"].as_array().unwrap();
+    assert_eq!(keys.len(), 2);
+
+    for key in keys {
+        let mut fields: Vec<&str> = key.as_object().unwrap().keys().map(String::as_str).collect();
+        fields.sort();
+
+        match key["kid"].as_str().unwrap() {
+            RSA_KID => {
+                assert_eq!(fields, vec!["alg", "e", "kid", "kty", "n", "use"]);
+                assert_eq!(key["kty"], "RSA");
+                assert_eq!(key["alg"], "RS256");
+            }
+            ED_KID => {
+                assert_eq!(fields, vec!["alg", "crv", "kid", "kty", "use", "x"]);
+                assert_eq!(key["crv"], "Ed25519");
+                assert_eq!(key["alg"], "EdDSA");
+            }
+            other => panic!("unexpected kid {}", other),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Members of one authority don't see another authority's conversion requests
+//! or data objects through `Repositories::scoped`.
+//!
+//! Authority A is British and authority B French. B submits one request
+//! between French and Italian nations and one to GBR. A's members see their
+//! own request and B's request to GBR, never B's other request or its data
+//! object, and the reverse holds for B.
+
+use std::collections::HashSet;
+
+use uuid::Uuid;
+
+use graphql_api::common_utils::UserRole;
+use graphql_api::config_variables::SEARCH_RESULT_LIMIT;
+use graphql_api::models::{NatoClassification, NewAuthority, NewAuthorityMember, NewNation};
+use graphql_api::repositories::{Clearance, Repositories, SearchVisibility, TenantScope};
+
+mod common;
+use common::{conversion_request, create_user};
+
+struct Fixture {
+    repos: Repositories,
+    authority_a: Uuid,
+    authority_b: Uuid,
+    /// A's request, GBR to DEU
+    request_a: (Uuid, Uuid),
+    /// B's request, FRA to ITA
+    request_b: (Uuid, Uuid),
+    /// B's request, FRA to GBR
+    request_b_to_gbr: (Uuid, Uuid),
+}
+
+async fn create_authority(repos: &Repositories, creator_id: Uuid, nation_code: &str) -> Uuid {
+    let nation = repos
+        .nations
+        .create(NewNation::new(creator_id, nation_code.to_owned(), nation_code.to_owned()))
+        .await
+        .unwrap();
+
+    repos
+        .authorities
+        .create(NewAuthority::new(
+            creator_id,
+            nation.id,
+            format!("{} authority", nation_code),
+            format!("authority@{}.example", nation_code.to_lowercase()),
+            "0".to_owned(),
+            None,
+        ))
+        .await
+        .unwrap()
+        .id
+}
+
+/// Submits a request and returns its id and its data object's id
+async fn submit(repos: &Repositories, user_id: Uuid, authority_id: Uuid, from: &str, to: &str) -> (Uuid, Uuid) {
+    let request = repos
+        .conversion_requests
+        .process_payload(conversion_request(user_id, authority_id, &format!("{} to {}", from, to), from, to))
+        .await
+        .unwrap();
+
+    (request.id, request.data_object_id)
+}
+
+async fn fixture() -> Fixture {
+    let repos = Repositories::in_memory();
+    let creator = create_user(&repos, "creator@example.org", UserRole::Analyst, Some(NatoClassification::TopSecret), Some("GBR")).await;
+
+    let authority_a = create_authority(&repos, creator, "GBR").await;
+    let authority_b = create_authority(&repos, creator, "FRA").await;
+
+    let request_a = submit(&repos, creator, authority_a, "GBR", "DEU").await;
+    let request_b = submit(&repos, creator, authority_b, "FRA", "ITA").await;
+    let request_b_to_gbr = submit(&repos, creator, authority_b, "FRA", "GBR").await;
+
+    Fixture { repos, authority_a, authority_b, request_a, request_b, request_b_to_gbr }
+}
+
+/// The repositories a member of `authority_id` gets
+async fn member_of(fixture: &Fixture, authority_id: Uuid, email: &str, nationality: &str) -> Repositories {
+    let repos = &fixture.repos;
+    let user_id = create_user(repos, email, UserRole::Analyst, Some(NatoClassification::TopSecret), Some(nationality)).await;
+
+    repos
+        .authority_members
+        .add(NewAuthorityMember { user_id, authority_id })
+        .await
+        .unwrap();
+
+    let scope = TenantScope::for_caller(repos, UserRole::Analyst, user_id, None).await.unwrap();
+    let user = repos.users.get_by_id(user_id).await.unwrap();
+
+    repos.scoped(&scope, &Clearance::for_user(&user))
+}
+
+async fn visible_requests(repos: &Repositories) -> HashSet<Uuid> {
+    repos.conversion_requests.get_all().await.unwrap().into_iter().map(|r| r.id).collect()
+}
+
+async fn visible_data_objects(repos: &Repositories) -> HashSet<Uuid> {
+    repos.data_objects.get_all().await.unwrap().into_iter().map(|d| d.id).collect()
+}
+
+#[actix_rt::test]
+async fn members_only_see_their_authority_and_nation() {
+    let fixture = fixture().await;
+    let a = member_of(&fixture, fixture.authority_a, "a@example.org", "GBR").await;
+    let b = member_of(&fixture, fixture.authority_b, "b@example.org", "FRA").await;
+
+    assert_eq!(visible_requests(&a).await, HashSet::from([fixture.request_a.0, fixture.request_b_to_gbr.0]));
+    assert_eq!(visible_requests(&b).await, HashSet::from([fixture.request_b.0, fixture.request_b_to_gbr.0]));
+
+    assert_eq!(visible_data_objects(&a).await, HashSet::from([fixture.request_a.1, fixture.request_b_to_gbr.1]));
+    assert_eq!(visible_data_objects(&b).await, HashSet::from([fixture.request_b.1, fixture.request_b_to_gbr.1]));
+
+    assert_eq!(a.data_objects.count().await.unwrap(), 2);
+    assert_eq!(b.data_objects.count().await.unwrap(), 2);
+}
+
+#[actix_rt::test]
+async fn lookups_outside_the_scope_are_not_found() {
+    let fixture = fixture().await;
+    let a = member_of(&fixture, fixture.authority_a, "a@example.org", "GBR").await;
+
+    let (request_b, data_object_b) = fixture.request_b;
+
+    assert!(a.conversion_requests.get_by_id(request_b).await.is_err());
+    assert!(a.data_objects.get_by_id(data_object_b).await.is_err());
+    assert!(a.conversion_requests.get_by_authority_id(fixture.authority_b).await.unwrap()
+        .iter()
+        .all(|r| r.id != request_b));
+    assert!(a.conversion_requests.get_by_data_object_id(data_object_b).await.unwrap().is_empty());
+
+    let all_ids = vec![fixture.request_a.1, data_object_b, fixture.request_b_to_gbr.1];
+    let found: HashSet<Uuid> = a.data_objects.get_by_ids(all_ids).await.unwrap().into_iter().map(|d| d.id).collect();
+    assert_eq!(found, HashSet::from([fixture.request_a.1, fixture.request_b_to_gbr.1]));
+}
+
+#[actix_rt::test]
+async fn limited_listing_stays_in_scope() {
+    let fixture = fixture().await;
+    let a = member_of(&fixture, fixture.authority_a, "a@example.org", "GBR").await;
+
+    let in_scope = HashSet::from([fixture.request_a.0, fixture.request_b_to_gbr.0]);
+
+    for count in 0..4 {
+        let requests = a.conversion_requests.get_limited(count).await.unwrap();
+
+        assert_eq!(requests.len(), (count as usize).min(2));
+        assert!(requests.iter().all(|r| in_scope.contains(&r.id)));
+    }
+}
+
+#[actix_rt::test]
+async fn members_of_no_authority_see_nothing() {
+    let fixture = fixture().await;
+    let repos = &fixture.repos;
+
+    let user_id = create_user(repos, "loner@example.org", UserRole::Analyst, Some(NatoClassification::TopSecret), Some("USA")).await;
+    let scope = TenantScope::for_caller(repos, UserRole::Analyst, user_id, None).await.unwrap();
+    let user = repos.users.get_by_id(user_id).await.unwrap();
+    let scoped = repos.scoped(&scope, &Clearance::for_user(&user));
+
+    assert!(visible_requests(&scoped).await.is_empty());
+    assert!(visible_data_objects(&scoped).await.is_empty());
+    assert_eq!(scoped.data_objects.count().await.unwrap(), 0);
+}
+
+#[actix_rt::test]
+async fn search_results_are_not_crowded_out_by_other_authorities() {
+    let fixture = fixture().await;
+    let repos = &fixture.repos;
+    let a = member_of(&fixture, fixture.authority_a, "a@example.org", "GBR").await;
+
+    let creator = repos.conversion_requests.get_by_id(fixture.request_b.0).await.unwrap().creator_id;
+    for _ in 0..SEARCH_RESULT_LIMIT {
+        submit(repos, creator, fixture.authority_b, "FRA", "ITA").await;
+    }
+
+    let found: HashSet<Uuid> = a
+        .data_objects
+        .search("plan".to_owned(), None, None, SearchVisibility::default())
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.data_object.id)
+        .collect();
+    assert_eq!(found, HashSet::from([fixture.request_a.1, fixture.request_b_to_gbr.1]));
+
+    let requests = a.conversion_requests.get_limited(2).await.unwrap();
+    assert_eq!(requests.len(), 2);
+}
@@ -0,0 +1,276 @@
+//! `/data-objects/upload` into a `LocalBlobStore` in a fresh directory:
+//! documents are stored with their SHA-256 and sniffed type, refused forms
+//! leave no blob behind, and the data object recorded can be converted by
+//! `submitConversionRequest`.
+//!
+//! UPLOAD_MAX_BYTES is lowered for the whole process, and MFA_REQUIRED_ROLES
+//! emptied so operators sign in with their password alone.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Once};
+
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{test, web, App};
+use async_graphql::Request;
+use sha2::{Digest, Sha256};
+use tera::Tera;
+use uuid::Uuid;
+
+use graphql_api::blob_store::{BlobStore, LocalBlobStore};
+use graphql_api::common_utils::UserRole;
+use graphql_api::config_variables::{UPLOAD_FIELD_LIMIT, UPLOAD_MAX_FIELDS};
+use graphql_api::graphql::create_schema_with_context;
+use graphql_api::handlers::upload_data_object;
+use graphql_api::mail::Mailer;
+use graphql_api::models::{NatoClassification, NewAuthority, NewNation};
+use graphql_api::repositories::Repositories;
+
+mod common;
+use common::{classification_schema, create_user};
+
+const MAX_BYTES: usize = 4096;
+const BOUNDARY: &str = "scc-upload-boundary";
+
+static ENV: Once = Once::new();
+
+fn set_env() {
+    ENV.call_once(|| {
+        // SAFETY: runs once, before any test reads the environment
+        unsafe {
+            std::env::set_var("JWT_SECRET_KEY", "upload-test-secret-upload-test-secret");
+            std::env::set_var("MFA_REQUIRED_ROLES", "");
+            std::env::set_var("UPLOAD_MAX_BYTES", MAX_BYTES.to_string());
+        };
+    });
+}
+
+/// An operator, their access token and a blob store in its own directory
+struct Uploader {
+    repos: Repositories,
+    user_id: Uuid,
+    bearer: String,
+    dir: PathBuf,
+}
+
+impl Uploader {
+    async fn new() -> Self {
+        set_env();
+        let repos = Repositories::in_memory();
+        let clearance = Some(NatoClassification::TopSecret);
+        let user_id = create_user(&repos, "op@example.org", UserRole::Operator, clearance, Some("GBR")).await;
+
+        let response = create_schema_with_context(repos.clone())
+            .execute(Request::new(
+                r#"mutation { signIn(input: {email: "op@example.org", password: "correct-horse-battery"}) {
+                    ... on UserResponse { bearer }
+                } }"#,
+            ))
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let bearer = response.data.into_json().unwrap()["signIn"]["bearer"].as_str().unwrap().to_owned();
+
+        let dir = std::env::temp_dir().join(format!("scc-uploads-{}", Uuid::new_v4()));
+
+        Uploader { repos, user_id, bearer, dir }
+    }
+
+    /// Blobs in the store, partial ones included
+    fn blobs(&self) -> Vec<String> {
+        std::fs::read_dir(&self.dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect()
+    }
+
+    /// Posts a form of `(name, content)` parts, `file` ones as files
+    async fn upload(&self, parts: &[(&str, &[u8])]) -> (u16, serde_json::Value) {
+        let store: Arc<dyn BlobStore> = Arc::new(LocalBlobStore::new(&self.dir).unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(self.repos.clone()))
+                .app_data(web::Data::from(store))
+                .route("/data-objects/upload", web::post().to(upload_data_object)),
+        )
+        .await;
+
+        let request = test::TestRequest::post()
+            .uri("/data-objects/upload")
+            .insert_header(("Authorization", format!("Bearer {}", self.bearer)))
+            .insert_header(("Content-Type", format!("multipart/form-data; boundary={}", BOUNDARY)))
+            .set_payload(form(parts))
+            .to_request();
+
+        read(&app, request).await
+    }
+}
+
+impl Drop for Uploader {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+async fn read<S>(app: &S, request: actix_http::Request) -> (u16, serde_json::Value)
+where
+    S: Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+{
+    let response = test::call_service(app, request).await;
+    let status = response.status().as_u16();
+
+    (status, test::read_body_json(response).await)
+}
+
+fn form(parts: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut body = Vec::new();
+
+    for (name, content) in parts {
+        body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+
+        let disposition = match *name {
+            "file" => "Content-Disposition: form-data; name=\"file\"; filename=\"plan.pdf\"\r\n\
+                Content-Type: application/pdf\r\n"
+                .to_owned(),
+            _ => format!("Content-Disposition: form-data; name=\"{}\"\r\n", name),
+        };
+        body.extend_from_slice(disposition.as_bytes());
+        body.extend_from_slice(b"\r\n");
+        body.extend_from_slice(content);
+        body.extend_from_slice(b"\r\n");
+    }
+
+    body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+    body
+}
+
+const PDF: &[u8] = b"%PDF-1.7\nthe plan\n%%EOF\n";
+
+#[actix_rt::test]
+async fn the_document_is_stored_with_its_sha256() {
+    let uploader = Uploader::new().await;
+
+    let (status, body) = uploader
+        .upload(&[("title", b"plan"), ("domain", b"OPERATIONS"), ("tags", b"alpha, beta"), ("file", PDF)])
+        .await;
+    assert_eq!(status, 201, "{}", body);
+
+    let sha256 = format!("{:x}", Sha256::digest(PDF));
+    assert_eq!(body["contentDigest"], sha256);
+    assert_eq!(body["digestAlgorithm"], "SHA256");
+    assert_eq!(body["contentSize"], PDF.len());
+    assert_eq!(body["mediaType"], "application/pdf");
+
+    let id = body["dataObjectId"].as_str().unwrap().parse().unwrap();
+    let data_object = uploader.repos.data_objects.get_by_id(id).await.unwrap();
+    assert_eq!(data_object.content_digest.as_deref(), Some(sha256.as_str()));
+
+    // The blob under the storage key holds exactly the bytes sent
+    let key = data_object.storage_key.unwrap();
+    assert_eq!(uploader.blobs(), vec![key.clone()]);
+    let stored = std::fs::read(uploader.dir.join(&key)).unwrap();
+    assert_eq!(format!("{:x}", Sha256::digest(&stored)), sha256);
+}
+
+#[actix_rt::test]
+async fn documents_over_the_limit_are_refused_with_413() {
+    let uploader = Uploader::new().await;
+    let large = [b"%PDF-1.7\n".as_slice(), &[b'a'; MAX_BYTES]].concat();
+
+    let (status, body) = uploader.upload(&[("title", b"plan"), ("domain", b"OPERATIONS"), ("file", &large)]).await;
+    assert_eq!(status, 413, "{}", body);
+    assert!(uploader.blobs().is_empty());
+}
+
+#[actix_rt::test]
+async fn executables_are_refused_with_415() {
+    let uploader = Uploader::new().await;
+
+    // A DOS stub whose e_lfanew points to the PE signature
+    let mut portable_executable = vec![0u8; 0x88];
+    portable_executable[..2].copy_from_slice(b"MZ");
+    portable_executable[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+    portable_executable[0x80..0x84].copy_from_slice(b"PE\0\0");
+
+    for executable in [portable_executable.as_slice(), b"\x7fELF\x02\x01\x01\0"] {
+        let (status, body) = uploader
+            .upload(&[("title", b"plan"), ("domain", b"OPERATIONS"), ("file", executable)])
+            .await;
+        assert_eq!(status, 415, "{}", body);
+    }
+
+    assert!(uploader.blobs().is_empty());
+
+    // Only "MZ" in common with one
+    let (status, body) = uploader
+        .upload(&[("title", b"plan"), ("domain", b"OPERATIONS"), ("file", b"MZ,Mozambique,SECRET\n")])
+        .await;
+    assert_eq!((status, body["mediaType"].as_str()), (201, Some("text/plain; charset=utf-8")), "{}", body);
+}
+
+#[actix_rt::test]
+async fn the_blob_is_deleted_when_title_or_domain_is_missing() {
+    let uploader = Uploader::new().await;
+
+    let (status, body) = uploader.upload(&[("domain", b"OPERATIONS"), ("file", PDF)]).await;
+    assert_eq!((status, body["error"].as_str()), (400, Some("title and domain are required")), "{}", body);
+    assert!(uploader.blobs().is_empty());
+
+    // Fields after the file still count
+    let (status, body) = uploader.upload(&[("file", PDF), ("title", b"plan"), ("domain", b"  ")]).await;
+    assert_eq!(status, 400, "{}", body);
+    assert!(uploader.blobs().is_empty());
+}
+
+#[actix_rt::test]
+async fn forms_are_capped_in_fields_and_field_size() {
+    let uploader = Uploader::new().await;
+
+    let mut parts: Vec<(&str, &[u8])> = vec![("tags", b"alpha"); UPLOAD_MAX_FIELDS];
+    parts.push(("file", PDF));
+    parts.push(("title", b"plan"));
+    let (status, body) = uploader.upload(&parts).await;
+    assert_eq!(status, 413, "{}", body);
+    assert!(uploader.blobs().is_empty());
+
+    let long_title = vec![b'a'; UPLOAD_FIELD_LIMIT + 1];
+    let (status, body) = uploader.upload(&[("title", &long_title), ("domain", b"OPERATIONS"), ("file", PDF)]).await;
+    assert_eq!(status, 413, "{}", body);
+    assert!(uploader.blobs().is_empty());
+}
+
+#[actix_rt::test]
+async fn the_uploaded_data_object_can_be_converted() {
+    let uploader = Uploader::new().await;
+    let (repos, operator) = (&uploader.repos, uploader.user_id);
+
+    let (status, body) = uploader.upload(&[("title", b"plan"), ("domain", b"OPERATIONS"), ("file", PDF)]).await;
+    assert_eq!(status, 201, "{}", body);
+    let data_object_id = body["dataObjectId"].as_str().unwrap();
+
+    let gbr = repos.nations.create(NewNation::new(operator, "GBR".to_owned(), "United Kingdom".to_owned())).await.unwrap();
+    repos.nations.create(NewNation::new(operator, "USA".to_owned(), "United States".to_owned())).await.unwrap();
+    let authority = repos
+        .authorities
+        .create(NewAuthority::new(operator, gbr.id, "UK NSA".to_owned(), "nsa@example.org".to_owned(), "0".to_owned(), None))
+        .await
+        .unwrap()
+        .id;
+    repos.schemas.create(classification_schema(operator, authority, "GBR")).await.unwrap();
+    repos.schemas.create(classification_schema(operator, authority, "USA")).await.unwrap();
+
+    let query = format!(
+        r#"mutation {{ submitConversionRequest(input: {{
+            authorityId: "{}", dataObjectId: "{}", sourceNationCode: "GBR",
+            sourceMarking: "GBR SECRET", targetNationCodes: ["USA"]
+        }}) {{ conversionRequest {{ dataObjectId }} results {{ targetMarking }} }} }}"#,
+        authority, data_object_id,
+    );
+    let mailer = Mailer::new(None, "converter@example.org".parse().unwrap(), Tera::default());
+    let response = create_schema_with_context(repos.clone())
+        .execute(Request::new(query).data(UserRole::Operator).data(operator).data(mailer))
+        .await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let data = response.data.into_json().unwrap();
+    assert_eq!(data["submitConversionRequest"]["conversionRequest"]["dataObjectId"], data_object_id);
+    assert_eq!(data["submitConversionRequest"]["results"][0]["targetMarking"], "USA SECRET");
+}
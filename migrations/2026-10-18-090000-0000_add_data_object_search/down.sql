@@ -0,0 +1,8 @@
+-- Drop data object full-text search
+DROP TRIGGER IF EXISTS metadata_search_refresh ON metadata;
+DROP TRIGGER IF EXISTS data_objects_search_refresh ON data_objects;
+DROP FUNCTION IF EXISTS metadata_search_trigger();
+DROP FUNCTION IF EXISTS data_objects_search_trigger();
+DROP FUNCTION IF EXISTS refresh_data_object_search(UUID);
+DROP INDEX IF EXISTS data_object_search__document_idx;
+DROP TABLE IF EXISTS data_object_search;
@@ -0,0 +1,68 @@
+-- Full-text search documents for data objects. A data object's title and
+-- description live on data_objects while its domain and tags live on metadata,
+-- so the combined tsvector is kept in its own table and maintained by triggers.
+CREATE TABLE IF NOT EXISTS data_object_search (
+    data_object_id UUID PRIMARY KEY,
+        FOREIGN KEY(data_object_id)
+        REFERENCES data_objects(id) ON DELETE CASCADE,
+    document TSVECTOR NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX data_object_search__document_idx ON data_object_search USING GIN (document);
+
+-- Rebuilds the search document for a single data object.
+-- Weights: title (A), domain and tags (B), description (C)
+CREATE OR REPLACE FUNCTION refresh_data_object_search(_data_object_id UUID) RETURNS VOID AS $$
+BEGIN
+    INSERT INTO data_object_search (data_object_id, document, updated_at)
+    SELECT
+        d.id,
+        setweight(to_tsvector('english', d.title), 'A') ||
+        setweight(to_tsvector('english', coalesce(string_agg(m.domain, ' '), '')), 'B') ||
+        setweight(to_tsvector('english', coalesce(string_agg(array_to_string(m.tags, ' '), ' '), '')), 'B') ||
+        setweight(to_tsvector('english', d.description), 'C'),
+        NOW()
+    FROM data_objects d
+    LEFT JOIN metadata m ON m.data_object_id = d.id
+    WHERE d.id = _data_object_id
+    GROUP BY d.id
+    ON CONFLICT (data_object_id) DO UPDATE
+        SET document = EXCLUDED.document,
+            updated_at = EXCLUDED.updated_at;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION data_objects_search_trigger() RETURNS trigger AS $$
+BEGIN
+    PERFORM refresh_data_object_search(NEW.id);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION metadata_search_trigger() RETURNS trigger AS $$
+BEGIN
+    IF (TG_OP = 'DELETE') THEN
+        PERFORM refresh_data_object_search(OLD.data_object_id);
+        RETURN OLD;
+    END IF;
+
+    IF (TG_OP = 'UPDATE' AND OLD.data_object_id <> NEW.data_object_id) THEN
+        PERFORM refresh_data_object_search(OLD.data_object_id);
+    END IF;
+
+    PERFORM refresh_data_object_search(NEW.data_object_id);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER data_objects_search_refresh
+    AFTER INSERT OR UPDATE OF title, description ON data_objects
+    FOR EACH ROW EXECUTE PROCEDURE data_objects_search_trigger();
+
+CREATE TRIGGER metadata_search_refresh
+    AFTER INSERT OR UPDATE OR DELETE ON metadata
+    FOR EACH ROW EXECUTE PROCEDURE metadata_search_trigger();
+
+-- Backfill existing data objects
+SELECT refresh_data_object_search(id) FROM data_objects;
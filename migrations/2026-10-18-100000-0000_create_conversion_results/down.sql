@@ -0,0 +1,2 @@
+-- Drop conversion_results table
+DROP TABLE IF EXISTS conversion_results;
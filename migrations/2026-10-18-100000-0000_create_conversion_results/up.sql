@@ -0,0 +1,22 @@
+-- One row per target nation for each processed conversion request
+CREATE TABLE IF NOT EXISTS conversion_results (
+    id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+    conversion_request_id UUID NOT NULL,
+        FOREIGN KEY(conversion_request_id)
+        REFERENCES conversion_requests(id) ON DELETE CASCADE,
+    source_nation_code VARCHAR(3) NOT NULL,
+    source_marking VARCHAR(128) NOT NULL,
+    source_schema_id UUID NOT NULL,
+        FOREIGN KEY(source_schema_id)
+        REFERENCES classification_schemas(id) ON DELETE RESTRICT,
+    nato_classification VARCHAR(32) NOT NULL,
+    target_nation_code VARCHAR(3) NOT NULL,
+    target_marking VARCHAR(128) NOT NULL,
+    target_schema_id UUID NOT NULL,
+        FOREIGN KEY(target_schema_id)
+        REFERENCES classification_schemas(id) ON DELETE RESTRICT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX conversion_results__conversion_request_id_idx ON conversion_results(conversion_request_id);
+CREATE INDEX conversion_results__target_nation_code_idx ON conversion_results(target_nation_code);
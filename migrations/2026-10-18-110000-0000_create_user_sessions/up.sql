@@ -0,0 +1,16 @@
+-- One row per signed-in device. Access tokens carry the session id and are
+-- only honoured while the session is active; refresh tokens are stored hashed.
+CREATE TABLE IF NOT EXISTS user_sessions (
+    id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+    user_id UUID NOT NULL,
+        FOREIGN KEY(user_id)
+        REFERENCES users(id) ON DELETE CASCADE,
+    refresh_token_hash VARCHAR(64) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP
+);
+
+CREATE UNIQUE INDEX user_sessions__refresh_token_hash_idx ON user_sessions(refresh_token_hash);
+CREATE INDEX user_sessions__user_id_idx ON user_sessions(user_id);
@@ -0,0 +1,29 @@
+-- Keys for machine-to-machine clients. Each key acts as a service user with a
+-- fixed role, for a single authority, within its scopes. Only a SHA-256 of the
+-- key is stored; key_prefix identifies it in listings.
+CREATE TABLE IF NOT EXISTS api_keys (
+    id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+    user_id UUID NOT NULL,
+        FOREIGN KEY(user_id)
+        REFERENCES users(id) ON DELETE CASCADE,
+    authority_id UUID NOT NULL,
+        FOREIGN KEY(authority_id)
+        REFERENCES authorities(id) ON DELETE CASCADE,
+    name VARCHAR(128) NOT NULL,
+    key_prefix VARCHAR(16) NOT NULL,
+    key_hash VARCHAR(64) NOT NULL,
+    role VARCHAR(64) NOT NULL,
+        FOREIGN KEY(role)
+        REFERENCES valid_roles(role),
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    created_by UUID NOT NULL,
+        FOREIGN KEY(created_by)
+        REFERENCES users(id) ON DELETE RESTRICT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    last_used_at TIMESTAMP,
+    revoked_at TIMESTAMP
+);
+
+CREATE UNIQUE INDEX api_keys__key_hash_idx ON api_keys(key_hash);
+CREATE INDEX api_keys__user_id_idx ON api_keys(user_id);
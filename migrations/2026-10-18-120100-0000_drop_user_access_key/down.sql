@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN access_key VARCHAR(256) NOT NULL DEFAULT '';
@@ -0,0 +1,3 @@
+-- access_key was never read and every user was written with an empty one.
+-- API keys live in api_keys.
+ALTER TABLE users DROP COLUMN IF EXISTS access_key;
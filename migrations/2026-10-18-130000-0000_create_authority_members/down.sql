@@ -0,0 +1,2 @@
+-- Drop authority_members table
+DROP TABLE IF EXISTS authority_members;
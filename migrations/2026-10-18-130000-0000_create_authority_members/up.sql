@@ -0,0 +1,14 @@
+-- Which authorities a user acts for. Users only see conversion requests
+-- created by, or targeted at, their authorities and those authorities' nations.
+CREATE TABLE IF NOT EXISTS authority_members (
+    user_id UUID NOT NULL,
+        FOREIGN KEY(user_id)
+        REFERENCES users(id) ON DELETE CASCADE,
+    authority_id UUID NOT NULL,
+        FOREIGN KEY(authority_id)
+        REFERENCES authorities(id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, authority_id)
+);
+
+CREATE INDEX authority_members__authority_id_idx ON authority_members(authority_id);
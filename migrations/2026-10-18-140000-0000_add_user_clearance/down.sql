@@ -0,0 +1,8 @@
+-- Drop user clearance and nationality
+ALTER TABLE users DROP COLUMN IF EXISTS nationality;
+
+ALTER TABLE users
+    DROP CONSTRAINT IF EXISTS users__access_level_check,
+    ALTER COLUMN access_level DROP DEFAULT;
+
+UPDATE users SET access_level = 'detailed';
@@ -0,0 +1,14 @@
+-- access_level now holds the user's clearance on the NATO scale. Earlier
+-- values were never enforced, so existing users start at UNCLASSIFIED and
+-- are raised by an admin.
+UPDATE users SET access_level = 'UNCLASSIFIED'
+    WHERE access_level NOT IN ('UNCLASSIFIED', 'RESTRICTED', 'CONFIDENTIAL', 'SECRET', 'TOP_SECRET');
+
+ALTER TABLE users
+    ALTER COLUMN access_level SET DEFAULT 'UNCLASSIFIED',
+    ADD CONSTRAINT users__access_level_check
+        CHECK (access_level IN ('UNCLASSIFIED', 'RESTRICTED', 'CONFIDENTIAL', 'SECRET', 'TOP_SECRET'));
+
+-- Nation code of the user's nationality. Data objects are only shown to users
+-- of the nations they are released to.
+ALTER TABLE users ADD COLUMN nationality VARCHAR(3);
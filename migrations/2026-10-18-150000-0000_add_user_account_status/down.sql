@@ -0,0 +1,6 @@
+-- Drop the account lifecycle columns
+DROP INDEX IF EXISTS users__account_status_idx;
+
+ALTER TABLE users
+    DROP COLUMN IF EXISTS created_by_user_uid,
+    DROP COLUMN IF EXISTS account_status;
@@ -0,0 +1,13 @@
+-- Account lifecycle. New accounts wait for approval by an administrator other
+-- than the one who created them, recorded in approved_by_user_uid. Accounts
+-- that already exist were active, so they start approved.
+ALTER TABLE users
+    ADD COLUMN account_status VARCHAR(16) NOT NULL DEFAULT 'PENDING'
+        CONSTRAINT users__account_status_check
+        CHECK (account_status IN ('PENDING', 'APPROVED', 'SUSPENDED')),
+    ADD COLUMN created_by_user_uid UUID
+        REFERENCES users(id) ON DELETE SET NULL;
+
+UPDATE users SET account_status = 'APPROVED';
+
+CREATE INDEX users__account_status_idx ON users(account_status);
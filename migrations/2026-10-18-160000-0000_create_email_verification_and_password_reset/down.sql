@@ -0,0 +1,4 @@
+-- Drop email verification and password reset tables
+ALTER TABLE users DROP COLUMN IF EXISTS email_verified_at;
+DROP TABLE IF EXISTS password_reset_token;
+DROP TABLE IF EXISTS email_verification_code;
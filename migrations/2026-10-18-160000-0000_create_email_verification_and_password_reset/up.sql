@@ -0,0 +1,26 @@
+-- Single-use tokens for email verification and password resets. One pending
+-- token per address; issuing a new one replaces it. Only SHA-256 hashes of
+-- the tokens are stored.
+CREATE TABLE IF NOT EXISTS email_verification_code (
+    id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+    email_address VARCHAR(128) NOT NULL UNIQUE,
+        FOREIGN KEY(email_address)
+        REFERENCES users(email) ON UPDATE CASCADE ON DELETE CASCADE,
+    activation_code_hash VARCHAR(64) NOT NULL,
+    expires_on TIMESTAMP NOT NULL
+);
+
+CREATE UNIQUE INDEX email_verification_code__hash_idx ON email_verification_code(activation_code_hash);
+
+CREATE TABLE IF NOT EXISTS password_reset_token (
+    id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+    email_address VARCHAR(128) NOT NULL UNIQUE,
+        FOREIGN KEY(email_address)
+        REFERENCES users(email) ON UPDATE CASCADE ON DELETE CASCADE,
+    reset_token_hash VARCHAR(64) NOT NULL,
+    expires_on TIMESTAMP NOT NULL
+);
+
+CREATE UNIQUE INDEX password_reset_token__hash_idx ON password_reset_token(reset_token_hash);
+
+ALTER TABLE users ADD COLUMN email_verified_at TIMESTAMP;
@@ -0,0 +1,36 @@
+-- TOTP second factor. totp_secret is the base32 shared secret; it is pending
+-- until the first code is confirmed and totp_enabled_at is set.
+-- totp_last_step is the time step of the last accepted code, so a code can't
+-- be replayed.
+ALTER TABLE users
+    ADD COLUMN totp_secret VARCHAR(64),
+    ADD COLUMN totp_enabled_at TIMESTAMP,
+    ADD COLUMN totp_last_step BIGINT;
+
+-- Single-use recovery codes for a lost authenticator. Only SHA-256 hashes are
+-- stored.
+CREATE TABLE IF NOT EXISTS totp_recovery_codes (
+    id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+    user_id UUID NOT NULL,
+        FOREIGN KEY(user_id)
+        REFERENCES users(id) ON DELETE CASCADE,
+    code_hash VARCHAR(64) NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE UNIQUE INDEX totp_recovery_codes__user_id_code_hash_idx ON totp_recovery_codes(user_id, code_hash);
+
+-- Second step of a password sign-in. The challenge is handed out once the
+-- password checks out and exchanged with a code for a session.
+CREATE TABLE IF NOT EXISTS mfa_challenges (
+    id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+    user_id UUID NOT NULL,
+        FOREIGN KEY(user_id)
+        REFERENCES users(id) ON DELETE CASCADE,
+    challenge_hash VARCHAR(64) NOT NULL,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE UNIQUE INDEX mfa_challenges__challenge_hash_idx ON mfa_challenges(challenge_hash);
@@ -0,0 +1,31 @@
+-- Failed sign-in counters, per account (lowercased email) and per client IP.
+-- Accounts that don't exist are counted too, so lockouts don't reveal which
+-- addresses have accounts. The backoff is derived from failed_attempts and
+-- last_failed_at, and rows are reset once failures stop for long enough.
+CREATE TABLE IF NOT EXISTS sign_in_throttles (
+    id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+    kind VARCHAR(16) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    first_failed_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    last_failed_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX sign_in_throttles__kind_subject_idx ON sign_in_throttles(kind, subject);
+
+-- Sign-in successes, failures, lockouts and unlocks. Rows are only ever
+-- inserted.
+CREATE TABLE IF NOT EXISTS security_events (
+    id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+    event_type VARCHAR(32) NOT NULL,
+    user_id UUID,
+        FOREIGN KEY(user_id)
+        REFERENCES users(id) ON DELETE SET NULL,
+    email VARCHAR(255),
+    ip_address VARCHAR(64),
+    detail TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX security_events__created_at_idx ON security_events(created_at);
+CREATE INDEX security_events__user_id_idx ON security_events(user_id);
@@ -0,0 +1,37 @@
+-- Append-only audit trail. Each entry carries the hash of the one before it
+-- (64 zeros for the first), and entry_hash covers the entry's fields and
+-- prev_hash, so editing, removing or reordering entries breaks the chain.
+-- Actor and entity ids are not foreign keys, so entries outlive what they
+-- refer to.
+CREATE TABLE IF NOT EXISTS audit_log (
+    sequence BIGINT PRIMARY KEY,
+    occurred_at TIMESTAMP NOT NULL,
+    actor_id UUID,
+    action VARCHAR(64) NOT NULL,
+    entity_type VARCHAR(64) NOT NULL,
+    entity_id UUID,
+    nation_code VARCHAR(3),
+    -- JSON text exactly as hashed
+    detail TEXT NOT NULL,
+    prev_hash VARCHAR(64) NOT NULL,
+    entry_hash VARCHAR(64) NOT NULL
+);
+
+CREATE UNIQUE INDEX audit_log__prev_hash_idx ON audit_log(prev_hash);
+CREATE INDEX audit_log__actor_id_idx ON audit_log(actor_id);
+CREATE INDEX audit_log__entity_id_idx ON audit_log(entity_id);
+CREATE INDEX audit_log__occurred_at_idx ON audit_log(occurred_at);
+
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log__no_update_or_delete
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+
+CREATE TRIGGER audit_log__no_truncate
+    BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_log_append_only();
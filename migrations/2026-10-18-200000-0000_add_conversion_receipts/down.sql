@@ -0,0 +1,4 @@
+ALTER TABLE conversion_results
+    DROP COLUMN IF EXISTS signing_key_id,
+    DROP COLUMN IF EXISTS signature,
+    DROP COLUMN IF EXISTS receipt;
@@ -0,0 +1,8 @@
+-- Signed conversion receipts. receipt is the canonical JSON statement of the
+-- conversion and signature its base64 Ed25519 signature by the key
+-- signing_key_id. Results stored before receipts, and results stored while no
+-- signing key is configured, leave the signature null.
+ALTER TABLE conversion_results
+    ADD COLUMN receipt TEXT,
+    ADD COLUMN signature VARCHAR(128),
+    ADD COLUMN signing_key_id VARCHAR(64);
@@ -0,0 +1,35 @@
+-- Merkle anchoring of audit entries and conversion receipts. Each anchor is a
+-- batch: its leaves are the new audit entries in sequence order, then the new
+-- receipts, and root_hash is the root of the tree over them. statement is the
+-- canonical JSON of the root that signature signs, as published.
+CREATE TABLE IF NOT EXISTS merkle_anchors (
+    id UUID DEFAULT gen_random_uuid() PRIMARY KEY,
+    root_hash VARCHAR(64) NOT NULL,
+    leaf_count INTEGER NOT NULL,
+    first_audit_sequence BIGINT,
+    last_audit_sequence BIGINT,
+    statement TEXT NOT NULL,
+    signature VARCHAR(128),
+    signing_key_id VARCHAR(64),
+    created_at TIMESTAMP NOT NULL,
+    published_at TIMESTAMP
+);
+
+CREATE INDEX merkle_anchors__published_at_idx ON merkle_anchors(published_at);
+
+-- Leaf hashes of each anchor in tree order, to build inclusion proofs. A leaf
+-- is either an audit entry or a receipt.
+CREATE TABLE IF NOT EXISTS merkle_leaves (
+    anchor_id UUID NOT NULL,
+        FOREIGN KEY(anchor_id)
+        REFERENCES merkle_anchors(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    leaf_hash VARCHAR(64) NOT NULL,
+    audit_sequence BIGINT,
+    receipt_id UUID,
+        FOREIGN KEY(receipt_id)
+        REFERENCES conversion_results(id) ON DELETE SET NULL,
+    PRIMARY KEY (anchor_id, position)
+);
+
+CREATE UNIQUE INDEX merkle_leaves__receipt_id_idx ON merkle_leaves(receipt_id);
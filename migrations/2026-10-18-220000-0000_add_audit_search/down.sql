@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS audit_log__action_idx;
+DROP INDEX IF EXISTS audit_log__nation_code_idx;
+
+-- Auditors fall back to USER, and their API keys stop working
+UPDATE users SET role = 'USER' WHERE role = 'AUDITOR';
+DELETE FROM api_keys WHERE role = 'AUDITOR';
+DELETE FROM valid_roles WHERE role = 'AUDITOR';
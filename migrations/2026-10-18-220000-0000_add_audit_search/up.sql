@@ -0,0 +1,6 @@
+-- Auditors read, search and export the audit log. Searches also filter by
+-- nation and action, which the audit log migration didn't index.
+INSERT INTO valid_roles (role) VALUES ('AUDITOR');
+
+CREATE INDEX audit_log__nation_code_idx ON audit_log(nation_code);
+CREATE INDEX audit_log__action_idx ON audit_log(action);
@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS data_objects__content_digest_idx;
+
+ALTER TABLE data_objects
+    DROP CONSTRAINT IF EXISTS data_objects__content_digest_check,
+    DROP COLUMN IF EXISTS content_size,
+    DROP COLUMN IF EXISTS digest_algorithm,
+    DROP COLUMN IF EXISTS content_digest;
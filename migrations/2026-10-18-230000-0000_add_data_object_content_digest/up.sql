@@ -0,0 +1,15 @@
+-- Content digests tie a data object to the exact bytes of its document.
+-- content_digest is the lowercase hex digest under digest_algorithm, and
+-- content_size the document's length in bytes. Objects created before
+-- digests, or without a document, leave all three null.
+ALTER TABLE data_objects
+    ADD COLUMN content_digest VARCHAR(128),
+    ADD COLUMN digest_algorithm VARCHAR(16),
+    ADD COLUMN content_size BIGINT,
+    ADD CONSTRAINT data_objects__content_digest_check CHECK (
+        (content_digest IS NULL) = (digest_algorithm IS NULL)
+        AND (content_size IS NULL OR content_size >= 0)
+        AND (digest_algorithm IS DISTINCT FROM 'SHA256' OR content_digest ~ '^[0-9a-f]{64}$')
+    );
+
+CREATE INDEX data_objects__content_digest_idx ON data_objects(content_digest);
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS data_objects__storage_key_idx;
+
+ALTER TABLE data_objects
+    DROP COLUMN IF EXISTS media_type,
+    DROP COLUMN IF EXISTS storage_key;
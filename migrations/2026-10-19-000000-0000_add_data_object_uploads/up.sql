@@ -0,0 +1,9 @@
+-- Documents uploaded through /data-objects/upload. storage_key locates the
+-- document in the blob store (BLOB_DIR or S3_BUCKET) and media_type is the
+-- MIME type sniffed from its first bytes. Both are null for data objects
+-- submitted without a document.
+ALTER TABLE data_objects
+    ADD COLUMN storage_key VARCHAR(512),
+    ADD COLUMN media_type VARCHAR(128);
+
+CREATE UNIQUE INDEX data_objects__storage_key_idx ON data_objects(storage_key);